@@ -0,0 +1,13 @@
+// Generates the gRPC service from proto/bridge.proto with a bundled protoc,
+// so that building needs nothing installed.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut config = tonic_prost_build::Config::new();
+    config.protoc_executable(protoc_bin_vendored::protoc_bin_path()?);
+    config.bytes(["."]);
+    tonic_prost_build::configure().build_client(false).compile_with_config(
+        config,
+        &["proto/bridge.proto"],
+        &["proto"],
+    )?;
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::os::unix::net::UnixListener;
+
+use anyhow::{Result, bail};
+
+use crate::bridge::Inherited;
+
+// The first descriptor systemd passes (SD_LISTEN_FDS_START).
+const FIRST_FD: i32 = 3;
+
+// Takes the sockets systemd passed for socket activation, paired with their
+// FileDescriptorName. With Accept=no these are listeners; with Accept=yes a
+// single connected socket, and the bridge ends with that connection.
+pub fn listen_fds() -> Result<Vec<(String, Inherited)>> {
+    let pid = std::env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+    if pid != Some(std::process::id()) {
+        return Ok(Vec::new());
+    }
+    let count: i32 = match std::env::var("LISTEN_FDS").ok().and_then(|n| n.parse().ok()) {
+        Some(count) => count,
+        None => bail!("LISTEN_PID is set but LISTEN_FDS is not a number"),
+    };
+    let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let mut names = names.split(':');
+    let mut sockets = Vec::new();
+    for fd in FIRST_FD..FIRST_FD + count {
+        // SAFETY: systemd hands these descriptors to this process, which owns
+        // them from here on; nothing else in the process refers to them.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let name = names.next().unwrap_or_default().to_string();
+        sockets.push((name, classify(fd)?));
+    }
+    Ok(sockets)
+}
+
+// Tells a connected TCP socket from a TCP or Unix listener, using only what
+// std exposes: a connection has a peer, a TCP listener has an inet address.
+fn classify(fd: OwnedFd) -> Result<Inherited> {
+    let stream = TcpStream::from(fd);
+    if stream.peer_addr().is_ok() {
+        stream.set_nonblocking(true)?;
+        return Ok(Inherited::Connection(stream));
+    }
+    let listener = TcpListener::from(OwnedFd::from(stream));
+    listener.set_nonblocking(true)?;
+    if listener.local_addr().is_ok() {
+        return Ok(Inherited::TcpListener(listener));
+    }
+    Ok(Inherited::UnixListener(UnixListener::from(OwnedFd::from(listener))))
+}
@@ -0,0 +1,263 @@
+#[cfg(unix)]
+use std::path::Path;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result, anyhow, bail};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+use crate::bridge::{Bridge, Registry};
+use crate::stats;
+#[cfg(unix)]
+use crate::unix;
+
+const MAX_LINE: u64 = 256;
+
+const HELP: &str = "\
+commands: bridges, sessions [bridge], stats [bridge], buffers [bridge],
+kick <bridge> <id>, pause <bridge>, resume <bridge>, bans [bridge],
+unban <bridge> <address>, help, quit
+";
+
+// Binds the admin socket, a Unix socket only its owner may use, and serves
+// it in the background.
+#[cfg(unix)]
+pub fn spawn_unix(path: &Path, registry: Arc<Registry>) -> Result<()> {
+    let listener = unix::bind(path, Some(0o600), None)?;
+    info!("Admin socket on {}", path.display());
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => {
+                    tokio::spawn(handle(socket, registry.clone()));
+                }
+                Err(e) => error!("Admin socket accept failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+// The same on a loopback TCP port, for platforms without Unix sockets.
+// Anyone on the machine may connect.
+pub async fn spawn_tcp(port: u16, registry: Arc<Registry>) -> Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .await
+        .with_context(|| format!("failed to bind admin port {}", port))?;
+    info!("Admin socket on 127.0.0.1:{}", port);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => {
+                    tokio::spawn(handle(socket, registry.clone()));
+                }
+                Err(e) => error!("Admin socket accept failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn handle<S>(stream: S, registry: Arc<Registry>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if let Err(e) = serve(stream, &registry).await {
+        info!("Admin client error: {}", e);
+    }
+}
+
+// One command per line. Listings come one item per line, and every answer
+// ends with "OK", or "ERR" and the reason:
+//
+//   > sessions
+//   < ttyUSB0 3 writer=1 read-only=0 connected=125s in=42 out=9107 peer=10.0.0.7:51234
+//   < OK
+//   > stats ttyUSB0
+//   < ttyUSB0 rx=88123 rx_rate=12 rx_last=0s tx=512 tx_rate=0 tx_last=40s dropped=0
+//   < OK
+//   > kick ttyUSB0 3
+//   < OK
+//   > bans
+//   < ttyUSB0 203.0.113.9 remaining=412s
+//   < OK
+async fn serve<S>(stream: S, registry: &Registry) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if (&mut stream).take(MAX_LINE).read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+        if !line.ends_with('\n') && line.len() as u64 == MAX_LINE {
+            stream.write_all(b"ERR line too long\n").await?;
+            return Ok(());
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let reply = match words.as_slice() {
+            [] => continue,
+            ["quit"] => return Ok(()),
+            ["help"] => HELP.to_string(),
+            words => match run(words, registry) {
+                Ok(lines) => lines.into_iter().map(|line| line + "\n").collect::<String>() + "OK\n",
+                Err(e) => format!("ERR {}\n", e),
+            },
+        };
+        stream.write_all(reply.as_bytes()).await?;
+    }
+}
+
+// Logs what the bridges, stats, buffers, sessions and bans commands would
+// answer, all at once, for when the admin socket is not there to ask.
+pub fn log_state(registry: &Registry) {
+    let bridges = registry.list();
+    info!("State of {} running bridge(s):", bridges.len());
+    for bridge in &bridges {
+        let lines = [describe(bridge), stats(bridge), buffers(bridge)]
+            .into_iter()
+            .chain(sessions(bridge))
+            .chain(bans(bridge));
+        for line in lines {
+            info!("  {}", line);
+        }
+    }
+}
+
+fn run(words: &[&str], registry: &Registry) -> Result<Vec<String>> {
+    let bridge = |name: &str| registry.get(name).ok_or_else(|| anyhow!("no bridge named '{}'", name));
+    match words {
+        ["bridges"] => Ok(registry.list().iter().map(|bridge| describe(bridge)).collect()),
+        ["sessions"] => Ok(registry.list().iter().flat_map(|bridge| sessions(bridge)).collect()),
+        ["sessions", name] => Ok(sessions(&*bridge(name)?)),
+        ["stats"] => Ok(registry.list().iter().map(|bridge| stats(bridge)).collect()),
+        ["stats", name] => Ok(vec![stats(&*bridge(name)?)]),
+        ["buffers"] => Ok(registry.list().iter().map(|bridge| buffers(bridge)).collect()),
+        ["buffers", name] => Ok(vec![buffers(&*bridge(name)?)]),
+        ["kick", name, id] => {
+            let bridge = bridge(name)?;
+            let id = id.parse().map_err(|_| anyhow!("invalid session id"))?;
+            if !bridge.sessions.kick(id) {
+                bail!("no session {} on {}", id, name);
+            }
+            info!(bridge = %bridge.name, "Session {} kicked via admin socket", id);
+            Ok(Vec::new())
+        }
+        [verb @ ("pause" | "resume"), name] => {
+            let bridge = bridge(name)?;
+            let pause = *verb == "pause";
+            if bridge.sessions.pause(pause) {
+                match pause {
+                    true => info!(bridge = %bridge.name, "Paused via admin socket"),
+                    false => info!(bridge = %bridge.name, "Resumed via admin socket"),
+                }
+            }
+            Ok(Vec::new())
+        }
+        ["bans"] => Ok(registry.list().iter().flat_map(|bridge| bans(bridge)).collect()),
+        ["bans", name] => Ok(bans(&*bridge(name)?)),
+        ["unban", name, ip] => {
+            let bridge = bridge(name)?;
+            let ip: IpAddr = ip.parse().map_err(|_| anyhow!("invalid address"))?;
+            if !bridge.bans.as_ref().is_some_and(|bans| bans.clear(ip)) {
+                bail!("{} is not banned on {}", ip, name);
+            }
+            info!(bridge = %bridge.name, "Ban on {} lifted via admin socket", ip);
+            Ok(Vec::new())
+        }
+        _ => bail!("unknown command '{}', try 'help'", words.join(" ")),
+    }
+}
+
+fn describe(bridge: &Bridge) -> String {
+    let flag = |on: bool| if on { 1 } else { 0 };
+    let port = match bridge.config.tcp_port {
+        Some(port) => port.to_string(),
+        None => "-".to_string(),
+    };
+    format!(
+        "{} port={} sessions={} total={} paused={} serial={}",
+        bridge.name,
+        port,
+        bridge.sessions.list().len(),
+        bridge.sessions.total(),
+        flag(bridge.sessions.is_paused()),
+        bridge.config.serial_port,
+    )
+}
+
+// Rates are bytes per second; "last" is how long ago data last went that
+// way, which tells a quiet console from a dead one.
+fn stats(bridge: &Bridge) -> String {
+    let counters = bridge.serial.counters();
+    let last = |at: &AtomicU64| match stats::since(at.load(Ordering::Relaxed)) {
+        Some(secs) => format!("{}s", secs),
+        None => "never".to_string(),
+    };
+    format!(
+        "{} rx={} rx_rate={} rx_last={} tx={} tx_rate={} tx_last={} dropped={}",
+        bridge.name,
+        counters.rx_bytes.load(Ordering::Relaxed),
+        counters.rx_rate.load(Ordering::Relaxed),
+        last(&counters.last_rx),
+        counters.tx_bytes.load(Ordering::Relaxed),
+        counters.tx_rate.load(Ordering::Relaxed),
+        last(&counters.last_tx),
+        counters.dropped_reads.load(Ordering::Relaxed),
+    )
+}
+
+// Each as queued/capacity: serial reads the slowest subscriber has yet to
+// take, requests waiting for the port, and retained output in bytes.
+fn buffers(bridge: &Bridge) -> String {
+    let fill = bridge.serial.fill();
+    let retained = match fill.retained {
+        Some((len, capacity)) => format!("{}/{}", len, capacity),
+        None => "-".to_string(),
+    };
+    format!(
+        "{} output={}/{} requests={}/{} retained={}",
+        bridge.name, fill.output.0, fill.output.1, fill.requests.0, fill.requests.1, retained,
+    )
+}
+
+fn bans(bridge: &Bridge) -> Vec<String> {
+    let Some(bans) = &bridge.bans else {
+        return Vec::new();
+    };
+    bans.list()
+        .iter()
+        .map(|ban| format!("{} {} remaining={}s", bridge.name, ban.ip, ban.remaining.as_secs()))
+        .collect()
+}
+
+// The peer goes last, as it may contain spaces.
+fn sessions(bridge: &Bridge) -> Vec<String> {
+    let flag = |on: bool| if on { 1 } else { 0 };
+    bridge
+        .sessions
+        .list()
+        .iter()
+        .map(|info| {
+            format!(
+                "{} {} writer={} read-only={} connected={}s in={} out={} peer={}",
+                bridge.name,
+                info.id,
+                flag(bridge.sessions.is_writer(info.id)),
+                flag(info.read_only),
+                SystemTime::now()
+                    .duration_since(info.connected_at)
+                    .map_or(0, |d| d.as_secs()),
+                info.bytes_in.load(Ordering::Relaxed),
+                info.bytes_out.load(Ordering::Relaxed),
+                info.peer,
+            )
+        })
+        .collect()
+}
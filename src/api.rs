@@ -0,0 +1,422 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpStream;
+use tokio_serial::{DataBits, StopBits};
+use tracing::{error, info, warn};
+
+use crate::auth;
+use crate::bridge::{self, Bridge, Registry};
+use crate::http::{self, Request};
+use crate::sse;
+use crate::serial::{Control, PortStatus};
+use crate::{FlowControlArg, LineAction, Mode, ParityArg, Sharing};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize)]
+struct BridgeStatus {
+    name: String,
+    serial_port: String,
+    tcp_port: Option<u16>,
+    unix_socket: Option<String>,
+    connect: Option<String>,
+    mode: Mode,
+    sharing: Sharing,
+    paused: bool,
+    connected: bool,
+    baud_rate: u32,
+    data_bits: u8,
+    parity: ParityArg,
+    stop_bits: u8,
+    flow_control: String,
+    dtr: bool,
+    rts: bool,
+    cts: bool,
+    dsr: bool,
+    ri: bool,
+    cd: bool,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    clients: Vec<ClientStatus>,
+}
+
+#[derive(Serialize)]
+struct ClientStatus {
+    id: u64,
+    peer: String,
+    // Unix time in seconds.
+    connected_at: u64,
+    writer: bool,
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+#[derive(Serialize)]
+struct BanStatus {
+    address: IpAddr,
+    // Seconds until it runs out.
+    remaining: u64,
+}
+
+#[derive(Deserialize)]
+struct BaudRateRequest {
+    baud_rate: u32,
+}
+
+// Any of the port settings, as GET reports them; those left out stay as
+// they are.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SerialRequest {
+    pub baud_rate: Option<u32>,
+    pub data_bits: Option<u8>,
+    pub parity: Option<ParityArg>,
+    pub stop_bits: Option<u8>,
+    pub flow_control: Option<FlowControlArg>,
+}
+
+impl SerialRequest {
+    pub fn controls(&self) -> Result<Vec<Control>, &'static str> {
+        let mut controls = Vec::new();
+        if let Some(baud) = self.baud_rate {
+            controls.push(Control::BaudRate(baud));
+        }
+        if let Some(bits) = self.data_bits {
+            controls.push(Control::DataBits(match bits {
+                5 => DataBits::Five,
+                6 => DataBits::Six,
+                7 => DataBits::Seven,
+                8 => DataBits::Eight,
+                _ => return Err("data_bits must be 5, 6, 7 or 8"),
+            }));
+        }
+        if let Some(parity) = self.parity {
+            controls.push(Control::Parity(parity));
+        }
+        if let Some(bits) = self.stop_bits {
+            controls.push(Control::StopBits(match bits {
+                1 => StopBits::One,
+                2 => StopBits::Two,
+                _ => return Err("stop_bits must be 1 or 2"),
+            }));
+        }
+        if let Some(flow) = self.flow_control {
+            controls.push(Control::FlowControl(flow.into()));
+        }
+        Ok(controls)
+    }
+}
+
+#[derive(Deserialize)]
+struct LineRequest {
+    action: LineAction,
+}
+
+struct Response {
+    status: u16,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json<T: Serialize>(value: &T) -> Response {
+        Response::with_status(200, value)
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response::with_status(status, &serde_json::json!({ "error": message }))
+    }
+
+    fn with_status<T: Serialize>(status: u16, value: &T) -> Response {
+        let mut body = serde_json::to_vec_pretty(value).expect("response serializes");
+        body.push(b'\n');
+        Response { status, body }
+    }
+}
+
+// Binds the management API and serves it in the background. With a token,
+// every request must carry it as "Authorization: Bearer <token>".
+pub fn spawn(bind: &[IpAddr], port: u16, token: Option<String>, registry: Arc<Registry>) -> Result<()> {
+    let listeners = bridge::listeners(bind, port, "API port")?;
+    info!("Management API on port {}", port);
+    let token: Option<Arc<str>> = token.map(Arc::from);
+    for listener in listeners {
+        let (token, registry) = (token.clone(), registry.clone());
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((socket, addr)) => {
+                        tokio::spawn(handle(socket, addr, token.clone(), registry.clone()));
+                    }
+                    Err(e) => error!("API accept failed: {}", e),
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn handle(mut socket: TcpStream, addr: SocketAddr, token: Option<Arc<str>>, registry: Arc<Registry>) {
+    let response = match tokio::time::timeout(REQUEST_TIMEOUT, http::read_request(&mut socket)).await {
+        Ok(Ok(request)) if !authorized(&request, token.as_deref()) => {
+            info!("Refusing API request from {}: missing or invalid token", addr);
+            Response::error(401, "missing or invalid token")
+        }
+        // An event stream holds the connection rather than answering once.
+        Ok(Ok(request)) => match events(&request, &registry) {
+            Some(bridge) => return stream_events(socket, addr, &bridge).await,
+            None => route(&request, &registry).await,
+        },
+        Ok(Err(e)) => Response::error(400, &e.to_string()),
+        Err(_) => return,
+    };
+    let _ = http::respond(&mut socket, response.status, "application/json", &response.body).await;
+}
+
+fn authorized(request: &Request, token: Option<&str>) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    let presented = request.header("authorization").and_then(|value| value.strip_prefix("Bearer "));
+    auth::constant_time_eq(presented.unwrap_or_default().as_bytes(), token.as_bytes())
+}
+
+// The bridge whose output GET /bridges/<name>/events streams.
+fn events(request: &Request, registry: &Registry) -> Option<Arc<Bridge>> {
+    let segments: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["bridges", name, "events"]) => registry.get(name),
+        _ => None,
+    }
+}
+
+// Under the bridge's ACL and bans, and only for bridges that let anyone who
+// can reach them read their output: there is no way to log in here.
+async fn stream_events(mut socket: TcpStream, addr: SocketAddr, bridge: &Bridge) {
+    let refusal = if !bridge.admits(addr.ip()) {
+        Some("address not allowed")
+    } else if !bridge.open_control() {
+        Some("the bridge requires authentication")
+    } else {
+        None
+    };
+    if let Some(refusal) = refusal {
+        info!(bridge = %bridge.name, "Refusing event stream for {}: {}", addr, refusal);
+        let response = Response::error(403, refusal);
+        let _ = http::respond(&mut socket, response.status, "application/json", &response.body).await;
+        return;
+    }
+    info!(bridge = %bridge.name, "Event stream opened for {}", addr);
+    if let Err(e) = sse::serve(socket, &bridge.serial).await {
+        warn!(bridge = %bridge.name, "Event stream error: {}", e);
+    }
+    info!(bridge = %bridge.name, "Event stream closed");
+}
+
+async fn route(request: &Request, registry: &Registry) -> Response {
+    let segments: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["bridges"]) => {
+            let mut statuses = Vec::new();
+            for bridge in registry.list() {
+                match status(&bridge).await {
+                    Ok(status) => statuses.push(status),
+                    Err(e) => return Response::error(503, &e.to_string()),
+                }
+            }
+            Response::json(&statuses)
+        }
+        (method, ["bridges", name, rest @ ..]) => {
+            let Some(bridge) = registry.get(name) else {
+                return Response::error(404, "no such bridge");
+            };
+            bridge_route(method, rest, request, &bridge).await
+        }
+        _ => Response::error(404, "not found"),
+    }
+}
+
+async fn bridge_route(method: &str, path: &[&str], request: &Request, bridge: &Bridge) -> Response {
+    match (method, path) {
+        ("GET", []) => match status(bridge).await {
+            Ok(status) => Response::json(&status),
+            Err(e) => Response::error(503, &e.to_string()),
+        },
+        ("PUT", ["baud-rate"]) => {
+            let Ok(body) = serde_json::from_slice::<BaudRateRequest>(&request.body) else {
+                return Response::error(400, "expected {\"baud_rate\": N}");
+            };
+            if let Err(e) = bridge.serial.control(Control::BaudRate(body.baud_rate)).await {
+                return Response::error(503, &e.to_string());
+            }
+            info!(bridge = %bridge.name, "Baud rate set to {} via API", body.baud_rate);
+            match status(bridge).await {
+                Ok(status) => Response::json(&status),
+                Err(e) => Response::error(503, &e.to_string()),
+            }
+        }
+        // Reconfigures the open port in place, leaving listeners and
+        // sessions be, for devices that switch speed mid-session. Settings
+        // apply in the order listed in SerialRequest; one the port refuses
+        // fails the request, with those before it already applied.
+        ("PUT", ["serial"]) => {
+            let controls = match serde_json::from_slice::<SerialRequest>(&request.body) {
+                Ok(body) => match body.controls() {
+                    Ok(controls) => controls,
+                    Err(message) => return Response::error(400, message),
+                },
+                Err(_) => {
+                    return Response::error(
+                        400,
+                        "expected any of baud_rate, data_bits, parity, stop_bits and flow_control",
+                    );
+                }
+            };
+            for control in &controls {
+                match bridge.serial.control(control.clone()).await {
+                    Ok(port) if took(control, &port) => {}
+                    Ok(_) => return Response::error(422, &format!("the port refused {:?}", control)),
+                    Err(e) => return Response::error(503, &e.to_string()),
+                }
+            }
+            info!(bridge = %bridge.name, "Serial settings changed via API: {:?}", controls);
+            match status(bridge).await {
+                Ok(status) => Response::json(&status),
+                Err(e) => Response::error(503, &e.to_string()),
+            }
+        }
+        ("PUT", [line @ ("dtr" | "rts")]) => {
+            let Ok(body) = serde_json::from_slice::<LineRequest>(&request.body) else {
+                return Response::error(400, "expected {\"action\": \"set\" | \"clear\" | \"pulse\"}");
+            };
+            let control = if *line == "dtr" { Control::Dtr } else { Control::Rts };
+            if let Err(e) = bridge.serial.line(control, body.action).await {
+                return Response::error(503, &e.to_string());
+            }
+            info!(bridge = %bridge.name, "{:?} {} via API", body.action, line.to_uppercase());
+            match status(bridge).await {
+                Ok(status) => Response::json(&status),
+                Err(e) => Response::error(503, &e.to_string()),
+            }
+        }
+        ("DELETE", ["clients", id]) => {
+            let Ok(id) = id.parse() else {
+                return Response::error(400, "invalid client id");
+            };
+            if !bridge.sessions.kick(id) {
+                return Response::error(404, "no such client");
+            }
+            Response {
+                status: 204,
+                body: Vec::new(),
+            }
+        }
+        ("POST", ["clients", id, "takeover"]) => {
+            let Ok(id) = id.parse() else {
+                return Response::error(400, "invalid client id");
+            };
+            match bridge.sessions.take_over(id) {
+                Some(true) => {
+                    info!(bridge = %bridge.name, "Write lock given to client {} via API", id);
+                    Response {
+                        status: 204,
+                        body: Vec::new(),
+                    }
+                }
+                Some(false) => Response::error(409, "client is read-only"),
+                None => Response::error(404, "no such client"),
+            }
+        }
+        ("GET", ["bans"]) => {
+            let bans = bridge.bans.as_ref().map(|bans| bans.list()).unwrap_or_default();
+            let bans: Vec<BanStatus> = bans
+                .into_iter()
+                .map(|ban| BanStatus {
+                    address: ban.ip,
+                    remaining: ban.remaining.as_secs(),
+                })
+                .collect();
+            Response::json(&bans)
+        }
+        ("DELETE", ["bans", ip]) => {
+            let Ok(ip) = ip.parse::<IpAddr>() else {
+                return Response::error(400, "invalid address");
+            };
+            if !bridge.bans.as_ref().is_some_and(|bans| bans.clear(ip)) {
+                return Response::error(404, "address not banned");
+            }
+            info!(bridge = %bridge.name, "Ban on {} lifted via API", ip);
+            Response {
+                status: 204,
+                body: Vec::new(),
+            }
+        }
+        (_, [] | ["baud-rate" | "serial" | "dtr" | "rts" | "bans" | "events"]) => {
+            Response::error(405, "method not allowed")
+        }
+        _ => Response::error(404, "not found"),
+    }
+}
+
+// Whether the port reports the setting as asked for. While the device is
+// away it reports what it will be reopened with.
+pub fn took(control: &Control, port: &PortStatus) -> bool {
+    match *control {
+        Control::BaudRate(baud) => port.baud_rate == baud,
+        Control::DataBits(bits) => port.data_bits == bits,
+        Control::Parity(parity) => port.parity == parity,
+        Control::StopBits(bits) => port.stop_bits == bits,
+        Control::FlowControl(flow) => port.flow_control == flow,
+        _ => true,
+    }
+}
+
+async fn status(bridge: &Bridge) -> Result<BridgeStatus> {
+    let port = bridge.serial.control(Control::Status).await?;
+    let counters = bridge.serial.counters();
+    let clients = bridge
+        .sessions
+        .list()
+        .into_iter()
+        .map(|info| ClientStatus {
+            id: info.id,
+            peer: info.peer.clone(),
+            connected_at: info
+                .connected_at
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            writer: bridge.sessions.is_writer(info.id),
+            bytes_in: info.bytes_in.load(Ordering::Relaxed),
+            bytes_out: info.bytes_out.load(Ordering::Relaxed),
+        })
+        .collect();
+    Ok(BridgeStatus {
+        name: bridge.name.to_string(),
+        serial_port: bridge.config.serial_port.clone(),
+        tcp_port: bridge.config.tcp_port,
+        unix_socket: bridge.config.unix_socket.as_ref().map(|p| p.display().to_string()),
+        connect: bridge.config.connect.clone(),
+        mode: bridge.config.mode,
+        sharing: bridge.config.sharing,
+        paused: bridge.sessions.is_paused(),
+        connected: counters.connected.load(Ordering::Relaxed),
+        baud_rate: port.baud_rate,
+        data_bits: port.data_bits.into(),
+        parity: port.parity,
+        stop_bits: port.stop_bits.into(),
+        flow_control: port.flow_control.to_string().to_lowercase(),
+        dtr: port.dtr,
+        rts: port.rts,
+        cts: port.cts,
+        dsr: port.dsr,
+        ri: port.ri,
+        cd: port.cd,
+        rx_bytes: counters.rx_bytes.load(Ordering::Relaxed),
+        tx_bytes: counters.tx_bytes.load(Ordering::Relaxed),
+        clients,
+    })
+}
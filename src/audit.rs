@@ -0,0 +1,115 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::{Instrument, error};
+
+use crate::client::Peer;
+use crate::record;
+
+// A record of what clients sent the device, one JSON object a line; bridges
+// may share a file. It is only ever appended to, with a line per session
+// arriving and leaving, and one per write or break in between (wrapped
+// here):
+//
+//   {"time":"2026-03-01T09:12:44.031Z","bridge":"core-sw1","session":4,
+//    "peer":"10.0.0.7:51234","identity":"pam:alice","event":"input","data":"reload\r"}
+//
+// Data is what reached the port, after line editing and input filters, and
+// escaped as in session transcripts.
+#[derive(Clone)]
+pub struct AuditLog(mpsc::UnboundedSender<String>);
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    time: String,
+    bridge: &'a str,
+    session: u64,
+    peer: &'a str,
+    identity: Option<&'a str>,
+    event: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+}
+
+impl AuditLog {
+    // Opens the log and writes it on a task of its own. Lines that cannot
+    // be written are reported each time rather than stopping the log, so
+    // that it picks up again once, say, the disk has room.
+    pub async fn open(path: &Path) -> Result<AuditLog> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .with_context(|| format!("failed to open audit log {}", path.display()))?;
+        let path: PathBuf = path.into();
+        let (sender, mut lines) = mpsc::unbounded_channel::<String>();
+        let write = async move {
+            while let Some(line) = lines.recv().await {
+                if let Err(e) = file.write_all(line.as_bytes()).await {
+                    error!("Failed to write audit log {}: {}", path.display(), e);
+                }
+            }
+        };
+        tokio::spawn(write.in_current_span());
+        Ok(AuditLog(sender))
+    }
+
+    // Starts a session's part of the log, which ends when it is dropped.
+    pub fn session(&self, bridge: Arc<str>, session: u64, peer: &Peer) -> AuditSession {
+        let session = AuditSession {
+            log: self.clone(),
+            bridge,
+            session,
+            peer: peer.addr.clone(),
+            identity: peer.identity.clone(),
+        };
+        session.entry("connect", None);
+        session
+    }
+}
+
+pub struct AuditSession {
+    log: AuditLog,
+    bridge: Arc<str>,
+    session: u64,
+    peer: String,
+    identity: Option<String>,
+}
+
+impl AuditSession {
+    pub fn input(&self, data: &[u8]) {
+        self.entry("input", Some(record::escape(data)));
+    }
+
+    pub fn sent_break(&self) {
+        self.entry("break", None);
+    }
+
+    fn entry(&self, event: &'static str, data: Option<String>) {
+        let entry = Entry {
+            time: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            bridge: &self.bridge,
+            session: self.session,
+            peer: &self.peer,
+            identity: self.identity.as_deref(),
+            event,
+            data,
+        };
+        let mut line = serde_json::to_string(&entry).expect("audit entry serializes");
+        line.push('\n');
+        let _ = self.log.0.send(line);
+    }
+}
+
+impl Drop for AuditSession {
+    fn drop(&mut self) {
+        self.entry("disconnect", None);
+    }
+}
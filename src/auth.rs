@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+#[cfg(unix)]
+use crate::pam;
+
+const MAX_LINE: usize = 512;
+
+// What a client must present before it is served.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Credentials {
+    // `AUTH <token>\n`, the same token for everyone.
+    Token(String),
+    // `LOGIN <user> <password>\n`, checked by PAM under this service.
+    Pam(String),
+}
+
+// Waits for the client's credentials and checks them, returning who it
+// logged in as if it was by name. Reads one byte at a time so nothing after
+// the newline is consumed; it belongs to the serial stream.
+pub async fn authenticate<S>(stream: &mut S, credentials: &Credentials, timeout: Duration) -> Result<Option<String>>
+where
+    S: AsyncRead + Unpin,
+{
+    let line = match tokio::time::timeout(timeout, read_line(stream)).await {
+        Ok(line) => line?,
+        Err(_) => bail!("no credentials within {}s", timeout.as_secs()),
+    };
+    match credentials {
+        Credentials::Token(token) => {
+            let presented = line.strip_prefix(b"AUTH ").unwrap_or_default();
+            if !constant_time_eq(presented, token.as_bytes()) {
+                bail!("invalid token");
+            }
+            Ok(None)
+        }
+        #[cfg(unix)]
+        Credentials::Pam(service) => {
+            let Some((user, password)) = line
+                .strip_prefix(b"LOGIN ")
+                .and_then(|login| std::str::from_utf8(login).ok())
+                .and_then(|login| login.split_once(' '))
+            else {
+                bail!("expected LOGIN <user> <password>");
+            };
+            let (service, user, password) = (service.clone(), user.to_string(), password.to_string());
+            let (user, checked) = tokio::task::spawn_blocking(move || {
+                let checked = pam::authenticate(&service, &user, &password);
+                (user, checked)
+            })
+            .await?;
+            if let Err(e) = checked {
+                bail!("login failed for {}: {}", user, e);
+            }
+            Ok(Some(user))
+        }
+        #[cfg(not(unix))]
+        Credentials::Pam(_) => bail!("PAM is not supported on this platform"),
+    }
+}
+
+async fn read_line<S>(stream: &mut S) -> Result<Vec<u8>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut line = Vec::new();
+    loop {
+        let b = stream.read_u8().await?;
+        if b == b'\n' {
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            return Ok(line);
+        }
+        if line.len() == MAX_LINE {
+            bail!("credentials line too long");
+        }
+        line.push(b);
+    }
+}
+
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
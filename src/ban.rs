@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+// Past this many addresses, those neither banned nor recently failing are
+// forgotten, as in the throttle.
+const PRUNE_AT: usize = 1024;
+
+// Addresses that failed to authenticate too many times in a row, shut out
+// for a while. Failures are forgotten after as long as a ban lasts without
+// another, and as soon as the address authenticates.
+pub struct Bans {
+    threshold: u32,
+    duration: Duration,
+    addresses: Mutex<HashMap<IpAddr, Failures>>,
+}
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    banned_until: Option<Instant>,
+}
+
+impl Failures {
+    fn banned(&self, now: Instant) -> bool {
+        self.banned_until.is_some_and(|until| until > now)
+    }
+}
+
+// A ban in force, for the admin interfaces to list.
+pub struct Ban {
+    pub ip: IpAddr,
+    pub remaining: Duration,
+}
+
+impl Bans {
+    pub fn new(threshold: u32, duration: Duration) -> Self {
+        Bans {
+            threshold,
+            duration,
+            addresses: Mutex::default(),
+        }
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        let addresses = self.addresses.lock().unwrap();
+        addresses.get(&ip).is_some_and(|failures| failures.banned(Instant::now()))
+    }
+
+    // Counts a failed attempt, returning true if it gets the address
+    // banned.
+    pub fn fail(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut addresses = self.addresses.lock().unwrap();
+        if addresses.len() >= PRUNE_AT {
+            addresses.retain(|_, failures| failures.banned(now) || now - failures.last < self.duration);
+        }
+        let failures = addresses.entry(ip).or_insert(Failures {
+            count: 0,
+            last: now,
+            banned_until: None,
+        });
+        if now - failures.last >= self.duration {
+            failures.count = 0;
+        }
+        failures.count += 1;
+        failures.last = now;
+        if failures.count < self.threshold || failures.banned(now) {
+            return false;
+        }
+        failures.count = 0;
+        failures.banned_until = Some(now + self.duration);
+        true
+    }
+
+    pub fn succeed(&self, ip: IpAddr) {
+        self.addresses.lock().unwrap().remove(&ip);
+    }
+
+    pub fn list(&self) -> Vec<Ban> {
+        let now = Instant::now();
+        let addresses = self.addresses.lock().unwrap();
+        let mut bans: Vec<Ban> = addresses
+            .iter()
+            .filter_map(|(ip, failures)| {
+                let until = failures.banned_until.filter(|&until| until > now)?;
+                Some(Ban {
+                    ip: *ip,
+                    remaining: until - now,
+                })
+            })
+            .collect();
+        bans.sort_by_key(|ban| ban.ip);
+        bans
+    }
+
+    // Lifts a ban, returning false if the address was not banned.
+    pub fn clear(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut addresses = self.addresses.lock().unwrap();
+        let banned = addresses.get(&ip).is_some_and(|failures| failures.banned(now));
+        if banned {
+            addresses.remove(&ip);
+        }
+        banned
+    }
+}
@@ -0,0 +1,113 @@
+// Baud rates beyond the standard ones, such as 250000 for 3D printers or
+// 74880 for ESP8266 boot logs. serialport asks for any rate by BOTHER on
+// Linux, IOSSIOSPEED on macOS and directly on the BSDs and Windows; the
+// driver then settles on the nearest rate its clock can divide down to,
+// which is read back here rather than trusted. On Linux a driver that
+// ignores BOTHER, or a serialport that only knows the standard rates (as
+// on musl), gets the old custom divisor instead: 38400 baud standing in for
+// the UART's base rate over a divisor set by TIOCSSERIAL.
+
+use tokio_serial::{Error, ErrorKind, SerialPort, SerialPortBuilder, SerialPortBuilderExt, SerialStream};
+#[cfg(target_os = "linux")]
+use tracing::debug;
+
+// A UART resynchronises on every start bit, so a rate this far off still
+// frames characters correctly.
+const TOLERANCE: f64 = 0.02;
+
+const STANDARD: &[u32] = &[
+    50, 75, 110, 134, 150, 200, 300, 600, 1200, 1800, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400,
+    460800, 500000, 576000, 921600, 1000000, 1152000, 1500000, 2000000, 2500000, 3000000, 3500000, 4000000,
+];
+
+// Opens the port at `baud`, by way of a standard rate if it is not one.
+pub fn open(builder: SerialPortBuilder, baud: u32) -> tokio_serial::Result<SerialStream> {
+    let initial = if STANDARD.contains(&baud) { baud } else { 9600 };
+    let mut port = builder.baud_rate(initial).open_native_async()?;
+    set(&mut port, baud)?;
+    Ok(port)
+}
+
+// Fails, with the nearest rate the adapter offered, if it cannot come
+// within TOLERANCE of `baud`.
+pub fn set(port: &mut SerialStream, baud: u32) -> tokio_serial::Result<()> {
+    if baud == 0 {
+        return Err(Error::new(ErrorKind::InvalidInput, "baud rate must be at least 1"));
+    }
+    #[cfg(target_os = "linux")]
+    linux::clear_divisor(port);
+    let set = port.set_baud_rate(baud);
+    if set.is_ok() && close(rate(port), baud) {
+        return Ok(());
+    }
+    #[cfg(target_os = "linux")]
+    match linux::set_divisor(port, baud) {
+        Ok(()) if close(rate(port), baud) => {
+            debug!("{} baud by custom divisor", baud);
+            return Ok(());
+        }
+        Ok(()) => linux::clear_divisor(port),
+        Err(e) => debug!("No custom divisor for {} baud: {}", baud, e),
+    }
+    let message = match set {
+        Err(e) => format!("the adapter cannot be set to {} baud: {}", baud, e),
+        Ok(()) => format!("the adapter cannot run at {} baud, the nearest it offers is {}", baud, rate(port)),
+    };
+    Err(Error::new(ErrorKind::InvalidInput, message))
+}
+
+// The rate the port runs at, 0 if unknown.
+pub fn rate(port: &SerialStream) -> u32 {
+    let rate = port.baud_rate().unwrap_or(0);
+    #[cfg(target_os = "linux")]
+    if rate == 38400
+        && let Some(rate) = linux::divided(port)
+    {
+        return rate;
+    }
+    rate
+}
+
+fn close(actual: u32, baud: u32) -> bool {
+    (actual as f64 - baud as f64).abs() <= baud as f64 * TOLERANCE
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::io;
+
+    use libc::c_int;
+    use tokio_serial::{SerialPort, SerialStream};
+
+    use crate::serial_struct::{self, ASYNC_SPD_CUST, ASYNC_SPD_MASK};
+
+    pub fn set_divisor(port: &mut SerialStream, baud: u32) -> io::Result<()> {
+        let mut serial = serial_struct::get(port)?;
+        if serial.baud_base <= 0 {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "the driver reports no base rate"));
+        }
+        serial.custom_divisor = ((serial.baud_base as f64 / baud as f64).round() as c_int).max(1);
+        serial.flags = (serial.flags & !ASYNC_SPD_MASK) | ASYNC_SPD_CUST;
+        serial_struct::set(port, &serial)?;
+        port.set_baud_rate(38400).map_err(io::Error::from)
+    }
+
+    // So that 38400 means 38400 again. Most ports never had a divisor, and
+    // many cannot be asked, so this is best effort.
+    pub fn clear_divisor(port: &SerialStream) {
+        if let Ok(mut serial) = serial_struct::get(port)
+            && serial.flags & ASYNC_SPD_MASK == ASYNC_SPD_CUST
+        {
+            serial.flags &= !ASYNC_SPD_MASK;
+            serial.custom_divisor = 0;
+            let _ = serial_struct::set(port, &serial);
+        }
+    }
+
+    // What 38400 baud stands for under a custom divisor, if one is set.
+    pub fn divided(port: &SerialStream) -> Option<u32> {
+        let serial = serial_struct::get(port).ok()?;
+        (serial.flags & ASYNC_SPD_MASK == ASYNC_SPD_CUST && serial.custom_divisor > 0 && serial.baud_base > 0)
+            .then(|| (serial.baud_base / serial.custom_divisor) as u32)
+    }
+}
@@ -0,0 +1,1437 @@
+use std::collections::{BTreeMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use clap::ValueEnum;
+use socket2::SockRef;
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::net::{TcpListener, TcpSocket, TcpStream, UdpSocket};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, oneshot, watch};
+use tokio::task::{AbortHandle, JoinHandle, JoinSet};
+use tokio_rustls::TlsAcceptor;
+use tokio_serial::{DataBits, FlowControl, StopBits};
+use tracing::{Instrument, Span, field, info, info_span, warn};
+
+use crate::acl::{Acl, SharedAcl};
+use crate::audit::AuditLog;
+use crate::auth::{self, Credentials};
+use crate::ban::Bans;
+use crate::capture::Capture;
+use crate::compress::ZstdStream;
+use crate::embed::{self, Callbacks};
+use crate::dump::HexDump;
+use crate::escape::Escapes;
+use crate::filter::{Filter, Pipeline, Record, StripAnsi};
+use crate::framing::{Frames, Framing};
+use crate::http::Url;
+use crate::line_input::LineInput;
+use crate::modbus::Gateway;
+use crate::kafka::KafkaConfig;
+use crate::mqtt::MqttConfig;
+use crate::nats::NatsConfig;
+use crate::parse::{self, LineParser};
+use crate::redis::RedisConfig;
+use crate::newline::Newlines;
+use crate::nmea::Framer;
+use crate::peer::PeerConfig;
+use crate::client::{self, IdleTimeout, Peer, SessionGuard, SessionInfo, SessionOptions, Sessions};
+use crate::record::{RecordTap, Recorder};
+use crate::plugin::Plugin;
+use crate::rotate::Rotation;
+use crate::rs485::Rs485;
+use crate::script::Script;
+use crate::serial::{self, Buffers, Control, Device, Direction, Heartbeat, Retain, SerialHandle, Taps};
+use crate::throttle::Throttle;
+use crate::timestamp::Timestamps;
+use crate::transport::{Noise, SocketOptions, Tcp, Tls, WebSocket};
+use crate::tee::Tee;
+use crate::triggers::Trigger;
+use crate::udp::Multicast;
+use crate::usb::UsbId;
+use crate::watchdog::Watchdog;
+use crate::{
+    Compression, Dump, FilterName, LineAction, LineEnding, Mode, ParityArg, Sharing, TimestampFormat, Transport,
+};
+#[cfg(unix)]
+use crate::unix;
+use crate::ssh::SshServer;
+use crate::{
+    control, gpsd, kafka, mdns, mqtt, nats, noise, peer, quic, redis, stats, tee, tls, transport, triggers, udp,
+    watchdog, web,
+};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// How long clients get to go away on shutdown before the port is closed
+// under them.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+const MIN_CALL_HOME_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_CALL_HOME_BACKOFF: Duration = Duration::from_secs(60);
+
+// Which of a bridge's listeners a connection arrived on.
+#[derive(Clone, Copy)]
+enum Endpoint {
+    Data,
+    // A data listener whose clients only watch.
+    ReadOnly,
+    Web,
+    Control,
+    Gpsd,
+    Ssh,
+}
+
+// Everything needed to run one serial port <-> TCP port bridge.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BridgeConfig {
+    pub name: String,
+    // The device path, or "usb:VID:PID[:SERIAL]" when opened by USB id.
+    pub serial_port: String,
+    pub usb_id: Option<UsbId>,
+    // None when clients only come in over the Unix socket, or the bridge
+    // only calls home.
+    pub tcp_port: Option<u16>,
+    pub transport: Transport,
+    pub udp_peer: Option<SocketAddr>,
+    pub multicast: Option<Multicast>,
+    pub bind: Vec<IpAddr>,
+    // The mDNS service type to advertise tcp_port under.
+    pub mdns: Option<String>,
+    pub unix_socket: Option<PathBuf>,
+    // HOST:PORT to call home to.
+    pub connect: Option<String>,
+    pub mqtt: Option<MqttConfig>,
+    pub redis: Option<RedisConfig>,
+    pub nats: Option<NatsConfig>,
+    pub kafka: Option<KafkaConfig>,
+    // Turns lines into JSON for MQTT and post_url.
+    pub parser: Option<Arc<LineParser>>,
+    pub post_url: Option<Url>,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub unix_socket_mode: Option<u32>,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub unix_socket_owner: Option<String>,
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: ParityArg,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    pub rs485: Option<Rs485>,
+    pub pace_writes: bool,
+    pub low_latency: bool,
+    pub firmware_flash: bool,
+    pub init_send: Option<Bytes>,
+    pub heartbeat: Option<Heartbeat>,
+    pub peer: Option<PeerConfig>,
+    pub mode: Mode,
+    pub modbus_unit_map: BTreeMap<u8, u8>,
+    pub modbus_timeout: Duration,
+    pub nmea_filter: Vec<String>,
+    pub sharing: Sharing,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub tls_client_ca: Option<PathBuf>,
+    pub noise_key: Option<noise::Key>,
+    pub ws: bool,
+    pub compress: Option<Compression>,
+    pub web_port: Option<u16>,
+    pub read_only_port: Option<u16>,
+    pub read_only_identities: Vec<String>,
+    pub control_port: Option<u16>,
+    pub gpsd_port: Option<u16>,
+    pub ssh_port: Option<u16>,
+    pub ssh_host_key: Option<PathBuf>,
+    pub ssh_authorized_keys: Option<PathBuf>,
+    // What the certificate and key files above held when the config was
+    // read, so that a reload once one is replaced in place, as when a
+    // certificate is renewed, restarts the bridge to load it again.
+    pub key_files: Vec<Option<u64>>,
+    pub quic_port: Option<u16>,
+    pub capture: Option<PathBuf>,
+    pub dump: Option<Dump>,
+    pub record: Option<PathBuf>,
+    pub record_rotation: Rotation,
+    pub record_keep: Option<usize>,
+    pub audit_log: Option<PathBuf>,
+    pub stats_interval: Option<Duration>,
+    pub watchdog: Option<Watchdog>,
+    pub triggers: Arc<[Trigger]>,
+    pub tees: Vec<Tee>,
+    pub script: Option<PathBuf>,
+    // Set when embedded by another program.
+    pub callbacks: Callbacks,
+    pub notify_reconnect: bool,
+    pub banner: bool,
+    pub motd: Option<String>,
+    pub retain: Option<Retain>,
+    pub buffers: Buffers,
+    pub client_buffer: usize,
+    pub line_buffered: bool,
+    pub local_echo: bool,
+    pub line_ending: Option<LineEnding>,
+    pub output_line_ending: Option<LineEnding>,
+    pub framing: Option<Framing>,
+    pub timestamps: Option<TimestampFormat>,
+    // What sessions do to bytes each way, timestamps and the line endings
+    // configuring the filters of those names.
+    pub output_filters: Vec<FilterName>,
+    pub input_filters: Vec<FilterName>,
+    pub plugin: Option<PathBuf>,
+    pub timestamp_reads: bool,
+    pub break_sequence: Option<String>,
+    pub takeover_sequence: Option<String>,
+    pub dtr_on_connect: Option<LineAction>,
+    pub auth: Option<Credentials>,
+    pub auth_timeout: Duration,
+    pub ban_after: Option<u32>,
+    pub ban_time: Duration,
+    pub tcp_keepalive: Option<Duration>,
+    pub tcp_keepalive_interval: Option<Duration>,
+    pub no_delay: bool,
+    pub idle_timeout: Option<IdleTimeout>,
+    pub acl: Acl,
+    pub proxy_protocol: bool,
+    pub max_connections: Option<usize>,
+    // Connections a minute from each address.
+    pub connect_rate: Option<u32>,
+}
+
+// How a bridge's config changed in a reload.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    None,
+    // Only its ACL or credentials, which update() takes on with sessions
+    // left as they are.
+    Live,
+    // Anything else, for which it restarts.
+    Restart,
+}
+
+pub fn change(old: &BridgeConfig, new: &BridgeConfig) -> Change {
+    let fixed = |config: &BridgeConfig| BridgeConfig {
+        acl: Acl::default(),
+        auth: None,
+        ..config.clone()
+    };
+    if fixed(old) != fixed(new) {
+        Change::Restart
+    } else if old != new {
+        Change::Live
+    } else {
+        Change::None
+    }
+}
+
+// State shared by all connections to one bridge.
+pub struct Bridge {
+    pub name: Arc<str>,
+    pub config: BridgeConfig,
+    pub serial: SerialHandle,
+    pub sessions: Arc<Sessions>,
+    // Set when addresses failing to authenticate are banned.
+    pub bans: Option<Arc<Bans>>,
+    tls: Option<TlsAcceptor>,
+    ssh: Option<SshServer>,
+    // Set in modbus-gateway mode.
+    modbus: Option<Gateway>,
+    plugin: Option<Plugin>,
+    script: Option<Arc<Script>>,
+    audit: Option<AuditLog>,
+    // What a config reload can change without a restart; the config keeps
+    // what the bridge started with.
+    acl: SharedAcl,
+    auth: RwLock<Option<Credentials>>,
+    // Open connections, when they are limited.
+    connections: Option<Arc<Semaphore>>,
+    throttle: Option<Arc<Throttle>>,
+}
+
+// The running bridges, for the management API to look up by name.
+pub struct Registry {
+    bridges: Mutex<Vec<Arc<Bridge>>>,
+    // What the process is configured to run, whether running or not.
+    configured: Mutex<Vec<String>>,
+    // Set once the process is shutting down.
+    stopping: watch::Sender<bool>,
+    // Bridges a config reload dropped or must restart, until they stop.
+    retiring: watch::Sender<HashSet<String>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry {
+            bridges: Mutex::default(),
+            configured: Mutex::default(),
+            stopping: watch::Sender::new(false),
+            retiring: watch::Sender::new(HashSet::new()),
+        }
+    }
+}
+
+impl Registry {
+    // Tells every bridge, including ones still starting, to shut down.
+    pub fn shutdown(&self) {
+        self.stopping.send_replace(true);
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        *self.stopping.borrow()
+    }
+
+    // Tells one bridge to shut down; clear_retired() once it has.
+    pub fn retire(&self, name: &str) {
+        self.retiring.send_modify(|names| {
+            names.insert(name.to_string());
+        });
+    }
+
+    pub fn clear_retired(&self, name: &str) {
+        self.retiring.send_modify(|names| {
+            names.remove(name);
+        });
+    }
+
+    async fn stopped(&self, name: &str) {
+        let mut stopping = self.stopping.subscribe();
+        let mut retiring = self.retiring.subscribe();
+        tokio::select! {
+            _ = stopping.wait_for(|stopping| *stopping) => {}
+            _ = retiring.wait_for(|names| names.contains(name)) => {}
+        }
+    }
+
+    pub fn set_configured(&self, names: Vec<String>) {
+        *self.configured.lock().unwrap() = names;
+    }
+
+    pub fn configured(&self) -> Vec<String> {
+        self.configured.lock().unwrap().clone()
+    }
+
+    pub fn list(&self) -> Vec<Arc<Bridge>> {
+        self.bridges.lock().unwrap().clone()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Bridge>> {
+        self.list().into_iter().find(|b| &*b.name == name)
+    }
+
+    fn add(&self, bridge: Arc<Bridge>) {
+        self.bridges.lock().unwrap().push(bridge);
+    }
+
+    fn remove(&self, bridge: &Arc<Bridge>) {
+        self.bridges.lock().unwrap().retain(|b| !Arc::ptr_eq(b, bridge));
+    }
+}
+
+// Ends a session's log with what it moved, for log pipelines to total up.
+fn log_disconnect(info: &SessionInfo) {
+    info!(
+        event = "session_end",
+        session = info.id,
+        bytes_in = info.bytes_in.load(Ordering::Relaxed),
+        bytes_out = info.bytes_out.load(Ordering::Relaxed),
+        duration = info.connected_at.elapsed().map_or(0, |elapsed| elapsed.as_secs()),
+        "Client disconnected"
+    );
+}
+
+// The character format in the usual shorthand, as "8N1".
+fn char_format(data_bits: DataBits, parity: ParityArg, stop_bits: StopBits) -> String {
+    let data_bits = match data_bits {
+        DataBits::Five => 5,
+        DataBits::Six => 6,
+        DataBits::Seven => 7,
+        DataBits::Eight => 8,
+    };
+    let parity = match parity {
+        ParityArg::None => 'N',
+        ParityArg::Odd => 'O',
+        ParityArg::Even => 'E',
+        ParityArg::Mark => 'M',
+        ParityArg::Space => 'S',
+    };
+    let stop_bits = match stop_bits {
+        StopBits::One => 1,
+        StopBits::Two => 2,
+    };
+    format!("{}{}{}", data_bits, parity, stop_bits)
+}
+
+fn advertise(service: &str, config: &BridgeConfig, port: u16, tls: bool) -> Result<mdns::Advertisement> {
+    let mode = config.mode.to_possible_value().map_or(String::new(), |value| value.get_name().to_string());
+    let mut properties = vec![
+        ("name", config.name.clone()),
+        ("device", config.serial_port.clone()),
+        ("baud", config.baud_rate.to_string()),
+        ("format", char_format(config.data_bits, config.parity, config.stop_bits)),
+        ("mode", mode),
+    ];
+    if tls {
+        properties.push(("tls", "1".to_string()));
+    }
+    if config.ws {
+        properties.push(("ws", "1".to_string()));
+    }
+    mdns::advertise(service, &config.name, port, &properties)
+}
+
+// A client source handed to the bridge instead of one it opens itself: a
+// socket from systemd socket activation, or stdin/stdout under inetd.
+pub enum Inherited {
+    Stdio,
+    #[cfg(unix)]
+    TcpListener(std::net::TcpListener),
+    #[cfg(unix)]
+    UnixListener(std::os::unix::net::UnixListener),
+    // A single accepted connection; the bridge ends when it closes.
+    #[cfg(unix)]
+    Connection(std::net::TcpStream),
+}
+
+// Runs a bridge until one of its listeners fails or the registry shuts
+// down; `ready` fires once the port is open and clients can connect.
+pub async fn run(
+    config: BridgeConfig,
+    registry: Arc<Registry>,
+    inherited: Vec<Inherited>,
+    ready: oneshot::Sender<()>,
+) -> Result<()> {
+    let name: Arc<str> = config.name.as_str().into();
+
+    let tls = match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => Some(tls::acceptor(cert, key, config.tls_client_ca.as_deref())?),
+        _ => None,
+    };
+    let ssh = match (config.ssh_port, &config.ssh_host_key, &config.ssh_authorized_keys) {
+        (Some(_), Some(host_key), Some(authorized_keys)) => Some(SshServer::load(host_key, authorized_keys)?),
+        _ => None,
+    };
+    let quic = match (config.quic_port, &config.tls_cert, &config.tls_key) {
+        (Some(port), Some(cert), Some(key)) => {
+            quic::listen(&config.bind, port, cert, key, config.tls_client_ca.as_deref())?
+        }
+        _ => Vec::new(),
+    };
+    let plugin = match &config.plugin {
+        Some(path) => Some(Plugin::load(path)?),
+        None => None,
+    };
+    let script = match &config.script {
+        Some(path) => Some(Arc::new(Script::load(path)?)),
+        None => None,
+    };
+    let audit = match &config.audit_log {
+        Some(path) => Some(AuditLog::open(path).await?),
+        None => None,
+    };
+
+    let path = match &config.usb_id {
+        Some(id) => {
+            let path = id.find()?;
+            info!("Found USB adapter {} at {}", id, path);
+            path
+        }
+        None => config.serial_port.clone(),
+    };
+    let builder = tokio_serial::new(&path, config.baud_rate)
+        .data_bits(config.data_bits)
+        .stop_bits(config.stop_bits)
+        .flow_control(config.flow_control);
+    let port = serial::open(&path, builder.clone(), config.baud_rate, config.parity)
+        .with_context(|| format!("failed to open {}", path))?;
+
+    let capture = match &config.capture {
+        Some(path) => Some(Capture::create(path, &config.serial_port).await?),
+        None => None,
+    };
+    let dump = config.dump.map(|Dump::Hex| HexDump::new(&config.name));
+    let (kafka, producer) = match config.kafka.clone() {
+        Some(kafka) => {
+            let (tap, producer) = kafka::tap(kafka, &config.name, &config.serial_port);
+            (Some(tap), Some(producer))
+        }
+        None => (None, None),
+    };
+    let device = Device {
+        path: path.clone(),
+        builder,
+        usb_id: config.usb_id.clone(),
+        rs485: config.rs485.clone(),
+        pace: config.pace_writes,
+        low_latency: config.low_latency,
+        init: config.init_send.clone(),
+        heartbeat: config.heartbeat.clone(),
+    };
+    let serial = serial::spawn(
+        port,
+        device,
+        Taps { capture, dump, kafka },
+        config.notify_reconnect,
+        config.retain,
+        config.buffers,
+        script.clone(),
+    );
+    if let Some(producer) = producer {
+        tokio::spawn(producer.in_current_span());
+    }
+    let peer = config.peer.as_ref().map(|peer| peer::open(peer, config.buffers)).transpose()?;
+    // Sniffing, clients follow the feed from peer::serve, while the relay
+    // and triggers still see the device itself.
+    let (watched, feed) = match &config.peer {
+        Some(peer) if peer.sniff => {
+            let (watched, feed) = serial.fed();
+            (watched, Some(feed))
+        }
+        _ => (serial.clone(), None),
+    };
+    let sessions = Sessions::new(config.sharing);
+
+    // Inherited sockets take the place of the bridge's own client ports.
+    let (tcp_port, unix_socket) = match inherited.is_empty() {
+        true => (config.tcp_port, config.unix_socket.clone()),
+        false => (None, None),
+    };
+    let mut listeners = Vec::new();
+    let udp_socket = match (config.transport, tcp_port) {
+        (Transport::Tcp, Some(port)) => {
+            listeners.extend(bind(&config.bind, port, Endpoint::Data)?);
+            None
+        }
+        (Transport::Udp, Some(port)) => Some(
+            UdpSocket::bind((config.bind[0], port))
+                .await
+                .with_context(|| format!("failed to bind UDP port {}", port))?,
+        ),
+        (_, None) => None,
+    };
+    let multicast_socket = config.multicast.as_ref().map(udp::multicast_socket).transpose()?;
+    #[cfg(unix)]
+    let unix_listener = match &unix_socket {
+        Some(path) => Some(unix::bind(path, config.unix_socket_mode, config.unix_socket_owner.as_deref())?),
+        None => None,
+    };
+    #[cfg(not(unix))]
+    if unix_socket.is_some() {
+        anyhow::bail!("Unix domain sockets are not supported on this platform");
+    }
+    #[cfg(not(unix))]
+    if matches!(config.auth, Some(Credentials::Pam(_))) {
+        anyhow::bail!("PAM is not supported on this platform");
+    }
+    if let Some(port) = config.web_port {
+        listeners.extend(bind(&config.bind, port, Endpoint::Web)?);
+    }
+    if let Some(port) = config.read_only_port {
+        listeners.extend(bind(&config.bind, port, Endpoint::ReadOnly)?);
+    }
+    if let Some(port) = config.control_port {
+        listeners.extend(bind(&config.bind, port, Endpoint::Control)?);
+    }
+    if let Some(port) = config.gpsd_port {
+        listeners.extend(bind(&config.bind, port, Endpoint::Gpsd)?);
+    }
+    if let Some(port) = config.ssh_port {
+        listeners.extend(bind(&config.bind, port, Endpoint::Ssh)?);
+    }
+    match tcp_port {
+        Some(port) => info!(
+            "Bridging {} on port {}{}{}{}",
+            config.serial_port,
+            port,
+            if udp_socket.is_some() { " (UDP)" } else { "" },
+            if tls.is_some() { " (TLS)" } else { "" },
+            if config.ws { " (WebSocket)" } else { "" }
+        ),
+        None => info!("Bridging {}", config.serial_port),
+    }
+    if let Some(path) = &unix_socket {
+        info!("Accepting clients on {}", path.display());
+    }
+    for source in &inherited {
+        match source {
+            Inherited::Stdio => info!("Serving stdin/stdout"),
+            #[cfg(unix)]
+            Inherited::TcpListener(_) | Inherited::UnixListener(_) => info!("Accepting clients on an activated socket"),
+            #[cfg(unix)]
+            Inherited::Connection(_) => info!("Serving an activated connection"),
+        }
+    }
+    match &config.peer {
+        Some(peer) if peer.sniff => info!("Sniffing between {} and {}", config.serial_port, peer.path),
+        Some(peer) => info!("Bridging {} to {}", config.serial_port, peer.path),
+        None => {}
+    }
+    if let Some(target) = &config.connect {
+        info!("Calling home to {}", target);
+    }
+    if let Some(multicast) = &config.multicast {
+        info!("Multicasting to {}", multicast.group);
+    }
+    if let Some(mqtt) = &config.mqtt {
+        info!(
+            "Publishing to {} and subscribing to {} on MQTT broker {}:{}",
+            mqtt.rx_topic, mqtt.tx_topic, mqtt.host, mqtt.port
+        );
+    }
+    if let Some(redis) = &config.redis {
+        info!(
+            "Publishing to {} and subscribing to {} on Redis {}:{}",
+            redis.rx_channel, redis.tx_channel, redis.host, redis.port
+        );
+    }
+    if let Some(nats) = &config.nats {
+        info!(
+            "Publishing to {} and subscribing to {} on NATS server {}:{}",
+            nats.rx_subject, nats.tx_subject, nats.host, nats.port
+        );
+    }
+    if let Some(url) = &config.post_url {
+        info!("Posting parsed lines to {}", url);
+    }
+    if let Some(kafka) = &config.kafka {
+        info!(
+            "Producing serial traffic to partition {} of Kafka topic {} via {}",
+            kafka.partition,
+            kafka.topic,
+            kafka.brokers.join(", ")
+        );
+    }
+    if let Some(port) = config.web_port {
+        info!("Web terminal on port {}", port);
+    }
+    if let Some(port) = config.read_only_port {
+        info!("Read-only clients on port {}", port);
+    }
+    if let Some(port) = config.control_port {
+        info!("Control channel on port {}", port);
+    }
+    if let Some(port) = config.gpsd_port {
+        info!("gpsd clients on port {}", port);
+    }
+    if let Some(port) = config.ssh_port {
+        info!("SSH on port {}", port);
+    }
+    if let Some(port) = config.quic_port {
+        info!("QUIC on UDP port {}", port);
+    }
+
+    // Withdrawn when the bridge stops.
+    let _advertisement = match (&config.mdns, tcp_port) {
+        (Some(service), Some(port)) => match advertise(service, &config, port, tls.is_some()) {
+            Ok(advertisement) => {
+                info!("Advertising as {} by mDNS", service);
+                Some(advertisement)
+            }
+            Err(e) => {
+                warn!("Not advertising by mDNS: {:#}", e);
+                None
+            }
+        },
+        _ => None,
+    };
+
+    let modbus = (config.mode == Mode::ModbusGateway)
+        .then(|| Gateway::new(serial.clone(), config.modbus_unit_map.clone(), config.modbus_timeout));
+    let bridge = Arc::new(Bridge {
+        name,
+        serial: watched,
+        sessions,
+        tls,
+        ssh,
+        modbus,
+        plugin,
+        script,
+        audit,
+        acl: SharedAcl::new(config.acl.clone()),
+        auth: RwLock::new(config.auth.clone()),
+        connections: config.max_connections.map(|max| Arc::new(Semaphore::new(max))),
+        throttle: config.connect_rate.map(|rate| Arc::new(Throttle::new(rate))),
+        bans: config.ban_after.map(|threshold| Arc::new(Bans::new(threshold, config.ban_time))),
+        config,
+    });
+    registry.add(bridge.clone());
+    let mut accepting = JoinSet::new();
+    // Accept loops, as opposed to tasks serving a single inherited client.
+    let mut loops = Vec::new();
+    for (listener, endpoint) in listeners {
+        loops.push(bridge.spawn_secured(&mut accepting, bridge.tcp(listener), endpoint));
+    }
+    for endpoint in quic {
+        loops.push(accepting.spawn(bridge.clone().accept_quic(endpoint).in_current_span()));
+    }
+    #[cfg(unix)]
+    if let Some(listener) = unix_listener {
+        loops.push(bridge.spawn_with_ws(&mut accepting, transport::Unix(listener), Endpoint::Data));
+    }
+    if let Some(target) = bridge.config.connect.clone() {
+        loops.push(accepting.spawn(bridge.clone().call_home(target).in_current_span()));
+    }
+    if let Some(peer) = peer.clone() {
+        loops.push(accepting.spawn(peer::serve(serial.clone(), peer, feed).in_current_span()));
+    }
+    if let Some(config) = bridge.config.mqtt.clone() {
+        let serve = mqtt::serve(config, bridge.config.parser.clone(), bridge.serial.clone());
+        loops.push(accepting.spawn(serve.in_current_span()));
+    }
+    if let Some(config) = bridge.config.redis.clone() {
+        loops.push(accepting.spawn(redis::serve(config, bridge.serial.clone()).in_current_span()));
+    }
+    if let Some(config) = bridge.config.nats.clone() {
+        loops.push(accepting.spawn(nats::serve(config, bridge.serial.clone()).in_current_span()));
+    }
+    if let (Some(url), Some(parser)) = (bridge.config.post_url.clone(), bridge.config.parser.clone()) {
+        loops.push(accepting.spawn(parse::post(url, parser, bridge.serial.clone()).in_current_span()));
+    }
+    let stats = stats::sample(bridge.serial.clone(), bridge.config.stats_interval);
+    loops.push(accepting.spawn(stats.in_current_span()));
+    if let Some(watchdog) = bridge.config.watchdog.clone() {
+        let run = watchdog::run(
+            bridge.serial.clone(),
+            bridge.config.name.clone(),
+            bridge.config.serial_port.clone(),
+            watchdog,
+        );
+        loops.push(accepting.spawn(run.in_current_span()));
+    }
+    if !bridge.config.triggers.is_empty() {
+        let run = triggers::run(
+            serial.clone(),
+            bridge.config.name.clone(),
+            bridge.config.serial_port.clone(),
+            bridge.config.triggers.clone(),
+        );
+        loops.push(accepting.spawn(run.in_current_span()));
+    }
+    for tee in bridge.config.tees.clone() {
+        let run = tee::run(serial.clone(), bridge.config.name.clone(), bridge.config.serial_port.clone(), tee);
+        loops.push(accepting.spawn(run.in_current_span()));
+    }
+    if !bridge.config.callbacks.is_empty() {
+        let deliver = embed::deliver(
+            bridge.serial.clone(),
+            bridge.sessions.clone(),
+            bridge.config.callbacks.clone(),
+        );
+        loops.push(accepting.spawn(deliver.in_current_span()));
+    }
+    if let Some(socket) = udp_socket {
+        let serve = udp::serve(socket, bridge.serial.clone(), bridge.acl.clone(), bridge.config.udp_peer);
+        loops.push(accepting.spawn(serve.in_current_span()));
+    }
+    if let (Some(socket), Some(multicast)) = (multicast_socket, &bridge.config.multicast) {
+        let serve = udp::multicast(socket, bridge.serial.clone(), multicast.group);
+        loops.push(accepting.spawn(serve.in_current_span()));
+    }
+    for source in inherited {
+        match source {
+            Inherited::Stdio => {
+                bridge.spawn_with_ws(&mut accepting, transport::stdio(), Endpoint::Data);
+            }
+            #[cfg(unix)]
+            Inherited::TcpListener(listener) => {
+                let listener = TcpListener::from_std(listener)?;
+                loops.push(bridge.spawn_secured(&mut accepting, bridge.tcp(listener), Endpoint::Data));
+            }
+            #[cfg(unix)]
+            Inherited::UnixListener(listener) => {
+                let unix = transport::Unix(UnixListener::from_std(listener)?);
+                loops.push(bridge.spawn_with_ws(&mut accepting, unix, Endpoint::Data));
+            }
+            #[cfg(unix)]
+            Inherited::Connection(stream) => {
+                let stream = TcpStream::from_std(stream)?;
+                if let Err(e) = transport::tune(&stream, bridge.socket_options()) {
+                    warn!("Failed to set socket options: {}", e);
+                }
+                let peer = Peer {
+                    addr: stream.peer_addr()?.to_string(),
+                    identity: None,
+                    read_only: false,
+                };
+                bridge.spawn_secured(&mut accepting, transport::Single::new(stream, peer), Endpoint::Data);
+            }
+        }
+    }
+    let _ = ready.send(());
+    // Accept loops and the UDP socket only end on error, which takes the
+    // whole bridge down; a single inherited client ends it by leaving.
+    let result = tokio::select! {
+        joined = accepting.join_next() => match joined {
+            Some(Ok(result)) => result,
+            Some(Err(e)) => Err(e.into()),
+            None => Ok(()),
+        },
+        _ = registry.stopped(&bridge.name) => {
+            for handle in loops {
+                handle.abort();
+            }
+            bridge.sessions.close_all(SHUTDOWN_TIMEOUT).await;
+            if let Err(e) = bridge.serial.close().await {
+                warn!("Failed to close serial port: {}", e);
+            }
+            if let Some(peer) = &peer
+                && let Err(e) = peer.close().await
+            {
+                warn!("Failed to close peer port: {}", e);
+            }
+            #[cfg(unix)]
+            if let Some(path) = unix_socket {
+                let _ = std::fs::remove_file(path);
+            }
+            Ok(())
+        }
+    };
+    registry.remove(&bridge);
+    result
+}
+
+// Listens for an endpoint on `port` at each of the addresses.
+fn bind(addrs: &[IpAddr], port: u16, endpoint: Endpoint) -> Result<Vec<(TcpListener, Endpoint)>> {
+    let listeners = listeners(addrs, port, "TCP port")?;
+    Ok(listeners.into_iter().map(|listener| (listener, endpoint)).collect())
+}
+
+// Listens on `port` at each of `addrs`; `what` names the port in errors.
+pub fn listeners(addrs: &[IpAddr], port: u16, what: &str) -> Result<Vec<TcpListener>> {
+    // Otherwise [::] takes IPv4 as well on most systems, and 0.0.0.0 would
+    // be in use.
+    let v6_only = addrs.iter().any(IpAddr::is_ipv4);
+    addrs
+        .iter()
+        .map(|&ip| {
+            let addr = SocketAddr::new(ip, port);
+            listen(addr, v6_only).with_context(|| format!("failed to bind {} {}", what, addr))
+        })
+        .collect()
+}
+
+fn listen(addr: SocketAddr, v6_only: bool) -> std::io::Result<TcpListener> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    // As TcpListener::bind does, so that a restart need not wait out
+    // TIME_WAIT.
+    #[cfg(unix)]
+    socket.set_reuseaddr(true)?;
+    if addr.is_ipv6() {
+        SockRef::from(&socket).set_only_v6(v6_only)?;
+    }
+    socket.bind(addr)?;
+    socket.listen(1024)
+}
+
+impl Bridge {
+    // Serves an endpoint on a transport, under the bridge's encryption
+    // settings. SSH brings its own encryption.
+    fn spawn_secured<T: transport::Transport>(
+        self: &Arc<Self>,
+        tasks: &mut JoinSet<Result<()>>,
+        transport: T,
+        endpoint: Endpoint,
+    ) -> AbortHandle {
+        let secure = !matches!(endpoint, Endpoint::Ssh);
+        match (self.config.noise_key, &self.tls) {
+            (Some(key), _) if secure => self.spawn_with_ws(tasks, Noise::new(transport, key), endpoint),
+            (_, Some(acceptor)) if secure => self.spawn_with_ws(tasks, Tls::new(transport, acceptor.clone()), endpoint),
+            _ => self.spawn_with_ws(tasks, transport, endpoint),
+        }
+    }
+
+    // The same without encryption, but with the WebSocket layer if
+    // configured, which only data endpoints speak.
+    fn spawn_with_ws<T: transport::Transport>(
+        self: &Arc<Self>,
+        tasks: &mut JoinSet<Result<()>>,
+        transport: T,
+        endpoint: Endpoint,
+    ) -> AbortHandle {
+        match endpoint {
+            Endpoint::Data | Endpoint::ReadOnly if self.config.ws => {
+                tasks.spawn(self.clone().serve(WebSocket(transport), endpoint).in_current_span())
+            }
+            _ => tasks.spawn(self.clone().serve(transport, endpoint).in_current_span()),
+        }
+    }
+
+    // Accepts clients on a transport until it fails or, like stdin/stdout,
+    // has no more to give; then waits for the ones it has.
+    async fn serve<T: transport::Transport>(self: Arc<Self>, transport: T, endpoint: Endpoint) -> Result<()> {
+        let transport = Arc::new(transport);
+        let mut clients: Vec<JoinHandle<()>> = Vec::new();
+        while let Some((incoming, mut peer)) = transport.accept().await? {
+            let permit = match &self.connections {
+                Some(connections) => match connections.clone().try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => {
+                        info!("Refusing client {}: too many connections", peer.addr);
+                        continue;
+                    }
+                },
+                None => None,
+            };
+            // Behind a load balancer, who the peer is is only known once
+            // the connection is established.
+            let span = info_span!("client", peer = field::Empty, identity = field::Empty);
+            let bridge = self.clone();
+            let transport = transport.clone();
+            let serve = async move {
+                // Held until the connection closes.
+                let _permit = permit;
+                let established =
+                    tokio::time::timeout(HANDSHAKE_TIMEOUT, transport.establish(incoming, &mut peer)).await;
+                Span::current().record("peer", field::display(&peer.addr));
+                match established {
+                    Ok(Ok(stream)) => {
+                        if let Some(identity) = &peer.identity {
+                            Span::current().record("identity", field::display(identity));
+                        }
+                        bridge.serve_endpoint(stream, peer, endpoint).await
+                    }
+                    Ok(Err(e)) => warn!("{:#}", e),
+                    Err(_) => warn!("Handshake timed out"),
+                }
+            };
+            clients.retain(|client| !client.is_finished());
+            // Re-entering the bridge span keeps per-bridge log filters in effect.
+            clients.push(tokio::spawn(serve.instrument(span).in_current_span()));
+        }
+        for client in clients {
+            let _ = client.await;
+        }
+        Ok(())
+    }
+
+    async fn serve_endpoint<S>(&self, stream: S, mut peer: Peer, endpoint: Endpoint)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        match endpoint {
+            Endpoint::Data => self.serve_data(stream, peer).await,
+            Endpoint::ReadOnly => {
+                peer.read_only = true;
+                self.serve_data(stream, peer).await
+            }
+            Endpoint::Web => self.serve_web(stream, peer).await,
+            Endpoint::Control => self.serve_control(stream, peer).await,
+            Endpoint::Gpsd => self.serve_gpsd(stream).await,
+            Endpoint::Ssh => self.serve_ssh(stream, peer).await,
+        }
+    }
+
+    fn auth(&self) -> Option<Credentials> {
+        self.auth.read().unwrap().clone()
+    }
+
+    // Whether a connection from `ip` that did not come in on one of the
+    // bridge's own ports, such as a mux channel, may reach it.
+    pub fn admits(&self, ip: IpAddr) -> bool {
+        self.acl.permits(ip) && !self.bans.as_ref().is_some_and(|bans| bans.is_banned(ip))
+    }
+
+    // Whether the port may be reconfigured, or its output read, by whoever
+    // can reach it, as from a mux control channel or an API event stream,
+    // which have no way to authenticate or present a client certificate.
+    pub fn open_control(&self) -> bool {
+        self.auth().is_none() && self.config.tls_client_ca.is_none()
+    }
+
+    // Applies connect_rate and max_connections to a session from `ip`, as
+    // the bridge's listeners do: why it is refused, or the permit it holds
+    // while open when connections are limited.
+    pub fn limit(&self, ip: IpAddr) -> Result<Option<OwnedSemaphorePermit>, &'static str> {
+        if self.throttle.as_ref().is_some_and(|throttle| !throttle.admit(ip)) {
+            return Err("connecting too often");
+        }
+        match &self.connections {
+            Some(connections) => connections.clone().try_acquire_owned().map(Some).map_err(|_| "too many connections"),
+            None => Ok(None),
+        }
+    }
+
+    // Takes on what a reloaded config changed that can be changed live:
+    // see change().
+    pub fn update(&self, config: &BridgeConfig) {
+        self.acl.set(config.acl.clone());
+        *self.auth.write().unwrap() = config.auth.clone();
+    }
+
+    fn tcp(&self, listener: TcpListener) -> Tcp {
+        Tcp::new(
+            listener,
+            self.acl.clone(),
+            self.throttle.clone(),
+            self.bans.clone(),
+            self.socket_options(),
+            self.config.proxy_protocol,
+        )
+    }
+
+    fn socket_options(&self) -> SocketOptions {
+        SocketOptions {
+            no_delay: self.config.no_delay,
+            keepalive: self.config.tcp_keepalive,
+            keepalive_interval: self.config.tcp_keepalive_interval,
+        }
+    }
+
+    // Each QUIC connection is one session, on the first stream the client
+    // opens. The endpoint already did the TLS handshake.
+    async fn accept_quic(self: Arc<Self>, endpoint: quinn::Endpoint) -> Result<()> {
+        while let Some(incoming) = endpoint.accept().await {
+            let addr = incoming.remote_address();
+            if !self.acl.permits(addr.ip()) {
+                info!("Refusing client {}: address not allowed", addr);
+                incoming.refuse();
+                continue;
+            }
+            if self.bans.as_ref().is_some_and(|bans| bans.is_banned(addr.ip())) {
+                info!("Refusing client {}: banned", addr);
+                incoming.refuse();
+                continue;
+            }
+            let permit = match self.limit(addr.ip()) {
+                Ok(permit) => permit,
+                Err(refusal) => {
+                    info!("Refusing client {}: {}", addr, refusal);
+                    incoming.refuse();
+                    continue;
+                }
+            };
+            let span = info_span!("client", peer = %addr, identity = field::Empty);
+            let bridge = self.clone();
+            let serve = async move {
+                // Held until the connection closes.
+                let _permit = permit;
+                let connection = match tokio::time::timeout(HANDSHAKE_TIMEOUT, incoming).await {
+                    Ok(Ok(connection)) => connection,
+                    Ok(Err(e)) => return warn!("QUIC handshake failed: {}", e),
+                    Err(_) => return warn!("QUIC handshake timed out"),
+                };
+                let identity = quic::peer_common_name(&connection).map(|cn| format!("CN={}", cn));
+                if let Some(identity) = &identity {
+                    Span::current().record("identity", field::display(identity));
+                }
+                let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, quic::accept(&connection)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => return warn!("QUIC client opened no session: {}", e),
+                    Err(_) => return warn!("QUIC client opened no session in time"),
+                };
+                let peer = Peer {
+                    addr: addr.to_string(),
+                    identity,
+                    read_only: false,
+                };
+                bridge.serve_data(stream, peer).await;
+                // Closing at once could lose what is still in flight; the
+                // client hangs up when it sees the stream end.
+                let _ = tokio::time::timeout(HANDSHAKE_TIMEOUT, connection.closed()).await;
+                connection.close(0u32.into(), b"");
+            };
+            tokio::spawn(serve.instrument(span).in_current_span());
+        }
+        Ok(())
+    }
+
+    // Keeps a connection out to `target` up, dialling again with growing
+    // delays while it cannot be reached. The session runs as its own task so
+    // that shutdown can end it like any other.
+    async fn call_home(self: Arc<Self>, target: String) -> Result<()> {
+        let mut backoff = MIN_CALL_HOME_BACKOFF;
+        loop {
+            let socket = match TcpStream::connect(&target).await {
+                Ok(socket) => socket,
+                Err(e) => {
+                    warn!("Failed to connect to {}: {}; retrying in {:?}", target, e, backoff);
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_CALL_HOME_BACKOFF);
+                    continue;
+                }
+            };
+            backoff = MIN_CALL_HOME_BACKOFF;
+            if let Err(e) = transport::tune(&socket, self.socket_options()) {
+                warn!("Failed to set socket options: {}", e);
+            }
+            let addr = match socket.peer_addr() {
+                Ok(addr) => addr.to_string(),
+                Err(_) => target.clone(),
+            };
+            let span = info_span!("client", peer = %addr, identity = field::Empty);
+            let peer = Peer {
+                addr,
+                identity: None,
+                read_only: false,
+            };
+            let bridge = self.clone();
+            let serve = async move { bridge.serve_data(socket, peer).await };
+            let _ = tokio::spawn(serve.instrument(span).in_current_span()).await;
+            tokio::time::sleep(MIN_CALL_HOME_BACKOFF).await;
+        }
+    }
+
+    // Data connections get the bridge's mode, over compression if
+    // configured.
+    pub async fn serve_data<S>(&self, stream: S, peer: Peer)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        match self.config.compress {
+            None => self.attach(stream, peer, self.config.mode).await,
+            Some(Compression::Zstd) => match ZstdStream::new(stream) {
+                Ok(stream) => self.attach(stream, peer, self.config.mode).await,
+                Err(e) => warn!("Failed to set up compression: {}", e),
+            },
+        }
+    }
+
+    // Raw sessions that come in by some other way than a socket, such as a
+    // gRPC stream, authenticating like any other.
+    pub async fn serve_raw<S>(&self, stream: S, peer: Peer)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.attach(stream, peer, Mode::Raw).await
+    }
+
+    // Serves the terminal page; the page's WebSocket becomes a raw session,
+    // since the browser talks plain bytes rather than RFC 2217.
+    async fn serve_web<S>(&self, stream: S, peer: Peer)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let auth = self.auth();
+        let page = web::handle(stream, &self.name, auth.as_ref());
+        match tokio::time::timeout(HANDSHAKE_TIMEOUT, page).await {
+            Ok(Ok(Some(stream))) => self.attach(stream, peer, Mode::Raw).await,
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => warn!("Web request failed: {}", e),
+            Err(_) => warn!("Web request timed out"),
+        }
+    }
+
+    // Control connections are not sessions: they do not count against the
+    // sharing policy and may reconfigure the port regardless of who writes.
+    async fn serve_control<S>(&self, mut stream: S, peer: Peer)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        if let Some(credentials) = self.auth() {
+            match auth::authenticate(&mut stream, &credentials, self.config.auth_timeout).await {
+                Ok(user) => {
+                    self.authenticated(&peer, true);
+                    if let Some(user) = user {
+                        Span::current().record("identity", field::display(format!("pam:{}", user)));
+                    }
+                }
+                Err(e) => {
+                    info!("Dropping unauthenticated control client: {}", e);
+                    return self.authenticated(&peer, false);
+                }
+            }
+        }
+        info!("Control client connected");
+        if let Err(e) = control::serve(stream, &self.serial).await {
+            warn!("Control client error: {}", e);
+        }
+        info!("Control client disconnected");
+    }
+
+    // SSH clients have authenticated with their key, so they skip the
+    // token; their session channel becomes a raw session.
+    async fn serve_ssh<S>(&self, stream: S, mut peer: Peer)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let Some(ssh) = &self.ssh else {
+            return;
+        };
+        let mut connection = match tokio::time::timeout(HANDSHAKE_TIMEOUT, ssh.accept(stream)).await {
+            Ok(Ok(connection)) => connection,
+            Ok(Err(e)) => {
+                warn!("SSH handshake failed: {:#}", e);
+                return self.authenticated(&peer, false);
+            }
+            Err(_) => {
+                warn!("SSH handshake timed out");
+                return self.authenticated(&peer, false);
+            }
+        };
+        self.authenticated(&peer, true);
+        let identity = format!("ssh:{}", connection.user);
+        Span::current().record("identity", field::display(&identity));
+        peer.identity = Some(identity);
+        self.run_session(&mut connection.stream, peer, Mode::Raw).await;
+        connection.close().await;
+    }
+
+    // gpsd clients only listen, so like control connections they are not
+    // sessions.
+    async fn serve_gpsd<S>(&self, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        info!("gpsd client connected");
+        if let Err(e) = gpsd::serve(stream, &self.serial, &self.config.serial_port).await {
+            warn!("gpsd client error: {}", e);
+        }
+        info!("gpsd client disconnected");
+    }
+
+    // Runs a client session over an established stream.
+    async fn attach<S>(&self, mut stream: S, mut peer: Peer, mode: Mode)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        if let Some(credentials) = self.auth() {
+            match auth::authenticate(&mut stream, &credentials, self.config.auth_timeout).await {
+                Ok(user) => {
+                    self.authenticated(&peer, true);
+                    // Who logged in says more than any certificate.
+                    if let Some(user) = user {
+                        let identity = format!("pam:{}", user);
+                        Span::current().record("identity", field::display(&identity));
+                        peer.identity = Some(identity);
+                    }
+                }
+                Err(e) => {
+                    info!("Dropping unauthenticated client: {}", e);
+                    return self.authenticated(&peer, false);
+                }
+            }
+        }
+        self.run_session(stream, peer, mode).await
+    }
+
+    // Counts an attempt to authenticate towards banning the client's
+    // address, or clears its failures. Unix socket clients have no address
+    // to ban.
+    fn authenticated(&self, peer: &Peer, ok: bool) {
+        let (Some(bans), Ok(addr)) = (&self.bans, peer.addr.parse::<SocketAddr>()) else {
+            return;
+        };
+        if ok {
+            bans.succeed(addr.ip());
+        } else if bans.fail(addr.ip()) {
+            warn!("Banning {} for {}s after failing to authenticate", addr.ip(), self.config.ban_time.as_secs());
+        }
+    }
+
+    // Registers the client and serves it until it disconnects.
+    async fn run_session<S>(&self, stream: S, mut peer: Peer, mode: Mode)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        if let Some(identity) = &peer.identity
+            && self.config.read_only_identities.contains(identity)
+        {
+            peer.read_only = true;
+        }
+        if self.config.peer.as_ref().is_some_and(|peer| peer.sniff) {
+            peer.read_only = true;
+        }
+        let Some(session) = self.sessions.register(&peer) else {
+            match self.sessions.is_paused() {
+                true => info!("Rejecting client: bridge paused"),
+                false => info!("Rejecting client: serial port in use"),
+            }
+            return;
+        };
+        info!(
+            event = "session_start",
+            session = session.id(),
+            read_only = peer.read_only,
+            "Client connected{}",
+            if peer.read_only { " (read-only)" } else { "" }
+        );
+        if let Some(action) = self.config.dtr_on_connect
+            && session.can_write()
+            && let Err(e) = self.serial.line(Control::Dtr, action).await
+        {
+            warn!("Failed to drive DTR: {}", e);
+        }
+        if let (Mode::ModbusGateway, Some(gateway)) = (mode, &self.modbus) {
+            if let Err(e) = gateway.serve(stream, &session).await {
+                warn!("Client error: {}", e);
+            }
+            log_disconnect(&session.info());
+            return;
+        }
+        let records = [&self.config.output_filters, &self.config.input_filters]
+            .iter()
+            .any(|filters| filters.contains(&FilterName::Record));
+        let recorder = match self.config.record.as_ref().filter(|_| records) {
+            Some(dir) => match Recorder::create(
+                dir,
+                &self.name,
+                session.id(),
+                self.config.record_rotation,
+                self.config.record_keep,
+            )
+            .await
+            {
+                Ok(recorder) => {
+                    info!("Recording to {}", recorder.path().display());
+                    Some(recorder.spawn())
+                }
+                Err(e) => {
+                    warn!("Not recording session: {:#}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        // Telnet and RFC 2217 clients have telnet BRK instead.
+        let escape = match mode {
+            Mode::Raw => Escapes::new(
+                self.config.break_sequence.as_deref(),
+                self.config.takeover_sequence.as_deref(),
+            ),
+            _ => None,
+        };
+        let serial = self.serial.clone();
+        let mut output_filters = Pipeline::default();
+        // Sentences are whole before anything else sees them.
+        if mode == Mode::Nmea {
+            output_filters.push(Box::new(Framer::new(self.config.nmea_filter.clone())));
+        }
+        let mut input_filters = Pipeline::default();
+        let tap = recorder.as_ref();
+        if let Err(e) = self
+            .add_filters(&mut output_filters, &self.config.output_filters, Direction::Rx, tap)
+            .and_then(|_| self.add_filters(&mut input_filters, &self.config.input_filters, Direction::Tx, tap))
+        {
+            warn!("Disconnecting client, its plugin failed to start: {:#}", e);
+            return;
+        }
+        let options = SessionOptions {
+            escape,
+            idle: self.config.idle_timeout,
+            line_input: (self.config.line_buffered || self.config.local_echo)
+                .then(|| LineInput::new(self.config.line_buffered, self.config.local_echo)),
+            input_filters,
+            output_filters,
+            frames: self.config.framing.clone().map(Frames::new),
+            purge_output: self.config.firmware_flash,
+            read_size: self.config.client_buffer,
+            script: self.script.clone(),
+            banner: self.banner(&session, mode).await,
+            audit: self.audit.as_ref().map(|log| log.session(self.name.clone(), session.id(), &peer)),
+        };
+        let info = session.info();
+        if let Err(e) = client::serve(stream, serial, session, mode, options).await {
+            warn!("Client error: {}", e);
+        }
+        if let Some(script) = &self.script {
+            for data in script.on_disconnect(&info).to_serial {
+                if let Err(e) = self.serial.write(data).await {
+                    warn!("Failed to write script output: {}", e);
+                }
+            }
+        }
+        log_disconnect(&info);
+    }
+
+    // What the client sees first, in brackets like the port's other notices
+    // so that it stands apart from device output, then the MOTD.
+    async fn banner(&self, session: &SessionGuard, mode: Mode) -> Option<Vec<u8>> {
+        if !self.config.banner && self.config.motd.is_none() {
+            return None;
+        }
+        let mut lines = Vec::new();
+        if self.config.banner {
+            let mut port = format!("[{}: {}", self.name, self.config.serial_port);
+            // The settings as they are now, which clients may have changed.
+            if let Ok(status) = self.serial.control(Control::Status).await {
+                let format = char_format(status.data_bits, status.parity, status.stop_bits);
+                port += &format!(", {} {}", status.baud_rate, format);
+            }
+            if !self.serial.counters().connected.load(Ordering::Relaxed) {
+                port += ", disconnected";
+            }
+            lines.push(port + "]");
+            let shared = self.config.sharing != Sharing::FreeForAll;
+            let read_only = session.info().read_only;
+            lines.push(
+                match (read_only, session.can_write(), shared) {
+                    (true, _, _) => "[read-only: you may watch but not type]",
+                    (false, true, true) => "[you hold the write lock]",
+                    (false, true, false) => "[everyone connected may type]",
+                    (false, false, _) => "[another client holds the write lock, you are watching]",
+                }
+                .to_string(),
+            );
+            // Telnet clients send a break their own way.
+            let mut hints = Vec::new();
+            if mode == Mode::Raw
+                && let Some(sequence) = &self.config.break_sequence
+            {
+                hints.push(format!("{} to send a break", sequence));
+            }
+            if mode == Mode::Raw
+                && shared
+                && !read_only
+                && let Some(sequence) = &self.config.takeover_sequence
+            {
+                hints.push(format!("{} to take the write lock", sequence));
+            }
+            if !hints.is_empty() {
+                lines.push(format!("[type {}]", hints.join(", ")));
+            }
+        }
+        if let Some(motd) = &self.config.motd {
+            lines.extend(motd.lines().map(str::to_string));
+        }
+        lines.push(String::new());
+        Some(lines.join("\r\n").into_bytes())
+    }
+
+    fn add_filters(
+        &self,
+        pipeline: &mut Pipeline,
+        names: &[FilterName],
+        direction: Direction,
+        tap: Option<&RecordTap>,
+    ) -> Result<()> {
+        for name in names {
+            let filter: Box<dyn Filter> = match name {
+                FilterName::StripAnsi => Box::new(StripAnsi::default()),
+                FilterName::Timestamp => {
+                    let format = self.config.timestamps.unwrap_or(TimestampFormat::Iso8601);
+                    Box::new(Timestamps::new(format, self.config.timestamp_reads))
+                }
+                FilterName::LineEnding => {
+                    let ending = match direction {
+                        Direction::Rx => self.config.output_line_ending,
+                        Direction::Tx => self.config.line_ending,
+                    };
+                    match ending {
+                        Some(ending) => Box::new(Newlines::new(ending)),
+                        None => continue,
+                    }
+                }
+                // Without a transcript, because it could not be created.
+                FilterName::Record => match tap {
+                    Some(tap) => Box::new(Record::new(tap.clone(), direction)),
+                    None => continue,
+                },
+                // Not every plugin filters both ways.
+                FilterName::Plugin => match &self.plugin {
+                    Some(plugin) => match plugin.instantiate(direction)? {
+                        Some(filter) => Box::new(filter),
+                        None => continue,
+                    },
+                    None => continue,
+                },
+            };
+            pipeline.push(filter);
+        }
+        Ok(())
+    }
+}
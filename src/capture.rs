@@ -0,0 +1,124 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tracing::warn;
+
+use crate::serial::Direction;
+
+const SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+const ENHANCED_PACKET: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+// LINKTYPE_USER0: payload is the raw serial bytes, for a user-configured dissector.
+const LINKTYPE_USER0: u16 = 147;
+
+const OPT_END: u16 = 0;
+const OPT_SHB_USERAPPL: u16 = 4;
+const OPT_IF_NAME: u16 = 2;
+const OPT_EPB_FLAGS: u16 = 2;
+
+const FLAGS_INBOUND: u32 = 0b01;
+const FLAGS_OUTBOUND: u32 = 0b10;
+
+// pcapng file with one interface per serial port; every read or write becomes
+// a packet stamped with microsecond wall-clock time and its direction.
+pub struct Capture {
+    file: BufWriter<File>,
+}
+
+impl Capture {
+    pub async fn create(path: &Path, serial_port: &str) -> Result<Capture> {
+        let file = File::create(path)
+            .await
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        let mut capture = Capture {
+            file: BufWriter::new(file),
+        };
+
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        // Section length unknown.
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        let application = concat!("remote-serial-server ", env!("CARGO_PKG_VERSION"));
+        option(&mut shb, OPT_SHB_USERAPPL, application.as_bytes());
+        option(&mut shb, OPT_END, &[]);
+
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&LINKTYPE_USER0.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        // No snap length limit.
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        option(&mut idb, OPT_IF_NAME, serial_port.as_bytes());
+        option(&mut idb, OPT_END, &[]);
+
+        let write = async {
+            capture.block(SECTION_HEADER, &shb).await?;
+            capture.block(INTERFACE_DESCRIPTION, &idb).await?;
+            capture.file.flush().await
+        };
+        write
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(capture)
+    }
+
+    async fn packet(&mut self, direction: Direction, data: &[u8]) -> std::io::Result<()> {
+        let micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64);
+        let mut epb = Vec::with_capacity(data.len() + 40);
+        // Interface 0.
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(micros as u32).to_le_bytes());
+        epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        epb.extend_from_slice(data);
+        pad(&mut epb);
+        let flags = match direction {
+            Direction::Rx => FLAGS_INBOUND,
+            Direction::Tx => FLAGS_OUTBOUND,
+        };
+        option(&mut epb, OPT_EPB_FLAGS, &flags.to_le_bytes());
+        option(&mut epb, OPT_END, &[]);
+        self.block(ENHANCED_PACKET, &epb).await?;
+        self.file.flush().await
+    }
+
+    async fn block(&mut self, block_type: u32, body: &[u8]) -> std::io::Result<()> {
+        let total = (body.len() as u32 + 12).to_le_bytes();
+        self.file.write_all(&block_type.to_le_bytes()).await?;
+        self.file.write_all(&total).await?;
+        self.file.write_all(body).await?;
+        self.file.write_all(&total).await
+    }
+}
+
+fn option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    pad(buf);
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    buf.resize(buf.len().next_multiple_of(4), 0);
+}
+
+// Adds a packet to the capture, if any. A failing capture is dropped rather
+// than stopping the port.
+pub async fn capture(capture: &mut Option<Capture>, direction: Direction, data: &[u8]) {
+    let Some(c) = capture else {
+        return;
+    };
+    if let Err(e) = c.packet(direction, data).await {
+        warn!("Stopped packet capture: {}", e);
+        *capture = None;
+    }
+}
@@ -0,0 +1,693 @@
+use std::collections::{HashMap, HashSet};
+use std::io::IsTerminal;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use clap::{Parser, Subcommand};
+use tokio::sync::oneshot;
+use tokio::task::{self, JoinSet};
+use tracing::{Instrument, error, info, info_span, warn};
+use tracing_subscriber::{EnvFilter, reload};
+use tracing_subscriber::fmt::{self, format::JsonFields, writer::BoxMakeWriter};
+use tracing_subscriber::layer::{Layer, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+
+use crate::bridge::{self, BridgeConfig, Change, Inherited, Registry};
+use crate::config::{self, ConfigFile, Settings};
+use crate::json_log::JsonFormat;
+use crate::otlp::{self, OtlpLayer};
+use crate::rotate::{LogFile, Rotation};
+#[cfg(unix)]
+use crate::{activation, daemon};
+#[cfg(windows)]
+use crate::service;
+#[cfg(unix)]
+use crate::syslog;
+#[cfg(target_os = "linux")]
+use crate::systemd;
+use crate::{LogFormat, LogTarget, admin, api, grpc, health, local, metrics, mux, ports, tls, xmodem};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, subcommand_negates_reqs = true)]
+pub struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Read again on SIGHUP, as is a ser2net config.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Run the connections of this ser2net YAML file instead.
+    #[arg(long, conflicts_with = "config")]
+    ser2net: Option<PathBuf>,
+
+    /// Additional SERIAL_PORT:TCP_PORT pairs sharing the serial settings below.
+    #[arg(long, value_parser = parse_bridge)]
+    bridge: Vec<(String, u16)>,
+
+    /// Log filter such as "debug" or "info,[bridge{name=ttyUSB0}]=trace".
+    /// Defaults to RUST_LOG, then "info".
+    #[arg(long)]
+    log_level: Option<String>,
+
+    /// Send logs to the system logger instead of stderr.
+    #[arg(long, value_enum)]
+    log_target: Option<LogTarget>,
+
+    /// Format of log lines.
+    #[arg(long, value_enum)]
+    log_format: Option<LogFormat>,
+
+    /// Append logs to this file instead of stderr.
+    #[arg(long, conflicts_with = "log_target")]
+    log_file: Option<PathBuf>,
+
+    /// Rotate the log file once it reaches this many bytes. The rotated files
+    /// are FILE.1, FILE.2 and so on, the newest first.
+    #[arg(long)]
+    log_max_size: Option<u64>,
+
+    /// Rotate the log file, as with log_max_size, once it has been written to
+    /// for this many seconds.
+    #[arg(long)]
+    log_max_age: Option<u64>,
+
+    /// How many rotated log files to keep. Defaults to 5.
+    #[arg(long)]
+    log_keep: Option<usize>,
+
+    /// Serve a single client on stdin/stdout, e.g. under inetd, and exit
+    /// when it disconnects.
+    #[arg(long, conflicts_with = "bridge")]
+    stdio: bool,
+
+    /// Fork into the background. stderr stays open unless it is a terminal,
+    /// so logs can be redirected to a file.
+    #[cfg(unix)]
+    #[arg(long)]
+    daemon: bool,
+
+    /// Write the process id to this file.
+    #[cfg(unix)]
+    #[arg(long)]
+    pidfile: Option<PathBuf>,
+
+    /// Switch to this user, with its groups, once the ports are open. A
+    /// device that reappears must then be accessible to this user.
+    #[cfg(unix)]
+    #[arg(long)]
+    user: Option<String>,
+
+    /// Switch to this group instead of the user's.
+    #[cfg(unix)]
+    #[arg(long)]
+    group: Option<String>,
+
+    /// Serve admin commands (sessions, kick, pause) on this Unix socket.
+    #[cfg(unix)]
+    #[arg(long)]
+    admin_socket: Option<PathBuf>,
+
+    /// Serve admin commands on this port, on the loopback interface only.
+    #[arg(long)]
+    admin_port: Option<u16>,
+
+    /// Serve the HTTP management API on this port.
+    #[arg(long)]
+    api_port: Option<u16>,
+
+    /// Listen for the management API on these addresses. Defaults to
+    /// 127.0.0.1 only, since the API can reconfigure ports and kick clients.
+    #[arg(long, value_delimiter = ',')]
+    api_bind: Vec<IpAddr>,
+
+    /// Require this token on every management API request, as
+    /// "Authorization: Bearer <token>".
+    #[arg(long)]
+    api_token: Option<String>,
+
+    /// Serve Prometheus metrics at /metrics on this port.
+    #[arg(long)]
+    metrics_port: Option<u16>,
+
+    /// Serve /healthz and /readyz, for liveness and readiness probes, on
+    /// this port.
+    #[arg(long)]
+    health_port: Option<u16>,
+
+    /// Serve every bridge over single connections on this port, each
+    /// carrying any number of sessions and a control channel as tagged
+    /// frames; see mux.rs for the framing.
+    #[arg(long)]
+    mux_port: Option<u16>,
+
+    /// Serve the mux port over TLS with this certificate and key.
+    #[arg(long)]
+    mux_tls_cert: Option<PathBuf>,
+
+    /// The mux port's TLS private key.
+    #[arg(long)]
+    mux_tls_key: Option<PathBuf>,
+
+    /// Serve the gRPC API on this port: bridge status, port control and raw
+    /// sessions as bidirectional streams; see proto/bridge.proto.
+    #[arg(long)]
+    grpc_port: Option<u16>,
+
+    /// Export sessions as traces, and the metrics, to this OTLP/HTTP
+    /// collector, e.g. http://collector:4318.
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+
+    /// Seconds between metrics exports. Defaults to 60.
+    #[arg(long)]
+    otlp_interval: Option<u64>,
+
+    #[command(flatten)]
+    settings: Settings,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the serial ports on this machine, with USB details where available.
+    ListPorts {
+        /// Print the ports as JSON.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Connect to a bridge and use its port locally, on stdin/stdout or as a
+    /// pseudo-terminal for programs like minicom or esptool.
+    Client(local::ClientArgs),
+    /// Send a file to the device behind a bridge by XMODEM or YMODEM, as
+    /// bootloaders take firmware.
+    SendFile(xmodem::SendArgs),
+    /// Receive a file from the device behind a bridge by XMODEM or YMODEM.
+    RecvFile(xmodem::RecvArgs),
+    /// Manage the Windows service that runs the bridges at boot.
+    #[cfg(windows)]
+    Service {
+        #[command(subcommand)]
+        action: service::Action,
+    },
+}
+
+fn parse_bridge(s: &str) -> Result<(String, u16), String> {
+    let (serial_port, tcp_port) = s
+        .rsplit_once(':')
+        .ok_or_else(|| format!("expected SERIAL_PORT:TCP_PORT, got '{}'", s))?;
+    let tcp_port = tcp_port
+        .parse()
+        .map_err(|_| format!("invalid TCP port '{}'", tcp_port))?;
+    Ok((serial_port.to_string(), tcp_port))
+}
+
+const DEFAULT_LOG_KEEP: usize = 5;
+const DEFAULT_OTLP_INTERVAL: u64 = 60;
+
+// Where logs go, from the command line or failing that the config file.
+struct LogOutput {
+    target: LogTarget,
+    format: LogFormat,
+    file: Option<PathBuf>,
+    rotation: Rotation,
+    keep: usize,
+}
+
+impl LogOutput {
+    fn new(args: &Args, config: &ConfigFile) -> Result<LogOutput> {
+        let target = args.log_target.or(config.log_target).unwrap_or_default();
+        let format = args.log_format.or(config.log_format).unwrap_or_default();
+        let file = args.log_file.clone().or(config.log_file.clone());
+        let max_size = args.log_max_size.or(config.log_max_size);
+        let max_age = args.log_max_age.or(config.log_max_age);
+        let keep = args.log_keep.or(config.log_keep);
+        if file.is_none() && (max_size.is_some() || max_age.is_some() || keep.is_some()) {
+            bail!("log_max_size, log_max_age and log_keep require log_file");
+        }
+        if file.is_some() && target != LogTarget::Stderr {
+            bail!("log_file cannot be combined with log_target = {:?}", target);
+        }
+        // The journal has fields of its own.
+        if format == LogFormat::Json && target == LogTarget::Journald {
+            bail!("log_format = json cannot be combined with log_target = journald");
+        }
+        if max_size == Some(0) || max_age == Some(0) {
+            bail!("log_max_size and log_max_age must be at least 1");
+        }
+        Ok(LogOutput {
+            target,
+            format,
+            file,
+            rotation: Rotation {
+                max_size,
+                max_age: max_age.map(Duration::from_secs),
+            },
+            keep: keep.unwrap_or(DEFAULT_LOG_KEEP),
+        })
+    }
+}
+
+fn log_filter(level: Option<&str>) -> Result<EnvFilter> {
+    Ok(match level {
+        Some(level) => EnvFilter::try_new(level)?,
+        // rskafka logs each of its retries; the producer warns once per attempt.
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info,rskafka=error")),
+    })
+}
+
+// Returns what changes the log filter, for a config reload.
+fn init_logging(
+    level: Option<&str>,
+    output: LogOutput,
+    otlp: Option<OtlpLayer>,
+) -> Result<reload::Handle<EnvFilter, tracing_subscriber::Registry>> {
+    let (filter, handle) = reload::Layer::new(log_filter(level)?);
+    let (writer, ansi) = match (&output.file, output.target) {
+        (Some(path), _) => {
+            let file = LogFile::open(path, output.rotation, output.keep)
+                .with_context(|| format!("failed to open log file {}", path.display()))?;
+            (BoxMakeWriter::new(file), false)
+        }
+        (None, LogTarget::Stderr) => (BoxMakeWriter::new(std::io::stderr), std::io::stderr().is_terminal()),
+        #[cfg(unix)]
+        (None, LogTarget::Syslog) => (BoxMakeWriter::new(syslog::Syslog::open()), false),
+        #[cfg(target_os = "linux")]
+        (None, LogTarget::Journald) => {
+            let journald =
+                tracing_journald::layer().map_err(|e| anyhow::anyhow!("failed to connect to journald: {}", e))?;
+            tracing_subscriber::registry().with(filter).with(journald).with(otlp).init();
+            return Ok(handle);
+        }
+        #[allow(unreachable_patterns)]
+        (None, target) => bail!("--log-target {:?} is not supported on this platform", target),
+    };
+    let layer = match (output.format, output.target) {
+        (LogFormat::Json, _) => fmt::layer()
+            .event_format(JsonFormat)
+            .fmt_fields(JsonFields::new())
+            .with_writer(writer)
+            .boxed(),
+        // The logger adds the time, and the priority stands for the level.
+        (LogFormat::Text, LogTarget::Syslog) if output.file.is_none() => {
+            fmt::layer().with_writer(writer).with_ansi(false).without_time().with_level(false).boxed()
+        }
+        (LogFormat::Text, _) => fmt::layer().with_writer(writer).with_ansi(ansi).boxed(),
+    };
+    tracing_subscriber::registry().with(filter).with(layer).with(otlp).init();
+    Ok(handle)
+}
+
+// Parses the command line and does what it asks; the binary is just this.
+pub fn main() -> Result<()> {
+    let mut args = Args::parse();
+    match args.command.take() {
+        Some(Command::ListPorts { json }) => ports::list(json),
+        Some(Command::Client(client)) => run_client(&args, local::run(client)),
+        Some(Command::SendFile(send)) => run_client(&args, xmodem::send(send)),
+        Some(Command::RecvFile(recv)) => run_client(&args, xmodem::recv(recv)),
+        #[cfg(windows)]
+        Some(Command::Service { action }) => service::handle(action),
+        None => {
+            #[cfg(unix)]
+            if args.daemon {
+                daemon::daemonize()?;
+            }
+            #[cfg(unix)]
+            let _pidfile = args.pidfile.as_deref().map(daemon::Pidfile::create).transpose()?;
+            tokio::runtime::Runtime::new()?.block_on(serve(args, shutdown_signal()))
+        }
+    }
+}
+
+// Runs one of the subcommands that connect to a bridge, until it is done or
+// interrupted.
+fn run_client(args: &Args, client: impl Future<Output = Result<()>>) -> Result<()> {
+    init_logging(args.log_level.as_deref(), LogOutput::new(args, &ConfigFile::default())?, None)?;
+    tokio::runtime::Runtime::new()?.block_on(async {
+        tokio::select! {
+            result = client => result,
+            _ = shutdown_signal() => Ok(()),
+        }
+    })
+}
+
+// Resolves on SIGINT or SIGTERM (Ctrl-C on Windows).
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut terminate = signal(SignalKind::terminate()).expect("failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+// Runs the configured bridges until they have all stopped, or until `stop`
+// resolves and they have been shut down.
+pub async fn serve(args: Args, stop: impl Future<Output = ()>) -> Result<()> {
+    let config = match &args.config {
+        Some(path) => config::load(path)?,
+        None => ConfigFile::default(),
+    };
+    let log_output = LogOutput::new(&args, &config)?;
+    let otlp_interval = args.otlp_interval.or(config.otlp_interval);
+    let otlp = match args.otlp_endpoint.as_deref().or(config.otlp_endpoint.as_deref()) {
+        Some(endpoint) => {
+            let interval = Duration::from_secs(otlp_interval.unwrap_or(DEFAULT_OTLP_INTERVAL));
+            Some(otlp::new(endpoint, interval)?)
+        }
+        None if otlp_interval.is_some() => bail!("otlp_interval requires otlp_endpoint"),
+        None => None,
+    };
+    let (otlp_layer, otlp_exporter) = otlp.unzip();
+    let log_filter_handle =
+        init_logging(args.log_level.as_deref().or(config.log_level.as_deref()), log_output, otlp_layer)?;
+    // Read after logging is up, so its warnings about what it leaves out
+    // are seen.
+    let config = match &args.ser2net {
+        Some(path) => config::load_ser2net(path)?,
+        None => config,
+    };
+
+    #[cfg(unix)]
+    let admin_socket = args.admin_socket.clone().or(config.admin_socket.clone());
+    let admin_port = args.admin_port.or(config.admin_port);
+    let api_port = args.api_port.or(config.api_port);
+    let api_bind = match args.api_bind.is_empty() {
+        true => config.api_bind.clone(),
+        false => args.api_bind.clone(),
+    };
+    let api_token = args.api_token.clone().or(config.api_token.clone());
+    if api_port.is_none() && (!api_bind.is_empty() || api_token.is_some()) {
+        bail!("api_bind and api_token require api_port");
+    }
+    let api_bind = match api_bind.is_empty() {
+        true => vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+        false => api_bind,
+    };
+    if api_port.is_some() && api_token.is_none() && api_bind.iter().any(|ip| !ip.is_loopback()) {
+        warn!("The management API has no api_token, so anyone who can reach it may use it");
+    }
+    let metrics_port = args.metrics_port.or(config.metrics_port);
+    let health_port = args.health_port.or(config.health_port);
+    let mux_port = args.mux_port.or(config.mux_port);
+    let mux_tls_cert = args.mux_tls_cert.clone().or(config.mux_tls_cert.clone());
+    let mux_tls_key = args.mux_tls_key.clone().or(config.mux_tls_key.clone());
+    let mux_tls = match (mux_tls_cert, mux_tls_key) {
+        (Some(cert), Some(key)) if mux_port.is_some() => Some(tls::acceptor(&cert, &key, None)?),
+        (None, None) => None,
+        (Some(_), Some(_)) => bail!("mux_tls_cert and mux_tls_key require mux_port"),
+        _ => bail!("mux_tls_cert and mux_tls_key must be given together"),
+    };
+    let grpc_port = args.grpc_port.or(config.grpc_port);
+    let bind = config::service_bind(&args.settings, &config);
+    let bridges = config::bridges(&args.settings, &args.bridge, config)?;
+
+    // Activated sockets go to the bridge their FileDescriptorName names, or
+    // all to the only bridge.
+    #[cfg(unix)]
+    let mut inherited = activation::listen_fds()?;
+    #[cfg(not(unix))]
+    let mut inherited = Vec::new();
+    if args.stdio {
+        if bridges.len() != 1 {
+            bail!("--stdio serves exactly one bridge, {} configured", bridges.len());
+        }
+        if bridges[0].dump.is_some() {
+            bail!("--dump writes to stdout and cannot be combined with --stdio");
+        }
+        inherited.push((bridges[0].name.clone(), Inherited::Stdio));
+    }
+    let single = bridges.len() == 1;
+
+    let registry = Arc::new(Registry::default());
+    #[cfg(unix)]
+    if let Some(path) = &admin_socket {
+        admin::spawn_unix(path, registry.clone())?;
+    }
+    if let Some(port) = admin_port {
+        admin::spawn_tcp(port, registry.clone()).await?;
+    }
+    if let Some(port) = api_port {
+        api::spawn(&api_bind, port, api_token, registry.clone())?;
+    }
+    if let Some(port) = metrics_port {
+        metrics::spawn(&bind, port, registry.clone())?;
+    }
+    if let Some(port) = health_port {
+        health::spawn(&bind, port, registry.clone())?;
+    }
+    if let Some(port) = mux_port {
+        mux::spawn(&bind, port, mux_tls, registry.clone())?;
+    }
+    if let Some(port) = grpc_port {
+        grpc::spawn(&bind, port, registry.clone())?;
+    }
+    if let Some(exporter) = otlp_exporter {
+        exporter.spawn(registry.clone());
+    }
+    #[cfg(target_os = "linux")]
+    systemd::spawn_watchdog(registry.clone());
+
+    registry.set_configured(bridges.iter().map(|bridge| bridge.name.clone()).collect());
+    let mut running = Bridges::new(registry.clone());
+    let mut starting = Vec::new();
+    for bridge in bridges {
+        let (mine, rest): (Vec<_>, Vec<_>) = inherited
+            .into_iter()
+            .partition(|(name, _)| single || *name == bridge.name);
+        inherited = rest;
+        let sources = mine.into_iter().map(|(_, source)| source).collect();
+        starting.push(running.start(bridge, sources));
+    }
+    for (name, _) in &inherited {
+        warn!("No bridge named '{}' for an activated socket", name);
+    }
+    // Ready once every bridge is either up or has failed to start.
+    #[cfg(unix)]
+    let (user, group) = (args.user.clone(), args.group.clone());
+    tokio::spawn(async move {
+        for started in starting {
+            let _ = started.await;
+        }
+        #[cfg(unix)]
+        if (user.is_some() || group.is_some())
+            && let Err(e) = daemon::drop_privileges(user.as_deref(), group.as_deref())
+        {
+            error!("Failed to drop privileges: {:#}", e);
+            std::process::exit(1);
+        }
+        #[cfg(target_os = "linux")]
+        systemd::notify("READY=1");
+    });
+
+    let mut stop = std::pin::pin!(stop);
+    // SIGHUP reloads the config, SIGUSR1 logs the state of every bridge.
+    #[cfg(unix)]
+    let (mut reloads, mut dumps) = {
+        use tokio::signal::unix::SignalKind;
+        (Signal::new(SignalKind::hangup())?, Signal::new(SignalKind::user_defined1())?)
+    };
+    #[cfg(not(unix))]
+    let (mut reloads, mut dumps) = (Signal::new()?, Signal::new()?);
+    let mut stopping = false;
+    let mut failed = 0;
+    loop {
+        tokio::select! {
+            joined = running.join() => {
+                let Some(ok) = joined else {
+                    break;
+                };
+                if !ok {
+                    failed += 1;
+                }
+            }
+            _ = reloads.recv(), if !stopping => {
+                info!("Reloading config");
+                let reloaded = reread(&args).and_then(|config| {
+                    let filter = log_filter(args.log_level.as_deref().or(config.log_level.as_deref()))?;
+                    Ok((filter, config::bridges(&args.settings, &args.bridge, config)?))
+                });
+                match reloaded {
+                    Ok((filter, bridges)) => {
+                        let _ = log_filter_handle.reload(filter);
+                        running.reload(bridges);
+                    }
+                    Err(e) => error!("Failed to reload config, keeping the running one: {:#}", e),
+                }
+            }
+            _ = dumps.recv() => admin::log_state(&registry),
+            _ = &mut stop, if !stopping => {
+                info!("Shutting down");
+                #[cfg(target_os = "linux")]
+                systemd::notify("STOPPING=1");
+                registry.shutdown();
+                stopping = true;
+            }
+        }
+    }
+    if failed > 0 {
+        bail!("{} bridge(s) failed", failed);
+    }
+    Ok(())
+}
+
+// The config file read again, for a reload. Settings given on the command
+// line still win over it, as at startup.
+fn reread(args: &Args) -> Result<ConfigFile> {
+    match (&args.config, &args.ser2net) {
+        (_, Some(path)) => config::load_ser2net(path),
+        (Some(path), None) => config::load(path),
+        (None, None) => bail!("there is no config file to reload"),
+    }
+}
+
+// A signal serve acts on; off Unix, one that never comes.
+struct Signal {
+    #[cfg(unix)]
+    signal: tokio::signal::unix::Signal,
+}
+
+impl Signal {
+    #[cfg(unix)]
+    fn new(kind: tokio::signal::unix::SignalKind) -> Result<Signal> {
+        Ok(Signal {
+            signal: tokio::signal::unix::signal(kind)?,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn new() -> Result<Signal> {
+        Ok(Signal {})
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        self.signal.recv().await;
+        #[cfg(not(unix))]
+        std::future::pending::<()>().await;
+    }
+}
+
+// The bridges the process runs, which a reload may add to, stop, restart or
+// change in place. A reload takes effect with the old privileges dropped,
+// if they were, so a bridge it starts may be unable to bind a low port.
+// Process-wide settings other than log_level take a restart to change.
+struct Bridges {
+    registry: Arc<Registry>,
+    tasks: JoinSet<Result<()>>,
+    names: HashMap<task::Id, String>,
+    // The config each bridge is running, or starting, with.
+    configs: HashMap<String, BridgeConfig>,
+    // Told to stop and not gone yet.
+    retiring: HashSet<String>,
+    // To start once no bridge is retiring, as one may hold its ports.
+    pending: Vec<BridgeConfig>,
+}
+
+impl Bridges {
+    fn new(registry: Arc<Registry>) -> Bridges {
+        Bridges {
+            registry,
+            tasks: JoinSet::new(),
+            names: HashMap::new(),
+            configs: HashMap::new(),
+            retiring: HashSet::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    // The receiver resolves once the bridge is up or has failed to start.
+    fn start(&mut self, bridge: BridgeConfig, sources: Vec<Inherited>) -> oneshot::Receiver<()> {
+        let name = bridge.name.clone();
+        self.configs.insert(name.clone(), bridge.clone());
+        let registry = self.registry.clone();
+        let (ready, started) = oneshot::channel();
+        let span = info_span!("bridge", name = %name);
+        let task = self.tasks.spawn(
+            async move {
+                let result = bridge::run(bridge, registry, sources, ready).await;
+                if let Err(e) = &result {
+                    error!("Bridge stopped: {:#}", e);
+                }
+                result
+            }
+            .instrument(span),
+        );
+        self.names.insert(task.id(), name);
+        started
+    }
+
+    // Waits for a bridge to stop, returning whether it stopped cleanly,
+    // or None once there are none.
+    async fn join(&mut self) -> Option<bool> {
+        let (id, ok) = match self.tasks.join_next_with_id().await? {
+            Ok((id, result)) => (id, result.is_ok()),
+            Err(e) => (e.id(), false),
+        };
+        if let Some(name) = self.names.remove(&id) {
+            self.configs.remove(&name);
+            self.retiring.remove(&name);
+            self.registry.clear_retired(&name);
+        }
+        self.start_pending();
+        Some(ok)
+    }
+
+    fn reload(&mut self, bridges: Vec<BridgeConfig>) {
+        self.pending.clear();
+        self.registry.set_configured(bridges.iter().map(|bridge| bridge.name.clone()).collect());
+        let names: HashSet<&str> = bridges.iter().map(|bridge| bridge.name.as_str()).collect();
+        let removed: Vec<String> = self.configs.keys().filter(|name| !names.contains(name.as_str())).cloned().collect();
+        for name in removed {
+            if !self.retiring.contains(&name) {
+                info!("Stopping bridge {}, which is no longer configured", name);
+                self.retire(name);
+            }
+        }
+        for bridge in bridges {
+            let old = self.configs.get(&bridge.name).filter(|_| !self.retiring.contains(&bridge.name));
+            let running = self.registry.get(&bridge.name);
+            match (old.map(|old| bridge::change(old, &bridge)), running) {
+                (Some(Change::None), _) => {}
+                (Some(Change::Live), Some(running)) => {
+                    info!("Updating the ACL and credentials of bridge {}", bridge.name);
+                    running.update(&bridge);
+                    self.configs.insert(bridge.name.clone(), bridge);
+                }
+                // Still starting, with the old config.
+                (Some(_), _) => {
+                    info!("Restarting bridge {} with its new settings", bridge.name);
+                    self.retire(bridge.name.clone());
+                    self.pending.push(bridge);
+                }
+                (None, _) => {
+                    info!("Starting bridge {}", bridge.name);
+                    self.pending.push(bridge);
+                }
+            }
+        }
+        self.start_pending();
+    }
+
+    fn retire(&mut self, name: String) {
+        self.registry.retire(&name);
+        self.retiring.insert(name);
+    }
+
+    fn start_pending(&mut self) {
+        if !self.retiring.is_empty() || self.registry.is_stopping() {
+            return;
+        }
+        for bridge in std::mem::take(&mut self.pending) {
+            self.start(bridge, Vec::new());
+        }
+    }
+}
@@ -0,0 +1,528 @@
+use std::fmt;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Notify;
+use tokio::sync::broadcast::{self, Receiver};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+use tokio_serial::ClearBuffer;
+use tracing::{info, warn};
+
+use crate::audit::AuditSession;
+use crate::escape::Escapes;
+use crate::filter::Pipeline;
+use crate::framing::Frames;
+use crate::line_input::LineInput;
+use crate::rfc2217;
+use crate::script::{Effects, Script};
+use crate::telnet::Event;
+use crate::serial::{Control, SerialHandle};
+use crate::{Backpressure, Mode, Sharing};
+
+// Arrivals and departures not yet taken by a slow listener are dropped.
+const EVENT_QUEUE: usize = 64;
+
+// Where a client connected from and, once authenticated, who it is.
+pub struct Peer {
+    // An IP address and port, or "unix:uid=N" for Unix socket clients.
+    pub addr: String,
+    pub identity: Option<String>,
+    // Its writes are discarded, and it never holds write access.
+    pub read_only: bool,
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.identity {
+            Some(identity) => write!(f, "{} ({})", self.addr, identity),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
+
+// When to drop a client that has gone quiet.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IdleTimeout {
+    pub after: Duration,
+    // Ignore serial output, so only the client typing keeps it connected.
+    pub input_only: bool,
+}
+
+// Connected clients in arrival order. One of them holds the write lock
+// unless the sharing policy lets everyone write: the first to arrive, or
+// whoever took it over since.
+pub struct Sessions {
+    sharing: Sharing,
+    inner: Mutex<SessionList>,
+    // While set, no traffic passes and no new clients are admitted.
+    paused: AtomicBool,
+    events: broadcast::Sender<SessionEvent>,
+}
+
+// A client admitted or gone, for a program embedding the bridge.
+#[derive(Clone)]
+pub enum SessionEvent {
+    Connected(Arc<SessionInfo>),
+    Disconnected(Arc<SessionInfo>),
+}
+
+struct SessionList {
+    next_id: u64,
+    active: Vec<Arc<SessionInfo>>,
+    writer: Option<u64>,
+}
+
+// What the management API can see of a connected client.
+pub struct SessionInfo {
+    pub id: u64,
+    pub peer: String,
+    pub connected_at: SystemTime,
+    // Bytes received from and sent to the client.
+    pub bytes_in: AtomicU64,
+    pub bytes_out: AtomicU64,
+    pub read_only: bool,
+    kick: Notify,
+}
+
+impl Sessions {
+    pub fn new(sharing: Sharing) -> Arc<Self> {
+        Arc::new(Sessions {
+            sharing,
+            inner: Mutex::new(SessionList {
+                next_id: 0,
+                active: Vec::new(),
+                writer: None,
+            }),
+            paused: AtomicBool::new(false),
+            events: broadcast::Sender::new(EVENT_QUEUE),
+        })
+    }
+
+    pub fn events(&self) -> Receiver<SessionEvent> {
+        self.events.subscribe()
+    }
+
+    // Returns None when the sharing policy does not admit another client.
+    // Read-only clients are always admitted, and do not take the port.
+    pub fn register(self: &Arc<Self>, peer: &Peer) -> Option<SessionGuard> {
+        if self.is_paused() {
+            return None;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if self.sharing == Sharing::Exclusive && !peer.read_only && inner.active.iter().any(|info| !info.read_only) {
+            return None;
+        }
+        let info = Arc::new(SessionInfo {
+            id: inner.next_id,
+            peer: peer.to_string(),
+            connected_at: SystemTime::now(),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            read_only: peer.read_only,
+            kick: Notify::new(),
+        });
+        inner.next_id += 1;
+        inner.active.push(info.clone());
+        if inner.writer.is_none() && !info.read_only {
+            inner.writer = Some(info.id);
+            self.log_lock(&format!("Write lock taken by {}", info.peer));
+        }
+        let _ = self.events.send(SessionEvent::Connected(info.clone()));
+        Some(SessionGuard {
+            sessions: self.clone(),
+            info,
+        })
+    }
+
+    // Sessions admitted since the bridge started, including current ones.
+    pub fn total(&self) -> u64 {
+        self.inner.lock().unwrap().next_id
+    }
+
+    pub fn list(&self) -> Vec<Arc<SessionInfo>> {
+        self.inner.lock().unwrap().active.clone()
+    }
+
+    // Asks the session to end; returns false if there is no such session.
+    pub fn kick(&self, id: u64) -> bool {
+        let inner = self.inner.lock().unwrap();
+        let Some(info) = inner.active.iter().find(|info| info.id == id) else {
+            return false;
+        };
+        info.kick.notify_one();
+        true
+    }
+
+    // Asks every session to end and waits up to `timeout` for them to go.
+    pub async fn close_all(&self, timeout: Duration) {
+        for info in self.list() {
+            info.kick.notify_one();
+        }
+        let _ = tokio::time::timeout(timeout, async {
+            while !self.list().is_empty() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await;
+    }
+
+    // Returns whether that changed anything.
+    pub fn pause(&self, paused: bool) -> bool {
+        self.paused.swap(paused, Ordering::Relaxed) != paused
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    // Whether `id` currently holds write access.
+    pub fn is_writer(&self, id: u64) -> bool {
+        if self.is_paused() {
+            return false;
+        }
+        let inner = self.inner.lock().unwrap();
+        match self.sharing {
+            Sharing::FreeForAll => inner.active.iter().any(|info| info.id == id && !info.read_only),
+            _ => inner.writer == Some(id),
+        }
+    }
+
+    // Gives the write lock to `id`, whoever holds it. None if there is no
+    // such session, Some(false) if it is read-only.
+    pub fn take_over(&self, id: u64) -> Option<bool> {
+        let mut inner = self.inner.lock().unwrap();
+        let info = inner.active.iter().find(|info| info.id == id)?.clone();
+        if info.read_only {
+            return Some(false);
+        }
+        if inner.writer == Some(id) {
+            return Some(true);
+        }
+        let previous = inner.writer.replace(id);
+        match previous.and_then(|previous| inner.active.iter().find(|info| info.id == previous)) {
+            Some(previous) => self.log_lock(&format!("Write lock taken over by {} from {}", info.peer, previous.peer)),
+            None => self.log_lock(&format!("Write lock taken by {}", info.peer)),
+        }
+        Some(true)
+    }
+
+    // Only meaningful, and only logged, when there is a lock.
+    fn log_lock(&self, message: &str) {
+        if self.sharing != Sharing::FreeForAll {
+            info!("{}", message);
+        }
+    }
+}
+
+pub struct SessionGuard {
+    sessions: Arc<Sessions>,
+    info: Arc<SessionInfo>,
+}
+
+impl SessionGuard {
+    pub fn id(&self) -> u64 {
+        self.info.id
+    }
+
+    pub fn info(&self) -> Arc<SessionInfo> {
+        self.info.clone()
+    }
+
+    pub fn can_write(&self) -> bool {
+        self.sessions.is_writer(self.info.id)
+    }
+
+    pub fn take_over(&self) -> Option<bool> {
+        self.sessions.take_over(self.info.id)
+    }
+}
+
+impl Drop for SessionGuard {
+    // A departing lock holder passes the lock on to the earliest client
+    // that may write.
+    fn drop(&mut self) {
+        let mut inner = self.sessions.inner.lock().unwrap();
+        inner.active.retain(|info| info.id != self.info.id);
+        let _ = self.sessions.events.send(SessionEvent::Disconnected(self.info.clone()));
+        if inner.writer != Some(self.info.id) {
+            return;
+        }
+        let next = inner.active.iter().find(|info| !info.read_only).cloned();
+        inner.writer = next.as_ref().map(|info| info.id);
+        match next {
+            Some(next) => self
+                .sessions
+                .log_lock(&format!("Write lock passed to {} as {} left", next.peer, self.info.peer)),
+            None => self.sessions.log_lock(&format!("Write lock released by {}", self.info.peer)),
+        }
+    }
+}
+
+// What a session does besides relaying bytes, all of it optional, and how
+// much it reads from the client at a time.
+pub struct SessionOptions {
+    pub escape: Option<Escapes>,
+    pub idle: Option<IdleTimeout>,
+    // Echo and line editing for what the client types.
+    pub line_input: Option<LineInput>,
+    // What is done to bytes from and to the client.
+    pub input_filters: Pipeline,
+    pub output_filters: Pipeline,
+    // Passes serial output on in whole frames.
+    pub frames: Option<Frames>,
+    // Whether purging the port's input also drops its output read but not
+    // yet sent, with firmware_flash.
+    pub purge_output: bool,
+    pub read_size: usize,
+    pub script: Option<Arc<Script>>,
+    // Sent before anything else, serial output included.
+    pub banner: Option<Vec<u8>>,
+    pub audit: Option<AuditSession>,
+}
+
+pub async fn serve<S>(
+    mut socket: S,
+    serial: SerialHandle,
+    session: SessionGuard,
+    mode: Mode,
+    options: SessionOptions,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let SessionOptions {
+        mut escape,
+        idle,
+        mut line_input,
+        mut input_filters,
+        mut output_filters,
+        mut frames,
+        purge_output,
+        read_size,
+        script,
+        banner,
+        audit,
+    } = options;
+    let mut output = serial.attach();
+    // Output retained for the session goes out first.
+    let mut pending = Some(std::mem::take(&mut output.backlog)).filter(|backlog| !backlog.is_empty());
+    let mut telnet = match mode {
+        Mode::Rfc2217 => Some(rfc2217::Session::new(true)),
+        Mode::Telnet => Some(rfc2217::Session::new(false)),
+        _ => None,
+    };
+    if let Some(t) = telnet.as_mut() {
+        socket.write_all(&t.greeting()).await?;
+    }
+    if let Some(banner) = banner {
+        match telnet {
+            Some(_) => socket.write_all(&rfc2217::Session::encode(&banner)).await?,
+            None => socket.write_all(&banner).await?,
+        }
+    }
+    let mut modem_poll = tokio::time::interval(Duration::from_secs(1));
+
+    let mut socket_buf = vec![0u8; read_size];
+    let info = session.info.clone();
+    if let Some(script) = &script {
+        let effects = script.on_connect(&info);
+        for data in effects.to_client {
+            match telnet {
+                Some(_) => socket.write_all(&rfc2217::Session::encode(&data)).await?,
+                None => socket.write_all(&data).await?,
+            }
+        }
+        for data in effects.to_serial {
+            serial.write(data).await?;
+        }
+    }
+    let mut idle_deadline = idle.map(|idle| Instant::now() + idle.after);
+
+    loop {
+        let suspended = telnet.as_ref().is_some_and(|t| t.suspended());
+        tokio::select! {
+            received = next_output(&mut pending, &mut output.output, &mut frames), if !suspended => {
+                match received {
+                    Ok(_) if session.sessions.is_paused() => {}
+                    Ok(data) => {
+                        let data = output_filters.apply(data);
+                        if data.is_empty() {
+                            continue;
+                        }
+                        match telnet {
+                            Some(_) => socket.write_all(&rfc2217::Session::encode(&data)).await?,
+                            None => socket.write_all(&data).await?,
+                        }
+                        // A no-op on sockets, but stdout holds data back until flushed.
+                        socket.flush().await?;
+                        info.bytes_out.fetch_add(data.len() as u64, Ordering::Relaxed);
+                        if let Some(idle) = idle.filter(|idle| !idle.input_only) {
+                            idle_deadline = Some(Instant::now() + idle.after);
+                        }
+                    }
+                    Err(RecvError::Lagged(n)) if serial.backpressure() == Backpressure::Disconnect => {
+                        warn!("Disconnecting client, it fell behind by {} serial reads", n);
+                        serial.disconnected(n);
+                        return Ok(());
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("Client fell behind, {} serial reads dropped", n);
+                        serial.dropped(n);
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                }
+            },
+            read_socket = socket.read(&mut socket_buf) => {
+                let n = match read_socket {
+                    Ok(n) => n,
+                    // TLS peers commonly hang up without sending close_notify.
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => 0,
+                    Err(e) => return Err(e.into()),
+                };
+                if n == 0 {
+                    return Ok(());
+                }
+                info.bytes_in.fetch_add(n as u64, Ordering::Relaxed);
+                if let Some(idle) = idle {
+                    idle_deadline = Some(Instant::now() + idle.after);
+                }
+                let mut reply = Vec::new();
+                let events = match (telnet.as_mut(), escape.as_mut()) {
+                    (Some(t), _) => t.decode(&socket_buf[..n], &mut reply),
+                    (None, Some(escape)) => escape.scan(&socket_buf[..n]),
+                    (None, None) => vec![Event::Data(socket_buf[..n].to_vec())],
+                };
+                for event in events {
+                    match event {
+                        Event::Data(data) if session.can_write() => {
+                            let data = match line_input.as_mut() {
+                                Some(input) => input.feed(&data, &mut reply),
+                                None => data,
+                            };
+                            let (data, effects) = match &script {
+                                Some(script) => script.on_client_data(&info, Bytes::from(data)),
+                                None => (Bytes::from(data), Effects::default()),
+                            };
+                            let data = input_filters.apply(data);
+                            if !data.is_empty() {
+                                if let Some(audit) = &audit {
+                                    audit.input(&data);
+                                }
+                                serial.write(data).await?;
+                            }
+                            for data in effects.to_serial {
+                                serial.write(data).await?;
+                            }
+                            for data in effects.to_client {
+                                match telnet {
+                                    Some(_) => reply.extend_from_slice(&rfc2217::Session::encode(&data)),
+                                    None => reply.extend_from_slice(&data),
+                                }
+                            }
+                        }
+                        Event::Data(_) => {}
+                        Event::Control(control) => {
+                            // Observers may query the port but not reconfigure it.
+                            let control = if session.can_write() { control } else { Control::Status };
+                            let purge = purge_output
+                                && matches!(control, Control::Purge(ClearBuffer::Input | ClearBuffer::All));
+                            let status = serial.control(control).await?;
+                            // Output read from the port but not yet sent
+                            // goes too, as if it were still in the port's
+                            // buffer; flashing tools purge stale output
+                            // before talking to a bootloader.
+                            if purge {
+                                pending = None;
+                                output.output = output.output.resubscribe();
+                                if let Some(frames) = frames.as_mut() {
+                                    frames.clear();
+                                }
+                            }
+                            if let Some(t) = telnet.as_mut() {
+                                reply.extend_from_slice(&t.ack(&status));
+                            }
+                        }
+                        Event::Break if session.can_write() => {
+                            info!("Sending break");
+                            if let Some(audit) = &audit {
+                                audit.sent_break();
+                            }
+                            serial.send_break().await?;
+                        }
+                        Event::Break => {}
+                        Event::Takeover => match session.take_over() {
+                            Some(true) => {}
+                            _ => info!("Refusing write lock takeover by a read-only client"),
+                        },
+                    }
+                }
+                if !reply.is_empty() {
+                    socket.write_all(&reply).await?;
+                }
+            },
+            _ = modem_poll.tick(), if telnet.is_some() => {
+                let status = serial.control(Control::Status).await?;
+                if let Some(update) = telnet.as_mut().and_then(|t| t.poll_modem(&status)) {
+                    socket.write_all(&update).await?;
+                }
+            },
+            _ = info.kick.notified() => {
+                info!("Disconnecting client on request");
+                // Sends TLS close_notify or the WebSocket close frame, then FIN.
+                socket.shutdown().await?;
+                return Ok(());
+            }
+            _ = until(idle_deadline) => {
+                info!("Disconnecting idle client");
+                socket.shutdown().await?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+// The next piece of output for the client: retained output, then whatever
+// the port sends, or whole frames of either. Cancelling this loses nothing.
+async fn next_output(
+    pending: &mut Option<Bytes>,
+    output: &mut Receiver<Bytes>,
+    frames: &mut Option<Frames>,
+) -> Result<Bytes, RecvError> {
+    let Some(frames) = frames else {
+        return match pending.take() {
+            Some(data) => Ok(data),
+            None => output.recv().await,
+        };
+    };
+    if let Some(data) = pending.take() {
+        let framed = frames.push(&data);
+        if !framed.is_empty() {
+            return Ok(Bytes::from(framed));
+        }
+    }
+    loop {
+        tokio::select! {
+            received = output.recv() => {
+                let framed = frames.push(&received?);
+                if !framed.is_empty() {
+                    return Ok(Bytes::from(framed));
+                }
+            }
+            _ = until(frames.deadline()) => return Ok(Bytes::from(frames.flush())),
+        }
+    }
+}
+
+// Completes at `deadline`, or never without one.
+async fn until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
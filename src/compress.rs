@@ -0,0 +1,169 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll, ready};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use zstd::stream::raw::{Decoder, Encoder, InBuffer, Operation, OutBuffer};
+
+// Each side opens with these four bytes: a tag and the algorithm, so that a
+// peer that is not compressing, or compressing differently, is told so
+// rather than fed garbage.
+const HEADER: [u8; 4] = *b"RSZ\x01";
+// Text compresses well at zstd's default level without costing a small
+// device much CPU.
+const LEVEL: i32 = 3;
+const CHUNK: usize = 16384;
+
+// A zstd stream in each direction. Both directions are one long zstd frame,
+// so each write is compressed against everything sent before, and flushed
+// at once so that interactive use is not held up.
+pub struct ZstdStream<S> {
+    inner: S,
+    encoder: Encoder<'static>,
+    decoder: Decoder<'static>,
+    // How much of the peer's header has been checked.
+    header_seen: usize,
+    // Received bytes not yet decompressed.
+    received: Vec<u8>,
+    // Decompressed bytes not yet read, from `offset`.
+    plain: Vec<u8>,
+    offset: usize,
+    // The decoder filled the last buffer and may have more to give.
+    more: bool,
+    // Compressed bytes not yet fully written, the header first.
+    sending: Vec<u8>,
+}
+
+impl<S> ZstdStream<S> {
+    pub fn new(inner: S) -> io::Result<Self> {
+        Ok(ZstdStream {
+            inner,
+            encoder: Encoder::new(LEVEL)?,
+            decoder: Decoder::new()?,
+            header_seen: 0,
+            received: Vec::new(),
+            plain: Vec::new(),
+            offset: 0,
+            more: false,
+            sending: HEADER.to_vec(),
+        })
+    }
+
+    // Checks as much of the header as has arrived and drops it from
+    // `received`.
+    fn check_header(&mut self) -> io::Result<()> {
+        let n = (HEADER.len() - self.header_seen).min(self.received.len());
+        if self.received[..n] != HEADER[self.header_seen..self.header_seen + n] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "peer is not using zstd compression",
+            ));
+        }
+        self.header_seen += n;
+        self.received.drain(..n);
+        Ok(())
+    }
+
+    // Decompresses what it can of `received` into `plain`.
+    fn decompress(&mut self) -> io::Result<()> {
+        self.plain.resize(CHUNK, 0);
+        let status = self.decoder.run_on_buffers(&self.received, &mut self.plain)?;
+        self.plain.truncate(status.bytes_written);
+        self.offset = 0;
+        self.more = status.bytes_written == CHUNK;
+        self.received.drain(..status.bytes_read);
+        Ok(())
+    }
+
+    fn compress(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut chunk = vec![0u8; CHUNK];
+        let mut input = InBuffer::around(data);
+        while input.pos() < data.len() {
+            let mut output = OutBuffer::around(&mut chunk[..]);
+            self.encoder.run(&mut input, &mut output)?;
+            let n = output.pos();
+            self.sending.extend_from_slice(&chunk[..n]);
+        }
+        loop {
+            let mut output = OutBuffer::around(&mut chunk[..]);
+            let left = self.encoder.flush(&mut output)?;
+            let n = output.pos();
+            self.sending.extend_from_slice(&chunk[..n]);
+            if left == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> ZstdStream<S> {
+    fn poll_send(&mut self, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        while !self.sending.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.sending))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.sending.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ZstdStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.offset < this.plain.len() {
+                let n = buf.remaining().min(this.plain.len() - this.offset);
+                buf.put_slice(&this.plain[this.offset..this.offset + n]);
+                this.offset += n;
+                return Poll::Ready(Ok(()));
+            }
+            if this.header_seen < HEADER.len() && !this.received.is_empty() {
+                this.check_header()?;
+            }
+            if this.header_seen == HEADER.len() && (!this.received.is_empty() || this.more) {
+                let before = this.received.len();
+                this.decompress()?;
+                if !this.plain.is_empty() || this.received.len() < before {
+                    continue;
+                }
+            }
+            let mut chunk = [0u8; 4096];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            if chunk.filled().is_empty() {
+                // The frame is never finished, so the stream simply stops.
+                return Poll::Ready(Ok(()));
+            }
+            this.received.extend_from_slice(chunk.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ZstdStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        let n = buf.len().min(CHUNK);
+        this.compress(&buf[..n])?;
+        // Start it on its way; whatever is left goes out on the next write
+        // or flush.
+        if let Poll::Ready(Err(e)) = this.poll_send(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,2092 @@
+use std::collections::BTreeMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use bytes::Bytes;
+use clap::ValueEnum;
+use regex::Regex;
+use rumqttc::QoS;
+use serde::Deserialize;
+use tokio_serial::DataBits;
+use tracing::warn;
+
+use crate::acl::{Acl, Cidr};
+use crate::auth::Credentials;
+use crate::bridge::BridgeConfig;
+use crate::client::IdleTimeout;
+use crate::framing::Framing;
+use crate::http::Url;
+use crate::kafka::KafkaConfig;
+use crate::mqtt::MqttConfig;
+use crate::nats::NatsConfig;
+use crate::parse::LineParser;
+use crate::redis::RedisConfig;
+use crate::embed::Callbacks;
+use crate::{mdns, noise};
+use crate::rotate::Rotation;
+use crate::rs485::{Pin, Rs485};
+use crate::peer::PeerConfig;
+use crate::serial::{Buffers, Heartbeat, Retain};
+use crate::ser2net;
+use crate::usb::UsbId;
+use crate::tee::Tee;
+use crate::triggers::Trigger;
+use crate::udp::Multicast;
+use crate::watchdog::{Recovery, Watchdog};
+use crate::{
+    Backpressure, Compression, Dump, FilterName, FlowControlArg, LineAction, LineEnding, LogFormat, LogTarget, Mode,
+    ParityArg, Sharing, StopBitsArg, TimestampFormat, Transport, WatchdogAction,
+};
+
+const DEFAULT_TCP_PORT: u16 = 11223;
+const DEFAULT_BAUD_RATE: u32 = 115200;
+const DEFAULT_AUTH_TIMEOUT: u64 = 10;
+const DEFAULT_BAN_TIME: u64 = 600;
+const DEFAULT_MODBUS_TIMEOUT: u64 = 1000;
+const DEFAULT_BUFFER: usize = 1024;
+// With passthrough, for reads to keep up with a 1K XMODEM block and more.
+const PASSTHROUGH_BUFFER: usize = 8192;
+const DEFAULT_OUTPUT_QUEUE: usize = 256;
+const DEFAULT_MAX_FRAME: usize = 4096;
+
+// Everything that describes one bridge. The same fields come from the command
+// line, a [[bridge]] entry and the [defaults] table, in that precedence.
+#[derive(clap::Args, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    #[arg(skip)]
+    pub name: Option<String>,
+
+    /// The serial device, e.g. /dev/ttyUSB0 or COM3.
+    #[arg(long, required_unless_present_any = ["bridge", "config", "ser2net", "usb_id"])]
+    pub serial_port: Option<String>,
+
+    /// Open the USB adapter with this VID:PID[:SERIAL] instead of a fixed path.
+    #[arg(long, conflicts_with = "serial_port")]
+    pub usb_id: Option<UsbId>,
+
+    /// Serve clients on this port. Defaults to 11223 unless another way
+    /// of reaching the bridge is given.
+    #[arg(long)]
+    pub tcp_port: Option<u16>,
+
+    /// Listen on these addresses, e.g. "::" for IPv6 as well as IPv4 where
+    /// the system allows it; by default 0.0.0.0. IPv6 sockets are kept to
+    /// IPv6 when IPv4 addresses are listed too. Those given on the command
+    /// line, or else in [defaults], are also where the metrics, health, mux
+    /// and gRPC ports listen.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub bind: Vec<IpAddr>,
+
+    /// With "udp", tcp_port is a UDP port instead.
+    #[arg(long, value_enum)]
+    pub transport: Option<Transport>,
+
+    /// Send serial data to this address rather than to whoever sent the
+    /// last datagram.
+    #[arg(long)]
+    pub udp_peer: Option<SocketAddr>,
+
+    /// Also send serial output to this multicast group, e.g. 239.1.2.3:5000
+    /// or [ff15::1]:5000, a datagram per read, for dashboards and loggers to
+    /// listen on without a session each. Listeners cannot write to the port.
+    #[arg(long)]
+    pub multicast: Option<SocketAddr>,
+
+    /// Routers multicast datagrams may cross, 1 by default for the local
+    /// network only.
+    #[arg(long)]
+    pub multicast_ttl: Option<u32>,
+
+    /// For an IPv4 group, the address of the local interface to multicast
+    /// from.
+    #[arg(long)]
+    pub multicast_interface: Option<Ipv4Addr>,
+
+    /// Advertise tcp_port on the LAN by mDNS, with the device, baud rate and
+    /// framing in TXT records, so that client tools can find the bridge.
+    #[arg(long)]
+    #[serde(default)]
+    pub mdns: bool,
+
+    /// The service type to advertise; by default "_rfb-serial._tcp".
+    #[arg(long)]
+    pub mdns_service: Option<String>,
+
+    /// Also accept clients on this Unix domain socket. Without an explicit
+    /// tcp_port, no TCP port is opened at all.
+    #[arg(long)]
+    pub unix_socket: Option<PathBuf>,
+
+    /// Connect out to this HOST:PORT and serve the port over that
+    /// connection, reconnecting whenever it drops, for devices behind NAT.
+    /// Without an explicit tcp_port, nothing is listened on.
+    #[arg(long)]
+    pub connect: Option<String>,
+
+    /// Publish serial output to, and write messages from, this MQTT broker
+    /// (HOST[:PORT]). Without an explicit tcp_port, nothing is listened on.
+    #[arg(long)]
+    pub mqtt_broker: Option<String>,
+
+    /// Topic serial output is published to; defaults to
+    /// "remote-serial-server/<name>/rx".
+    #[arg(long)]
+    pub mqtt_rx_topic: Option<String>,
+
+    /// Topic whose messages are written to the port; defaults to
+    /// "remote-serial-server/<name>/tx".
+    #[arg(long)]
+    pub mqtt_tx_topic: Option<String>,
+
+    /// 0, 1 or 2 for both topics (default 0).
+    #[arg(long)]
+    pub mqtt_qos: Option<u8>,
+
+    /// Defaults to "remote-serial-server-<name>".
+    #[arg(long)]
+    pub mqtt_client_id: Option<String>,
+
+    /// Log in to the broker as this user.
+    #[arg(long)]
+    pub mqtt_username: Option<String>,
+
+    /// The password for mqtt_username.
+    #[arg(long)]
+    pub mqtt_password: Option<String>,
+
+    /// Connect to the broker over TLS; the default port becomes 8883.
+    #[arg(long)]
+    #[serde(default)]
+    pub mqtt_tls: bool,
+
+    /// Verify the broker against this CA file instead of the system's roots.
+    #[arg(long)]
+    pub mqtt_ca: Option<PathBuf>,
+
+    /// Publish serial output to, and write messages from, this Redis server
+    /// (HOST[:PORT]). Output goes out a line per message. Without an
+    /// explicit tcp_port, nothing is listened on.
+    #[arg(long)]
+    pub redis_server: Option<String>,
+
+    /// Channel serial lines are published to; defaults to
+    /// "remote-serial-server:<name>:rx".
+    #[arg(long)]
+    pub redis_rx_channel: Option<String>,
+
+    /// Channel whose messages are written to the port; defaults to
+    /// "remote-serial-server:<name>:tx".
+    #[arg(long)]
+    pub redis_tx_channel: Option<String>,
+
+    /// For an ACL user; the password alone logs in as the default user.
+    #[arg(long)]
+    pub redis_username: Option<String>,
+
+    /// Log in to the server with this password.
+    #[arg(long)]
+    pub redis_password: Option<String>,
+
+    /// Connect to the server over TLS.
+    #[arg(long)]
+    #[serde(default)]
+    pub redis_tls: bool,
+
+    /// Verify the server against this CA file instead of the system's roots.
+    #[arg(long)]
+    pub redis_ca: Option<PathBuf>,
+
+    /// Publish serial output to, and write messages from, this NATS server
+    /// (HOST[:PORT]). Without an explicit tcp_port, nothing is listened on.
+    #[arg(long)]
+    pub nats_server: Option<String>,
+
+    /// Subject serial output is published to; defaults to
+    /// "remote-serial-server.<name>.rx".
+    #[arg(long)]
+    pub nats_rx_subject: Option<String>,
+
+    /// Subject whose messages are written to the port; defaults to
+    /// "remote-serial-server.<name>.tx".
+    #[arg(long)]
+    pub nats_tx_subject: Option<String>,
+
+    /// Log in to the server with this token rather than a user and password.
+    #[arg(long, conflicts_with = "nats_username")]
+    pub nats_token: Option<String>,
+
+    /// Log in to the server as this user, with nats_password.
+    #[arg(long)]
+    pub nats_username: Option<String>,
+
+    /// The password for nats_username.
+    #[arg(long)]
+    pub nats_password: Option<String>,
+
+    /// Connect to the server over TLS.
+    #[arg(long)]
+    #[serde(default)]
+    pub nats_tls: bool,
+
+    /// Verify the server against this CA file instead of the system's roots.
+    #[arg(long)]
+    pub nats_ca: Option<PathBuf>,
+
+    /// Persist serial output in this JetStream stream, which is created to
+    /// capture the rx subject if it does not exist. Each message is
+    /// acknowledged, and failures to store it logged.
+    #[arg(long)]
+    pub nats_jetstream: Option<String>,
+
+    /// Produce every read from and write to the port as a record to Kafka,
+    /// bootstrapping from these brokers (HOST:PORT, comma-separated). Records
+    /// are keyed by bridge name, carry the serial port and direction ("rx"
+    /// or "tx") as headers and the time as their timestamp, and hold the
+    /// bytes as their value.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub kafka_brokers: Vec<String>,
+
+    /// Defaults to "remote-serial-server".
+    #[arg(long)]
+    pub kafka_topic: Option<String>,
+
+    /// Defaults to 0.
+    #[arg(long)]
+    pub kafka_partition: Option<i32>,
+
+    /// Defaults to "remote-serial-server-<name>".
+    #[arg(long)]
+    pub kafka_client_id: Option<String>,
+
+    /// Log in with SASL PLAIN.
+    #[arg(long)]
+    pub kafka_username: Option<String>,
+
+    /// The password for kafka_username.
+    #[arg(long)]
+    pub kafka_password: Option<String>,
+
+    /// Connect to the brokers over TLS.
+    #[arg(long)]
+    #[serde(default)]
+    pub kafka_tls: bool,
+
+    /// Verify the brokers against this CA file instead of the system's roots.
+    #[arg(long)]
+    pub kafka_ca: Option<PathBuf>,
+
+    /// Parse serial output a line at a time into JSON objects, which MQTT
+    /// publishes instead of the raw output and post_url is sent. Lines that do
+    /// not parse are left out. Either a regex, whose named groups become
+    /// fields, or parse_csv.
+    #[arg(long, conflicts_with = "parse_csv")]
+    pub parse_regex: Option<String>,
+
+    /// Types for parse_regex's groups, which are otherwise strings, as
+    /// NAME:TYPE (comma-separated), TYPE being string, int, float or bool.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub parse_types: Vec<String>,
+
+    /// Parse lines as CSV instead, split into these columns as NAME[:TYPE]
+    /// (comma-separated). Lines with fewer columns do not parse; further
+    /// columns are ignored.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub parse_csv: Vec<String>,
+
+    /// Separates CSV columns; defaults to ",".
+    #[arg(long)]
+    pub parse_delimiter: Option<char>,
+
+    /// POST each parsed line, as a JSON object, to this http:// or https://
+    /// URL. Without an explicit tcp_port, nothing is listened on.
+    #[arg(long)]
+    pub post_url: Option<String>,
+
+    /// Permissions of the socket in octal, e.g. "660".
+    #[arg(long)]
+    pub unix_socket_mode: Option<String>,
+
+    /// Owner of the socket as user[:group].
+    #[arg(long)]
+    pub unix_socket_owner: Option<String>,
+
+    /// Defaults to 115200.
+    #[arg(long)]
+    pub baud_rate: Option<u32>,
+
+    /// 5, 6, 7 or 8; defaults to 8.
+    #[arg(long)]
+    pub data_bits: Option<u8>,
+
+    /// Defaults to "none".
+    #[arg(long, value_enum)]
+    pub parity: Option<ParityArg>,
+
+    /// Defaults to 1.
+    #[arg(long, value_enum)]
+    pub stop_bits: Option<StopBitsArg>,
+
+    /// Defaults to "none".
+    #[arg(long, value_enum)]
+    pub flow_control: Option<FlowControlArg>,
+
+    /// Drive an RS-485 transceiver's direction pin around every write.
+    #[arg(long)]
+    #[serde(default)]
+    pub rs485: bool,
+
+    /// Use this sysfs GPIO as the direction pin instead of RTS.
+    #[arg(long)]
+    pub rs485_gpio: Option<u32>,
+
+    /// Drive the direction pin low while transmitting.
+    #[arg(long)]
+    #[serde(default)]
+    pub rs485_invert: bool,
+
+    /// Milliseconds between enabling the driver and the first byte.
+    #[arg(long)]
+    pub rs485_delay_before: Option<u64>,
+
+    /// Milliseconds between the last byte leaving and releasing the driver.
+    #[arg(long)]
+    pub rs485_delay_after: Option<u64>,
+
+    /// Hold client writes to the line rate, so that a device without flow
+    /// control isn't sent data faster than its UART can take it.
+    #[arg(long, conflicts_with = "rs485")]
+    #[serde(default)]
+    pub pace_writes: bool,
+
+    /// Have USB serial adapters pass on received bytes at once rather than
+    /// batch them for up to 16 ms, for interactive protocols such as GDB's
+    /// (Linux).
+    #[arg(long)]
+    #[serde(default)]
+    pub low_latency: bool,
+
+    /// Bytes to send the device as soon as it is opened, and again on every
+    /// reopen, e.g. "ATE0\r" to set a modem up before clients attach. Takes
+    /// \r, \n, \t, \0, \\ and \xHH; "@PATH" sends a file as it is instead.
+    #[arg(long)]
+    pub init_send: Option<String>,
+
+    /// Send the device these bytes every heartbeat_interval milliseconds,
+    /// clients or none, for devices that want a keep-alive or to be polled.
+    /// Escaped, or read from a file, as init_send is.
+    #[arg(long)]
+    pub heartbeat_send: Option<String>,
+
+    /// Milliseconds between heartbeats.
+    #[arg(long)]
+    pub heartbeat_interval: Option<u64>,
+
+    /// Bridge serial_port to this second local port, each passed what the
+    /// other's device sends. The peer_ settings set it up, and default to
+    /// serial_port's.
+    #[arg(long)]
+    pub peer_port: Option<String>,
+
+    /// The peer port's baud rate.
+    #[arg(long)]
+    pub peer_baud_rate: Option<u32>,
+
+    /// The peer port's data bits.
+    #[arg(long)]
+    pub peer_data_bits: Option<u8>,
+
+    /// The peer port's parity.
+    #[arg(long, value_enum)]
+    pub peer_parity: Option<ParityArg>,
+
+    /// The peer port's stop bits.
+    #[arg(long, value_enum)]
+    pub peer_stop_bits: Option<StopBitsArg>,
+
+    /// The peer port's flow control.
+    #[arg(long, value_enum)]
+    pub peer_flow_control: Option<FlowControlArg>,
+
+    /// Sit between a device on serial_port and its controller on peer_port,
+    /// passing everything through untouched, and give clients a line per
+    /// chunk either way instead of the device's output: when it was read,
+    /// the seconds since the chunk before, "device" or "controller" for who
+    /// sent it, and its bytes escaped as in transcripts. Clients cannot
+    /// write; capture records the same traffic as pcapng.
+    #[arg(long)]
+    #[serde(default)]
+    pub sniff: bool,
+
+    /// How clients talk to the bridge. Defaults to "raw".
+    #[arg(long, value_enum)]
+    pub mode: Option<Mode>,
+
+    /// Keep the path clean for binary transfers such as XMODEM to a
+    /// bootloader: 8N1, nothing translated, framed or injected on the way,
+    /// writes sent at once, and larger serial and client buffers unless
+    /// given.
+    #[arg(long)]
+    #[serde(default)]
+    pub passthrough: bool,
+
+    /// Relay the DTR and RTS toggling that esptool and STM32 bootloaders are
+    /// reset into flashing with, over mode = "rfc2217" or control_port: the
+    /// path kept as clean as passthrough keeps it, bar parity, with nothing
+    /// else driving the lines and low latency both ways.
+    #[arg(long)]
+    #[serde(default)]
+    pub firmware_flash: bool,
+
+    /// With mode = "modbus-gateway": TCP unit ids to RTU addresses, as
+    /// "TCP=RTU,...". Unlisted unit ids are used as they are.
+    #[arg(long)]
+    pub modbus_unit_map: Option<String>,
+
+    /// Milliseconds to wait for an RTU device to answer (default 1000).
+    #[arg(long)]
+    pub modbus_timeout: Option<u64>,
+
+    /// With mode = "nmea": only pass these sentence types ("RMC") or talker
+    /// and type ("GPRMC") pairs (repeatable or comma-separated).
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub nmea_filter: Vec<String>,
+
+    /// Defaults to "broadcast" with mode = "nmea", else "exclusive".
+    #[arg(long, value_enum)]
+    pub sharing: Option<Sharing>,
+
+    /// Serve clients over TLS with this certificate chain and tls_key.
+    #[arg(long)]
+    pub tls_cert: Option<PathBuf>,
+
+    /// The private key for tls_cert.
+    #[arg(long)]
+    pub tls_key: Option<PathBuf>,
+
+    /// Require clients to present a certificate signed by this CA.
+    #[arg(long)]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Encrypt client connections with Noise (NNpsk0) under this pre-shared
+    /// key, 64 hex digits, for clients too small for TLS.
+    #[arg(long)]
+    pub noise_key: Option<String>,
+
+    /// Speak WebSocket (binary messages) on the listener instead of raw TCP.
+    #[arg(long)]
+    #[serde(default)]
+    pub ws: bool,
+
+    /// Compress data connections, for verbose devices behind metered links.
+    /// Clients must compress too, e.g. the client subcommand with
+    /// --compress.
+    #[arg(long, value_enum)]
+    pub compress: Option<Compression>,
+
+    /// Also serve a browser terminal (HTTP + WebSocket) on this port.
+    #[arg(long)]
+    pub web_port: Option<u16>,
+
+    /// Clients on this port only watch: they get serial output, and what
+    /// they send is discarded.
+    #[arg(long)]
+    pub read_only_port: Option<u16>,
+
+    /// Clients authenticated as one of these ("CN=trainee", "ssh:guest")
+    /// only watch, whichever port they use (repeatable or comma-separated).
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub read_only_identities: Vec<String>,
+
+    /// Accept line commands that reconfigure the serial port on this port.
+    #[arg(long)]
+    pub control_port: Option<u16>,
+
+    /// Speak the gpsd protocol on this port, so gpsd clients get the
+    /// receiver's position as TPV/SKY reports. Without an explicit tcp_port,
+    /// this replaces the raw listener.
+    #[arg(long)]
+    pub gpsd_port: Option<u16>,
+
+    /// Accept SSH clients on this port; their session is a raw session on
+    /// the port. Needs ssh_host_key and ssh_authorized_keys.
+    #[arg(long)]
+    pub ssh_port: Option<u16>,
+
+    /// The server's private key, in OpenSSH format.
+    #[arg(long)]
+    pub ssh_host_key: Option<PathBuf>,
+
+    /// Public keys allowed to log in, one per line as in authorized_keys.
+    #[arg(long)]
+    pub ssh_authorized_keys: Option<PathBuf>,
+
+    /// Experimental: accept QUIC connections on this UDP port, each carrying
+    /// one session. Uses tls_cert and tls_key, and tls_client_ca if set.
+    #[arg(long)]
+    pub quic_port: Option<u16>,
+
+    /// Capture all serial traffic into this pcapng file (link type USER0).
+    #[arg(long)]
+    pub capture: Option<PathBuf>,
+
+    /// Mirror all serial traffic to stdout in this format.
+    #[arg(long, value_enum)]
+    pub dump: Option<Dump>,
+
+    /// Write a transcript of every client session into this directory.
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+
+    /// Carry a session's transcript on in a new file once it reaches this
+    /// many bytes.
+    #[arg(long)]
+    pub record_max_size: Option<u64>,
+
+    /// Carry a session's transcript on in a new file once it has been written
+    /// to for this many seconds.
+    #[arg(long)]
+    pub record_max_age: Option<u64>,
+
+    /// Delete the bridge's oldest transcripts beyond this many, counting
+    /// every file rather than every session.
+    #[arg(long)]
+    pub record_keep: Option<usize>,
+
+    /// Append what every client sends the port, and who it is, to this file.
+    #[arg(long)]
+    pub audit_log: Option<PathBuf>,
+
+    /// Log serial throughput and last activity every this many seconds.
+    #[arg(long)]
+    pub stats_interval: Option<u64>,
+
+    /// Try to recover the device when nothing has come from it for this
+    /// many seconds.
+    #[arg(long)]
+    pub serial_watchdog: Option<u64>,
+
+    /// What the watchdog does to recover the device. Defaults to "reopen".
+    #[arg(long, value_enum)]
+    pub watchdog_action: Option<WatchdogAction>,
+
+    /// Shell command for watchdog_action = "hook". REMOTE_SERIAL_BRIDGE and
+    /// REMOTE_SERIAL_PORT name the bridge and its device.
+    #[arg(long)]
+    pub watchdog_hook: Option<String>,
+
+    /// Patterns to watch serial output for, as [[bridge.trigger]] entries;
+    /// config file only.
+    #[arg(skip)]
+    #[serde(default)]
+    pub trigger: Vec<TriggerSettings>,
+
+    /// Files and commands to copy serial output to besides clients, as
+    /// [[bridge.tee]] entries; config file only.
+    #[arg(skip)]
+    #[serde(default)]
+    pub tee: Vec<TeeSettings>,
+
+    /// A Rhai script with hooks into the bridge's traffic and sessions.
+    #[arg(long)]
+    pub script: Option<PathBuf>,
+
+    /// Tell clients in-band when the serial device disappears and returns.
+    #[arg(long)]
+    #[serde(default)]
+    pub notify_reconnect: bool,
+
+    /// Greet clients with what they have attached to: the port and its
+    /// settings, who may write, and the escape sequences to type.
+    #[arg(long)]
+    #[serde(default)]
+    pub banner: bool,
+
+    /// Text for clients to see on connecting, after the banner if shown.
+    #[arg(long)]
+    pub motd: Option<String>,
+
+    /// Keep up to this many bytes of serial output while no client is
+    /// connected, and send them to the next one that connects.
+    #[arg(long)]
+    pub offline_buffer: Option<usize>,
+
+    /// Replay up to this many bytes of the most recent serial output to each
+    /// client that connects, so it sees what the device printed before.
+    #[arg(long, conflicts_with = "offline_buffer")]
+    pub replay_buffer: Option<usize>,
+
+    /// Bytes read from the serial port at a time (default 1024).
+    #[arg(long)]
+    pub serial_buffer: Option<usize>,
+
+    /// Bytes read from a client at a time (default 1024).
+    #[arg(long)]
+    pub client_buffer: Option<usize>,
+
+    /// Serial reads queued for each client before `backpressure` applies
+    /// (default 256).
+    #[arg(long)]
+    pub output_queue: Option<usize>,
+
+    /// What happens when a client's output queue is full.
+    #[arg(long, value_enum)]
+    pub backpressure: Option<Backpressure>,
+
+    /// Send what clients type to the port a line at a time, with backspace
+    /// editing, for devices that don't handle it themselves.
+    #[arg(long)]
+    #[serde(default)]
+    pub line_buffered: bool,
+
+    /// Echo what clients type back to them, for devices that don't echo.
+    #[arg(long)]
+    #[serde(default)]
+    pub local_echo: bool,
+
+    /// Rewrite line endings in what clients send as this, for devices that
+    /// want CR-only input from clients sending LF.
+    #[arg(long, value_enum)]
+    pub line_ending: Option<LineEnding>,
+
+    /// The same for serial output on its way to clients.
+    #[arg(long, value_enum)]
+    pub output_line_ending: Option<LineEnding>,
+
+    /// Prefix each line of serial output with the time it arrived.
+    #[arg(long, value_enum)]
+    pub timestamps: Option<TimestampFormat>,
+
+    /// Timestamp each read from the port instead of each line.
+    #[arg(long)]
+    #[serde(default)]
+    pub timestamp_reads: bool,
+
+    /// What is done to serial output on its way to clients, in order, e.g.
+    /// ["strip-ansi", "timestamp", "record"]. Given this, timestamps,
+    /// output_line_ending and record only configure their filters; without
+    /// it, they apply themselves in that order.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    #[serde(default)]
+    pub output_filters: Vec<FilterName>,
+
+    /// The same for what clients send, after line_buffered; by default
+    /// line_ending then record.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    #[serde(default)]
+    pub input_filters: Vec<FilterName>,
+
+    /// A WebAssembly module for the plugin filter. By default it comes first
+    /// on output and after line_ending on input.
+    #[arg(long)]
+    pub plugin: Option<PathBuf>,
+
+    /// Frames of serial output end with these bytes, given in hex, e.g.
+    /// "0d0a"; each frame reaches clients in one write.
+    #[arg(long)]
+    pub frame_delimiter: Option<String>,
+
+    /// Frames also end after this many milliseconds without serial output.
+    #[arg(long)]
+    pub frame_gap: Option<u64>,
+
+    /// Frames longer than this many bytes are cut (default 4096).
+    #[arg(long)]
+    pub max_frame: Option<usize>,
+
+    /// Send each frame behind its length, two bytes big-endian.
+    #[arg(long)]
+    #[serde(default)]
+    pub frame_length_prefix: bool,
+
+    /// Raw clients send a BREAK by typing this sequence, e.g. "~B".
+    #[arg(long)]
+    pub break_sequence: Option<String>,
+
+    /// Raw clients take the write lock from whoever holds it by typing this
+    /// sequence, e.g. "~T".
+    #[arg(long)]
+    pub takeover_sequence: Option<String>,
+
+    /// Drive DTR whenever a client that may write connects; "pulse" resets
+    /// an Arduino the way its IDE does.
+    #[arg(long, value_enum)]
+    pub dtr_on_connect: Option<LineAction>,
+
+    /// Require clients to send `AUTH <token>` before anything else.
+    #[arg(long)]
+    pub auth_token: Option<String>,
+
+    /// Have clients log in as a user of this host instead, with
+    /// `LOGIN <user> <password>`, checked by PAM under this service name
+    /// (Unix only). They are then known as "pam:<user>".
+    #[arg(long)]
+    pub pam_service: Option<String>,
+
+    /// Seconds a client has to authenticate before it is dropped.
+    #[arg(long)]
+    pub auth_timeout: Option<u64>,
+
+    /// Ban an address after this many failed attempts in a row to
+    /// authenticate, by token or SSH key; its connections are then refused
+    /// until the ban runs out or is lifted from the admin socket or API.
+    #[arg(long)]
+    pub ban_after: Option<u32>,
+
+    /// Seconds a ban lasts, and failed attempts are remembered for.
+    #[arg(long)]
+    pub ban_time: Option<u64>,
+
+    /// Send TCP keepalive probes after this many idle seconds, to notice
+    /// clients that vanished behind a NAT.
+    #[arg(long)]
+    pub tcp_keepalive: Option<u64>,
+
+    /// Seconds between unanswered keepalive probes.
+    #[arg(long)]
+    pub tcp_keepalive_interval: Option<u64>,
+
+    /// Disable Nagle's algorithm so keystrokes go out immediately.
+    #[arg(long)]
+    #[serde(default)]
+    pub no_delay: bool,
+
+    /// Disconnect clients after this many seconds without traffic; 0 turns
+    /// off a timeout inherited from the defaults.
+    #[arg(long)]
+    pub idle_timeout: Option<u64>,
+
+    /// Only data from the client counts as activity, so a chatty device does
+    /// not keep a forgotten session alive.
+    #[arg(long)]
+    #[serde(default)]
+    pub idle_input_only: bool,
+
+    /// Only accept clients from these CIDR ranges (repeatable).
+    #[arg(long)]
+    #[serde(default)]
+    pub allow: Vec<Cidr>,
+
+    /// Refuse clients from these CIDR ranges (repeatable); takes precedence over --allow.
+    #[arg(long)]
+    #[serde(default)]
+    pub deny: Vec<Cidr>,
+
+    /// Expect a PROXY protocol header, version 1 or 2, on every TCP
+    /// connection, as HAProxy and nginx send behind a load balancer. The
+    /// client address it gives is the one logged and checked against allow
+    /// and deny.
+    #[arg(long)]
+    #[serde(default)]
+    pub proxy_protocol: bool,
+
+    /// Refuse connections beyond this many at once, counting those still
+    /// in their handshake.
+    #[arg(long)]
+    pub max_connections: Option<usize>,
+
+    /// Refuse connections from an address beyond this many a minute.
+    #[arg(long)]
+    pub connect_rate: Option<u32>,
+}
+
+impl Settings {
+    // Fills every unset field from `fallback`.
+    pub fn or(self, fallback: Settings) -> Settings {
+        // A path and a USB id are alternatives, so they are inherited together.
+        let (serial_port, usb_id) = if self.serial_port.is_some() || self.usb_id.is_some() {
+            (self.serial_port, self.usb_id)
+        } else {
+            (fallback.serial_port, fallback.usb_id)
+        };
+        Settings {
+            name: self.name.or(fallback.name),
+            serial_port,
+            usb_id,
+            tcp_port: self.tcp_port.or(fallback.tcp_port),
+            bind: or_list(self.bind, fallback.bind),
+            baud_rate: self.baud_rate.or(fallback.baud_rate),
+            data_bits: self.data_bits.or(fallback.data_bits),
+            parity: self.parity.or(fallback.parity),
+            stop_bits: self.stop_bits.or(fallback.stop_bits),
+            flow_control: self.flow_control.or(fallback.flow_control),
+            rs485: self.rs485 || fallback.rs485,
+            rs485_gpio: self.rs485_gpio.or(fallback.rs485_gpio),
+            rs485_invert: self.rs485_invert || fallback.rs485_invert,
+            rs485_delay_before: self.rs485_delay_before.or(fallback.rs485_delay_before),
+            rs485_delay_after: self.rs485_delay_after.or(fallback.rs485_delay_after),
+            pace_writes: self.pace_writes || fallback.pace_writes,
+            low_latency: self.low_latency || fallback.low_latency,
+            init_send: self.init_send.or(fallback.init_send),
+            heartbeat_send: self.heartbeat_send.or(fallback.heartbeat_send),
+            heartbeat_interval: self.heartbeat_interval.or(fallback.heartbeat_interval),
+            peer_port: self.peer_port.or(fallback.peer_port),
+            sniff: self.sniff || fallback.sniff,
+            peer_baud_rate: self.peer_baud_rate.or(fallback.peer_baud_rate),
+            peer_data_bits: self.peer_data_bits.or(fallback.peer_data_bits),
+            peer_parity: self.peer_parity.or(fallback.peer_parity),
+            peer_stop_bits: self.peer_stop_bits.or(fallback.peer_stop_bits),
+            peer_flow_control: self.peer_flow_control.or(fallback.peer_flow_control),
+            mode: self.mode.or(fallback.mode),
+            passthrough: self.passthrough || fallback.passthrough,
+            firmware_flash: self.firmware_flash || fallback.firmware_flash,
+            modbus_unit_map: self.modbus_unit_map.or(fallback.modbus_unit_map),
+            modbus_timeout: self.modbus_timeout.or(fallback.modbus_timeout),
+            nmea_filter: or_list(self.nmea_filter, fallback.nmea_filter),
+            transport: self.transport.or(fallback.transport),
+            udp_peer: self.udp_peer.or(fallback.udp_peer),
+            multicast: self.multicast.or(fallback.multicast),
+            multicast_ttl: self.multicast_ttl.or(fallback.multicast_ttl),
+            multicast_interface: self.multicast_interface.or(fallback.multicast_interface),
+            mdns: self.mdns || fallback.mdns,
+            mdns_service: self.mdns_service.or(fallback.mdns_service),
+            unix_socket: self.unix_socket.or(fallback.unix_socket),
+            connect: self.connect.or(fallback.connect),
+            mqtt_broker: self.mqtt_broker.or(fallback.mqtt_broker),
+            mqtt_rx_topic: self.mqtt_rx_topic.or(fallback.mqtt_rx_topic),
+            mqtt_tx_topic: self.mqtt_tx_topic.or(fallback.mqtt_tx_topic),
+            mqtt_qos: self.mqtt_qos.or(fallback.mqtt_qos),
+            mqtt_client_id: self.mqtt_client_id.or(fallback.mqtt_client_id),
+            mqtt_username: self.mqtt_username.or(fallback.mqtt_username),
+            mqtt_password: self.mqtt_password.or(fallback.mqtt_password),
+            mqtt_tls: self.mqtt_tls || fallback.mqtt_tls,
+            mqtt_ca: self.mqtt_ca.or(fallback.mqtt_ca),
+            redis_server: self.redis_server.or(fallback.redis_server),
+            redis_rx_channel: self.redis_rx_channel.or(fallback.redis_rx_channel),
+            redis_tx_channel: self.redis_tx_channel.or(fallback.redis_tx_channel),
+            redis_username: self.redis_username.or(fallback.redis_username),
+            redis_password: self.redis_password.or(fallback.redis_password),
+            redis_tls: self.redis_tls || fallback.redis_tls,
+            redis_ca: self.redis_ca.or(fallback.redis_ca),
+            nats_server: self.nats_server.or(fallback.nats_server),
+            nats_rx_subject: self.nats_rx_subject.or(fallback.nats_rx_subject),
+            nats_tx_subject: self.nats_tx_subject.or(fallback.nats_tx_subject),
+            nats_token: self.nats_token.or(fallback.nats_token),
+            nats_username: self.nats_username.or(fallback.nats_username),
+            nats_password: self.nats_password.or(fallback.nats_password),
+            nats_tls: self.nats_tls || fallback.nats_tls,
+            nats_ca: self.nats_ca.or(fallback.nats_ca),
+            nats_jetstream: self.nats_jetstream.or(fallback.nats_jetstream),
+            kafka_brokers: or_list(self.kafka_brokers, fallback.kafka_brokers),
+            kafka_topic: self.kafka_topic.or(fallback.kafka_topic),
+            kafka_partition: self.kafka_partition.or(fallback.kafka_partition),
+            kafka_client_id: self.kafka_client_id.or(fallback.kafka_client_id),
+            kafka_username: self.kafka_username.or(fallback.kafka_username),
+            kafka_password: self.kafka_password.or(fallback.kafka_password),
+            kafka_tls: self.kafka_tls || fallback.kafka_tls,
+            kafka_ca: self.kafka_ca.or(fallback.kafka_ca),
+            parse_regex: self.parse_regex.or(fallback.parse_regex),
+            parse_types: or_list(self.parse_types, fallback.parse_types),
+            parse_csv: or_list(self.parse_csv, fallback.parse_csv),
+            parse_delimiter: self.parse_delimiter.or(fallback.parse_delimiter),
+            post_url: self.post_url.or(fallback.post_url),
+            unix_socket_mode: self.unix_socket_mode.or(fallback.unix_socket_mode),
+            unix_socket_owner: self.unix_socket_owner.or(fallback.unix_socket_owner),
+            sharing: self.sharing.or(fallback.sharing),
+            tls_cert: self.tls_cert.or(fallback.tls_cert),
+            tls_key: self.tls_key.or(fallback.tls_key),
+            tls_client_ca: self.tls_client_ca.or(fallback.tls_client_ca),
+            noise_key: self.noise_key.or(fallback.noise_key),
+            ws: self.ws || fallback.ws,
+            compress: self.compress.or(fallback.compress),
+            web_port: self.web_port.or(fallback.web_port),
+            read_only_port: self.read_only_port.or(fallback.read_only_port),
+            read_only_identities: or_list(self.read_only_identities, fallback.read_only_identities),
+            control_port: self.control_port.or(fallback.control_port),
+            gpsd_port: self.gpsd_port.or(fallback.gpsd_port),
+            ssh_port: self.ssh_port.or(fallback.ssh_port),
+            quic_port: self.quic_port.or(fallback.quic_port),
+            ssh_host_key: self.ssh_host_key.or(fallback.ssh_host_key),
+            ssh_authorized_keys: self.ssh_authorized_keys.or(fallback.ssh_authorized_keys),
+            capture: self.capture.or(fallback.capture),
+            dump: self.dump.or(fallback.dump),
+            record: self.record.or(fallback.record),
+            record_max_size: self.record_max_size.or(fallback.record_max_size),
+            record_max_age: self.record_max_age.or(fallback.record_max_age),
+            record_keep: self.record_keep.or(fallback.record_keep),
+            audit_log: self.audit_log.or(fallback.audit_log),
+            stats_interval: self.stats_interval.or(fallback.stats_interval),
+            serial_watchdog: self.serial_watchdog.or(fallback.serial_watchdog),
+            watchdog_action: self.watchdog_action.or(fallback.watchdog_action),
+            watchdog_hook: self.watchdog_hook.or(fallback.watchdog_hook),
+            trigger: or_list(self.trigger, fallback.trigger),
+            tee: or_list(self.tee, fallback.tee),
+            script: self.script.or(fallback.script),
+            notify_reconnect: self.notify_reconnect || fallback.notify_reconnect,
+            banner: self.banner || fallback.banner,
+            motd: self.motd.or(fallback.motd),
+            offline_buffer: self.offline_buffer.or(fallback.offline_buffer),
+            replay_buffer: self.replay_buffer.or(fallback.replay_buffer),
+            serial_buffer: self.serial_buffer.or(fallback.serial_buffer),
+            client_buffer: self.client_buffer.or(fallback.client_buffer),
+            output_queue: self.output_queue.or(fallback.output_queue),
+            backpressure: self.backpressure.or(fallback.backpressure),
+            line_buffered: self.line_buffered || fallback.line_buffered,
+            local_echo: self.local_echo || fallback.local_echo,
+            line_ending: self.line_ending.or(fallback.line_ending),
+            output_line_ending: self.output_line_ending.or(fallback.output_line_ending),
+            timestamps: self.timestamps.or(fallback.timestamps),
+            output_filters: or_list(self.output_filters, fallback.output_filters),
+            input_filters: or_list(self.input_filters, fallback.input_filters),
+            plugin: self.plugin.or(fallback.plugin),
+            timestamp_reads: self.timestamp_reads || fallback.timestamp_reads,
+            frame_delimiter: self.frame_delimiter.or(fallback.frame_delimiter),
+            frame_gap: self.frame_gap.or(fallback.frame_gap),
+            max_frame: self.max_frame.or(fallback.max_frame),
+            frame_length_prefix: self.frame_length_prefix || fallback.frame_length_prefix,
+            break_sequence: self.break_sequence.or(fallback.break_sequence),
+            takeover_sequence: self.takeover_sequence.or(fallback.takeover_sequence),
+            dtr_on_connect: self.dtr_on_connect.or(fallback.dtr_on_connect),
+            auth_token: self.auth_token.or(fallback.auth_token),
+            pam_service: self.pam_service.or(fallback.pam_service),
+            auth_timeout: self.auth_timeout.or(fallback.auth_timeout),
+            ban_after: self.ban_after.or(fallback.ban_after),
+            ban_time: self.ban_time.or(fallback.ban_time),
+            tcp_keepalive: self.tcp_keepalive.or(fallback.tcp_keepalive),
+            tcp_keepalive_interval: self.tcp_keepalive_interval.or(fallback.tcp_keepalive_interval),
+            no_delay: self.no_delay || fallback.no_delay,
+            idle_timeout: self.idle_timeout.or(fallback.idle_timeout),
+            idle_input_only: self.idle_input_only || fallback.idle_input_only,
+            allow: or_list(self.allow, fallback.allow),
+            deny: or_list(self.deny, fallback.deny),
+            proxy_protocol: self.proxy_protocol || fallback.proxy_protocol,
+            max_connections: self.max_connections.or(fallback.max_connections),
+            connect_rate: self.connect_rate.or(fallback.connect_rate),
+        }
+    }
+
+    // The settings minus the fields that identify a particular bridge, for
+    // applying command line flags on top of config file bridges.
+    fn overrides(&self) -> Settings {
+        Settings {
+            name: None,
+            serial_port: None,
+            usb_id: None,
+            peer_port: None,
+            tcp_port: None,
+            unix_socket: None,
+            connect: None,
+            mqtt_rx_topic: None,
+            mqtt_tx_topic: None,
+            mqtt_client_id: None,
+            redis_rx_channel: None,
+            redis_tx_channel: None,
+            nats_rx_subject: None,
+            nats_tx_subject: None,
+            kafka_client_id: None,
+            web_port: None,
+            read_only_port: None,
+            control_port: None,
+            gpsd_port: None,
+            ssh_port: None,
+            quic_port: None,
+            ..self.clone()
+        }
+    }
+
+    // The first setting given that changes, cuts up or adds to what passes
+    // between clients and the port, for settings that need it untouched.
+    fn unclean(&self) -> Option<&'static str> {
+        let settings = [
+            ("mode = \"modbus-gateway\"", self.mode == Some(Mode::ModbusGateway)),
+            ("mode = \"nmea\"", self.mode == Some(Mode::Nmea)),
+            ("line_buffered", self.line_buffered),
+            ("local_echo", self.local_echo),
+            ("line_ending", self.line_ending.is_some()),
+            ("output_line_ending", self.output_line_ending.is_some()),
+            ("timestamps", self.timestamps.is_some()),
+            ("output_filters", !self.output_filters.is_empty()),
+            ("input_filters", !self.input_filters.is_empty()),
+            ("plugin", self.plugin.is_some()),
+            ("script", self.script.is_some()),
+            ("frame_delimiter", self.frame_delimiter.is_some()),
+            ("frame_gap", self.frame_gap.is_some()),
+            ("break_sequence", self.break_sequence.is_some()),
+            ("takeover_sequence", self.takeover_sequence.is_some()),
+            ("banner", self.banner),
+            ("motd", self.motd.is_some()),
+            ("notify_reconnect", self.notify_reconnect),
+            ("heartbeat_send", self.heartbeat_send.is_some()),
+        ];
+        settings.iter().find(|(_, set)| *set).map(|(setting, _)| *setting)
+    }
+
+    pub fn into_bridge(self) -> Result<BridgeConfig> {
+        let unclean = self.unclean();
+        let serial_port = match (self.serial_port, &self.usb_id) {
+            (Some(_), Some(_)) => bail!("serial_port and usb_id are mutually exclusive"),
+            (Some(path), None) => path,
+            (None, Some(id)) => format!("usb:{}", id),
+            (None, None) => bail!("bridge has no serial_port or usb_id"),
+        };
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            bail!("tls_cert and tls_key must be given together");
+        }
+        if self.tls_client_ca.is_some() && self.tls_cert.is_none() {
+            bail!("tls_client_ca requires tls_cert and tls_key");
+        }
+        if self.noise_key.is_some() && self.tls_cert.is_some() {
+            bail!("noise_key and tls_cert are mutually exclusive");
+        }
+        // Browsers cannot speak Noise, and the web terminal should not be
+        // the one way in without it.
+        if self.noise_key.is_some() && self.web_port.is_some() {
+            bail!("noise_key is not supported with web_port");
+        }
+        let noise_key = self.noise_key.as_deref().map(noise::parse_key).transpose()?;
+        if self.compress.is_some() && self.ws {
+            bail!("compress is not supported with ws");
+        }
+        if !self.rs485
+            && (self.rs485_gpio.is_some()
+                || self.rs485_invert
+                || self.rs485_delay_before.is_some()
+                || self.rs485_delay_after.is_some())
+        {
+            bail!("rs485_* settings require rs485 = true");
+        }
+        // Every RS-485 write already waits for the line to drain.
+        if self.pace_writes && self.rs485 {
+            bail!("pace_writes is not supported with rs485");
+        }
+        if self.break_sequence.as_deref() == Some("") {
+            bail!("break_sequence must not be empty");
+        }
+        if self.takeover_sequence.as_deref() == Some("") {
+            bail!("takeover_sequence must not be empty");
+        }
+        if self.takeover_sequence.is_some() && self.takeover_sequence == self.break_sequence {
+            bail!("takeover_sequence and break_sequence must differ");
+        }
+        if self.tcp_keepalive_interval.is_some() && self.tcp_keepalive.is_none() {
+            bail!("tcp_keepalive_interval requires tcp_keepalive");
+        }
+        if self.tcp_keepalive == Some(0) || self.tcp_keepalive_interval == Some(0) {
+            bail!("tcp_keepalive and tcp_keepalive_interval must be at least 1 second");
+        }
+        if self.stats_interval == Some(0) {
+            bail!("stats_interval must be at least 1 second");
+        }
+        if self.serial_watchdog.is_none() && (self.watchdog_action.is_some() || self.watchdog_hook.is_some()) {
+            bail!("watchdog_* settings require serial_watchdog");
+        }
+        if self.serial_watchdog == Some(0) {
+            bail!("serial_watchdog must be at least 1 second");
+        }
+        let recovery = match (self.watchdog_action.unwrap_or_default(), &self.watchdog_hook) {
+            (WatchdogAction::Hook, None) => bail!("watchdog_action = \"hook\" requires watchdog_hook"),
+            (WatchdogAction::Hook, Some(command)) => Recovery::Hook(command.clone()),
+            (_, Some(_)) => bail!("watchdog_hook requires watchdog_action = \"hook\""),
+            (WatchdogAction::PulseDtr, None) => Recovery::PulseDtr,
+            (WatchdogAction::Reopen, None) => Recovery::Reopen,
+        };
+        let watchdog = self.serial_watchdog.map(|secs| Watchdog {
+            after: Duration::from_secs(secs),
+            recovery,
+        });
+        let triggers = self
+            .trigger
+            .into_iter()
+            .map(TriggerSettings::into_trigger)
+            .collect::<Result<Vec<_>>>()?;
+        let tees = self.tee.into_iter().map(TeeSettings::into_tee).collect::<Result<Vec<_>>>()?;
+        if self.idle_input_only && self.idle_timeout.is_none() {
+            bail!("idle_input_only requires idle_timeout");
+        }
+        let transport = self.transport.unwrap_or_default();
+        if transport == Transport::Udp {
+            let unsupported = [
+                ("tls_cert", self.tls_cert.is_some()),
+                ("noise_key", self.noise_key.is_some()),
+                ("ws", self.ws),
+                ("auth_token", self.auth_token.is_some()),
+                ("pam_service", self.pam_service.is_some()),
+                ("audit_log", self.audit_log.is_some()),
+                ("mdns", self.mdns),
+                ("more than one bind address", self.bind.len() > 1),
+                ("proxy_protocol", self.proxy_protocol),
+                ("max_connections", self.max_connections.is_some()),
+                ("connect_rate", self.connect_rate.is_some()),
+                ("read_only_port", self.read_only_port.is_some()),
+                ("mode = \"rfc2217\"", self.mode == Some(Mode::Rfc2217)),
+                ("mode = \"telnet\"", self.mode == Some(Mode::Telnet)),
+                ("mode = \"modbus-gateway\"", self.mode == Some(Mode::ModbusGateway)),
+                ("mode = \"nmea\"", self.mode == Some(Mode::Nmea)),
+                ("break_sequence", self.break_sequence.is_some()),
+                ("takeover_sequence", self.takeover_sequence.is_some()),
+                ("banner", self.banner),
+                ("motd", self.motd.is_some()),
+                ("offline_buffer", self.offline_buffer.is_some()),
+                ("replay_buffer", self.replay_buffer.is_some()),
+                ("client_buffer", self.client_buffer.is_some()),
+                ("line_buffered", self.line_buffered),
+                ("local_echo", self.local_echo),
+                ("line_ending", self.line_ending.is_some()),
+                ("output_line_ending", self.output_line_ending.is_some()),
+                ("frame_delimiter", self.frame_delimiter.is_some()),
+                ("frame_gap", self.frame_gap.is_some()),
+                ("timestamps", self.timestamps.is_some()),
+                ("output_filters", !self.output_filters.is_empty()),
+                ("plugin", self.plugin.is_some()),
+                ("input_filters", !self.input_filters.is_empty()),
+                ("compress", self.compress.is_some()),
+            ];
+            if let Some((setting, _)) = unsupported.iter().find(|(_, set)| *set) {
+                bail!("{} is not supported with transport = \"udp\"", setting);
+            }
+        } else if self.udp_peer.is_some() {
+            bail!("udp_peer requires transport = \"udp\"");
+        }
+        if let Some(target) = &self.connect {
+            if target.rsplit_once(':').is_none_or(|(_, port)| port.parse::<u16>().is_err()) {
+                bail!("connect must be HOST:PORT, got '{}'", target);
+            }
+            let unsupported = [
+                ("transport = \"udp\"", transport == Transport::Udp),
+                ("tls_cert", self.tls_cert.is_some()),
+                ("noise_key", self.noise_key.is_some()),
+                ("ws", self.ws),
+            ];
+            if let Some((setting, _)) = unsupported.iter().find(|(_, set)| *set) {
+                bail!("{} is not supported with connect", setting);
+            }
+        }
+        let mode = self.mode.unwrap_or_default();
+        if mode != Mode::ModbusGateway && (self.modbus_unit_map.is_some() || self.modbus_timeout.is_some()) {
+            bail!("modbus_* settings require mode = \"modbus-gateway\"");
+        }
+        if mode != Mode::Nmea && !self.nmea_filter.is_empty() {
+            bail!("nmea_filter requires mode = \"nmea\"");
+        }
+        if mode == Mode::ModbusGateway && self.break_sequence.is_some() {
+            bail!("break_sequence is not supported with mode = \"modbus-gateway\"");
+        }
+        if mode == Mode::ModbusGateway && self.takeover_sequence.is_some() {
+            bail!("takeover_sequence is not supported with mode = \"modbus-gateway\"");
+        }
+        // Anywhere else the client is a program, which would take them for
+        // serial data.
+        if !matches!(mode, Mode::Raw | Mode::Telnet) && (self.banner || self.motd.is_some()) {
+            bail!("banner and motd require mode = \"raw\" or \"telnet\"");
+        }
+        // Telnet clients echo and edit lines themselves, as they negotiate.
+        if mode != Mode::Raw && (self.line_buffered || self.local_echo) {
+            bail!("line_buffered and local_echo require mode = \"raw\"");
+        }
+        if mode == Mode::ModbusGateway && (self.line_ending.is_some() || self.output_line_ending.is_some()) {
+            bail!("line_ending and output_line_ending are not supported with mode = \"modbus-gateway\"");
+        }
+        let init_send = self.init_send.as_deref().map(|text| payload("init_send", text)).transpose()?;
+        let heartbeat = match (self.heartbeat_interval, &self.heartbeat_send) {
+            (None, None) => None,
+            (Some(0), _) => bail!("heartbeat_interval must be at least 1 millisecond"),
+            (Some(interval), Some(text)) => Some(Heartbeat {
+                interval: Duration::from_millis(interval),
+                data: payload("heartbeat_send", text)?,
+            }),
+            _ => bail!("heartbeat_interval and heartbeat_send must be given together"),
+        };
+        if mode == Mode::ModbusGateway && heartbeat.is_some() {
+            bail!("heartbeat_send is not supported with mode = \"modbus-gateway\"");
+        }
+        if self.passthrough {
+            if let Some(setting) = unclean {
+                bail!("passthrough cannot be combined with {}", setting);
+            }
+            let stop_bits = matches!(self.stop_bits, Some(StopBitsArg::Two));
+            if self.data_bits.unwrap_or(8) != 8 || self.parity.unwrap_or_default() != ParityArg::None || stop_bits {
+                bail!("passthrough requires 8 data bits, no parity and 1 stop bit");
+            }
+        }
+        if self.firmware_flash {
+            if mode != Mode::Rfc2217 && self.control_port.is_none() {
+                bail!("firmware_flash requires mode = \"rfc2217\" or control_port");
+            }
+            if let Some(setting) = unclean {
+                bail!("firmware_flash cannot be combined with {}", setting);
+            }
+            let lines = [
+                ("rs485", self.rs485),
+                ("dtr_on_connect", self.dtr_on_connect.is_some()),
+                ("flow_control = \"hardware\"", matches!(self.flow_control, Some(FlowControlArg::Hardware))),
+                ("watchdog_action = \"pulse-dtr\"", self.watchdog_action == Some(WatchdogAction::PulseDtr)),
+            ];
+            if let Some((setting, _)) = lines.iter().find(|(_, set)| *set) {
+                bail!("firmware_flash cannot be combined with {}, which drives DTR or RTS itself", setting);
+            }
+        }
+        let framing = match (&self.frame_delimiter, self.frame_gap) {
+            (None, None) if self.max_frame.is_some() || self.frame_length_prefix => {
+                bail!("max_frame and frame_length_prefix require frame_delimiter or frame_gap")
+            }
+            (None, None) => None,
+            (delimiter, gap) => Some(Framing {
+                delimiter: delimiter.as_deref().map(parse_hex).transpose()?,
+                gap: gap.map(Duration::from_millis),
+                max_frame: self.max_frame.unwrap_or(DEFAULT_MAX_FRAME),
+                length_prefix: self.frame_length_prefix,
+            }),
+        };
+        if let Some(framing) = &framing {
+            if framing.gap == Some(Duration::ZERO) || framing.max_frame == 0 {
+                bail!("frame_gap and max_frame must be at least 1");
+            }
+            if framing.length_prefix && framing.max_frame > u16::MAX as usize {
+                bail!("max_frame must be at most {} with frame_length_prefix", u16::MAX);
+            }
+            let unsupported = [
+                ("mode = \"modbus-gateway\"", mode == Mode::ModbusGateway),
+                ("mode = \"nmea\"", mode == Mode::Nmea),
+                ("output_line_ending", self.output_line_ending.is_some()),
+                ("timestamps", self.timestamps.is_some()),
+                ("output_filters", !self.output_filters.is_empty()),
+                ("plugin", self.plugin.is_some()),
+            ];
+            if let Some((setting, _)) = unsupported.iter().find(|(_, set)| *set) {
+                bail!("framing is not supported with {}", setting);
+            }
+        }
+        if self.timestamp_reads && self.timestamps.is_none() {
+            bail!("timestamp_reads requires timestamps");
+        }
+        if mode == Mode::ModbusGateway && self.timestamps.is_some() {
+            bail!("timestamps is not supported with mode = \"modbus-gateway\"");
+        }
+        if mode == Mode::ModbusGateway && !(self.output_filters.is_empty() && self.input_filters.is_empty()) {
+            bail!("output_filters and input_filters are not supported with mode = \"modbus-gateway\"");
+        }
+        if mode == Mode::ModbusGateway && self.plugin.is_some() {
+            bail!("plugin is not supported with mode = \"modbus-gateway\"");
+        }
+        if mode == Mode::ModbusGateway && self.audit_log.is_some() {
+            bail!("audit_log is not supported with mode = \"modbus-gateway\"");
+        }
+        let rotates = self.record_max_size.is_some() || self.record_max_age.is_some() || self.record_keep.is_some();
+        if rotates && self.record.is_none() {
+            bail!("record_max_size, record_max_age and record_keep require record");
+        }
+        if self.record_max_size == Some(0) || self.record_max_age == Some(0) || self.record_keep == Some(0) {
+            bail!("record_max_size, record_max_age and record_keep must be at least 1");
+        }
+        let output_filters = filters(
+            self.output_filters,
+            "output_filters",
+            &[
+                (FilterName::Plugin, "plugin", self.plugin.is_some()),
+                (FilterName::Timestamp, "timestamps", self.timestamps.is_some()),
+                (FilterName::LineEnding, "output_line_ending", self.output_line_ending.is_some()),
+                (FilterName::Record, "record", self.record.is_some()),
+            ],
+        )?;
+        let input_filters = filters(
+            self.input_filters,
+            "input_filters",
+            &[
+                (FilterName::LineEnding, "line_ending", self.line_ending.is_some()),
+                (FilterName::Plugin, "plugin", self.plugin.is_some()),
+                (FilterName::Record, "record", self.record.is_some()),
+            ],
+        )?;
+        // RTU frames must not be broken up by gaps.
+        if mode == Mode::ModbusGateway && self.pace_writes {
+            bail!("pace_writes is not supported with mode = \"modbus-gateway\"");
+        }
+        let retain = match (self.offline_buffer, self.replay_buffer) {
+            (Some(_), Some(_)) => bail!("offline_buffer and replay_buffer are mutually exclusive"),
+            (Some(0), _) | (_, Some(0)) => bail!("offline_buffer and replay_buffer must be at least 1 byte"),
+            (Some(size), None) => Some(Retain::Offline(size)),
+            (None, Some(size)) => Some(Retain::Recent(size)),
+            (None, None) => None,
+        };
+        // Stale replies would only confuse the next Modbus master.
+        if mode == Mode::ModbusGateway && retain.is_some() {
+            bail!("offline_buffer and replay_buffer are not supported with mode = \"modbus-gateway\"");
+        }
+        if self.serial_buffer == Some(0) || self.client_buffer == Some(0) || self.output_queue == Some(0) {
+            bail!("serial_buffer, client_buffer and output_queue must be at least 1");
+        }
+        // Everyone already holds write access.
+        if self.sharing == Some(Sharing::FreeForAll) && self.takeover_sequence.is_some() {
+            bail!("takeover_sequence does not apply with sharing = \"free-for-all\"");
+        }
+        let modbus_unit_map = match &self.modbus_unit_map {
+            Some(map) => parse_unit_map(map)?,
+            None => BTreeMap::new(),
+        };
+        if self.mqtt_broker.is_none()
+            && (self.mqtt_rx_topic.is_some()
+                || self.mqtt_tx_topic.is_some()
+                || self.mqtt_qos.is_some()
+                || self.mqtt_client_id.is_some()
+                || self.mqtt_username.is_some()
+                || self.mqtt_tls)
+        {
+            bail!("mqtt_* settings require mqtt_broker");
+        }
+        if self.mqtt_password.is_some() && self.mqtt_username.is_none() {
+            bail!("mqtt_password requires mqtt_username");
+        }
+        if self.mqtt_ca.is_some() && !self.mqtt_tls {
+            bail!("mqtt_ca requires mqtt_tls = true");
+        }
+        if self.redis_server.is_none()
+            && (self.redis_rx_channel.is_some()
+                || self.redis_tx_channel.is_some()
+                || self.redis_password.is_some()
+                || self.redis_tls)
+        {
+            bail!("redis_* settings require redis_server");
+        }
+        if self.redis_username.is_some() && self.redis_password.is_none() {
+            bail!("redis_username requires redis_password");
+        }
+        if self.redis_ca.is_some() && !self.redis_tls {
+            bail!("redis_ca requires redis_tls = true");
+        }
+        if self.nats_server.is_none()
+            && (self.nats_rx_subject.is_some()
+                || self.nats_tx_subject.is_some()
+                || self.nats_token.is_some()
+                || self.nats_username.is_some()
+                || self.nats_tls
+                || self.nats_jetstream.is_some())
+        {
+            bail!("nats_* settings require nats_server");
+        }
+        if self.nats_password.is_some() && self.nats_username.is_none() {
+            bail!("nats_password requires nats_username");
+        }
+        if self.nats_token.is_some() && self.nats_username.is_some() {
+            bail!("nats_token and nats_username are mutually exclusive");
+        }
+        if self.nats_ca.is_some() && !self.nats_tls {
+            bail!("nats_ca requires nats_tls = true");
+        }
+        if self.kafka_brokers.is_empty()
+            && (self.kafka_topic.is_some()
+                || self.kafka_partition.is_some()
+                || self.kafka_client_id.is_some()
+                || self.kafka_username.is_some()
+                || self.kafka_tls)
+        {
+            bail!("kafka_* settings require kafka_brokers");
+        }
+        if self.kafka_username.is_some() != self.kafka_password.is_some() {
+            bail!("kafka_username and kafka_password must be given together");
+        }
+        if self.kafka_ca.is_some() && !self.kafka_tls {
+            bail!("kafka_ca requires kafka_tls = true");
+        }
+        if self.kafka_partition.is_some_and(|partition| partition < 0) {
+            bail!("kafka_partition must not be negative");
+        }
+        if !self.parse_types.is_empty() && self.parse_regex.is_none() {
+            bail!("parse_types requires parse_regex");
+        }
+        if self.parse_delimiter.is_some() && self.parse_csv.is_empty() {
+            bail!("parse_delimiter requires parse_csv");
+        }
+        let parser = match (&self.parse_regex, self.parse_csv.is_empty()) {
+            (Some(_), false) => bail!("parse_regex and parse_csv cannot be combined"),
+            (Some(pattern), true) => {
+                let types = self.parse_types.iter().map(|field| field.parse()).collect::<Result<_>>()?;
+                Some(Arc::new(LineParser::regex(pattern, types)?))
+            }
+            (None, false) => {
+                let columns = self.parse_csv.iter().map(|field| field.parse()).collect::<Result<_>>()?;
+                Some(Arc::new(LineParser::csv(self.parse_delimiter.unwrap_or(','), columns)?))
+            }
+            (None, true) => None,
+        };
+        let post_url: Option<Url> = match (&self.post_url, &parser) {
+            (Some(_), None) => bail!("post_url requires parse_regex or parse_csv"),
+            (Some(url), Some(_)) => Some(url.parse().context("invalid post_url")?),
+            (None, _) => None,
+        };
+        // Stream names end up in API subjects.
+        if let Some(stream) = &self.nats_jetstream
+            && (stream.is_empty() || stream.contains(|c: char| c.is_whitespace() || ".*>/\\".contains(c)))
+        {
+            bail!("nats_jetstream must be a stream name, without whitespace or any of . * > / \\, got '{}'", stream);
+        }
+        // gpsd clients neither speak TLS nor know to send credentials.
+        if self.gpsd_port.is_some() {
+            let unsupported = [
+                ("tls_cert", self.tls_cert.is_some()),
+                ("noise_key", self.noise_key.is_some()),
+                ("auth_token", self.auth_token.is_some()),
+                ("pam_service", self.pam_service.is_some()),
+            ];
+            if let Some((setting, _)) = unsupported.iter().find(|(_, set)| *set) {
+                bail!("gpsd_port is not supported with {}", setting);
+            }
+        }
+        if self.ssh_port.is_none() && (self.ssh_host_key.is_some() || self.ssh_authorized_keys.is_some()) {
+            bail!("ssh_host_key and ssh_authorized_keys require ssh_port");
+        }
+        if self.ssh_port.is_some() && (self.ssh_host_key.is_none() || self.ssh_authorized_keys.is_none()) {
+            bail!("ssh_port requires ssh_host_key and ssh_authorized_keys");
+        }
+        if self.quic_port.is_some() && (self.tls_cert.is_none() || self.tls_key.is_none()) {
+            bail!("quic_port requires tls_cert and tls_key");
+        }
+        if self.unix_socket.is_none() && (self.unix_socket_mode.is_some() || self.unix_socket_owner.is_some()) {
+            bail!("unix_socket_* settings require unix_socket");
+        }
+        let unix_socket_mode = match &self.unix_socket_mode {
+            Some(mode) => Some(
+                u32::from_str_radix(mode, 8)
+                    .ok()
+                    .filter(|mode| *mode <= 0o7777)
+                    .with_context(|| format!("unix_socket_mode must be octal, e.g. \"660\", got '{}'", mode))?,
+            ),
+            None => None,
+        };
+        let multicast = match self.multicast {
+            None if self.multicast_ttl.is_some() || self.multicast_interface.is_some() => {
+                bail!("multicast_* settings require multicast")
+            }
+            None => None,
+            Some(group) if !group.ip().is_multicast() => {
+                bail!("multicast must be a multicast group address, got {}", group)
+            }
+            Some(group) if group.is_ipv6() && self.multicast_interface.is_some() => {
+                bail!("multicast_interface requires an IPv4 group")
+            }
+            Some(_) if self.multicast_ttl.is_some_and(|ttl| ttl > 255) => bail!("multicast_ttl must be at most 255"),
+            Some(group) => Some(Multicast {
+                group,
+                ttl: self.multicast_ttl.unwrap_or(1),
+                interface: self.multicast_interface,
+            }),
+        };
+        let tcp_port = match self.tcp_port {
+            Some(port) => Some(port),
+            None if (self.unix_socket.is_some()
+                || self.connect.is_some()
+                || self.mqtt_broker.is_some()
+                || self.redis_server.is_some()
+                || self.nats_server.is_some()
+                || !self.kafka_brokers.is_empty()
+                || self.post_url.is_some()
+                || self.multicast.is_some()
+                || self.gpsd_port.is_some())
+                && transport == Transport::Tcp =>
+            {
+                None
+            }
+            None => Some(DEFAULT_TCP_PORT),
+        };
+        let bind = match self.bind.is_empty() {
+            true => vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)],
+            false => self.bind,
+        };
+        if self.max_connections == Some(0) {
+            bail!("max_connections must be at least 1");
+        }
+        if self.connect_rate == Some(0) {
+            bail!("connect_rate must be at least 1");
+        }
+        if self.ban_after == Some(0) {
+            bail!("ban_after must be at least 1");
+        }
+        if self.ban_time.is_some() && self.ban_after.is_none() {
+            bail!("ban_time requires ban_after");
+        }
+        let authenticates = self.auth_token.is_some() || self.pam_service.is_some() || self.ssh_port.is_some();
+        if self.ban_after.is_some() && !authenticates {
+            bail!("ban_after requires auth_token, pam_service or ssh_port");
+        }
+        let auth = match (self.auth_token, self.pam_service) {
+            (Some(_), Some(_)) => bail!("auth_token and pam_service are mutually exclusive"),
+            (Some(token), None) => Some(Credentials::Token(token)),
+            (None, Some(service)) => Some(Credentials::Pam(service)),
+            (None, None) => None,
+        };
+        if self.mdns_service.is_some() && !self.mdns {
+            bail!("mdns_service requires mdns");
+        }
+        if self.mdns && tcp_port.is_none() {
+            bail!("mdns requires tcp_port");
+        }
+        let mdns = match self.mdns_service {
+            Some(service) if !(service.starts_with('_') && service.ends_with("._tcp")) => {
+                bail!("mdns_service must look like \"_name._tcp\", got '{}'", service)
+            }
+            Some(service) => Some(service),
+            None => self.mdns.then(|| mdns::DEFAULT_SERVICE.to_string()),
+        };
+        let name = self.name.unwrap_or_else(|| short_name(&serial_port));
+        let mqtt = match &self.mqtt_broker {
+            Some(broker) => {
+                let default_port = if self.mqtt_tls { 8883 } else { 1883 };
+                let (host, port) = host_port(broker, "mqtt_broker", default_port)?;
+                let qos = match self.mqtt_qos.unwrap_or(0) {
+                    0 => QoS::AtMostOnce,
+                    1 => QoS::AtLeastOnce,
+                    2 => QoS::ExactlyOnce,
+                    other => bail!("mqtt_qos must be 0, 1 or 2, got {}", other),
+                };
+                Some(MqttConfig {
+                    host,
+                    port,
+                    client_id: self
+                        .mqtt_client_id
+                        .unwrap_or_else(|| format!("remote-serial-server-{}", name)),
+                    rx_topic: self
+                        .mqtt_rx_topic
+                        .unwrap_or_else(|| format!("remote-serial-server/{}/rx", name)),
+                    tx_topic: self
+                        .mqtt_tx_topic
+                        .unwrap_or_else(|| format!("remote-serial-server/{}/tx", name)),
+                    qos,
+                    username: self.mqtt_username,
+                    password: self.mqtt_password,
+                    tls: self.mqtt_tls,
+                    ca: self.mqtt_ca,
+                })
+            }
+            None => None,
+        };
+        let redis = match &self.redis_server {
+            Some(server) => {
+                let (host, port) = host_port(server, "redis_server", 6379)?;
+                Some(RedisConfig {
+                    host,
+                    port,
+                    rx_channel: self
+                        .redis_rx_channel
+                        .unwrap_or_else(|| format!("remote-serial-server:{}:rx", name)),
+                    tx_channel: self
+                        .redis_tx_channel
+                        .unwrap_or_else(|| format!("remote-serial-server:{}:tx", name)),
+                    username: self.redis_username,
+                    password: self.redis_password,
+                    tls: self.redis_tls,
+                    ca: self.redis_ca,
+                })
+            }
+            None => None,
+        };
+        let kafka = match self.kafka_brokers.is_empty() {
+            true => None,
+            false => Some(KafkaConfig {
+                brokers: self.kafka_brokers,
+                topic: self.kafka_topic.unwrap_or_else(|| "remote-serial-server".to_string()),
+                partition: self.kafka_partition.unwrap_or(0),
+                client_id: self
+                    .kafka_client_id
+                    .unwrap_or_else(|| format!("remote-serial-server-{}", name)),
+                username: self.kafka_username,
+                password: self.kafka_password,
+                tls: self.kafka_tls,
+                ca: self.kafka_ca,
+            }),
+        };
+        let nats = match &self.nats_server {
+            Some(server) => {
+                let (host, port) = host_port(server, "nats_server", 4222)?;
+                Some(NatsConfig {
+                    host,
+                    port,
+                    name: format!("remote-serial-server-{}", name),
+                    rx_subject: self
+                        .nats_rx_subject
+                        .unwrap_or_else(|| format!("remote-serial-server.{}.rx", name)),
+                    tx_subject: self
+                        .nats_tx_subject
+                        .unwrap_or_else(|| format!("remote-serial-server.{}.tx", name)),
+                    token: self.nats_token,
+                    username: self.nats_username,
+                    password: self.nats_password,
+                    tls: self.nats_tls,
+                    ca: self.nats_ca,
+                    jetstream: self.nats_jetstream,
+                })
+            }
+            None => None,
+        };
+        let data_bits = parse_data_bits(&name, self.data_bits.unwrap_or(8));
+        let peered = self.peer_baud_rate.is_some()
+            || self.peer_data_bits.is_some()
+            || self.peer_parity.is_some()
+            || self.peer_stop_bits.is_some()
+            || self.peer_flow_control.is_some();
+        if self.sniff {
+            if self.peer_port.is_none() {
+                bail!("sniff requires peer_port");
+            }
+            if !matches!(mode, Mode::Raw | Mode::Telnet) {
+                bail!("sniff requires mode = \"raw\" or \"telnet\"");
+            }
+            if let Some(setting) = unclean {
+                bail!("sniff cannot be combined with {}", setting);
+            }
+            let writers = [
+                ("init_send", self.init_send.is_some()),
+                ("mqtt_broker", self.mqtt_broker.is_some()),
+                ("redis_server", self.redis_server.is_some()),
+                ("nats_server", self.nats_server.is_some()),
+                ("transport = \"udp\"", transport == Transport::Udp),
+            ];
+            if let Some((setting, _)) = writers.iter().find(|(_, set)| *set) {
+                bail!("sniff cannot be combined with {}, which writes to the device", setting);
+            }
+        }
+        let peer = match self.peer_port {
+            None if peered => bail!("the peer_ settings require peer_port"),
+            None => None,
+            Some(path) if path == serial_port => bail!("peer_port must differ from serial_port"),
+            Some(_) if mode == Mode::ModbusGateway => {
+                bail!("peer_port is not supported with mode = \"modbus-gateway\"")
+            }
+            Some(path) => Some(PeerConfig {
+                path,
+                baud_rate: self.peer_baud_rate.or(self.baud_rate).unwrap_or(DEFAULT_BAUD_RATE),
+                data_bits: parse_data_bits(&name, self.peer_data_bits.or(self.data_bits).unwrap_or(8)),
+                parity: self.peer_parity.or(self.parity).unwrap_or_default(),
+                stop_bits: self.peer_stop_bits.or(self.stop_bits).unwrap_or_default().into(),
+                flow_control: self.peer_flow_control.or(self.flow_control).unwrap_or_default().into(),
+                sniff: self.sniff,
+            }),
+        };
+        let buffer = if self.passthrough { PASSTHROUGH_BUFFER } else { DEFAULT_BUFFER };
+        let key_files = [
+            &self.tls_cert,
+            &self.tls_key,
+            &self.tls_client_ca,
+            &self.ssh_host_key,
+            &self.ssh_authorized_keys,
+        ];
+        let key_files = key_files
+            .into_iter()
+            .map(|path| path.as_deref().and_then(fingerprint))
+            .collect();
+        Ok(BridgeConfig {
+            name,
+            serial_port,
+            usb_id: self.usb_id,
+            tcp_port,
+            transport,
+            udp_peer: self.udp_peer,
+            multicast,
+            bind,
+            mdns,
+            unix_socket: self.unix_socket,
+            connect: self.connect,
+            mqtt,
+            redis,
+            nats,
+            kafka,
+            parser,
+            post_url,
+            unix_socket_mode,
+            unix_socket_owner: self.unix_socket_owner,
+            baud_rate: self.baud_rate.unwrap_or(DEFAULT_BAUD_RATE),
+            data_bits,
+            parity: self.parity.unwrap_or_default(),
+            stop_bits: self.stop_bits.unwrap_or_default().into(),
+            flow_control: self.flow_control.unwrap_or_default().into(),
+            rs485: self.rs485.then(|| Rs485 {
+                pin: self.rs485_gpio.map_or(Pin::Rts, Pin::Gpio),
+                invert: self.rs485_invert,
+                delay_before: Duration::from_millis(self.rs485_delay_before.unwrap_or(0)),
+                delay_after: Duration::from_millis(self.rs485_delay_after.unwrap_or(0)),
+            }),
+            pace_writes: self.pace_writes,
+            low_latency: self.low_latency || self.firmware_flash,
+            firmware_flash: self.firmware_flash,
+            init_send,
+            heartbeat,
+            peer,
+            mode,
+            modbus_unit_map,
+            modbus_timeout: Duration::from_millis(self.modbus_timeout.unwrap_or(DEFAULT_MODBUS_TIMEOUT)),
+            nmea_filter: self.nmea_filter,
+            sharing: match (self.sharing, mode) {
+                (Some(sharing), _) => sharing,
+                (None, Mode::Nmea) => Sharing::Broadcast,
+                (None, _) => Sharing::default(),
+            },
+            tls_cert: self.tls_cert,
+            tls_key: self.tls_key,
+            tls_client_ca: self.tls_client_ca,
+            noise_key,
+            ws: self.ws,
+            callbacks: Callbacks::default(),
+            compress: self.compress,
+            web_port: self.web_port,
+            read_only_port: self.read_only_port,
+            read_only_identities: self.read_only_identities,
+            control_port: self.control_port,
+            gpsd_port: self.gpsd_port,
+            ssh_port: self.ssh_port,
+            quic_port: self.quic_port,
+            ssh_host_key: self.ssh_host_key,
+            ssh_authorized_keys: self.ssh_authorized_keys,
+            key_files,
+            capture: self.capture,
+            dump: self.dump,
+            record: self.record,
+            record_rotation: Rotation {
+                max_size: self.record_max_size,
+                max_age: self.record_max_age.map(Duration::from_secs),
+            },
+            record_keep: self.record_keep,
+            audit_log: self.audit_log,
+            stats_interval: self.stats_interval.map(Duration::from_secs),
+            watchdog,
+            triggers: triggers.into(),
+            tees,
+            script: self.script,
+            notify_reconnect: self.notify_reconnect,
+            banner: self.banner,
+            motd: self.motd,
+            retain,
+            buffers: Buffers {
+                read_size: self.serial_buffer.unwrap_or(buffer),
+                queue: self.output_queue.unwrap_or(DEFAULT_OUTPUT_QUEUE),
+                backpressure: self.backpressure.unwrap_or_default(),
+            },
+            client_buffer: self.client_buffer.unwrap_or(buffer),
+            line_buffered: self.line_buffered,
+            local_echo: self.local_echo,
+            line_ending: self.line_ending,
+            output_line_ending: self.output_line_ending,
+            framing,
+            timestamps: self.timestamps,
+            output_filters,
+            input_filters,
+            plugin: self.plugin,
+            timestamp_reads: self.timestamp_reads,
+            break_sequence: self.break_sequence,
+            takeover_sequence: self.takeover_sequence,
+            dtr_on_connect: self.dtr_on_connect,
+            auth,
+            auth_timeout: Duration::from_secs(self.auth_timeout.unwrap_or(DEFAULT_AUTH_TIMEOUT)),
+            ban_after: self.ban_after,
+            ban_time: Duration::from_secs(self.ban_time.unwrap_or(DEFAULT_BAN_TIME)),
+            tcp_keepalive: self.tcp_keepalive.map(Duration::from_secs),
+            tcp_keepalive_interval: self.tcp_keepalive_interval.map(Duration::from_secs),
+            no_delay: self.no_delay || self.passthrough || self.firmware_flash,
+            idle_timeout: self.idle_timeout.filter(|&secs| secs > 0).map(|secs| IdleTimeout {
+                after: Duration::from_secs(secs),
+                input_only: self.idle_input_only,
+            }),
+            acl: Acl {
+                allow: self.allow,
+                deny: self.deny,
+            },
+            proxy_protocol: self.proxy_protocol,
+            max_connections: self.max_connections,
+            connect_rate: self.connect_rate,
+        })
+    }
+}
+
+// A pattern in serial output and the actions it fires.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerSettings {
+    pub pattern: String,
+    pub webhook: Option<String>,
+    pub command: Option<String>,
+    pub respond: Option<String>,
+}
+
+impl TriggerSettings {
+    fn into_trigger(self) -> Result<Trigger> {
+        let pattern = Regex::new(&self.pattern).with_context(|| format!("invalid trigger pattern '{}'", self.pattern))?;
+        if self.webhook.is_none() && self.command.is_none() && self.respond.is_none() {
+            bail!("trigger '{}' needs a webhook, command or respond", self.pattern);
+        }
+        Ok(Trigger {
+            pattern,
+            webhook: self.webhook.as_deref().map(str::parse).transpose()?,
+            command: self.command,
+            respond: self.respond.map(Bytes::from),
+        })
+    }
+}
+
+// Serial output copied to a file or a command, one or the other.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TeeSettings {
+    pub file: Option<PathBuf>,
+    pub command: Option<String>,
+}
+
+impl TeeSettings {
+    fn into_tee(self) -> Result<Tee> {
+        match (self.file, self.command) {
+            (Some(file), None) => Ok(Tee::File(file)),
+            (None, Some(command)) => Ok(Tee::Command(command)),
+            _ => bail!("each tee needs a file or a command"),
+        }
+    }
+}
+
+// The filters for one direction: those listed, or else the ones whose
+// settings are given, in their usual order. `settings` pairs each filter
+// with the setting that configures it and whether it is given.
+fn filters(listed: Vec<FilterName>, key: &str, settings: &[(FilterName, &str, bool)]) -> Result<Vec<FilterName>> {
+    if listed.is_empty() {
+        return Ok(settings.iter().filter(|(_, _, set)| *set).map(|(filter, _, _)| *filter).collect());
+    }
+    for (filter, setting, set) in settings {
+        let name = filter.to_possible_value().map_or(String::new(), |value| value.get_name().to_string());
+        match (listed.contains(filter), set) {
+            // Timestamps default to ISO 8601.
+            (true, false) if *filter != FilterName::Timestamp => {
+                bail!("the {} filter in {} requires {}", name, key, setting)
+            }
+            // Left out of the list, the setting would do nothing. The
+            // transcript may still be written, and the plugin run, in the
+            // other direction.
+            (false, true) if !matches!(filter, FilterName::Record | FilterName::Plugin) => {
+                bail!("{} has no effect unless {} includes {}", setting, key, name)
+            }
+            _ => {}
+        }
+    }
+    Ok(listed)
+}
+
+// A hash of a file's contents, or None if it cannot be read, which the
+// bridge reports when it comes to load it.
+fn fingerprint(path: &Path) -> Option<u64> {
+    let contents = std::fs::read(path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+// Where the ports that serve every bridge listen: see Settings::bind.
+pub fn service_bind(cli: &Settings, config: &ConfigFile) -> Vec<IpAddr> {
+    match or_list(cli.bind.clone(), config.defaults.bind.clone()) {
+        bind if bind.is_empty() => vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)],
+        bind => bind,
+    }
+}
+
+// A list given at a higher precedence level replaces the fallback entirely.
+fn or_list<T>(list: Vec<T>, fallback: Vec<T>) -> Vec<T> {
+    if list.is_empty() { fallback } else { list }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub log_level: Option<String>,
+    pub log_target: Option<LogTarget>,
+    pub log_format: Option<LogFormat>,
+    pub log_file: Option<PathBuf>,
+    pub log_max_size: Option<u64>,
+    pub log_max_age: Option<u64>,
+    pub log_keep: Option<usize>,
+    #[cfg(unix)]
+    pub admin_socket: Option<PathBuf>,
+    pub admin_port: Option<u16>,
+    pub api_port: Option<u16>,
+    #[serde(default)]
+    pub api_bind: Vec<IpAddr>,
+    pub api_token: Option<String>,
+    pub metrics_port: Option<u16>,
+    pub health_port: Option<u16>,
+    pub mux_port: Option<u16>,
+    pub mux_tls_cert: Option<PathBuf>,
+    pub mux_tls_key: Option<PathBuf>,
+    pub grpc_port: Option<u16>,
+    pub otlp_endpoint: Option<String>,
+    pub otlp_interval: Option<u64>,
+    #[serde(default)]
+    pub defaults: Settings,
+    #[serde(default)]
+    pub bridge: Vec<Settings>,
+}
+
+pub fn load(path: &Path) -> Result<ConfigFile> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    toml::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+// Reads a ser2net YAML file in place of a config file of our own.
+pub fn load_ser2net(path: &Path) -> Result<ConfigFile> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    ser2net::parse(&text).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+// Combines the command line with the config file into the list of bridges to run.
+pub fn bridges(
+    cli: &Settings,
+    cli_bridges: &[(String, u16)],
+    config: ConfigFile,
+) -> Result<Vec<BridgeConfig>> {
+    // Each bridge with where it came from, for telling clashing names apart.
+    let mut bridges = Vec::new();
+    if cli.serial_port.is_some() || cli.usb_id.is_some() {
+        let bridge = cli.clone().or(config.defaults.clone()).into_bridge()?;
+        bridges.push((bridge, "the command line's serial port".to_string()));
+    }
+    for (i, entry) in config.bridge.into_iter().enumerate() {
+        let settings = cli.overrides().or(entry).or(config.defaults.clone());
+        let bridge = settings
+            .into_bridge()
+            .with_context(|| format!("invalid [[bridge]] entry {}", i + 1))?;
+        bridges.push((bridge, format!("[[bridge]] entry {}", i + 1)));
+    }
+    for (serial_port, tcp_port) in cli_bridges {
+        let settings = Settings {
+            serial_port: Some(serial_port.clone()),
+            tcp_port: Some(*tcp_port),
+            ..cli.overrides()
+        };
+        let bridge = settings.or(config.defaults.clone()).into_bridge()?;
+        bridges.push((bridge, format!("--bridge {}:{}", serial_port, tcp_port)));
+    }
+    if bridges.is_empty() {
+        bail!("no serial ports configured");
+    }
+    // Bridges are looked up by name, by the API and on a reload.
+    for (i, (bridge, source)) in bridges.iter().enumerate() {
+        if let Some((_, other)) = bridges[..i].iter().find(|(other, _)| other.name == bridge.name) {
+            bail!(
+                "{} and {} are both named '{}'; give them different names",
+                other,
+                source,
+                bridge.name
+            );
+        }
+    }
+    Ok(bridges.into_iter().map(|(bridge, _)| bridge).collect())
+}
+
+// Parses "TCP=RTU,..." unit id pairs.
+fn parse_unit_map(map: &str) -> Result<BTreeMap<u8, u8>> {
+    map.split(',')
+        .map(|pair| {
+            let (tcp, rtu) = pair
+                .split_once('=')
+                .and_then(|(tcp, rtu)| Some((tcp.trim().parse().ok()?, rtu.trim().parse().ok()?)))
+                .with_context(|| format!("expected TCP=RTU unit ids in modbus_unit_map, got '{}'", pair))?;
+            Ok((tcp, rtu))
+        })
+        .collect()
+}
+
+// Bytes given as hex digits, e.g. "0d0a".
+fn parse_hex(text: &str) -> Result<Vec<u8>> {
+    if text.is_empty() || !text.len().is_multiple_of(2) || !text.is_ascii() {
+        bail!("expected an even number of hex digits, got '{}'", text);
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16).with_context(|| format!("invalid hex '{}'", text)))
+        .collect()
+}
+
+// Bytes for the device, given as escaped text or "@PATH" to a file, by the
+// setting `key`.
+fn payload(key: &str, text: &str) -> Result<Bytes> {
+    let data = match text.strip_prefix('@') {
+        Some(path) => std::fs::read(path).with_context(|| format!("failed to read {} file {}", key, path))?,
+        None => unescape(text).with_context(|| format!("invalid {} '{}'", key, text))?,
+    };
+    if data.is_empty() {
+        bail!("{} must not be empty", key);
+    }
+    Ok(Bytes::from(data))
+}
+
+// Text with C-style escapes for the bytes that cannot be typed.
+fn unescape(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut utf8 = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => bytes.push(b'\r'),
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('0') => bytes.push(0),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 => bytes.push(byte),
+                    _ => bail!("\\x needs two hex digits"),
+                }
+            }
+            Some(c) => bail!("unknown escape \\{}", c),
+            None => bail!("trailing backslash"),
+        }
+    }
+    Ok(bytes)
+}
+
+fn parse_data_bits(name: &str, bits: u8) -> DataBits {
+    match bits {
+        5 => DataBits::Five,
+        6 => DataBits::Six,
+        7 => DataBits::Seven,
+        8 => DataBits::Eight,
+        other => {
+            warn!(bridge = %name, "Unsupported data bits: {}. Using 8 as default.", other);
+            DataBits::Eight
+        }
+    }
+}
+
+// Splits HOST[:PORT], where HOST may be an IPv6 address: in brackets if a
+// port follows, and bare only without one.
+fn host_port(value: &str, setting: &str, default_port: u16) -> Result<(String, u16)> {
+    let (host, port) = match value.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, rest)) => match rest.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None => bail!("invalid {} '{}', expected [ADDRESS]:PORT", setting, value),
+            },
+            None => bail!("invalid {} '{}', missing ']'", setting, value),
+        },
+        // More than one colon is a bare IPv6 address.
+        None => match value.split_once(':') {
+            Some((host, port)) if !port.contains(':') => (host, Some(port)),
+            _ => (value, None),
+        },
+    };
+    if host.is_empty() {
+        bail!("{} '{}' has no host", setting, value);
+    }
+    let port = match port {
+        Some(port) => port
+            .parse()
+            .with_context(|| format!("invalid port in {} '{}'", setting, value))?,
+        None => default_port,
+    };
+    Ok((host.to_string(), port))
+}
+
+// Short label used to prefix a bridge's log lines, e.g. "ttyUSB0".
+fn short_name(serial_port: &str) -> String {
+    Path::new(serial_port)
+        .file_name()
+        .map_or_else(|| serial_port.to_string(), |n| n.to_string_lossy().into_owned())
+}
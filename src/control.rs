@@ -0,0 +1,103 @@
+use anyhow::{Result, anyhow, bail};
+use clap::ValueEnum;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio_serial::{DataBits, StopBits};
+
+use crate::serial::{Control, PortStatus, SerialHandle};
+use crate::{FlowControlArg, ParityArg};
+
+const MAX_LINE: u64 = 256;
+
+const HELP: &str = "\
+commands: status, baud <rate>, data-bits <5-8>, parity <none|odd|even|mark|space>,
+stop-bits <1|2>, flow-control <none|software|hardware>,
+dtr <set|clear|pulse>, rts <set|clear|pulse>, break, help, quit
+";
+
+// Serves the side channel that reconfigures the port under connected
+// clients. One command per line; each is answered with "OK" and the port
+// settings, or "ERR" and the reason:
+//
+//   > baud 115200
+//   < OK baud=115200 data-bits=8 parity=none stop-bits=1 flow-control=none dtr=1 rts=1 cts=0 dsr=0 ri=0 cd=0
+pub async fn serve<S>(stream: S, serial: &SerialHandle) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if (&mut stream).take(MAX_LINE).read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+        if !line.ends_with('\n') && line.len() as u64 == MAX_LINE {
+            stream.write_all(b"ERR line too long\n").await?;
+            return Ok(());
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let reply = match words.as_slice() {
+            [] => continue,
+            ["quit"] => return Ok(()),
+            ["help"] => HELP.to_string(),
+            words => answer(words, serial).await,
+        };
+        stream.write_all(reply.as_bytes()).await?;
+    }
+}
+
+// A command's reply line, for serve and for mux control channels.
+pub async fn answer(words: &[&str], serial: &SerialHandle) -> String {
+    match run(words, serial).await {
+        Ok(status) => format!("OK {}\n", describe(&status)),
+        Err(e) => format!("ERR {}\n", e),
+    }
+}
+
+async fn run(words: &[&str], serial: &SerialHandle) -> Result<PortStatus> {
+    let control = match words {
+        ["status"] => Control::Status,
+        ["baud", rate] => Control::BaudRate(rate.parse().map_err(|_| anyhow!("invalid baud rate"))?),
+        ["data-bits", bits] => Control::DataBits(match *bits {
+            "5" => DataBits::Five,
+            "6" => DataBits::Six,
+            "7" => DataBits::Seven,
+            "8" => DataBits::Eight,
+            _ => bail!("data bits must be 5, 6, 7 or 8"),
+        }),
+        ["parity", parity] => Control::Parity(value::<ParityArg>(parity)?),
+        ["stop-bits", bits] => Control::StopBits(match *bits {
+            "1" => StopBits::One,
+            "2" => StopBits::Two,
+            _ => bail!("stop bits must be 1 or 2"),
+        }),
+        ["flow-control", flow] => Control::FlowControl(value::<FlowControlArg>(flow)?.into()),
+        ["dtr", action] => return serial.line(Control::Dtr, value(action)?).await,
+        ["rts", action] => return serial.line(Control::Rts, value(action)?).await,
+        ["break"] => return serial.send_break().await,
+        _ => bail!("unknown command '{}', try 'help'", words.join(" ")),
+    };
+    serial.control(control).await
+}
+
+fn value<T: ValueEnum>(word: &str) -> Result<T> {
+    T::from_str(word, true).map_err(|e| anyhow!(e))
+}
+
+fn describe(status: &PortStatus) -> String {
+    let flag = |on: bool| if on { 1 } else { 0 };
+    format!(
+        "baud={} data-bits={} parity={} stop-bits={} flow-control={} dtr={} rts={} cts={} dsr={} ri={} cd={}",
+        status.baud_rate,
+        u8::from(status.data_bits),
+        status.parity.to_possible_value().map_or(String::new(), |value| value.get_name().to_string()),
+        u8::from(status.stop_bits),
+        status.flow_control.to_string().to_lowercase(),
+        flag(status.dtr),
+        flag(status.rts),
+        flag(status.cts),
+        flag(status.dsr),
+        flag(status.ri),
+        flag(status.cd),
+    )
+}
@@ -0,0 +1,98 @@
+use std::ffi::CString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, IsTerminal};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use tracing::info;
+
+use crate::unix;
+
+// Removes the pidfile again when the process exits normally.
+pub struct Pidfile(PathBuf);
+
+impl Pidfile {
+    pub fn create(path: &Path) -> Result<Pidfile> {
+        fs::write(path, format!("{}\n", std::process::id()))
+            .with_context(|| format!("failed to write pidfile {}", path.display()))?;
+        Ok(Pidfile(path.to_path_buf()))
+    }
+}
+
+impl Drop for Pidfile {
+    fn drop(&mut self) {
+        // May fail once privileges are dropped; there is nobody left to tell.
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+// Forks into the background and detaches from the terminal. Has to run
+// before the runtime starts any threads. stdin and stdout go to /dev/null,
+// stderr too unless it has been redirected, so logs can still be kept in a
+// file. The working directory is kept so relative paths in the
+// configuration resolve as they would in the foreground.
+pub fn daemonize() -> Result<()> {
+    // SAFETY: the process is single-threaded, so the child starts in a
+    // consistent state.
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()).context("fork failed"),
+        0 => {}
+        _ => std::process::exit(0),
+    }
+    // SAFETY: setsid and dup2 take no pointers.
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error()).context("setsid failed");
+    }
+    let null = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+    let mut targets = vec![libc::STDIN_FILENO, libc::STDOUT_FILENO];
+    if io::stderr().is_terminal() {
+        targets.push(libc::STDERR_FILENO);
+    }
+    for fd in targets {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error()).context("failed to redirect to /dev/null");
+        }
+    }
+    Ok(())
+}
+
+// Switches to `user` (with its supplementary groups, so membership of e.g.
+// dialout still allows reopening a port) and/or `group`. Without a group the
+// user's primary group is used.
+pub fn drop_privileges(user: Option<&str>, group: Option<&str>) -> Result<()> {
+    let user = user.map(unix::user).transpose()?;
+    let gid = match group {
+        Some(group) => Some(unix::group(group)?),
+        None => user.as_ref().map(|user| user.gid),
+    };
+    if let Some(gid) = gid {
+        let result = match &user {
+            Some(user) => {
+                let name = CString::new(user.name.as_str())?;
+                // SAFETY: `name` is a valid NUL-terminated string.
+                unsafe { libc::initgroups(name.as_ptr(), gid as _) }
+            }
+            // SAFETY: the list points at one gid, as the length says.
+            None => unsafe { libc::setgroups(1, &gid) },
+        };
+        check(result, "failed to set supplementary groups")?;
+        // SAFETY: setgid takes no pointers.
+        check(unsafe { libc::setgid(gid) }, "setgid failed")?;
+    }
+    if let Some(user) = &user {
+        // SAFETY: setuid takes no pointers.
+        check(unsafe { libc::setuid(user.uid) }, "setuid failed")?;
+    }
+    // SAFETY: getuid and getgid take no pointers.
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    info!("Running as uid {}, gid {}", uid, gid);
+    Ok(())
+}
+
+fn check(result: libc::c_int, what: &'static str) -> Result<()> {
+    if result == -1 {
+        return Err(io::Error::last_os_error()).context(what);
+    }
+    Ok(())
+}
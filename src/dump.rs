@@ -0,0 +1,59 @@
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::time::SystemTime;
+
+use crate::serial::Direction;
+
+const WIDTH: usize = 16;
+
+// Mirrors serial traffic to stdout as a timestamped hexdump, one block per
+// read or write:
+//
+//   2026-01-01T12:00:00.000Z ttyUSB0 RX 5 bytes
+//   00000000  68 65 6c 6c 6f                                    |hello|
+pub struct HexDump {
+    label: String,
+}
+
+impl HexDump {
+    pub fn new(label: &str) -> HexDump {
+        HexDump {
+            label: label.to_string(),
+        }
+    }
+
+    pub fn write(&self, direction: Direction, data: &[u8]) {
+        let mut out = format!(
+            "{} {} {} {} bytes\n",
+            humantime::format_rfc3339_millis(SystemTime::now()),
+            self.label,
+            match direction {
+                Direction::Rx => "RX",
+                Direction::Tx => "TX",
+            },
+            data.len()
+        );
+        for (i, chunk) in data.chunks(WIDTH).enumerate() {
+            let _ = write!(out, "{:08x} ", i * WIDTH);
+            for j in 0..WIDTH {
+                if j % 8 == 0 {
+                    out.push(' ');
+                }
+                match chunk.get(j) {
+                    Some(b) => {
+                        let _ = write!(out, "{:02x} ", b);
+                    }
+                    None => out.push_str("   "),
+                }
+            }
+            out.push_str(" |");
+            out.extend(chunk.iter().map(|&b| match b {
+                0x20..=0x7e => b as char,
+                _ => '.',
+            }));
+            out.push_str("|\n");
+        }
+        // Written in one go so dumps from several bridges do not interleave.
+        let _ = std::io::stdout().lock().write_all(out.as_bytes());
+    }
+}
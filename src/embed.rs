@@ -0,0 +1,255 @@
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio_serial::{FlowControl, StopBits};
+use tracing::{Instrument, info_span};
+
+use crate::bridge::{self, Registry};
+use crate::client::{SessionEvent, SessionInfo, Sessions};
+use crate::config::Settings;
+use crate::serial::SerialHandle;
+use crate::{Mode, ParityArg, Sharing};
+
+type DataCallback = Arc<dyn Fn(&[u8]) + Send + Sync>;
+type SessionCallback = Arc<dyn Fn(&SessionInfo) + Send + Sync>;
+
+// What a program embedding a bridge wants to hear about. Callbacks run on
+// the bridge's own task, so they should be quick.
+#[derive(Clone, Default)]
+pub struct Callbacks {
+    on_serial_data: Option<DataCallback>,
+    on_connect: Option<SessionCallback>,
+    on_disconnect: Option<SessionCallback>,
+}
+
+impl Callbacks {
+    pub fn is_empty(&self) -> bool {
+        self.on_serial_data.is_none() && self.on_connect.is_none() && self.on_disconnect.is_none()
+    }
+}
+
+// The very same callbacks, as closures cannot be compared otherwise.
+impl PartialEq for Callbacks {
+    fn eq(&self, other: &Callbacks) -> bool {
+        fn same<T: ?Sized>(a: &Option<Arc<T>>, b: &Option<Arc<T>>) -> bool {
+            match (a, b) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (a, b) => a.is_none() && b.is_none(),
+            }
+        }
+        same(&self.on_serial_data, &other.on_serial_data)
+            && same(&self.on_connect, &other.on_connect)
+            && same(&self.on_disconnect, &other.on_disconnect)
+    }
+}
+
+impl fmt::Debug for Callbacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Callbacks")
+            .field("on_serial_data", &self.on_serial_data.is_some())
+            .field("on_connect", &self.on_connect.is_some())
+            .field("on_disconnect", &self.on_disconnect.is_some())
+            .finish()
+    }
+}
+
+// A bridge run inside another program rather than by the binary. Anything
+// the builder methods do not cover can be set on the Settings, which are
+// those of a [[bridge]] entry:
+//
+//   let bridge = SerialBridge::new("/dev/ttyUSB0")
+//       .baud_rate(115200)
+//       .listen(7000)
+//       .on_serial_data(|data| println!("{} bytes", data.len()))
+//       .start()
+//       .await?;
+//   bridge.shutdown_handle().shutdown();
+//   bridge.wait().await?;
+pub struct SerialBridge {
+    settings: Settings,
+    callbacks: Callbacks,
+}
+
+impl SerialBridge {
+    pub fn new(serial_port: impl Into<String>) -> Self {
+        SerialBridge::from_settings(Settings {
+            serial_port: Some(serial_port.into()),
+            ..Settings::default()
+        })
+    }
+
+    pub fn from_settings(settings: Settings) -> Self {
+        SerialBridge {
+            settings,
+            callbacks: Callbacks::default(),
+        }
+    }
+
+    pub fn settings(mut self, configure: impl FnOnce(&mut Settings)) -> Self {
+        configure(&mut self.settings);
+        self
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.settings.name = Some(name.into());
+        self
+    }
+
+    pub fn baud_rate(mut self, baud_rate: u32) -> Self {
+        self.settings.baud_rate = Some(baud_rate);
+        self
+    }
+
+    pub fn data_bits(mut self, data_bits: u8) -> Self {
+        self.settings.data_bits = Some(data_bits);
+        self
+    }
+
+    pub fn parity(mut self, parity: impl Into<ParityArg>) -> Self {
+        self.settings.parity = Some(parity.into());
+        self
+    }
+
+    pub fn stop_bits(mut self, stop_bits: StopBits) -> Self {
+        self.settings.stop_bits = Some(stop_bits.into());
+        self
+    }
+
+    pub fn flow_control(mut self, flow_control: FlowControl) -> Self {
+        self.settings.flow_control = Some(flow_control.into());
+        self
+    }
+
+    // Accept clients on this TCP port. Without it, the bridge only serves
+    // the callbacks and whatever else the settings ask for.
+    pub fn listen(mut self, port: u16) -> Self {
+        self.settings.tcp_port = Some(port);
+        self
+    }
+
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.settings.mode = Some(mode);
+        self
+    }
+
+    pub fn sharing(mut self, sharing: Sharing) -> Self {
+        self.settings.sharing = Some(sharing);
+        self
+    }
+
+    // Called with each read from the port.
+    pub fn on_serial_data(mut self, callback: impl Fn(&[u8]) + Send + Sync + 'static) -> Self {
+        self.callbacks.on_serial_data = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn on_client_connected(mut self, callback: impl Fn(&SessionInfo) + Send + Sync + 'static) -> Self {
+        self.callbacks.on_connect = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn on_client_disconnected(mut self, callback: impl Fn(&SessionInfo) + Send + Sync + 'static) -> Self {
+        self.callbacks.on_disconnect = Some(Arc::new(callback));
+        self
+    }
+
+    // Opens the port and binds the listeners, returning once clients can
+    // connect. Must be called within a Tokio runtime.
+    pub async fn start(self) -> Result<RunningBridge> {
+        let mut config = self.settings.into_bridge()?;
+        config.callbacks = self.callbacks;
+        let registry = Arc::new(Registry::default());
+        let (ready, started) = oneshot::channel();
+        let span = info_span!("bridge", name = %config.name);
+        let mut task = tokio::spawn(bridge::run(config, registry.clone(), Vec::new(), ready).instrument(span));
+        if started.await.is_err() {
+            // It failed before it was up.
+            return Err(match (&mut task).await {
+                Ok(Err(e)) => e,
+                Ok(Ok(())) => anyhow!("bridge stopped while starting"),
+                Err(e) => e.into(),
+            });
+        }
+        Ok(RunningBridge {
+            shutdown: ShutdownHandle(registry),
+            task,
+        })
+    }
+}
+
+// A started bridge. Dropping it leaves the bridge running.
+pub struct RunningBridge {
+    shutdown: ShutdownHandle,
+    task: JoinHandle<Result<()>>,
+}
+
+impl RunningBridge {
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    // Waits for the bridge to stop: after a shutdown, or when it fails.
+    pub async fn wait(self) -> Result<()> {
+        self.task.await?
+    }
+}
+
+// Stops a bridge from anywhere, closing its sessions and port as the
+// binary does on SIGTERM.
+#[derive(Clone)]
+pub struct ShutdownHandle(Arc<Registry>);
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.0.shutdown();
+    }
+}
+
+// Passes serial output and session events to the callbacks. Runs until the
+// bridge stops.
+pub async fn deliver(serial: SerialHandle, sessions: Arc<Sessions>, callbacks: Callbacks) -> Result<()> {
+    // Subscribing to output nobody wants would hold up the port under
+    // backpressure = "block".
+    let mut output = callbacks.on_serial_data.is_some().then(|| serial.subscribe());
+    let mut events = sessions.events();
+    loop {
+        tokio::select! {
+            data = next_output(&mut output) => match data {
+                Ok(data) => {
+                    if let Some(callback) = &callbacks.on_serial_data {
+                        callback(&data);
+                    }
+                }
+                Err(RecvError::Lagged(n)) => serial.dropped(n),
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            event = events.recv() => match event {
+                Ok(SessionEvent::Connected(info)) => {
+                    if let Some(callback) = &callbacks.on_connect {
+                        callback(&info);
+                    }
+                }
+                Ok(SessionEvent::Disconnected(info)) => {
+                    if let Some(callback) = &callbacks.on_disconnect {
+                        callback(&info);
+                    }
+                }
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return Ok(()),
+            },
+        }
+    }
+}
+
+async fn next_output(output: &mut Option<Receiver<Bytes>>) -> Result<Bytes, RecvError> {
+    match output {
+        Some(output) => output.recv().await,
+        None => std::future::pending().await,
+    }
+}
@@ -0,0 +1,59 @@
+use crate::telnet::Event;
+
+// Picks escape sequences out of a raw client's byte stream: whenever a
+// configured sequence arrives, its event is passed on instead of the
+// sequence. Bytes that could begin a sequence are held back until the next
+// bytes show whether they do.
+pub struct Escapes {
+    sequences: Vec<(Vec<u8>, Escape)>,
+    held: Vec<u8>,
+}
+
+#[derive(Clone, Copy)]
+enum Escape {
+    Break,
+    Takeover,
+}
+
+impl Escapes {
+    // None when no sequence is configured.
+    pub fn new(break_sequence: Option<&str>, takeover_sequence: Option<&str>) -> Option<Self> {
+        let sequences: Vec<(Vec<u8>, Escape)> = [(break_sequence, Escape::Break), (takeover_sequence, Escape::Takeover)]
+            .into_iter()
+            .filter_map(|(sequence, escape)| Some((sequence?.as_bytes().to_vec(), escape)))
+            .collect();
+        if sequences.is_empty() {
+            return None;
+        }
+        Some(Escapes {
+            sequences,
+            held: Vec::new(),
+        })
+    }
+
+    pub fn scan(&mut self, input: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut data = Vec::with_capacity(input.len());
+        for &b in input {
+            self.held.push(b);
+            while !self.sequences.iter().any(|(sequence, _)| sequence.starts_with(&self.held)) {
+                data.push(self.held.remove(0));
+            }
+            let Some(&(_, escape)) = self.sequences.iter().find(|(sequence, _)| *sequence == self.held) else {
+                continue;
+            };
+            if !data.is_empty() {
+                events.push(Event::Data(std::mem::take(&mut data)));
+            }
+            events.push(match escape {
+                Escape::Break => Event::Break,
+                Escape::Takeover => Event::Takeover,
+            });
+            self.held.clear();
+        }
+        if !data.is_empty() {
+            events.push(Event::Data(data));
+        }
+        events
+    }
+}
@@ -0,0 +1,120 @@
+use bytes::Bytes;
+
+use crate::newline::Newlines;
+use crate::nmea::Framer;
+use crate::record::RecordTap;
+use crate::serial::Direction;
+use crate::timestamp::Timestamps;
+
+// One step of what a session does to bytes on their way in one direction.
+// Each session has its own filters, so they may carry state from one chunk
+// to the next, and may hold bytes back until they know what to do with
+// them.
+pub trait Filter: Send {
+    fn apply(&mut self, data: &[u8]) -> Vec<u8>;
+}
+
+// Filters applied in order.
+#[derive(Default)]
+pub struct Pipeline(Vec<Box<dyn Filter>>);
+
+impl Pipeline {
+    pub fn push(&mut self, filter: Box<dyn Filter>) {
+        self.0.push(filter);
+    }
+
+    pub fn apply(&mut self, data: Bytes) -> Bytes {
+        let mut data = data;
+        for filter in &mut self.0 {
+            if data.is_empty() {
+                break;
+            }
+            data = Bytes::from(filter.apply(&data));
+        }
+        data
+    }
+}
+
+impl Filter for Framer {
+    fn apply(&mut self, data: &[u8]) -> Vec<u8> {
+        self.push(data)
+    }
+}
+
+impl Filter for Timestamps {
+    fn apply(&mut self, data: &[u8]) -> Vec<u8> {
+        self.stamp(data)
+    }
+}
+
+impl Filter for Newlines {
+    fn apply(&mut self, data: &[u8]) -> Vec<u8> {
+        self.translate(data)
+    }
+}
+
+// Drops ANSI escape sequences: colours, cursor movement and window titles,
+// which make logs of a device's console hard to read or grep.
+#[derive(Default)]
+pub struct StripAnsi {
+    state: AnsiState,
+}
+
+#[derive(Default)]
+enum AnsiState {
+    #[default]
+    Text,
+    Escape,
+    // ESC [ parameters, up to a final byte.
+    Csi,
+    // ESC ] text, up to BEL or ESC \.
+    Osc,
+    OscEscape,
+}
+
+impl Filter for StripAnsi {
+    fn apply(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        for &byte in data {
+            self.state = match (&self.state, byte) {
+                (AnsiState::Text, 0x1b) => AnsiState::Escape,
+                (AnsiState::Text, _) => {
+                    out.push(byte);
+                    AnsiState::Text
+                }
+                (AnsiState::Escape, b'[') => AnsiState::Csi,
+                (AnsiState::Escape, b']') => AnsiState::Osc,
+                // Intermediate bytes, as in ESC ( B.
+                (AnsiState::Escape, 0x20..=0x2f) => AnsiState::Escape,
+                (AnsiState::Escape, _) => AnsiState::Text,
+                (AnsiState::Csi, 0x40..=0x7e) => AnsiState::Text,
+                (AnsiState::Csi, _) => AnsiState::Csi,
+                (AnsiState::Osc, 0x07) => AnsiState::Text,
+                (AnsiState::Osc, 0x1b) => AnsiState::OscEscape,
+                (AnsiState::Osc, _) => AnsiState::Osc,
+                (AnsiState::OscEscape, b'\\') => AnsiState::Text,
+                (AnsiState::OscEscape, _) => AnsiState::Osc,
+            };
+        }
+        out
+    }
+}
+
+// Records what passes this point, leaving it as it is.
+pub struct Record {
+    tap: RecordTap,
+    direction: Direction,
+}
+
+impl Record {
+    pub fn new(tap: RecordTap, direction: Direction) -> Self {
+        Record { tap, direction }
+    }
+}
+
+impl Filter for Record {
+    fn apply(&mut self, data: &[u8]) -> Vec<u8> {
+        self.tap.record(self.direction, data);
+        data.to_vec()
+    }
+}
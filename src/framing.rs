@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+// How serial output is cut into messages for clients, rather than passed on
+// in whatever pieces the port delivered it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Framing {
+    // A frame ends with these bytes...
+    pub delimiter: Option<Vec<u8>>,
+    // ...or once the line has been quiet for this long...
+    pub gap: Option<Duration>,
+    // ...or when it reaches this many bytes.
+    pub max_frame: usize,
+    // Each frame goes out behind its length, two bytes big-endian.
+    pub length_prefix: bool,
+}
+
+// One session's frame in progress.
+pub struct Frames {
+    framing: Framing,
+    frame: Vec<u8>,
+    deadline: Option<Instant>,
+}
+
+impl Frames {
+    pub fn new(framing: Framing) -> Frames {
+        Frames {
+            framing,
+            frame: Vec::new(),
+            deadline: None,
+        }
+    }
+
+    // Adds serial output and returns the frames it completed, ready to
+    // send.
+    pub fn push(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &byte in data {
+            self.frame.push(byte);
+            let delimited = self
+                .framing
+                .delimiter
+                .as_ref()
+                .is_some_and(|delimiter| self.frame.ends_with(delimiter));
+            if delimited || self.frame.len() >= self.framing.max_frame {
+                self.emit(&mut out);
+            }
+        }
+        self.deadline = match self.framing.gap {
+            Some(gap) if !self.frame.is_empty() => Some(Instant::now() + gap),
+            _ => None,
+        };
+        out
+    }
+
+    // When the frame in progress ends for lack of further output, if it
+    // does.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    // Ends the frame in progress, once its deadline has passed.
+    pub fn flush(&mut self) -> Vec<u8> {
+        self.deadline = None;
+        let mut out = Vec::new();
+        if !self.frame.is_empty() {
+            self.emit(&mut out);
+        }
+        out
+    }
+
+    // Drops the frame being built, unsent.
+    pub fn clear(&mut self) {
+        self.deadline = None;
+        self.frame.clear();
+    }
+
+    fn emit(&mut self, out: &mut Vec<u8>) {
+        if self.framing.length_prefix {
+            out.extend_from_slice(&(self.frame.len() as u16).to_be_bytes());
+        }
+        out.append(&mut self.frame);
+    }
+}
@@ -0,0 +1,346 @@
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{Value, json};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
+
+use crate::nmea::Framer;
+use crate::serial::SerialHandle;
+use crate::Backpressure;
+
+// Commands longer than this are not gpsd clients.
+const MAX_COMMAND: usize = 1024;
+const KNOTS_TO_METERS_PER_SECOND: f64 = 0.514444;
+
+// Speaks enough of the gpsd protocol for gpsd clients (cgps, gpsmon,
+// libgps, Home Assistant, ...) to follow the receiver on `device`: they ask
+// to ?WATCH and get TPV and SKY reports, or the raw NMEA with "nmea":true.
+pub async fn serve<S>(mut stream: S, serial: &SerialHandle, device: &str) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut output = serial.subscribe();
+    let mut framer = Framer::new(Vec::new());
+    let mut fix = Fix::default();
+    let mut watch = Watch::default();
+    let mut commands = Vec::new();
+    let mut buf = [0u8; 512];
+    send(&mut stream, &version()).await?;
+    loop {
+        tokio::select! {
+            received = output.recv() => {
+                let data = match received {
+                    Ok(data) => data,
+                    Err(RecvError::Lagged(n)) if serial.backpressure() == Backpressure::Disconnect => {
+                        warn!("Disconnecting gpsd client, it fell behind by {} serial reads", n);
+                        serial.disconnected(n);
+                        return Ok(());
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("gpsd client fell behind, {} serial reads dropped", n);
+                        serial.dropped(n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+                let sentences = framer.push(&data);
+                for sentence in sentences.split(|&b| b == b'\n').filter(|s| !s.is_empty()) {
+                    let sentence = String::from_utf8_lossy(sentence);
+                    let sentence = sentence.trim_end();
+                    let report = fix.update(sentence, device);
+                    if !watch.enable {
+                        continue;
+                    }
+                    if watch.nmea {
+                        stream.write_all(format!("{}\r\n", sentence).as_bytes()).await?;
+                    }
+                    if watch.json && let Some(report) = report {
+                        send(&mut stream, &report).await?;
+                    }
+                }
+            },
+            read = stream.read(&mut buf) => {
+                let n = read?;
+                if n == 0 {
+                    return Ok(());
+                }
+                commands.extend_from_slice(&buf[..n]);
+                while let Some(end) = commands.iter().position(|&b| b == b';' || b == b'\n') {
+                    let command: Vec<u8> = commands.drain(..=end).collect();
+                    let command = String::from_utf8_lossy(&command[..end]);
+                    for reply in respond(command.trim(), &mut watch, &fix, device) {
+                        send(&mut stream, &reply).await?;
+                    }
+                }
+                if commands.len() > MAX_COMMAND {
+                    anyhow::bail!("gpsd command too long");
+                }
+            }
+        }
+    }
+}
+
+async fn send<S>(stream: &mut S, report: &Value) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut line = report.to_string();
+    line.push_str("\r\n");
+    stream.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+#[derive(Default)]
+struct Watch {
+    enable: bool,
+    json: bool,
+    nmea: bool,
+}
+
+fn version() -> Value {
+    json!({
+        "class": "VERSION",
+        "release": env!("CARGO_PKG_VERSION"),
+        "rev": env!("CARGO_PKG_NAME"),
+        "proto_major": 3,
+        "proto_minor": 14,
+    })
+}
+
+// Answers one "?COMMAND[=JSON]" request.
+fn respond(command: &str, watch: &mut Watch, fix: &Fix, device: &str) -> Vec<Value> {
+    let Some(command) = command.strip_prefix('?') else {
+        return Vec::new();
+    };
+    let (name, args) = match command.split_once('=') {
+        Some((name, args)) => (name, serde_json::from_str(args).unwrap_or(Value::Null)),
+        None => (command, Value::Null),
+    };
+    let devices = json!({
+        "class": "DEVICES",
+        "devices": [{"class": "DEVICE", "path": device, "driver": "NMEA0183", "activated": true}],
+    });
+    match name {
+        "VERSION" => vec![version()],
+        "DEVICES" => vec![devices],
+        "WATCH" => {
+            let flag = |key: &str, current: bool| args.get(key).and_then(Value::as_bool).unwrap_or(current);
+            watch.enable = flag("enable", true);
+            // Enabling without saying which reports means JSON.
+            watch.json = flag("json", watch.json || args.get("nmea").is_none());
+            watch.nmea = flag("nmea", watch.nmea);
+            let state = json!({
+                "class": "WATCH",
+                "enable": watch.enable,
+                "json": watch.json,
+                "nmea": watch.nmea,
+            });
+            vec![devices, state]
+        }
+        "POLL" => {
+            let mut poll = json!({
+                "class": "POLL",
+                "active": 1,
+                "tpv": [fix.tpv(device)],
+                "sky": [fix.sky(device)],
+            });
+            if let Some(time) = fix.time() {
+                poll["time"] = Value::from(time);
+            }
+            vec![poll]
+        }
+        _ => {
+            debug!("Unsupported gpsd command {}", name);
+            vec![json!({"class": "ERROR", "message": format!("Unrecognized request '{}'", name)})]
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+struct Satellite {
+    #[serde(rename = "PRN")]
+    prn: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    el: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    az: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ss: Option<f64>,
+    used: bool,
+}
+
+// What is known about the receiver's position, merged from the sentences
+// seen so far.
+#[derive(Default)]
+struct Fix {
+    // gpsd modes: 1 no fix, 2 2D, 3 3D.
+    mode: u8,
+    // hhmmss.sss and ddmmyy as sent.
+    clock: Option<String>,
+    date: Option<String>,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    alt_msl: Option<f64>,
+    geoid_sep: Option<f64>,
+    speed: Option<f64>,
+    track: Option<f64>,
+    hdop: Option<f64>,
+    pdop: Option<f64>,
+    vdop: Option<f64>,
+    used: Vec<u32>,
+    // Receivers sending RMC get one TPV per fix from it, the rest from GGA.
+    rmc: bool,
+    // Collected from a GSV group until its last sentence.
+    in_view: Vec<Satellite>,
+    pending: Vec<Satellite>,
+}
+
+impl Fix {
+    // Takes in a sentence and returns the report it completes, if any:
+    // TPV after RMC (or GGA), SKY after the last GSV of a group.
+    fn update(&mut self, sentence: &str, device: &str) -> Option<Value> {
+        let body = sentence.get(1..)?.split('*').next()?;
+        let fields: Vec<&str> = body.split(',').collect();
+        let kind = fields[0].get(2..)?;
+        let field = |i: usize| fields.get(i).copied().filter(|f| !f.is_empty());
+        let number = |i: usize| field(i).and_then(|f| f.parse::<f64>().ok());
+        match kind {
+            "RMC" => {
+                self.clock = field(1).map(str::to_string);
+                self.date = field(9).map(str::to_string);
+                if field(2) == Some("V") {
+                    self.mode = 1;
+                } else if self.mode < 2 {
+                    self.mode = 2;
+                }
+                self.lat = coordinate(field(3), field(4));
+                self.lon = coordinate(field(5), field(6));
+                self.speed = number(7).map(|knots| knots * KNOTS_TO_METERS_PER_SECOND);
+                self.track = number(8);
+                self.rmc = true;
+                Some(self.tpv(device))
+            }
+            "GGA" => {
+                self.clock = field(1).map(str::to_string);
+                self.lat = coordinate(field(2), field(3));
+                self.lon = coordinate(field(4), field(5));
+                if field(6).is_none_or(|quality| quality == "0") {
+                    self.mode = 1;
+                } else if self.mode < 2 {
+                    self.mode = 2;
+                }
+                self.hdop = number(8);
+                self.alt_msl = number(9);
+                self.geoid_sep = number(11);
+                (!self.rmc).then(|| self.tpv(device))
+            }
+            "GSA" => {
+                if let Some(mode) = field(2).and_then(|m| m.parse().ok()) {
+                    self.mode = mode;
+                }
+                self.used = (3..15).filter_map(|i| field(i)?.parse().ok()).collect();
+                self.pdop = number(15);
+                self.hdop = number(16);
+                self.vdop = number(17);
+                None
+            }
+            "GSV" => {
+                let total: u32 = field(1)?.parse().ok()?;
+                let index: u32 = field(2)?.parse().ok()?;
+                if index == 1 {
+                    self.pending.clear();
+                }
+                for i in (4..fields.len()).step_by(4) {
+                    let Some(prn) = field(i).and_then(|p| p.parse().ok()) else {
+                        continue;
+                    };
+                    self.pending.push(Satellite {
+                        prn,
+                        el: number(i + 1),
+                        az: number(i + 2),
+                        ss: number(i + 3),
+                        used: false,
+                    });
+                }
+                if index < total {
+                    return None;
+                }
+                self.in_view = std::mem::take(&mut self.pending);
+                Some(self.sky(device))
+            }
+            _ => None,
+        }
+    }
+
+    // ISO 8601 time of the fix, once a date has been seen.
+    fn time(&self) -> Option<String> {
+        let (clock, date) = (self.clock.as_deref()?, self.date.as_deref()?);
+        let (hms, fraction) = clock.split_once('.').unwrap_or((clock, ""));
+        if hms.len() != 6 || date.len() != 6 {
+            return None;
+        }
+        let millis = format!("{:0<3}", fraction).get(..3)?.to_string();
+        // Two-digit years, read the way gpsd does.
+        let century = if date[4..6] < *"80" { "20" } else { "19" };
+        Some(format!(
+            "{}{}-{}-{}T{}:{}:{}.{}Z",
+            century,
+            &date[4..6],
+            &date[2..4],
+            &date[0..2],
+            &hms[0..2],
+            &hms[2..4],
+            &hms[4..6],
+            millis
+        ))
+    }
+
+    fn tpv(&self, device: &str) -> Value {
+        let mut tpv = json!({"class": "TPV", "device": device, "mode": self.mode.max(1)});
+        let fields = [
+            ("time", self.time().map(Value::from)),
+            ("lat", self.lat.map(Value::from)),
+            ("lon", self.lon.map(Value::from)),
+            ("altMSL", self.alt_msl.map(Value::from)),
+            ("altHAE", self.alt_msl.zip(self.geoid_sep).map(|(msl, sep)| Value::from(msl + sep))),
+            ("geoidSep", self.geoid_sep.map(Value::from)),
+            ("speed", self.speed.map(Value::from)),
+            ("track", self.track.map(Value::from)),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                tpv[key] = value;
+            }
+        }
+        tpv
+    }
+
+    fn sky(&self, device: &str) -> Value {
+        let satellites: Vec<Satellite> = self
+            .in_view
+            .iter()
+            .map(|satellite| Satellite {
+                used: self.used.contains(&satellite.prn),
+                ..satellite.clone()
+            })
+            .collect();
+        let mut sky = json!({"class": "SKY", "device": device, "satellites": satellites});
+        for (key, value) in [("hdop", self.hdop), ("pdop", self.pdop), ("vdop", self.vdop)] {
+            if let Some(value) = value {
+                sky[key] = Value::from(value);
+            }
+        }
+        sky
+    }
+}
+
+// Converts NMEA's dddmm.mmmm and hemisphere into signed decimal degrees.
+fn coordinate(value: Option<&str>, hemisphere: Option<&str>) -> Option<f64> {
+    let value: f64 = value?.parse().ok()?;
+    let degrees = (value / 100.0).trunc() + (value % 100.0) / 60.0;
+    match hemisphere? {
+        "S" | "W" => Some(-degrees),
+        _ => Some(degrees),
+    }
+}
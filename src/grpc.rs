@@ -0,0 +1,266 @@
+// The gRPC API, for backend services that would rather not speak the raw
+// protocols: what the management API reports and controls, plus sessions as
+// bidirectional streams. Like the management API it has no authentication
+// of its own, but calls are under their bridge's ACL and bans. Sessions are
+// also under its credentials, which go in-band as the first data as they
+// would over a socket, and its connection limits; the port is controlled
+// only on bridges without credentials or client certificates. The service
+// is defined in proto/bridge.proto.
+
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+use bytes::Bytes;
+use clap::ValueEnum;
+use futures_util::Stream;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf};
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{Instrument, error, field, info, info_span};
+
+use crate::api::{self, SerialRequest};
+use crate::bridge::{self, Bridge, Registry};
+use crate::client::Peer;
+use crate::serial::Control;
+use crate::{FlowControlArg, LineAction, Mode, ParityArg};
+
+mod proto {
+    tonic::include_proto!("remote_serial.v1");
+}
+
+use proto::bridges_server::BridgesServer;
+use proto::{
+    BridgeRequest, BridgeSummary, ConfigureRequest, LineRequest, ListRequest, ListResponse, PortStatus,
+    SessionRequest, SessionResponse,
+};
+
+// Bytes in flight between a stream and its session, each way.
+const SESSION_BUFFER: usize = 64 * 1024;
+const READ_SIZE: usize = 16 * 1024;
+
+type SessionStream = Pin<Box<dyn Stream<Item = Result<SessionResponse, Status>> + Send>>;
+
+struct Service {
+    registry: Arc<Registry>,
+}
+
+// Binds the gRPC port and serves it in the background.
+pub fn spawn(bind: &[IpAddr], port: u16, registry: Arc<Registry>) -> Result<()> {
+    let listeners = bridge::listeners(bind, port, "gRPC port")?;
+    info!("gRPC API on port {}", port);
+    for listener in listeners {
+        let service = BridgesServer::new(Service {
+            registry: registry.clone(),
+        });
+        let server = tonic::transport::Server::builder().add_service(service);
+        tokio::spawn(async move {
+            if let Err(e) = server.serve_with_incoming(TcpIncoming::from(listener)).await {
+                error!("gRPC server failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+impl Service {
+    fn bridge(&self, name: &str) -> Result<Arc<Bridge>, Status> {
+        self.registry
+            .get(name)
+            .ok_or_else(|| Status::not_found(format!("no bridge named '{}'", name)))
+    }
+
+    // The bridge a port-control call names, if the caller may control it:
+    // there is no way to log in here.
+    fn controlled(&self, name: &str, addr: SocketAddr) -> Result<Arc<Bridge>, Status> {
+        let bridge = self.bridge(name)?;
+        let refusal = if !bridge.admits(addr.ip()) {
+            "address not allowed"
+        } else if !bridge.open_control() {
+            "the bridge requires authentication"
+        } else {
+            return Ok(bridge);
+        };
+        info!(bridge = %bridge.name, "Refusing gRPC port control from {}: {}", addr, refusal);
+        Err(Status::permission_denied(refusal))
+    }
+}
+
+fn remote<T>(request: &Request<T>) -> SocketAddr {
+    request.remote_addr().unwrap_or(SocketAddr::from(([0, 0, 0, 0], 0)))
+}
+
+#[tonic::async_trait]
+impl proto::bridges_server::Bridges for Service {
+    async fn list(&self, _: Request<ListRequest>) -> Result<Response<ListResponse>, Status> {
+        let bridges = self
+            .registry
+            .list()
+            .into_iter()
+            .map(|bridge| BridgeSummary {
+                name: bridge.name.to_string(),
+                serial_port: bridge.config.serial_port.clone(),
+                connected: bridge.serial.counters().connected.load(Ordering::Relaxed),
+                clients: bridge.sessions.list().len() as u32,
+            })
+            .collect();
+        Ok(Response::new(ListResponse { bridges }))
+    }
+
+    async fn status(&self, request: Request<BridgeRequest>) -> Result<Response<PortStatus>, Status> {
+        let bridge = self.bridge(&request.into_inner().bridge)?;
+        status(&bridge).await.map(Response::new)
+    }
+
+    async fn configure(&self, request: Request<ConfigureRequest>) -> Result<Response<PortStatus>, Status> {
+        let addr = remote(&request);
+        let request = request.into_inner();
+        let bridge = self.controlled(&request.bridge, addr)?;
+        let settings = SerialRequest {
+            baud_rate: request.baud_rate,
+            data_bits: request.data_bits.map(|bits| bits.try_into().unwrap_or(0)),
+            parity: request.parity.as_deref().map(value::<ParityArg>).transpose()?,
+            stop_bits: request.stop_bits.map(|bits| bits.try_into().unwrap_or(0)),
+            flow_control: request.flow_control.as_deref().map(value::<FlowControlArg>).transpose()?,
+        };
+        let controls = settings.controls().map_err(Status::invalid_argument)?;
+        for control in &controls {
+            match bridge.serial.control(control.clone()).await {
+                Ok(port) if api::took(control, &port) => {}
+                Ok(_) => return Err(Status::failed_precondition(format!("the port refused {:?}", control))),
+                Err(e) => return Err(Status::unavailable(e.to_string())),
+            }
+        }
+        info!(bridge = %bridge.name, "Serial settings changed via gRPC: {:?}", controls);
+        status(&bridge).await.map(Response::new)
+    }
+
+    async fn set_line(&self, request: Request<LineRequest>) -> Result<Response<PortStatus>, Status> {
+        let addr = remote(&request);
+        let request = request.into_inner();
+        let bridge = self.controlled(&request.bridge, addr)?;
+        let control = match request.line.as_str() {
+            "dtr" => Control::Dtr,
+            "rts" => Control::Rts,
+            _ => return Err(Status::invalid_argument("line must be dtr or rts")),
+        };
+        let action = value::<LineAction>(&request.action)?;
+        bridge.serial.line(control, action).await.map_err(|e| Status::unavailable(e.to_string()))?;
+        info!(bridge = %bridge.name, "{:?} {} via gRPC", action, request.line.to_uppercase());
+        status(&bridge).await.map(Response::new)
+    }
+
+    async fn send_break(&self, request: Request<BridgeRequest>) -> Result<Response<PortStatus>, Status> {
+        let addr = remote(&request);
+        let bridge = self.controlled(&request.into_inner().bridge, addr)?;
+        bridge.serial.send_break().await.map_err(|e| Status::unavailable(e.to_string()))?;
+        info!(bridge = %bridge.name, "Break sent via gRPC");
+        status(&bridge).await.map(Response::new)
+    }
+
+    type SessionStream = SessionStream;
+
+    async fn session(
+        &self,
+        request: Request<Streaming<SessionRequest>>,
+    ) -> Result<Response<SessionStream>, Status> {
+        let addr = remote(&request);
+        let mut incoming = request.into_inner();
+        let first = incoming
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("the first message must name a bridge"))?;
+        let bridge = self.bridge(&first.bridge)?;
+        // A client certificate cannot be presented here, and a raw session
+        // would get around a Modbus gateway's turn-taking on the bus.
+        let refusal = if !bridge.admits(addr.ip()) {
+            Some("address not allowed")
+        } else if bridge.config.tls_client_ca.is_some() {
+            Some("the bridge requires a client certificate")
+        } else if bridge.config.mode == Mode::ModbusGateway {
+            Some("the bridge is a Modbus gateway")
+        } else {
+            None
+        };
+        if let Some(refusal) = refusal {
+            info!(bridge = %bridge.name, "Refusing gRPC session from {}: {}", addr, refusal);
+            return Err(Status::permission_denied(refusal));
+        }
+        let permit = bridge.limit(addr.ip()).map_err(|refusal| {
+            info!(bridge = %bridge.name, "Refusing gRPC session from {}: {}", addr, refusal);
+            Status::resource_exhausted(refusal)
+        })?;
+        let (ours, theirs) = tokio::io::duplex(SESSION_BUFFER);
+        let (output, mut input) = tokio::io::split(ours);
+        let bridge_span = info_span!(parent: None, "bridge", name = %bridge.name);
+        let span = info_span!(parent: &bridge_span, "client", peer = %addr, identity = field::Empty);
+        let peer = Peer {
+            addr: addr.to_string(),
+            identity: None,
+            read_only: false,
+        };
+        let session = async move {
+            // Held until the session ends.
+            let _permit = permit;
+            bridge.serve_raw(theirs, peer).await
+        };
+        tokio::spawn(session.instrument(span));
+        // The session ends on the end of its input, when the client finishes
+        // or abandons the call.
+        tokio::spawn(async move {
+            let mut data = first.data;
+            loop {
+                if input.write_all(&data).await.is_err() {
+                    break;
+                }
+                match incoming.message().await {
+                    Ok(Some(message)) => data = message.data,
+                    Ok(None) | Err(_) => break,
+                }
+            }
+            let _ = input.shutdown().await;
+        });
+        Ok(Response::new(Box::pin(responses(output))))
+    }
+}
+
+// The session's output, a message per read, until it ends.
+fn responses(output: ReadHalf<DuplexStream>) -> impl Stream<Item = Result<SessionResponse, Status>> + Send {
+    futures_util::stream::unfold(output, |mut output| async move {
+        let mut buf = vec![0u8; READ_SIZE];
+        match output.read(&mut buf).await {
+            Ok(0) | Err(_) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(SessionResponse { data: Bytes::from(buf) }), output))
+            }
+        }
+    })
+}
+
+fn value<T: ValueEnum>(word: &str) -> Result<T, Status> {
+    T::from_str(word, true).map_err(Status::invalid_argument)
+}
+
+async fn status(bridge: &Bridge) -> Result<PortStatus, Status> {
+    let port = bridge.serial.control(Control::Status).await.map_err(|e| Status::unavailable(e.to_string()))?;
+    let counters = bridge.serial.counters();
+    Ok(PortStatus {
+        connected: counters.connected.load(Ordering::Relaxed),
+        baud_rate: port.baud_rate,
+        data_bits: u8::from(port.data_bits).into(),
+        parity: port.parity.to_possible_value().map_or(String::new(), |value| value.get_name().to_string()),
+        stop_bits: u8::from(port.stop_bits).into(),
+        flow_control: port.flow_control.to_string().to_lowercase(),
+        dtr: port.dtr,
+        rts: port.rts,
+        cts: port.cts,
+        dsr: port.dsr,
+        ri: port.ri,
+        cd: port.cd,
+        rx_bytes: counters.rx_bytes.load(Ordering::Relaxed),
+        tx_bytes: counters.tx_bytes.load(Ordering::Relaxed),
+    })
+}
@@ -0,0 +1,110 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Serialize;
+use tokio::net::TcpStream;
+use tracing::{error, info};
+
+use crate::bridge::{self, Registry};
+use crate::http;
+use crate::serial::Control;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// How long a serial task has to answer before the process counts as hung.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+struct Health {
+    ok: bool,
+    bridges: Vec<BridgeHealth>,
+}
+
+#[derive(Serialize)]
+struct BridgeHealth {
+    name: String,
+    // Its listeners are up; a bridge whose accept loop fails stops.
+    running: bool,
+    serial_port_open: bool,
+}
+
+// Binds the health endpoint and serves it in the background, for probes:
+//
+//   GET /healthz  200 while every running bridge's serial task answers,
+//                 so a hung process can be restarted
+//   GET /readyz   200 once every configured bridge is running with its
+//                 serial port open, 503 otherwise and while shutting down
+//
+// Both answer with the state of each bridge the process is configured
+// with, as JSON; a bridge that fails to start never appears in the
+// registry, and counts as not running.
+pub fn spawn(bind: &[IpAddr], port: u16, registry: Arc<Registry>) -> Result<()> {
+    let listeners = bridge::listeners(bind, port, "health port")?;
+    info!("Health checks on port {}", port);
+    for listener in listeners {
+        let registry = registry.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((socket, _)) => {
+                        tokio::spawn(handle(socket, registry.clone()));
+                    }
+                    Err(e) => error!("Health accept failed: {}", e),
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn handle(mut socket: TcpStream, registry: Arc<Registry>) {
+    let Ok(Ok(request)) = tokio::time::timeout(REQUEST_TIMEOUT, http::read_request(&mut socket)).await else {
+        return;
+    };
+    let health = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/healthz") => live(&registry).await,
+        ("GET", "/readyz") => ready(&registry),
+        _ => {
+            let _ = http::respond(&mut socket, 404, "text/plain", b"not found\n").await;
+            return;
+        }
+    };
+    let body = serde_json::to_vec(&health).expect("health serializes");
+    let status = if health.ok { 200 } else { 503 };
+    let _ = http::respond(&mut socket, status, "application/json", &body).await;
+}
+
+async fn live(registry: &Registry) -> Health {
+    let mut health = ready(registry);
+    health.ok = true;
+    for bridge in registry.list() {
+        let answered = tokio::time::timeout(ANSWER_TIMEOUT, bridge.serial.control(Control::Status)).await;
+        if !matches!(answered, Ok(Ok(_))) {
+            health.ok = false;
+        }
+    }
+    health
+}
+
+fn ready(registry: &Registry) -> Health {
+    let running = registry.list();
+    let bridges: Vec<BridgeHealth> = registry
+        .configured()
+        .into_iter()
+        .map(|name| {
+            let bridge = running.iter().find(|bridge| *bridge.name == *name);
+            let open = bridge.is_some_and(|bridge| bridge.serial.counters().connected.load(Ordering::Relaxed));
+            BridgeHealth {
+                name,
+                running: bridge.is_some(),
+                serial_port_open: open,
+            }
+        })
+        .collect();
+    Health {
+        ok: !registry.is_stopping() && bridges.iter().all(|bridge| bridge.running && bridge.serial_port_open),
+        bridges,
+    }
+}
@@ -0,0 +1,26 @@
+use anyhow::{Result, bail};
+use tokio::process::Command;
+
+// Runs a shell command with the given environment variables added, and
+// waits for it. The command is killed if this future is dropped.
+pub async fn run(command: &str, env: &[(&str, &str)]) -> Result<()> {
+    let status = shell(command).envs(env.iter().copied()).kill_on_drop(true).status().await?;
+    if !status.success() {
+        bail!("'{}' failed with {}", command, status);
+    }
+    Ok(())
+}
+
+// The command as the platform's shell runs it.
+pub fn shell(command: &str) -> Command {
+    #[cfg(unix)]
+    let mut shell = Command::new("sh");
+    #[cfg(unix)]
+    shell.arg("-c");
+    #[cfg(windows)]
+    let mut shell = Command::new("cmd");
+    #[cfg(windows)]
+    shell.arg("/C");
+    shell.arg(command);
+    shell
+}
@@ -0,0 +1,205 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::ServerName;
+
+use crate::tls;
+
+const MAX_HEAD: usize = 8 * 1024;
+const MAX_BODY: usize = 64 * 1024;
+
+// Just enough HTTP/1.1 for the built-in endpoints: one request per
+// connection, no chunked bodies.
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+// Reads a request head (and body, if Content-Length is given). The head is
+// read a byte at a time so that nothing beyond it is consumed.
+pub async fn read_request<S>(stream: &mut S) -> Result<Request>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() == MAX_HEAD {
+            bail!("request head too large");
+        }
+        head.push(stream.read_u8().await?);
+    }
+    let head = String::from_utf8(head)?;
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        bail!("malformed request line");
+    };
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(n, v)| (n.trim().to_string(), v.trim().to_string()))
+        .collect();
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        headers,
+        body: Vec::new(),
+    };
+    let length: usize = request
+        .header("content-length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    if length > MAX_BODY {
+        bail!("request body too large");
+    }
+    request.body.resize(length, 0);
+    stream.read_exact(&mut request.body).await?;
+    Ok(request)
+}
+
+pub async fn respond<S>(stream: &mut S, status: u16, content_type: &str, body: &[u8]) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason(status),
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+// Where outgoing requests go: "http[s]://HOST[:PORT][/PATH]".
+#[derive(Clone, Debug, PartialEq)]
+pub struct Url {
+    https: bool,
+    host: String,
+    port: u16,
+    // The path and query, from the first '/'.
+    target: String,
+}
+
+impl FromStr for Url {
+    type Err = anyhow::Error;
+
+    fn from_str(url: &str) -> Result<Url> {
+        let (https, rest) = match url.split_once("://") {
+            Some(("http", rest)) => (false, rest),
+            Some(("https", rest)) => (true, rest),
+            _ => bail!("'{}' is not an http:// or https:// URL", url),
+        };
+        let (authority, target) = rest.find('/').map_or((rest, "/"), |i| rest.split_at(i));
+        let (host, port) = match authority.rsplit_once(':') {
+            // The colon may be inside an IPv6 address instead.
+            Some((host, port)) if !port.contains(']') => {
+                (host, port.parse().with_context(|| format!("invalid port in '{}'", url))?)
+            }
+            _ => (authority, if https { 443 } else { 80 }),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            bail!("'{}' has no host", url);
+        }
+        Ok(Url {
+            https,
+            host: host.to_string(),
+            port,
+            target: target.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scheme = if self.https { "https" } else { "http" };
+        match self.host.contains(':') {
+            true => write!(f, "{}://[{}]:{}{}", scheme, self.host, self.port, self.target),
+            false => write!(f, "{}://{}:{}{}", scheme, self.host, self.port, self.target),
+        }
+    }
+}
+
+// Sends one POST request and returns the response status; the response
+// itself is not read further.
+pub async fn post(url: &Url, content_type: &str, body: &[u8]) -> Result<u16> {
+    let host = match url.host.contains(':') {
+        true => format!("[{}]:{}", url.host, url.port),
+        false => format!("{}:{}", url.host, url.port),
+    };
+    let head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        url.target,
+        host,
+        content_type,
+        body.len()
+    );
+    let stream = TcpStream::connect((url.host.as_str(), url.port))
+        .await
+        .with_context(|| format!("failed to connect to {}", host))?;
+    if !url.https {
+        return exchange(stream, &head, body).await;
+    }
+    let connector = TlsConnector::from(Arc::new(tls::client_config(None)?));
+    let name = ServerName::try_from(url.host.clone()).with_context(|| format!("invalid host name {}", url.host))?;
+    exchange(connector.connect(name, stream).await?, &head, body).await
+}
+
+async fn exchange<S>(mut stream: S, head: &str, body: &[u8]) -> Result<u16>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.flush().await?;
+    let mut status_line = Vec::new();
+    while !status_line.ends_with(b"\r\n") {
+        if status_line.len() == MAX_HEAD {
+            bail!("response status line too long");
+        }
+        status_line.push(stream.read_u8().await?);
+    }
+    String::from_utf8_lossy(&status_line)
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .context("malformed HTTP response")
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        422 => "Unprocessable Entity",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
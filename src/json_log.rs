@@ -0,0 +1,87 @@
+use std::fmt;
+use std::time::SystemTime;
+
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::registry::LookupSpan;
+
+// Events as one flat JSON object a line, for log pipelines, its keys in
+// alphabetical order:
+//
+//   {"bridge":"core-sw1","bytes_in":112,"bytes_out":20480,"duration":95,"event":"session_end",
+//    "level":"INFO","message":"Client disconnected","peer":"10.0.0.7:51234","session":4,
+//    "target":"remote_serial_server::bridge","time":"2026-03-01T09:12:44.031207Z"}
+//
+// The event's fields sit alongside those of the spans it happened in; a
+// span's `name` field goes by the span's own name, as bridge{name} would
+// otherwise clash with anything else named. Span fields are written by
+// JsonFields, so that they can be read back here.
+pub struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        let meta = event.metadata();
+        let mut object = Map::new();
+        object.insert("time".into(), humantime::format_rfc3339_micros(SystemTime::now()).to_string().into());
+        object.insert("level".into(), meta.level().as_str().into());
+        object.insert("target".into(), meta.target().into());
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                let Some(fields) = extensions.get::<FormattedFields<N>>() else {
+                    continue;
+                };
+                let Ok(Value::Object(fields)) = serde_json::from_str(&fields.fields) else {
+                    continue;
+                };
+                for (key, value) in fields {
+                    let key = if key == "name" { span.name().to_string() } else { key };
+                    object.insert(key, value);
+                }
+            }
+        }
+        event.record(&mut Fields(&mut object));
+        writeln!(writer, "{}", Value::Object(object))
+    }
+}
+
+// An event's or span's fields into a JSON object.
+pub struct Fields<'a>(pub &'a mut Map<String, Value>);
+
+impl Visit for Fields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), value.into());
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.0.insert(field.name().into(), value.to_string().into());
+    }
+
+    // The message among them, as format arguments.
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(field.name().into(), format!("{:?}", value).into());
+    }
+}
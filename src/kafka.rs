@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use rskafka::BackoffConfig;
+use rskafka::chrono::DateTime;
+use rskafka::client::partition::{Compression, PartitionClient, UnknownTopicHandling};
+use rskafka::client::{ClientBuilder, Credentials, SaslConfig};
+use rskafka::record::Record;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::serial::Direction;
+use crate::tls;
+
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+// How long a produce request may keep being retried before it is reported
+// and tried afresh.
+const PRODUCE_DEADLINE: Duration = Duration::from_secs(30);
+// Records waiting to be produced; beyond this they are dropped rather than
+// hold up the port.
+const QUEUE_CAPACITY: usize = 4096;
+const MAX_BATCH: usize = 500;
+
+// Where and how a bridge produces its traffic to Kafka.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KafkaConfig {
+    // HOST:PORT of brokers to bootstrap from.
+    pub brokers: Vec<String>,
+    pub topic: String,
+    pub partition: i32,
+    pub client_id: String,
+    // SASL PLAIN credentials.
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls: bool,
+    // Verify the brokers against this CA instead of the system's roots.
+    pub ca: Option<PathBuf>,
+}
+
+// Feeds each read from and write to the port, as the serial task sees them,
+// to the producer.
+pub struct KafkaTap {
+    records: mpsc::Sender<Record>,
+    bridge: String,
+    port: String,
+    dropped: Arc<AtomicU64>,
+}
+
+impl KafkaTap {
+    // A record per chunk: keyed by bridge name, so that a bridge's records
+    // keep their order, with the serial port and "rx" or "tx" in the
+    // headers and the bytes themselves as the value.
+    pub fn observe(&self, direction: Direction, data: &[u8]) {
+        let direction = match direction {
+            Direction::Rx => "rx",
+            Direction::Tx => "tx",
+        };
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+        let record = Record {
+            key: Some(self.bridge.clone().into_bytes()),
+            value: Some(data.to_vec()),
+            headers: BTreeMap::from([
+                ("port".to_string(), self.port.clone().into_bytes()),
+                ("direction".to_string(), direction.as_bytes().to_vec()),
+            ]),
+            timestamp: DateTime::from_timestamp_nanos(nanos as i64),
+        };
+        if self.records.try_send(record).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// The tap for the serial task, and the producer to run alongside it, which
+// ends once the tap is dropped with the port's task.
+pub fn tap(config: KafkaConfig, bridge: &str, port: &str) -> (KafkaTap, impl Future<Output = ()> + use<>) {
+    let (records, queue) = mpsc::channel(QUEUE_CAPACITY);
+    let dropped = Arc::new(AtomicU64::new(0));
+    let tap = KafkaTap {
+        records,
+        bridge: bridge.to_string(),
+        port: port.to_string(),
+        dropped: dropped.clone(),
+    };
+    (tap, produce(config, queue, dropped))
+}
+
+async fn produce(config: KafkaConfig, mut queue: mpsc::Receiver<Record>, dropped: Arc<AtomicU64>) {
+    let mut client = None;
+    let mut batch = Vec::new();
+    loop {
+        if batch.is_empty() {
+            match queue.recv().await {
+                Some(record) => batch.push(record),
+                None => return,
+            }
+            while batch.len() < MAX_BATCH
+                && let Ok(record) = queue.try_recv()
+            {
+                batch.push(record);
+            }
+        }
+        let n = dropped.swap(0, Ordering::Relaxed);
+        if n > 0 {
+            warn!("Kafka fell behind, {} serial records dropped", n);
+        }
+        let produced = async {
+            if client.is_none() {
+                client = Some(connect(&config).await?);
+            }
+            let partition = client.as_ref().expect("connected above");
+            partition.produce(batch.clone(), Compression::NoCompression).await?;
+            Ok::<_, anyhow::Error>(())
+        };
+        match produced.await {
+            Ok(()) => batch.clear(),
+            Err(e) => {
+                warn!("Kafka topic {}: {:#}; retrying in {:?}", config.topic, e, RETRY_DELAY);
+                client = None;
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+async fn connect(config: &KafkaConfig) -> Result<PartitionClient> {
+    let mut builder = ClientBuilder::new(config.brokers.clone())
+        .client_id(config.client_id.as_str())
+        .backoff_config(BackoffConfig {
+            deadline: Some(PRODUCE_DEADLINE),
+            max_backoff: RETRY_DELAY,
+            ..BackoffConfig::default()
+        });
+    if config.tls {
+        builder = builder.tls_config(Arc::new(tls::client_config(config.ca.as_deref())?));
+    }
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        builder = builder.sasl_config(SaslConfig::Plain(Credentials::new(username.clone(), password.clone())));
+    }
+    let client = builder.build().await.context("failed to reach the brokers")?;
+    let partition = client
+        .partition_client(config.topic.as_str(), config.partition, UnknownTopicHandling::Retry)
+        .await
+        .with_context(|| format!("no partition {} of topic {}", config.partition, config.topic))?;
+    info!("Connected to Kafka brokers {}", config.brokers.join(", "));
+    Ok(partition)
+}
@@ -0,0 +1,39 @@
+// Low latency for USB serial adapters, which otherwise hold received bytes
+// back to fill a USB packet: an FTDI chip for 16 ms by default, long enough
+// to make interactive protocols such as GDB's crawl. ASYNC_LOW_LATENCY asks
+// the driver to pass on bytes at once; FTDI adapters also have their latency
+// timer in sysfs set to its 1 ms minimum. Only Linux offers either, and both
+// are best effort, as most drivers take the flag and do nothing with it.
+
+use tokio_serial::SerialStream;
+use tracing::warn;
+
+#[cfg(target_os = "linux")]
+const LATENCY_TIMER_MS: &str = "1";
+
+#[cfg(target_os = "linux")]
+pub fn set(port: &SerialStream, path: &str) {
+    use crate::serial_struct::{self, ASYNC_LOW_LATENCY};
+
+    let flagged = serial_struct::get(port).and_then(|mut serial| {
+        serial.flags |= ASYNC_LOW_LATENCY;
+        serial_struct::set(port, &serial)
+    });
+    if let Err(e) = flagged {
+        warn!("Cannot put {} in low latency mode: {}", path, e);
+    }
+    let Some(name) = std::fs::canonicalize(path).ok().and_then(|path| Some(path.file_name()?.to_owned())) else {
+        return;
+    };
+    let timer = std::path::Path::new("/sys/bus/usb-serial/devices").join(name).join("latency_timer");
+    if timer.exists()
+        && let Err(e) = std::fs::write(&timer, LATENCY_TIMER_MS)
+    {
+        warn!("Cannot set the latency timer of {}: {}", path, e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set(_port: &SerialStream, path: &str) {
+    warn!("Low latency mode for {} is only supported on Linux", path);
+}
@@ -0,0 +1,321 @@
+// The bridge as a library, for programs that embed it rather than run the
+// binary: see SerialBridge. The binary itself is cli::main.
+
+mod acl;
+mod admin;
+#[cfg(unix)]
+mod activation;
+mod api;
+mod audit;
+mod auth;
+mod ban;
+mod baud;
+mod bridge;
+mod capture;
+pub mod cli;
+mod client;
+mod compress;
+mod config;
+mod control;
+#[cfg(unix)]
+mod daemon;
+mod dump;
+mod embed;
+mod escape;
+mod filter;
+mod framing;
+mod gpsd;
+mod grpc;
+mod health;
+mod hook;
+mod http;
+mod json_log;
+mod kafka;
+mod latency;
+mod line_input;
+mod local;
+#[cfg(unix)]
+mod lock;
+mod mdns;
+mod metrics;
+mod modbus;
+mod mqtt;
+mod mux;
+mod nats;
+mod nmea;
+mod newline;
+mod noise;
+mod otlp;
+#[cfg(unix)]
+mod pam;
+mod parity;
+mod parse;
+mod peer;
+mod plugin;
+mod ports;
+mod proxy;
+#[cfg(unix)]
+mod pty;
+mod quic;
+mod record;
+mod redis;
+mod rfc2217;
+mod rotate;
+mod rs485;
+mod script;
+mod ser2net;
+mod serial;
+#[cfg(target_os = "linux")]
+mod serial_struct;
+mod ssh;
+mod sse;
+mod stats;
+#[cfg(unix)]
+mod syslog;
+#[cfg(windows)]
+mod service;
+#[cfg(target_os = "linux")]
+mod systemd;
+mod tee;
+mod telnet;
+mod throttle;
+mod timestamp;
+mod tls;
+mod transport;
+mod triggers;
+mod udp;
+#[cfg(unix)]
+mod unix;
+mod usb;
+mod watchdog;
+mod web;
+mod ws;
+mod xmodem;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tokio_serial::{FlowControl, Parity, StopBits};
+
+pub use crate::client::SessionInfo;
+pub use crate::config::{Settings, TriggerSettings};
+pub use crate::embed::{RunningBridge, SerialBridge, ShutdownHandle};
+pub use crate::usb::UsbId;
+
+#[derive(Copy, Clone, ValueEnum, Deserialize, Serialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    #[default]
+    Raw,
+    Rfc2217,
+    // Telnet with binary transmission and IAC escaping, for plain telnet
+    // clients that should not reconfigure the port.
+    Telnet,
+    // Modbus TCP from clients, Modbus RTU on the serial line.
+    ModbusGateway,
+    // Raw, but clients receive only whole, valid NMEA 0183 sentences.
+    Nmea,
+}
+
+#[derive(Copy, Clone, ValueEnum, Deserialize, Serialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sharing {
+    // One client at a time, besides read-only ones; further connections
+    // are refused.
+    #[default]
+    Exclusive,
+    // Every client sees serial output, only the earliest one that is not
+    // read-only may write.
+    Broadcast,
+    // Every client reads and writes.
+    FreeForAll,
+}
+
+// What happens when a subscriber cannot keep up with serial output.
+#[derive(Copy, Clone, ValueEnum, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backpressure {
+    // The subscriber misses the oldest output it has not taken yet.
+    #[default]
+    DropOldest,
+    // Reading the port stops until every subscriber has caught up, leaving
+    // the device to flow control or its own buffer.
+    Block,
+    // Client sessions that fall behind are disconnected.
+    Disconnect,
+}
+
+#[derive(Copy, Clone, ValueEnum, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transport {
+    #[default]
+    Tcp,
+    // Datagrams to and from the most recent (or a configured) peer.
+    Udp,
+}
+
+#[derive(Copy, Clone, ValueEnum, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Dump {
+    Hex,
+}
+
+// Where the process's own logs go.
+#[derive(Copy, Clone, ValueEnum, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogTarget {
+    #[default]
+    Stderr,
+    // The system logger, by syslog(3) with the daemon facility (Unix).
+    Syslog,
+    // The systemd journal, with the span fields as journal fields (Linux).
+    Journald,
+}
+
+// How each log event is written out.
+#[derive(Copy, Clone, ValueEnum, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    // One JSON object a line, with the fields of the event and its spans.
+    // Sessions, serial errors and stats carry an `event` field naming what
+    // happened: session_start, session_end (with its byte counts),
+    // serial_error, serial_reconnect, serial_stats.
+    Json,
+}
+
+// How the serial watchdog tries to bring a silent device back.
+#[derive(Copy, Clone, ValueEnum, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WatchdogAction {
+    // Clear DTR briefly, which resets many boards.
+    PulseDtr,
+    #[default]
+    Reopen,
+    // Run watchdog_hook.
+    Hook,
+}
+
+#[derive(Copy, Clone, ValueEnum, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LineEnding {
+    Cr,
+    Lf,
+    Crlf,
+}
+
+#[derive(Copy, Clone, ValueEnum, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimestampFormat {
+    // Wall clock time in UTC, to the millisecond.
+    Iso8601,
+    // Seconds since the client connected.
+    Relative,
+}
+
+// A step of what sessions do to bytes in one direction.
+#[derive(Copy, Clone, ValueEnum, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterName {
+    StripAnsi,
+    // In the format given by timestamps.
+    Timestamp,
+    // To line_ending or output_line_ending, by direction.
+    LineEnding,
+    // Into the session's transcript under record.
+    Record,
+    // The WebAssembly module given as plugin.
+    Plugin,
+}
+
+// How the network leg of a data connection is compressed.
+#[derive(Copy, Clone, ValueEnum, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compression {
+    Zstd,
+}
+
+// What to do with a modem control line.
+#[derive(Copy, Clone, ValueEnum, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LineAction {
+    Set,
+    Clear,
+    // Clear briefly and set again, which resets an Arduino.
+    Pulse,
+}
+
+#[derive(Copy, Clone, ValueEnum, Deserialize, Serialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ParityArg {
+    Even,
+    Odd,
+    #[default]
+    None,
+    // The parity bit always set (Linux only).
+    Mark,
+    // The parity bit always clear (Linux only).
+    Space,
+}
+impl From<Parity> for ParityArg {
+    fn from(val: Parity) -> Self {
+        match val {
+            Parity::Even => ParityArg::Even,
+            Parity::Odd => ParityArg::Odd,
+            Parity::None => ParityArg::None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum, Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum StopBitsArg {
+    #[default]
+    One,
+    Two,
+}
+impl From<StopBitsArg> for StopBits {
+    fn from(val: StopBitsArg) -> Self {
+        match val {
+            StopBitsArg::One => StopBits::One,
+            StopBitsArg::Two => StopBits::Two,
+        }
+    }
+}
+impl From<StopBits> for StopBitsArg {
+    fn from(val: StopBits) -> Self {
+        match val {
+            StopBits::One => StopBitsArg::One,
+            StopBits::Two => StopBitsArg::Two,
+        }
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum, Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum FlowControlArg {
+    #[default]
+    None,
+    // XON/XOFF.
+    Software,
+    // RTS/CTS.
+    Hardware,
+}
+impl From<FlowControlArg> for FlowControl {
+    fn from(val: FlowControlArg) -> Self {
+        match val {
+            FlowControlArg::None => FlowControl::None,
+            FlowControlArg::Software => FlowControl::Software,
+            FlowControlArg::Hardware => FlowControl::Hardware,
+        }
+    }
+}
+impl From<FlowControl> for FlowControlArg {
+    fn from(val: FlowControl) -> Self {
+        match val {
+            FlowControl::None => FlowControlArg::None,
+            FlowControl::Software => FlowControlArg::Software,
+            FlowControl::Hardware => FlowControlArg::Hardware,
+        }
+    }
+}
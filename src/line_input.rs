@@ -0,0 +1,62 @@
+// Longer lines go out in pieces.
+const MAX_LINE: usize = 4096;
+
+// Client input for devices that neither echo nor edit lines themselves.
+// Buffered input reaches the port a line at a time, after backspace has had
+// its effect; control characters such as Ctrl-C go out at once, along with
+// the line so far.
+pub struct LineInput {
+    // None unless line buffered.
+    line: Option<Vec<u8>>,
+    echo: bool,
+    after_cr: bool,
+}
+
+impl LineInput {
+    pub fn new(buffered: bool, echo: bool) -> LineInput {
+        LineInput {
+            line: buffered.then(Vec::new),
+            echo,
+            after_cr: false,
+        }
+    }
+
+    // Returns what goes to the port now, and adds what the client should
+    // see to `echo`.
+    pub fn feed(&mut self, data: &[u8], echo: &mut Vec<u8>) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &byte in data {
+            if self.echo {
+                let erasable = self.line.as_ref().is_none_or(|line| !line.is_empty());
+                match byte {
+                    b'\n' if self.after_cr => {}
+                    b'\r' | b'\n' => echo.extend_from_slice(b"\r\n"),
+                    0x08 | 0x7f if erasable => echo.extend_from_slice(b"\x08 \x08"),
+                    0x08 | 0x7f => {}
+                    _ => echo.push(byte),
+                }
+            }
+            self.after_cr = byte == b'\r';
+            let Some(line) = self.line.as_mut() else {
+                out.push(byte);
+                continue;
+            };
+            match byte {
+                0x08 | 0x7f => {
+                    line.pop();
+                }
+                byte if byte < 0x20 => {
+                    out.append(line);
+                    out.push(byte);
+                }
+                _ => {
+                    line.push(byte);
+                    if line.len() == MAX_LINE {
+                        out.append(line);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
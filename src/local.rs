@@ -0,0 +1,145 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[cfg(unix)]
+use crate::pty;
+use crate::compress::ZstdStream;
+use crate::{Compression, noise, quic};
+
+#[derive(clap::Args, Debug)]
+pub struct ClientArgs {
+    #[command(flatten)]
+    remote: RemoteArgs,
+
+    /// Expose the port as a pseudo-terminal instead of on stdin/stdout, and
+    /// keep reconnecting while it is open.
+    #[cfg(unix)]
+    #[arg(long)]
+    pty: bool,
+
+    /// Also make the pseudo-terminal available under this path.
+    #[cfg(unix)]
+    #[arg(long, requires = "pty")]
+    link: Option<PathBuf>,
+}
+
+// How to reach a bridge, for the subcommands that connect to one.
+#[derive(clap::Args, Debug)]
+pub struct RemoteArgs {
+    /// HOST:PORT of a bridge in raw mode.
+    address: String,
+
+    /// Connect to the bridge's quic_port instead of a TCP port.
+    #[arg(long)]
+    quic: bool,
+
+    /// Verify the bridge's certificate against this CA rather than the
+    /// system's trusted roots.
+    #[arg(long, requires = "quic")]
+    ca: Option<PathBuf>,
+
+    /// The bridge's noise_key, to encrypt the connection with.
+    #[arg(long, conflicts_with = "quic")]
+    noise_key: Option<String>,
+
+    /// The bridge's compress setting.
+    #[arg(long, value_enum)]
+    compress: Option<Compression>,
+}
+
+impl RemoteArgs {
+    pub fn remote(self) -> Result<Remote> {
+        Ok(Remote {
+            quic: self.quic.then(|| quic::client_config(self.ca.as_deref())).transpose()?,
+            noise_key: self.noise_key.as_deref().map(noise::parse_key).transpose()?,
+            address: self.address,
+            compress: self.compress,
+        })
+    }
+}
+
+// A connection to a bridge, whichever way it was made.
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+// Where the client connects, and how.
+pub struct Remote {
+    pub address: String,
+    quic: Option<quinn::ClientConfig>,
+    noise_key: Option<noise::Key>,
+    compress: Option<Compression>,
+}
+
+impl Remote {
+    pub async fn connect(&self) -> Result<Box<dyn Stream>> {
+        let stream: Box<dyn Stream> = match (&self.quic, &self.noise_key) {
+            (Some(config), _) => Box::new(quic::connect(&self.address, config).await?),
+            (None, Some(key)) => Box::new(noise::connect(connect(&self.address).await?, key).await?),
+            (None, None) => Box::new(connect(&self.address).await?),
+        };
+        match self.compress {
+            Some(Compression::Zstd) => Ok(Box::new(ZstdStream::new(stream)?)),
+            None => Ok(stream),
+        }
+    }
+}
+
+// Runs the client subcommand: the counterpart of a bridge, for programs
+// that want a local serial port.
+pub async fn run(args: ClientArgs) -> Result<()> {
+    let remote = args.remote.remote()?;
+    #[cfg(unix)]
+    if args.pty {
+        return pty::serve(&remote, args.link).await;
+    }
+    let socket = remote.connect().await?;
+    relay(socket, tokio::io::stdin(), tokio::io::stdout()).await
+}
+
+pub async fn connect(address: &str) -> Result<TcpStream> {
+    let socket = TcpStream::connect(address)
+        .await
+        .with_context(|| format!("failed to connect to {}", address))?;
+    // Interactive use; every keystroke should go out at once.
+    socket.set_nodelay(true)?;
+    Ok(socket)
+}
+
+// Copies both ways until the server hangs up. At the end of local input the
+// socket is half-closed, so the server's last output still arrives.
+pub async fn relay<S, R, W>(socket: S, mut input: R, mut output: W) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let (mut from_server, mut to_server) = tokio::io::split(socket);
+    let mut server_buf = [0u8; 4096];
+    let mut input_buf = [0u8; 4096];
+    let mut input_open = true;
+    loop {
+        tokio::select! {
+            read = from_server.read(&mut server_buf) => {
+                let n = read?;
+                if n == 0 {
+                    return Ok(());
+                }
+                output.write_all(&server_buf[..n]).await?;
+                output.flush().await?;
+            },
+            read = input.read(&mut input_buf), if input_open => {
+                let n = read?;
+                if n == 0 {
+                    input_open = false;
+                    to_server.shutdown().await?;
+                } else {
+                    to_server.write_all(&input_buf[..n]).await?;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use tracing::{debug, info, warn};
+
+// Where UUCP lock files go: /var/lock on Linux (often /run/lock under it),
+// /var/spool/lock on the BSDs and macOS.
+#[cfg(target_os = "linux")]
+const LOCK_DIR: &str = "/var/lock";
+#[cfg(not(target_os = "linux"))]
+const LOCK_DIR: &str = "/var/spool/lock";
+
+// A UUCP lock file, LCK..ttyUSB0 holding the owner's pid, as minicom,
+// picocom and ModemManager take before opening a port and respect from each
+// other. TIOCEXCL keeps their opens out while the port is open, but only
+// for processes without CAP_SYS_ADMIN, and only once it is open; the lock
+// file covers the rest. Removed when dropped.
+pub struct Lock(PathBuf);
+
+impl Lock {
+    // Fails if a live process holds the lock. A stale one, whose process is
+    // gone, is taken over. When the lock directory is missing or not
+    // writable the port is opened without a lock of its own, as it would be
+    // by most tools.
+    pub fn acquire(device: &str) -> Result<Option<Lock>> {
+        if !Path::new(LOCK_DIR).is_dir() {
+            debug!("No {} for a lock file", LOCK_DIR);
+            return Ok(None);
+        }
+        let path = Path::new(LOCK_DIR).join(format!("LCK..{}", name(device)));
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    // HDB format: the pid as ten characters and a newline.
+                    let pid = format!("{:>10}\n", std::process::id());
+                    file.write_all(pid.as_bytes()).with_context(|| format!("failed to write {}", path.display()))?;
+                    return Ok(Some(Lock(path)));
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => match owner(&path) {
+                    Some(pid) if pid == std::process::id() => bail!("{} is already open in this process", device),
+                    Some(pid) if alive(pid) => bail!("{} is locked by process {} ({})", device, pid, path.display()),
+                    _ => {
+                        info!("Removing stale lock file {}", path.display());
+                        if let Err(e) = fs::remove_file(&path) {
+                            warn!("Cannot remove stale lock file {}: {}", path.display(), e);
+                            return Ok(None);
+                        }
+                    }
+                },
+                Err(e) => {
+                    warn!("Cannot create lock file {}: {}; opening {} without one", path.display(), e, device);
+                    return Ok(None);
+                }
+            }
+        }
+        bail!("{} keeps being locked by another process", device)
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+// The device as lock files name it: its path under /dev with slashes made
+// underscores, after following symlinks such as /dev/serial/by-id/..., so
+// that every name for a port comes to the same lock.
+fn name(device: &str) -> String {
+    let path = fs::canonicalize(device).unwrap_or_else(|_| PathBuf::from(device));
+    match path.strip_prefix("/dev") {
+        Ok(rest) => rest.to_string_lossy().replace('/', "_"),
+        Err(_) => path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned()),
+    }
+}
+
+// The pid in a lock file, in ASCII or, from old tools, as a binary int.
+fn owner(path: &Path) -> Option<u32> {
+    let contents = fs::read(path).ok()?;
+    match std::str::from_utf8(&contents).ok().and_then(|text| text.trim().parse().ok()) {
+        Some(pid) => Some(pid),
+        None => Some(u32::from_ne_bytes(contents.get(..4)?.try_into().ok()?)),
+    }
+}
+
+fn alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks that the process exists.
+    pid > 0 && (unsafe { libc::kill(pid, 0) } == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM))
+}
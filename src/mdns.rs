@@ -0,0 +1,68 @@
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use tracing::warn;
+
+pub const DEFAULT_SERVICE: &str = "_rfb-serial._tcp";
+
+// One responder for the whole process, started by the first bridge to
+// advertise itself.
+static DAEMON: Mutex<Option<ServiceDaemon>> = Mutex::new(None);
+
+// A bridge's entry in mDNS, withdrawn when dropped.
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+// Announces `port` under `service` ("_name._tcp") on every interface, with
+// the TXT records given.
+pub fn advertise(service: &str, name: &str, port: u16, properties: &[(&str, String)]) -> Result<Advertisement> {
+    let daemon = {
+        let mut daemon = DAEMON.lock().unwrap();
+        match &*daemon {
+            Some(daemon) => daemon.clone(),
+            None => daemon.insert(ServiceDaemon::new().context("failed to start mDNS responder")?).clone(),
+        }
+    };
+    let host = hostname();
+    let info = ServiceInfo::new(
+        &format!("{}.local.", service),
+        &format!("{} on {}", name, host),
+        &format!("{}.local.", host),
+        "",
+        port,
+        properties,
+    )?
+    .enable_addr_auto();
+    let fullname = info.get_fullname().to_string();
+    daemon.register(info)?;
+    Ok(Advertisement { daemon, fullname })
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        if let Err(e) = self.daemon.unregister(&self.fullname) {
+            warn!("Failed to withdraw mDNS advertisement: {}", e);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return "localhost".to_string();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    let name = String::from_utf8_lossy(&buf[..len]);
+    // Only the first label; ".local." goes after it.
+    name.split('.').next().unwrap_or_default().to_string()
+}
+
+#[cfg(windows)]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string())
+}
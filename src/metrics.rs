@@ -0,0 +1,150 @@
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::net::TcpStream;
+use tracing::{error, info};
+
+use crate::bridge::{self, Bridge, Registry};
+use crate::http;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Binds the metrics endpoint and serves it in the background.
+pub fn spawn(bind: &[IpAddr], port: u16, registry: Arc<Registry>) -> Result<()> {
+    let listeners = bridge::listeners(bind, port, "metrics port")?;
+    info!("Metrics on port {}", port);
+    for listener in listeners {
+        let registry = registry.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((socket, _)) => {
+                        tokio::spawn(handle(socket, registry.clone()));
+                    }
+                    Err(e) => error!("Metrics accept failed: {}", e),
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn handle(mut socket: TcpStream, registry: Arc<Registry>) {
+    let Ok(Ok(request)) = tokio::time::timeout(REQUEST_TIMEOUT, http::read_request(&mut socket)).await else {
+        return;
+    };
+    let _ = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/metrics") => {
+            let body = render(&registry);
+            http::respond(&mut socket, 200, "text/plain; version=0.0.4", body.as_bytes()).await
+        }
+        _ => http::respond(&mut socket, 404, "text/plain", b"not found\n").await,
+    };
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Counter,
+    Gauge,
+}
+
+// A value every bridge has, as served here and pushed by OTLP.
+pub struct Family {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub help: &'static str,
+    pub value: fn(&Bridge) -> u64,
+}
+
+pub const FAMILIES: &[Family] = &[
+    Family {
+        name: "remote_serial_rx_bytes_total",
+        kind: Kind::Counter,
+        help: "Bytes read from the serial port.",
+        value: |b| b.serial.counters().rx_bytes.load(Ordering::Relaxed),
+    },
+    Family {
+        name: "remote_serial_tx_bytes_total",
+        kind: Kind::Counter,
+        help: "Bytes written to the serial port.",
+        value: |b| b.serial.counters().tx_bytes.load(Ordering::Relaxed),
+    },
+    Family {
+        name: "remote_serial_errors_total",
+        kind: Kind::Counter,
+        help: "Failed serial reads, writes and configuration changes.",
+        value: |b| b.serial.counters().errors.load(Ordering::Relaxed),
+    },
+    Family {
+        name: "remote_serial_reopens_total",
+        kind: Kind::Counter,
+        help: "Times the serial port was reopened after the device went away.",
+        value: |b| b.serial.counters().reopens.load(Ordering::Relaxed),
+    },
+    Family {
+        name: "remote_serial_dropped_reads_total",
+        kind: Kind::Counter,
+        help: "Serial reads lost to subscribers that fell behind.",
+        value: |b| b.serial.counters().dropped_reads.load(Ordering::Relaxed),
+    },
+    Family {
+        name: "remote_serial_slow_client_disconnects_total",
+        kind: Kind::Counter,
+        help: "Clients disconnected for falling behind, with backpressure = \"disconnect\".",
+        value: |b| b.serial.counters().slow_disconnects.load(Ordering::Relaxed),
+    },
+    Family {
+        name: "remote_serial_port_up",
+        kind: Kind::Gauge,
+        help: "Whether the serial device is currently open.",
+        value: |b| b.serial.counters().connected.load(Ordering::Relaxed) as u64,
+    },
+    Family {
+        name: "remote_serial_client_connections_total",
+        kind: Kind::Counter,
+        help: "Client sessions admitted since startup.",
+        value: |b| b.sessions.total(),
+    },
+    Family {
+        name: "remote_serial_clients",
+        kind: Kind::Gauge,
+        help: "Currently connected clients.",
+        value: |b| b.sessions.list().len() as u64,
+    },
+];
+
+// Prometheus text exposition format, one series per bridge.
+fn render(registry: &Registry) -> String {
+    let bridges = registry.list();
+    let mut out = String::new();
+    for family in FAMILIES {
+        let kind = match family.kind {
+            Kind::Counter => "counter",
+            Kind::Gauge => "gauge",
+        };
+        let _ = writeln!(out, "# HELP {} {}", family.name, family.help);
+        let _ = writeln!(out, "# TYPE {} {}", family.name, kind);
+        for bridge in &bridges {
+            let _ = writeln!(
+                out,
+                "{}{{bridge=\"{}\",serial_port=\"{}\"}} {}",
+                family.name,
+                escape(&bridge.name),
+                escape(&bridge.config.serial_port),
+                (family.value)(bridge)
+            );
+        }
+    }
+    out
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
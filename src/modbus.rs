@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::Instant;
+use tokio_serial::{DataBits, StopBits};
+use tracing::{debug, warn};
+
+use crate::ParityArg;
+use crate::client::SessionGuard;
+use crate::serial::{Control, PortStatus, SerialHandle};
+
+// Modbus TCP's MBAP header: transaction id, protocol id, length, unit id.
+const MBAP_LEN: usize = 7;
+const MAX_PDU_LEN: usize = 253;
+// Above 19200 baud the spec fixes the inter-frame gap instead.
+const FAST_FRAME_GAP: Duration = Duration::from_micros(1750);
+// When a response's length cannot be told from its function code, it ends
+// after this much silence; USB adapters deliver in bursts, so t3.5 itself
+// would cut frames apart.
+const MIN_SILENCE: Duration = Duration::from_millis(20);
+// Devices need a moment after a broadcast, which they do not answer.
+const BROADCAST_TURNAROUND: Duration = Duration::from_millis(100);
+
+const GATEWAY_PATH_UNAVAILABLE: u8 = 0x0a;
+const GATEWAY_TARGET_FAILED: u8 = 0x0b;
+
+// Translates Modbus TCP requests from clients into Modbus RTU transactions
+// on the serial line, one at a time.
+pub struct Gateway {
+    serial: SerialHandle,
+    // TCP unit ids to RTU addresses; unmapped ids pass through unchanged.
+    unit_map: BTreeMap<u8, u8>,
+    timeout: Duration,
+    // Held for the whole of a transaction, since RTU has no way to tell
+    // interleaved answers apart.
+    bus: Mutex<()>,
+}
+
+impl Gateway {
+    pub fn new(serial: SerialHandle, unit_map: BTreeMap<u8, u8>, timeout: Duration) -> Gateway {
+        Gateway {
+            serial,
+            unit_map,
+            timeout,
+            bus: Mutex::new(()),
+        }
+    }
+
+    // Serves one Modbus TCP client until it disconnects.
+    pub async fn serve<S>(&self, mut stream: S, session: &SessionGuard) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut header = [0u8; MBAP_LEN];
+        let mut pdu = [0u8; MAX_PDU_LEN];
+        loop {
+            match stream.read_exact(&mut header).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+            let protocol = u16::from_be_bytes([header[2], header[3]]);
+            let length = u16::from_be_bytes([header[4], header[5]]) as usize;
+            if protocol != 0 || !(2..=MAX_PDU_LEN + 1).contains(&length) {
+                bail!("not a Modbus TCP request (protocol {}, length {})", protocol, length);
+            }
+            let pdu = &mut pdu[..length - 1];
+            stream.read_exact(pdu).await?;
+            let unit = header[6];
+
+            let response = if !session.can_write() {
+                exception(pdu[0], GATEWAY_PATH_UNAVAILABLE)
+            } else {
+                let address = self.unit_map.get(&unit).copied().unwrap_or(unit);
+                match self.transact(address, pdu).await? {
+                    Some(response) => response,
+                    None => continue,
+                }
+            };
+            let mut reply = Vec::with_capacity(MBAP_LEN + response.len());
+            reply.extend_from_slice(&header[..4]);
+            reply.extend_from_slice(&(response.len() as u16 + 1).to_be_bytes());
+            reply.push(unit);
+            reply.extend_from_slice(&response);
+            stream.write_all(&reply).await?;
+        }
+    }
+
+    // Sends the request to the RTU device at `address` and returns the PDU
+    // it answers with, or an exception if it does not. Broadcasts get no
+    // answer at all.
+    async fn transact(&self, address: u8, pdu: &[u8]) -> Result<Option<Vec<u8>>> {
+        let function = pdu[0];
+        let _bus = self.bus.lock().await;
+        let frame_gap = frame_gap(&self.serial.control(Control::Status).await?);
+        // Everything after this point is a reply to this request.
+        let mut output = self.serial.subscribe();
+        tokio::time::sleep(frame_gap).await;
+
+        let mut frame = Vec::with_capacity(pdu.len() + 3);
+        frame.push(address);
+        frame.extend_from_slice(pdu);
+        frame.extend_from_slice(&crc(&frame).to_le_bytes());
+        self.serial.write(Bytes::from(frame)).await?;
+        if address == 0 {
+            tokio::time::sleep(BROADCAST_TURNAROUND).await;
+            return Ok(None);
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        let silence = frame_gap.max(MIN_SILENCE);
+        let mut response = Vec::new();
+        loop {
+            if expected_len(&response).is_some_and(|len| response.len() >= len) {
+                break;
+            }
+            // Unknown functions end with the line going quiet.
+            let until = match response.len() < 3 || expected_len(&response).is_some() {
+                true => deadline,
+                false => deadline.min(Instant::now() + silence),
+            };
+            match tokio::time::timeout_at(until, output.recv()).await {
+                Ok(Ok(data)) => response.extend_from_slice(&data),
+                Ok(Err(RecvError::Lagged(n))) => {
+                    warn!("Lost part of a Modbus response");
+                    self.serial.dropped(n);
+                    return Ok(Some(exception(function, GATEWAY_TARGET_FAILED)));
+                }
+                Ok(Err(RecvError::Closed)) => bail!("serial port task has stopped"),
+                Err(_) if response.is_empty() || Instant::now() >= deadline => {
+                    debug!("No answer from Modbus unit {}", address);
+                    return Ok(Some(exception(function, GATEWAY_TARGET_FAILED)));
+                }
+                Err(_) => break,
+            }
+        }
+        let len = expected_len(&response).unwrap_or(response.len()).min(response.len());
+        let response = &response[..len];
+        if response.len() < 4 || crc(&response[..len - 2]).to_le_bytes() != response[len - 2..] {
+            warn!("Dropping Modbus response with a bad CRC from unit {}", address);
+            return Ok(Some(exception(function, GATEWAY_TARGET_FAILED)));
+        }
+        if response[0] != address {
+            warn!("Modbus response from unit {} instead of {}", response[0], address);
+            return Ok(Some(exception(function, GATEWAY_TARGET_FAILED)));
+        }
+        Ok(Some(response[1..len - 2].to_vec()))
+    }
+}
+
+// The silence of 3.5 characters that separates RTU frames, at the port's
+// current settings.
+fn frame_gap(status: &PortStatus) -> Duration {
+    if status.baud_rate == 0 || status.baud_rate > 19200 {
+        return FAST_FRAME_GAP;
+    }
+    let data_bits = match status.data_bits {
+        DataBits::Five => 5,
+        DataBits::Six => 6,
+        DataBits::Seven => 7,
+        DataBits::Eight => 8,
+    };
+    let parity_bits = if status.parity == ParityArg::None { 0 } else { 1 };
+    let stop_bits = if status.stop_bits == StopBits::Two { 2 } else { 1 };
+    let bits: u64 = 1 + data_bits + parity_bits + stop_bits;
+    Duration::from_micros(bits * 3_500_000 / status.baud_rate as u64)
+}
+
+// The full length of an RTU response frame, once enough of it has arrived
+// to tell; None for functions whose replies are not self-describing.
+fn expected_len(frame: &[u8]) -> Option<usize> {
+    let function = *frame.get(1)?;
+    match function {
+        f if f & 0x80 != 0 => Some(5),
+        // Read coils, discrete inputs, holding and input registers.
+        0x01..=0x04 => frame.get(2).map(|&count| 5 + count as usize),
+        // Write single coil/register, write multiple coils/registers.
+        0x05 | 0x06 | 0x0f | 0x10 => Some(8),
+        _ => None,
+    }
+}
+
+fn exception(function: u8, code: u8) -> Vec<u8> {
+    vec![function | 0x80, code]
+}
+
+// CRC-16/MODBUS, sent low byte first.
+fn crc(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xa001 } else { crc >> 1 };
+        }
+    }
+    crc
+}
@@ -0,0 +1,95 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS, Transport};
+use serde_json::Value;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, info, warn};
+
+use crate::parse::{LineParser, Lines};
+use crate::serial::SerialHandle;
+use crate::tls;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+// Requests buffered while the broker is unreachable.
+const QUEUE_CAPACITY: usize = 64;
+
+// Where and how a bridge talks to an MQTT broker.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    // Serial output is published here...
+    pub rx_topic: String,
+    // ...and messages on this topic are written to the port.
+    pub tx_topic: String,
+    pub qos: QoS,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls: bool,
+    // Verify the broker against this CA instead of the system's roots.
+    pub ca: Option<PathBuf>,
+}
+
+// Relays between the serial port and the broker, reconnecting whenever the
+// broker goes away. With a parser, what is published is a JSON object per
+// line that parses rather than the output as read. Only ends if the serial
+// port task stops.
+pub async fn serve(config: MqttConfig, parser: Option<Arc<LineParser>>, serial: SerialHandle) -> Result<()> {
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(KEEP_ALIVE);
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+    if config.tls {
+        let client_config = tls::client_config(config.ca.as_deref())?;
+        options.set_transport(Transport::tls_with_config(client_config.into()));
+    }
+    let (client, mut events) = AsyncClient::new(options, QUEUE_CAPACITY);
+    let mut output = serial.subscribe();
+    let mut lines = Lines::default();
+    loop {
+        tokio::select! {
+            event = events.poll() => match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Connected to MQTT broker {}:{}", config.host, config.port);
+                    // Sessions are clean, so every connection subscribes afresh.
+                    client.try_subscribe(&config.tx_topic, config.qos)?;
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => serial.write(publish.payload).await?,
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("MQTT broker {}:{}: {}; retrying in {:?}", config.host, config.port, e, RETRY_DELAY);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            },
+            received = output.recv() => match received {
+                Ok(data) => match &parser {
+                    Some(parser) => lines.push(&data, |line| match parser.parse(line) {
+                        Some(object) => {
+                            let payload = Value::Object(object).to_string();
+                            if let Err(e) = client.try_publish(&config.rx_topic, config.qos, false, payload) {
+                                debug!("Dropping parsed line for MQTT: {}", e);
+                            }
+                        }
+                        None => debug!("Not publishing unparsed line: {}", line),
+                    }),
+                    None => {
+                        if let Err(e) = client.try_publish(&config.rx_topic, config.qos, false, data) {
+                            debug!("Dropping serial output for MQTT: {}", e);
+                        }
+                    }
+                },
+                Err(RecvError::Lagged(n)) => {
+                    debug!("MQTT fell behind, {} serial reads dropped", n);
+                    serial.dropped(n);
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+}
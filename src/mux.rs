@@ -0,0 +1,325 @@
+// Several bridges over one TCP or TLS connection, for gateways behind
+// firewalls that let a single port through. Everything travels in frames:
+// a channel number and a payload length, both big-endian u16s, then the
+// payload. Channel 0 carries control lines, each answered in turn with
+// "OK ..." or "ERR <reason>":
+//
+//   > list                    < OK ttyUSB0 ttyUSB1
+//   > open ttyUSB0            < OK 1
+//   > 1 baud 115200           < OK baud=115200 data-bits=8 parity=none ...
+//   > close 1                 < OK
+//
+// An open channel is a data session on its bridge, in the bridge's mode and
+// under its ACL, connection limits, sharing policy and authentication: a
+// token goes over the channel as it would over a connection of its own.
+// Bridges that require client certificates are not opened, since the mux
+// port does not ask for one. "closed N" follows on
+// channel 0 whenever a session ends, whoever ended it. Port commands, those
+// of control_port, are only taken for bridges without authentication, which
+// cannot be given on channel 0.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio_rustls::TlsAcceptor;
+use tracing::{Instrument, error, field, info, info_span, warn};
+
+use crate::bridge::{self, Bridge, Registry};
+use crate::client::Peer;
+use crate::control;
+
+const CONTROL: u16 = 0;
+const COMMANDS: &str = "list, open <bridge>, close <channel> or <channel> <port command>";
+const MAX_LINE: usize = 256;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Bytes in flight between a channel and its session, each way.
+const CHANNEL_BUFFER: usize = 64 * 1024;
+// Payloads waiting for a session that is not taking its input, beyond the
+// buffer; a channel that falls further behind is closed, so that it does not
+// hold up the others.
+const CHANNEL_QUEUE: usize = 16;
+
+// An open channel: where its payloads go, and the bridge it is a session on.
+// Dropping it ends the session's input, at once.
+struct Channel {
+    input: mpsc::Sender<Bytes>,
+    _closed: oneshot::Sender<()>,
+    bridge: Arc<Bridge>,
+}
+
+// Binds the mux port and serves it in the background.
+pub fn spawn(bind: &[IpAddr], port: u16, tls: Option<TlsAcceptor>, registry: Arc<Registry>) -> Result<()> {
+    let listeners = bridge::listeners(bind, port, "mux port")?;
+    info!("Multiplexed bridges on port {}{}", port, if tls.is_some() { " (TLS)" } else { "" });
+    for listener in listeners {
+        let (tls, registry) = (tls.clone(), registry.clone());
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((socket, addr)) => {
+                        let connection = connect(socket, addr, tls.clone(), registry.clone());
+                        tokio::spawn(connection.instrument(info_span!("mux", peer = %addr)));
+                    }
+                    Err(e) => error!("Mux accept failed: {}", e),
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn connect(socket: TcpStream, addr: SocketAddr, tls: Option<TlsAcceptor>, registry: Arc<Registry>) {
+    let served = match tls {
+        None => serve(socket, addr, registry).await,
+        Some(acceptor) => match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+            Ok(Ok(stream)) => serve(stream, addr, registry).await,
+            Ok(Err(e)) => Err(anyhow!("TLS handshake failed: {}", e)),
+            Err(_) => Err(anyhow!("TLS handshake timed out")),
+        },
+    };
+    if let Err(e) = served {
+        warn!("Mux client error: {:#}", e);
+    }
+}
+
+async fn serve<S>(stream: S, addr: SocketAddr, registry: Arc<Registry>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    info!("Mux client connected");
+    let (mut reader, writer) = tokio::io::split(stream);
+    let (frames, outgoing) = mpsc::channel(64);
+    let writing = tokio::spawn(write_frames(writer, outgoing));
+    let mut channels: HashMap<u16, Channel> = HashMap::new();
+    let mut last = CONTROL;
+    let mut line = Vec::new();
+    let served = loop {
+        let (channel, payload) = match read_frame(&mut reader).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        };
+        if channel != CONTROL {
+            // Payloads for a channel that is not open, or whose session has
+            // ended, are dropped.
+            let Some(open) = channels.get(&channel) else {
+                continue;
+            };
+            match open.input.try_send(Bytes::from(payload)) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    warn!("Closing channel {}: its session is not taking input", channel);
+                    channels.remove(&channel);
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => {
+                    channels.remove(&channel);
+                }
+            }
+            continue;
+        }
+        for byte in payload {
+            if byte != b'\n' {
+                line.push(byte);
+                continue;
+            }
+            let text = String::from_utf8_lossy(&line).into_owned();
+            line.clear();
+            let words: Vec<&str> = text.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let reply = match command(&words, &mut channels, &mut last, addr, &registry, &frames).await {
+                Ok(reply) => reply,
+                Err(e) => format!("ERR {}\n", e),
+            };
+            let _ = frames.send((CONTROL, Bytes::from(reply))).await;
+        }
+        if line.len() > MAX_LINE {
+            line.clear();
+            let _ = frames.send((CONTROL, Bytes::from_static(b"ERR line too long\n"))).await;
+        }
+    };
+    // Ending the channels' input ends their sessions, whose relays then let
+    // the writer finish.
+    drop(channels);
+    drop(frames);
+    let _ = writing.await;
+    info!("Mux client disconnected");
+    served
+}
+
+async fn command(
+    words: &[&str],
+    channels: &mut HashMap<u16, Channel>,
+    last: &mut u16,
+    addr: SocketAddr,
+    registry: &Registry,
+    frames: &mpsc::Sender<(u16, Bytes)>,
+) -> Result<String> {
+    match words {
+        ["list"] => {
+            let mut reply = "OK".to_string();
+            for bridge in registry.list() {
+                reply.push(' ');
+                reply.push_str(&bridge.name);
+            }
+            reply.push('\n');
+            Ok(reply)
+        }
+        ["open", name] => {
+            let bridge = registry.get(name).with_context(|| format!("no bridge named '{}'", name))?;
+            let refusal = if !bridge.admits(addr.ip()) {
+                Some("address not allowed")
+            } else if bridge.config.tls_client_ca.is_some() {
+                Some("the bridge requires a client certificate")
+            } else {
+                None
+            };
+            if let Some(refusal) = refusal {
+                info!("Refusing channel to {}: {}", name, refusal);
+                bail!(refusal);
+            }
+            let channel = (1..=u16::MAX)
+                .map(|n| last.wrapping_add(n))
+                .find(|channel| *channel != CONTROL && !channels.contains_key(channel))
+                .context("no free channels")?;
+            let permit = bridge.limit(addr.ip()).map_err(|refusal| {
+                info!("Refusing channel to {}: {}", name, refusal);
+                anyhow!(refusal)
+            })?;
+            *last = channel;
+            let (ours, theirs) = tokio::io::duplex(CHANNEL_BUFFER);
+            let (output, input) = tokio::io::split(ours);
+            let bridge_span = info_span!(parent: None, "bridge", name = %bridge.name);
+            let span = info_span!(parent: &bridge_span, "client", peer = %addr, identity = field::Empty);
+            let peer = Peer {
+                addr: addr.to_string(),
+                identity: None,
+                read_only: false,
+            };
+            let session = {
+                let bridge = bridge.clone();
+                async move {
+                    // Held until the session ends.
+                    let _permit = permit;
+                    bridge.serve_data(theirs, peer).await
+                }
+            };
+            tokio::spawn(session.instrument(span));
+            let (queue, queued) = mpsc::channel(CHANNEL_QUEUE);
+            let (closed, on_close) = oneshot::channel();
+            tokio::spawn(feed(input, queued, on_close));
+            tokio::spawn(relay(channel, output, frames.clone()).in_current_span());
+            channels.insert(
+                channel,
+                Channel {
+                    input: queue,
+                    _closed: closed,
+                    bridge,
+                },
+            );
+            info!("Opened channel {} to {}", channel, name);
+            Ok(format!("OK {}\n", channel))
+        }
+        ["close", channel] => {
+            let channel = number(channel)?;
+            // The session ends on the end of its input, and the relay then
+            // reports it.
+            channels.remove(&channel).with_context(|| format!("channel {} is not open", channel))?;
+            Ok("OK\n".to_string())
+        }
+        [channel, words @ ..] if channel.parse::<u16>().is_ok() => {
+            let channel = number(channel)?;
+            let open = channels.get(&channel).with_context(|| format!("channel {} is not open", channel))?;
+            if !open.bridge.open_control() {
+                bail!("{} requires authentication, so its port is not controlled from here", open.bridge.name);
+            }
+            Ok(control::answer(words, &open.bridge.serial).await)
+        }
+        _ => bail!("unknown command '{}', try {}", words.join(" "), COMMANDS),
+    }
+}
+
+fn number(word: &str) -> Result<u16> {
+    word.parse().ok().filter(|channel| *channel != CONTROL).with_context(|| format!("invalid channel '{}'", word))
+}
+
+// Writes a channel's payloads to its session until the session stops taking
+// them or the channel is closed, even mid-write, then ends its input.
+async fn feed(
+    mut input: WriteHalf<DuplexStream>,
+    mut queued: mpsc::Receiver<Bytes>,
+    mut closed: oneshot::Receiver<()>,
+) {
+    loop {
+        let payload = tokio::select! {
+            _ = &mut closed => break,
+            payload = queued.recv() => payload,
+        };
+        let Some(payload) = payload else {
+            break;
+        };
+        tokio::select! {
+            _ = &mut closed => break,
+            written = input.write_all(&payload) => {
+                if written.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    let _ = input.shutdown().await;
+}
+
+// Frames what the session on `channel` sends, then reports its end.
+async fn relay(channel: u16, mut output: ReadHalf<DuplexStream>, frames: mpsc::Sender<(u16, Bytes)>) {
+    let mut buf = vec![0u8; u16::MAX as usize];
+    loop {
+        match output.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => {
+                if frames.send((channel, Bytes::copy_from_slice(&buf[..n]))).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+    info!("Channel {} closed", channel);
+    let _ = frames.send((CONTROL, Bytes::from(format!("closed {}\n", channel)))).await;
+}
+
+// None at a clean end of the connection, between frames.
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<(u16, Vec<u8>)>> {
+    let mut header = [0u8; 4];
+    match reader.read_exact(&mut header[..1]).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    reader.read_exact(&mut header[1..]).await.context("connection ended mid-frame")?;
+    let channel = u16::from_be_bytes([header[0], header[1]]);
+    let mut payload = vec![0u8; u16::from_be_bytes([header[2], header[3]]) as usize];
+    reader.read_exact(&mut payload).await.context("connection ended mid-frame")?;
+    Ok(Some((channel, payload)))
+}
+
+async fn write_frames<W: AsyncWrite + Unpin>(mut writer: W, mut frames: mpsc::Receiver<(u16, Bytes)>) {
+    while let Some((channel, payload)) = frames.recv().await {
+        for chunk in payload.chunks(u16::MAX as usize) {
+            let mut frame = Vec::with_capacity(4 + chunk.len());
+            frame.extend(channel.to_be_bytes());
+            frame.extend((chunk.len() as u16).to_be_bytes());
+            frame.extend(chunk);
+            if writer.write_all(&frame).await.is_err() {
+                return;
+            }
+        }
+    }
+}
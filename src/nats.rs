@@ -0,0 +1,315 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use bytes::Bytes;
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tracing::{debug, info, warn};
+
+use crate::local::Stream;
+use crate::serial::SerialHandle;
+use crate::tls;
+
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// Longer protocol lines are not NATS...
+const MAX_LINE: usize = 4096;
+// ...and longer messages are not for a serial port.
+const MAX_PAYLOAD: usize = 1024 * 1024;
+// Subscription ids: the tx subject, and the inbox JetStream answers to.
+const TX: &str = "1";
+const INBOX: &str = "2";
+
+// Where and how a bridge talks to a NATS server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NatsConfig {
+    pub host: String,
+    pub port: u16,
+    pub name: String,
+    // Serial output is published here...
+    pub rx_subject: String,
+    // ...and messages on this subject are written to the port.
+    pub tx_subject: String,
+    pub token: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls: bool,
+    // Verify the server against this CA instead of the system's roots.
+    pub ca: Option<PathBuf>,
+    // Store serial output in this JetStream stream, created to capture
+    // rx_subject if it does not exist, and have each message acknowledged.
+    pub jetstream: Option<String>,
+}
+
+// Relays between the serial port and the server, reconnecting whenever the
+// server goes away; output read meanwhile is not published. Only ends if the
+// serial port task stops.
+pub async fn serve(config: NatsConfig, serial: SerialHandle) -> Result<()> {
+    let connector = match config.tls {
+        true => Some(TlsConnector::from(Arc::new(tls::client_config(config.ca.as_deref())?))),
+        false => None,
+    };
+    let inbox = inbox();
+    loop {
+        let relayed = async {
+            let mut connection = connect(&config, connector.as_ref()).await?;
+            if let Some(stream) = &config.jetstream {
+                ensure_stream(&mut connection, stream, &config.rx_subject, &inbox).await?;
+            }
+            connection
+                .get_mut()
+                .write_all(format!("SUB {} {}\r\n", config.tx_subject, TX).as_bytes())
+                .await?;
+            info!("Connected to NATS server {}:{}", config.host, config.port);
+            let (reader, writer) = tokio::io::split(connection);
+            let (frames, outgoing) = mpsc::channel(64);
+            let reply_to = config.jetstream.is_some().then_some(inbox.as_str());
+            tokio::select! {
+                published = publish(&frames, &config.rx_subject, reply_to, &serial) => published,
+                received = receive(BufReader::new(reader), &frames, &serial) => received,
+                written = write(writer, outgoing) => written,
+            }
+        };
+        match relayed.await {
+            Ok(()) => return Ok(()),
+            Err(e) => warn!("NATS server {}:{}: {:#}; retrying in {:?}", config.host, config.port, e, RETRY_DELAY),
+        }
+        tokio::time::sleep(RETRY_DELAY).await;
+    }
+}
+
+// Connects, upgrading to TLS where the server's INFO allows, and logs in.
+async fn connect(config: &NatsConfig, connector: Option<&TlsConnector>) -> Result<BufReader<Box<dyn Stream>>> {
+    let socket = TcpStream::connect((config.host.as_str(), config.port))
+        .await
+        .context("failed to connect")?;
+    let mut socket = BufReader::new(socket);
+    let Op::Info(info) = op(&mut socket).await? else {
+        bail!("expected INFO from the server");
+    };
+    let info: Value = serde_json::from_str(&info).context("malformed INFO from the server")?;
+    let tls_required = info["tls_required"].as_bool().unwrap_or(false);
+    let stream: Box<dyn Stream> = match connector {
+        None if tls_required => bail!("the server requires TLS; set nats_tls"),
+        None => Box::new(socket),
+        Some(connector) => {
+            let name = ServerName::try_from(config.host.clone())
+                .with_context(|| format!("invalid host name {}", config.host))?;
+            Box::new(
+                connector
+                    .connect(name, socket.into_inner())
+                    .await
+                    .context("TLS handshake failed")?,
+            )
+        }
+    };
+    let mut connection = BufReader::new(stream);
+    let mut options = json!({
+        "verbose": false,
+        "pedantic": false,
+        "tls_required": config.tls,
+        "name": config.name,
+        "lang": "rust",
+        "version": env!("CARGO_PKG_VERSION"),
+        "protocol": 1,
+    });
+    if let Some(token) = &config.token {
+        options["auth_token"] = json!(token);
+    }
+    if let Some(username) = &config.username {
+        options["user"] = json!(username);
+        options["pass"] = json!(config.password);
+    }
+    let hello = format!("CONNECT {}\r\nPING\r\n", options);
+    connection.get_mut().write_all(hello.as_bytes()).await?;
+    // Refusals come as -ERR, which op() fails on.
+    loop {
+        match op(&mut connection).await? {
+            Op::Pong => return Ok(connection),
+            Op::Ping => connection.get_mut().write_all(b"PONG\r\n").await?,
+            _ => {}
+        }
+    }
+}
+
+// Makes sure there is a stream to store the rx subject in, before anything
+// is published to it.
+async fn ensure_stream<S>(connection: &mut BufReader<S>, stream: &str, subject: &str, inbox: &str) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    connection.get_mut().write_all(format!("SUB {}.* {}\r\n", inbox, INBOX).as_bytes()).await?;
+    let info = request(connection, &format!("$JS.API.STREAM.INFO.{}", stream), b"", inbox).await?;
+    match info["error"]["code"].as_u64() {
+        None => return Ok(()),
+        Some(404) => {}
+        Some(_) => bail!("JetStream: {}", description(&info)),
+    }
+    let config = json!({ "name": stream, "subjects": [subject] }).to_string();
+    let created =
+        request(connection, &format!("$JS.API.STREAM.CREATE.{}", stream), config.as_bytes(), inbox).await?;
+    if !created["error"].is_null() {
+        bail!("failed to create JetStream stream {}: {}", stream, description(&created));
+    }
+    info!("Created JetStream stream {} for {}", stream, subject);
+    Ok(())
+}
+
+// Asks the JetStream API and waits for its answer.
+async fn request<S>(connection: &mut BufReader<S>, subject: &str, payload: &[u8], inbox: &str) -> Result<Value>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let reply_to = format!("{}.api", inbox);
+    let mut frame = format!("PUB {} {} {}\r\n", subject, reply_to, payload.len()).into_bytes();
+    frame.extend_from_slice(payload);
+    frame.extend_from_slice(b"\r\n");
+    connection.get_mut().write_all(&frame).await?;
+    let answer = async {
+        loop {
+            match op(connection).await? {
+                Op::Msg { subject, payload, .. } if subject == reply_to => return Ok::<_, anyhow::Error>(payload),
+                Op::Ping => connection.get_mut().write_all(b"PONG\r\n").await?,
+                _ => {}
+            }
+        }
+    };
+    let payload = tokio::time::timeout(REQUEST_TIMEOUT, answer)
+        .await
+        .context("JetStream did not answer; is it enabled on the server?")??;
+    serde_json::from_slice(&payload).context("malformed JetStream answer")
+}
+
+fn description(answer: &Value) -> &str {
+    answer["error"]["description"].as_str().unwrap_or("unknown error")
+}
+
+// Publishes the port's output until the port's task stops, which is the
+// only way it returns Ok. With JetStream, each message asks for an
+// acknowledgement on the inbox.
+async fn publish(
+    frames: &mpsc::Sender<Vec<u8>>,
+    subject: &str,
+    reply_to: Option<&str>,
+    serial: &SerialHandle,
+) -> Result<()> {
+    let mut output = serial.subscribe();
+    let mut sequence = 0u64;
+    loop {
+        let data = match output.recv().await {
+            Ok(data) => data,
+            Err(RecvError::Lagged(n)) => {
+                debug!("NATS fell behind, {} serial reads dropped", n);
+                serial.dropped(n);
+                continue;
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        let mut frame = match reply_to {
+            Some(inbox) => {
+                sequence += 1;
+                format!("PUB {} {}.{} {}\r\n", subject, inbox, sequence, data.len())
+            }
+            None => format!("PUB {} {}\r\n", subject, data.len()),
+        }
+        .into_bytes();
+        frame.extend_from_slice(&data);
+        frame.extend_from_slice(b"\r\n");
+        frames.send(frame).await.context("connection closed")?;
+    }
+}
+
+// Writes messages on the tx subject to the port, and checks JetStream's
+// acknowledgements, until the port's task stops.
+async fn receive<R>(mut reader: BufReader<R>, frames: &mpsc::Sender<Vec<u8>>, serial: &SerialHandle) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    loop {
+        match op(&mut reader).await? {
+            Op::Msg { sid, payload, .. } if sid == TX => {
+                // Failing only once the port's task has stopped.
+                let Ok(()) = serial.write(Bytes::from(payload)).await else {
+                    return Ok(());
+                };
+            }
+            Op::Msg { sid, payload, .. } if sid == INBOX => {
+                let ack: Value = serde_json::from_slice(&payload).unwrap_or_default();
+                if !ack["error"].is_null() {
+                    warn!("JetStream did not store serial output: {}", description(&ack));
+                }
+            }
+            Op::Ping => frames.send(b"PONG\r\n".to_vec()).await.context("connection closed")?,
+            _ => {}
+        }
+    }
+}
+
+async fn write<W: AsyncWrite + Unpin>(mut writer: W, mut frames: mpsc::Receiver<Vec<u8>>) -> Result<()> {
+    while let Some(frame) = frames.recv().await {
+        writer.write_all(&frame).await?;
+    }
+    Ok(())
+}
+
+enum Op {
+    Info(String),
+    Msg { subject: String, sid: String, payload: Vec<u8> },
+    Ping,
+    Pong,
+    Other,
+}
+
+// Reads one operation from the server; -ERR is an error.
+async fn op<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> Result<Op> {
+    let mut line = Vec::new();
+    if (&mut *reader).take(MAX_LINE as u64).read_until(b'\n', &mut line).await? == 0 {
+        bail!("connection closed");
+    }
+    if !line.ends_with(b"\r\n") {
+        bail!("malformed line from the server");
+    }
+    let line = String::from_utf8_lossy(&line[..line.len() - 2]).into_owned();
+    let (verb, rest) = line.split_once(' ').unwrap_or((&line, ""));
+    match verb.to_ascii_uppercase().as_str() {
+        "INFO" => Ok(Op::Info(rest.to_string())),
+        "PING" => Ok(Op::Ping),
+        "PONG" => Ok(Op::Pong),
+        "-ERR" => bail!("server error: {}", rest.trim_matches('\'')),
+        // MSG <subject> <sid> [reply-to] <#bytes>
+        "MSG" => {
+            let words: Vec<&str> = rest.split_whitespace().collect();
+            let (subject, sid, length) = match words.as_slice() {
+                [subject, sid, length] | [subject, sid, _, length] => (subject, sid, length),
+                _ => bail!("malformed MSG from the server"),
+            };
+            let length: usize = length.parse().context("malformed MSG from the server")?;
+            if length > MAX_PAYLOAD {
+                bail!("message too long");
+            }
+            let mut payload = vec![0u8; length + 2];
+            reader.read_exact(&mut payload).await?;
+            payload.truncate(length);
+            Ok(Op::Msg {
+                subject: subject.to_string(),
+                sid: sid.to_string(),
+                payload,
+            })
+        }
+        _ => Ok(Op::Other),
+    }
+}
+
+// A reply subject unique to this process and moment; nothing needs it to be
+// hard to guess.
+fn inbox() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    format!("_INBOX.{:x}{:x}", std::process::id(), nanos)
+}
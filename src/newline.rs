@@ -0,0 +1,32 @@
+use crate::LineEnding;
+
+// Rewrites every line ending, be it CR, LF or CRLF, as the one chosen. A
+// CRLF split between two chunks still counts as one ending.
+pub struct Newlines {
+    ending: &'static [u8],
+    after_cr: bool,
+}
+
+impl Newlines {
+    pub fn new(ending: LineEnding) -> Newlines {
+        let ending: &[u8] = match ending {
+            LineEnding::Cr => b"\r",
+            LineEnding::Lf => b"\n",
+            LineEnding::Crlf => b"\r\n",
+        };
+        Newlines { ending, after_cr: false }
+    }
+
+    pub fn translate(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len() + data.len() / 8);
+        for &byte in data {
+            match byte {
+                b'\n' if self.after_cr => {}
+                b'\r' | b'\n' => out.extend_from_slice(self.ending),
+                _ => out.push(byte),
+            }
+            self.after_cr = byte == b'\r';
+        }
+        out
+    }
+}
@@ -0,0 +1,84 @@
+use tracing::debug;
+
+// Longer than the 82 characters NMEA 0183 allows, for receivers that
+// overstep it; anything longer is line noise.
+const MAX_SENTENCE: usize = 256;
+
+// Cuts serial output into whole NMEA 0183 sentences, so every chunk a client
+// receives begins and ends on a sentence boundary.
+pub struct Framer {
+    partial: Vec<u8>,
+    // Sentence types ("RMC") or talker and type ("GPRMC") to pass; empty
+    // passes everything.
+    filter: Vec<String>,
+}
+
+impl Framer {
+    pub fn new(filter: Vec<String>) -> Framer {
+        Framer {
+            partial: Vec::new(),
+            filter,
+        }
+    }
+
+    // Returns the sentences `data` completes, each ending in CRLF. Sentences
+    // with a wrong checksum or that the filter rejects are dropped.
+    pub fn push(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &byte in data {
+            match byte {
+                // '!' starts AIS and other encapsulated sentences.
+                b'$' | b'!' => {
+                    self.partial.clear();
+                    self.partial.push(byte);
+                }
+                b'\r' | b'\n' => {
+                    if !self.partial.is_empty() {
+                        self.finish(&mut out);
+                    }
+                }
+                _ if self.partial.is_empty() => {}
+                _ if self.partial.len() >= MAX_SENTENCE => self.partial.clear(),
+                _ => self.partial.push(byte),
+            }
+        }
+        out
+    }
+
+    fn finish(&mut self, out: &mut Vec<u8>) {
+        let sentence = std::mem::take(&mut self.partial);
+        if !valid(&sentence) {
+            debug!("Dropping NMEA sentence with a bad checksum: {}", String::from_utf8_lossy(&sentence));
+            return;
+        }
+        if self.passes(&sentence) {
+            out.extend_from_slice(&sentence);
+            out.extend_from_slice(b"\r\n");
+        }
+    }
+
+    fn passes(&self, sentence: &[u8]) -> bool {
+        if self.filter.is_empty() {
+            return true;
+        }
+        let end = sentence.iter().position(|&b| b == b',' || b == b'*').unwrap_or(sentence.len());
+        let address = String::from_utf8_lossy(&sentence[1..end]);
+        self.filter.iter().any(|wanted| match wanted.len() {
+            3 => address.len() == 5 && address.ends_with(wanted.as_str()),
+            _ => address == wanted.as_str(),
+        })
+    }
+}
+
+// The checksum is optional in NMEA 0183; when present it is the XOR of
+// everything between the start character and '*', in two hex digits.
+fn valid(sentence: &[u8]) -> bool {
+    let Some(star) = sentence.iter().position(|&b| b == b'*') else {
+        return true;
+    };
+    let sum = sentence[1..star].iter().fold(0u8, |sum, &b| sum ^ b);
+    std::str::from_utf8(&sentence[star + 1..])
+        .ok()
+        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        == Some(sum)
+}
@@ -0,0 +1,206 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll, ready};
+
+use anyhow::{Context, Result, bail};
+use snow::{Builder, HandshakeState, TransportState};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+// Both sides know the key and nothing else, so neither has a static key to
+// prove; the handshake is one round trip.
+const PATTERN: &str = "Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s";
+pub const KEY_LEN: usize = 32;
+// Every message goes out behind a two-byte big-endian length, as the Noise
+// spec suggests for streams.
+const MAX_MESSAGE: usize = 65535;
+const TAG_LEN: usize = 16;
+const MAX_PAYLOAD: usize = MAX_MESSAGE - TAG_LEN;
+
+pub type Key = [u8; KEY_LEN];
+
+// A pre-shared key given as 64 hex digits.
+pub fn parse_key(text: &str) -> Result<Key> {
+    let text = text.trim();
+    if text.len() != KEY_LEN * 2 || !text.is_ascii() {
+        bail!("noise key must be {} hex digits", KEY_LEN * 2);
+    }
+    let mut key = [0u8; KEY_LEN];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).context("noise key must be hex")?;
+    }
+    Ok(key)
+}
+
+fn builder(key: &Key) -> Result<Builder<'_>> {
+    Ok(Builder::new(PATTERN.parse()?).psk(0, key)?)
+}
+
+// Answers a client's handshake. A client with the wrong key fails here,
+// before anything reaches the port.
+pub async fn accept<S>(mut stream: S, key: &Key) -> Result<NoiseStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut handshake = builder(key)?.build_responder()?;
+    let message = read_message(&mut stream).await?;
+    handshake
+        .read_message(&message, &mut vec![0u8; MAX_MESSAGE])
+        .context("noise handshake failed, wrong key?")?;
+    write_handshake(&mut stream, &mut handshake).await?;
+    Ok(NoiseStream::new(stream, handshake.into_transport_mode()?))
+}
+
+pub async fn connect<S>(mut stream: S, key: &Key) -> Result<NoiseStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut handshake = builder(key)?.build_initiator()?;
+    write_handshake(&mut stream, &mut handshake).await?;
+    let message = read_message(&mut stream).await?;
+    handshake
+        .read_message(&message, &mut vec![0u8; MAX_MESSAGE])
+        .context("noise handshake failed, wrong key?")?;
+    Ok(NoiseStream::new(stream, handshake.into_transport_mode()?))
+}
+
+async fn write_handshake<S>(stream: &mut S, handshake: &mut HandshakeState) -> Result<()>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut message = vec![0u8; MAX_MESSAGE];
+    let n = handshake.write_message(&[], &mut message)?;
+    stream.write_all(&(n as u16).to_be_bytes()).await?;
+    stream.write_all(&message[..n]).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_message<S>(stream: &mut S) -> Result<Vec<u8>>
+where
+    S: AsyncRead + Unpin,
+{
+    let len = stream.read_u16().await.context("connection closed during noise handshake")?;
+    let mut message = vec![0u8; len as usize];
+    stream.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+// An encrypted stream after the handshake. Writes are sealed into one
+// message each, up to MAX_PAYLOAD bytes at a time.
+pub struct NoiseStream<S> {
+    inner: S,
+    state: TransportState,
+    // Received bytes not yet making up a whole message.
+    received: Vec<u8>,
+    // Decrypted bytes not yet read, from `offset`.
+    plain: Vec<u8>,
+    offset: usize,
+    // A sealed message not yet fully written.
+    sending: Vec<u8>,
+}
+
+impl<S> NoiseStream<S> {
+    fn new(inner: S, state: TransportState) -> Self {
+        NoiseStream {
+            inner,
+            state,
+            received: Vec::new(),
+            plain: Vec::new(),
+            offset: 0,
+            sending: Vec::new(),
+        }
+    }
+
+    // Decrypts the first whole message in `received`, if there is one.
+    fn open(&mut self) -> io::Result<bool> {
+        let Some(len) = self.received.get(..2).map(|len| u16::from_be_bytes([len[0], len[1]]) as usize) else {
+            return Ok(false);
+        };
+        if self.received.len() < 2 + len {
+            return Ok(false);
+        }
+        self.plain.resize(len, 0);
+        let n = self
+            .state
+            .read_message(&self.received[2..2 + len], &mut self.plain)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.plain.truncate(n);
+        self.offset = 0;
+        self.received.drain(..2 + len);
+        Ok(true)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> NoiseStream<S> {
+    fn poll_send(&mut self, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        while !self.sending.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.sending))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.sending.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for NoiseStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.offset < this.plain.len() {
+                let n = buf.remaining().min(this.plain.len() - this.offset);
+                buf.put_slice(&this.plain[this.offset..this.offset + n]);
+                this.offset += n;
+                return Poll::Ready(Ok(()));
+            }
+            if this.open()? {
+                continue;
+            }
+            let mut chunk = [0u8; 4096];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            if chunk.filled().is_empty() {
+                return match this.received.is_empty() {
+                    true => Poll::Ready(Ok(())),
+                    false => Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+                };
+            }
+            this.received.extend_from_slice(chunk.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for NoiseStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        let n = buf.len().min(MAX_PAYLOAD);
+        let mut message = vec![0u8; 2 + n + TAG_LEN];
+        let len = this
+            .state
+            .write_message(&buf[..n], &mut message[2..])
+            .map_err(io::Error::other)?;
+        message[..2].copy_from_slice(&(len as u16).to_be_bytes());
+        message.truncate(2 + len);
+        this.sending = message;
+        // Start it on its way; whatever is left goes out on the next write
+        // or flush.
+        if let Poll::Ready(Err(e)) = this.poll_send(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,320 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde_json::{Map, Value, json};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_rustls::rustls::crypto::ring;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber, info, warn};
+use tracing_subscriber::layer::{self, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::bridge::Registry;
+use crate::http::{self, Url};
+use crate::json_log::Fields;
+use crate::metrics::{FAMILIES, Kind};
+
+// Finished sessions are exported at most this often...
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+// ...or as soon as this many are waiting.
+const BATCH: usize = 512;
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Makes each client span a span of its own in OTLP, named "session", with
+// what was logged in it as span events and its bridge, peer and identity as
+// attributes, along with its totals from session_end. A session that logged
+// a warning or an error ends with an error status, the last such message.
+pub struct OtlpLayer(mpsc::UnboundedSender<Value>);
+
+// Any span's fields as last recorded, so that a session can name its bridge.
+struct SpanFields(Map<String, Value>);
+
+struct Session {
+    trace_id: String,
+    span_id: String,
+    start: SystemTime,
+    events: Vec<Value>,
+    totals: Map<String, Value>,
+    error: Option<String>,
+}
+
+impl<S> Layer<S> for OtlpLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: layer::Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut fields = Map::new();
+        attrs.record(&mut Fields(&mut fields));
+        let mut extensions = span.extensions_mut();
+        extensions.insert(SpanFields(fields));
+        if span.name() == "client" {
+            extensions.insert(Session {
+                trace_id: random_id(16),
+                span_id: random_id(8),
+                start: SystemTime::now(),
+                events: Vec::new(),
+                totals: Map::new(),
+                error: None,
+            });
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: layer::Context<'_, S>) {
+        if let Some(span) = ctx.span(id)
+            && let Some(fields) = span.extensions_mut().get_mut::<SpanFields>()
+        {
+            values.record(&mut Fields(&mut fields.0));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: layer::Context<'_, S>) {
+        let Some(span) = ctx.event_scope(event).and_then(|mut scope| scope.find(|span| span.name() == "client")) else {
+            return;
+        };
+        let mut fields = Map::new();
+        event.record(&mut Fields(&mut fields));
+        let message = match fields.remove("message") {
+            Some(Value::String(message)) => message,
+            _ => String::new(),
+        };
+        let mut extensions = span.extensions_mut();
+        let Some(session) = extensions.get_mut::<Session>() else {
+            return;
+        };
+        if *event.metadata().level() <= Level::WARN {
+            session.error = Some(message.clone());
+        }
+        if fields.get("event").and_then(Value::as_str) == Some("session_end") {
+            session.totals = fields.clone();
+            session.totals.remove("event");
+        }
+        session.events.push(json!({
+            "timeUnixNano": nanos(SystemTime::now()),
+            "name": message,
+            "attributes": attributes(&fields),
+        }));
+    }
+
+    fn on_close(&self, id: Id, ctx: layer::Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        let Some(session) = extensions.remove::<Session>() else {
+            return;
+        };
+        let mut fields = extensions.remove::<SpanFields>().map(|fields| fields.0).unwrap_or_default();
+        drop(extensions);
+        let bridge = span.parent().and_then(|parent| parent.extensions().get::<SpanFields>()?.0.get("name").cloned());
+        if let Some(bridge) = bridge {
+            fields.insert("bridge".into(), bridge);
+        }
+        fields.extend(session.totals);
+        let status = match session.error {
+            Some(message) => json!({"code": 2, "message": message}),
+            None => json!({}),
+        };
+        let _ = self.0.send(json!({
+            "traceId": session.trace_id,
+            "spanId": session.span_id,
+            "name": "session",
+            // SPAN_KIND_SERVER
+            "kind": 2,
+            "startTimeUnixNano": nanos(session.start),
+            "endTimeUnixNano": nanos(SystemTime::now()),
+            "attributes": attributes(&fields),
+            "events": session.events,
+            "status": status,
+        }));
+    }
+}
+
+// Sends sessions, and every `interval` the figures /metrics serves, to the
+// OTLP/HTTP collector at `endpoint` (such as http://collector:4318) as JSON.
+// Counters lose their _total suffix, as OTLP has types for them instead.
+pub struct Exporter {
+    traces: Url,
+    metrics: Url,
+    interval: Duration,
+    sessions: mpsc::UnboundedReceiver<Value>,
+}
+
+// The layer goes to the log subscriber, and the exporter is spawned once
+// there are bridges to report on.
+pub fn new(endpoint: &str, interval: Duration) -> Result<(OtlpLayer, Exporter)> {
+    if interval.is_zero() {
+        bail!("otlp_interval must be at least 1");
+    }
+    let endpoint = endpoint.trim_end_matches('/');
+    let url = |path: &str| {
+        format!("{}{}", endpoint, path)
+            .parse::<Url>()
+            .with_context(|| format!("invalid otlp_endpoint '{}'", endpoint))
+    };
+    let (sender, sessions) = mpsc::unbounded_channel();
+    let exporter = Exporter {
+        traces: url("/v1/traces")?,
+        metrics: url("/v1/metrics")?,
+        interval,
+        sessions,
+    };
+    Ok((OtlpLayer(sender), exporter))
+}
+
+impl Exporter {
+    pub fn spawn(self, registry: Arc<Registry>) {
+        tokio::spawn(self.run(registry));
+    }
+
+    async fn run(mut self, registry: Arc<Registry>) {
+        let started = SystemTime::now();
+        let mut pending = Vec::new();
+        let mut failing = false;
+        let mut flush = tokio::time::interval(EXPORT_INTERVAL);
+        // Not at once, before there are bridges.
+        let mut collect = tokio::time::interval_at(Instant::now() + self.interval, self.interval);
+        loop {
+            tokio::select! {
+                session = self.sessions.recv() => {
+                    let Some(session) = session else {
+                        return;
+                    };
+                    pending.push(session);
+                    if pending.len() >= BATCH {
+                        post(&self.traces, &traces(std::mem::take(&mut pending)), &mut failing).await;
+                    }
+                }
+                _ = flush.tick() => {
+                    if !pending.is_empty() {
+                        post(&self.traces, &traces(std::mem::take(&mut pending)), &mut failing).await;
+                    }
+                }
+                _ = collect.tick() => post(&self.metrics, &metrics(&registry, started), &mut failing).await,
+            }
+        }
+    }
+}
+
+// What could not be sent is dropped rather than queued up behind a
+// collector that is down. Only the first failure in a row is a warning.
+async fn post(url: &Url, body: &Value, failing: &mut bool) {
+    let body = body.to_string();
+    let request = http::post(url, "application/json", body.as_bytes());
+    let result = match tokio::time::timeout(EXPORT_TIMEOUT, request).await {
+        Ok(Ok(status)) if (200..300).contains(&status) => Ok(()),
+        Ok(Ok(status)) => Err(anyhow!("collector answered {}", status)),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(anyhow!("collector did not answer")),
+    };
+    match result {
+        Ok(()) if *failing => {
+            info!("OTLP export is working again");
+            *failing = false;
+        }
+        Ok(()) => {}
+        Err(e) if !*failing => {
+            warn!("OTLP export failed: {:#}", e);
+            *failing = true;
+        }
+        Err(_) => {}
+    }
+}
+
+fn traces(sessions: Vec<Value>) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": resource(),
+            "scopeSpans": [{"scope": scope(), "spans": sessions}],
+        }],
+    })
+}
+
+fn metrics(registry: &Registry, started: SystemTime) -> Value {
+    let bridges = registry.list();
+    let (start, now) = (nanos(started), nanos(SystemTime::now()));
+    let metrics: Vec<Value> = FAMILIES
+        .iter()
+        .map(|family| {
+            let points: Vec<Value> = bridges
+                .iter()
+                .map(|bridge| {
+                    let labels = Map::from_iter([
+                        ("bridge".to_string(), Value::from(&*bridge.name)),
+                        ("serial_port".to_string(), Value::from(bridge.config.serial_port.as_str())),
+                    ]);
+                    json!({
+                        "attributes": attributes(&labels),
+                        "startTimeUnixNano": start,
+                        "timeUnixNano": now,
+                        "asInt": (family.value)(bridge).to_string(),
+                    })
+                })
+                .collect();
+            let name = family.name.strip_suffix("_total").unwrap_or(family.name);
+            match family.kind {
+                // AGGREGATION_TEMPORALITY_CUMULATIVE
+                Kind::Counter => json!({
+                    "name": name,
+                    "description": family.help,
+                    "sum": {"aggregationTemporality": 2, "isMonotonic": true, "dataPoints": points},
+                }),
+                Kind::Gauge => json!({
+                    "name": name,
+                    "description": family.help,
+                    "gauge": {"dataPoints": points},
+                }),
+            }
+        })
+        .collect();
+    json!({
+        "resourceMetrics": [{
+            "resource": resource(),
+            "scopeMetrics": [{"scope": scope(), "metrics": metrics}],
+        }],
+    })
+}
+
+fn resource() -> Value {
+    let fields = Map::from_iter([
+        ("service.name".to_string(), Value::from(env!("CARGO_PKG_NAME"))),
+        ("service.version".to_string(), Value::from(env!("CARGO_PKG_VERSION"))),
+    ]);
+    json!({"attributes": attributes(&fields)})
+}
+
+fn scope() -> Value {
+    json!({"name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION")})
+}
+
+// Fields as OTLP key-values. Integers go as strings, being 64-bit.
+fn attributes(fields: &Map<String, Value>) -> Vec<Value> {
+    fields
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::Bool(value) => json!({"boolValue": value}),
+                Value::Number(value) if value.is_f64() => json!({"doubleValue": value}),
+                Value::Number(value) => json!({"intValue": value.to_string()}),
+                Value::String(value) => json!({"stringValue": value}),
+                value => json!({"stringValue": value.to_string()}),
+            };
+            json!({"key": key, "value": value})
+        })
+        .collect()
+}
+
+fn nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos()).to_string()
+}
+
+// Trace and span ids, as hex.
+fn random_id(len: usize) -> String {
+    let mut bytes = vec![0; len];
+    let _ = ring::default_provider().secure_random.fill(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
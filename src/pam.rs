@@ -0,0 +1,147 @@
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::sync::OnceLock;
+
+use anyhow::{Result, anyhow, bail};
+
+// libpam is opened when first needed rather than linked, so that the
+// binary builds without its headers and runs on hosts without it.
+#[cfg(target_os = "macos")]
+const LIBRARIES: &[&CStr] = &[c"libpam.2.dylib", c"libpam.dylib"];
+#[cfg(not(target_os = "macos"))]
+const LIBRARIES: &[&CStr] = &[c"libpam.so.0", c"libpam.so"];
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_CONV_ERR: c_int = 19;
+const PAM_PROMPT_ECHO_OFF: c_int = 1;
+
+#[repr(C)]
+struct Message {
+    style: c_int,
+    text: *const c_char,
+}
+
+#[repr(C)]
+struct Response {
+    text: *mut c_char,
+    retcode: c_int,
+}
+
+type Converse = unsafe extern "C" fn(c_int, *mut *const Message, *mut *mut Response, *mut c_void) -> c_int;
+
+#[repr(C)]
+struct Conversation {
+    converse: Converse,
+    data: *mut c_void,
+}
+
+type Start = unsafe extern "C" fn(*const c_char, *const c_char, *const Conversation, *mut *mut c_void) -> c_int;
+// pam_authenticate, pam_acct_mgmt and pam_end: a handle and flags or a
+// status, returning a status.
+type Call = unsafe extern "C" fn(*mut c_void, c_int) -> c_int;
+type StrError = unsafe extern "C" fn(*mut c_void, c_int) -> *const c_char;
+
+struct Library {
+    start: Start,
+    authenticate: Call,
+    acct_mgmt: Call,
+    end: Call,
+    strerror: StrError,
+}
+
+static LIBRARY: OnceLock<Result<Library, String>> = OnceLock::new();
+
+fn library() -> Result<&'static Library> {
+    LIBRARY.get_or_init(open).as_ref().map_err(|e| anyhow!("{}", e))
+}
+
+fn open() -> Result<Library, String> {
+    // SAFETY: dlopen takes NUL-terminated names, and the handle is never
+    // closed, so the functions looked up in it stay valid.
+    unsafe {
+        let handle = LIBRARIES
+            .iter()
+            .map(|name| libc::dlopen(name.as_ptr(), libc::RTLD_NOW))
+            .find(|handle| !handle.is_null())
+            .ok_or("PAM is not available: libpam not found")?;
+        let symbol = |name: &CStr| {
+            let symbol = libc::dlsym(handle, name.as_ptr());
+            match symbol.is_null() {
+                true => Err(format!("PAM is not available: libpam has no {}", name.to_string_lossy())),
+                false => Ok(symbol),
+            }
+        };
+        Ok(Library {
+            start: std::mem::transmute::<*mut c_void, Start>(symbol(c"pam_start")?),
+            authenticate: std::mem::transmute::<*mut c_void, Call>(symbol(c"pam_authenticate")?),
+            acct_mgmt: std::mem::transmute::<*mut c_void, Call>(symbol(c"pam_acct_mgmt")?),
+            end: std::mem::transmute::<*mut c_void, Call>(symbol(c"pam_end")?),
+            strerror: std::mem::transmute::<*mut c_void, StrError>(symbol(c"pam_strerror")?),
+        })
+    }
+}
+
+// Checks a user's password, and that their account may be used now, the
+// way `service` in /etc/pam.d says. Blocks, for as long as the modules take;
+// pam_unix waits a couple of seconds before reporting a wrong password.
+pub fn authenticate(service: &str, user: &str, password: &str) -> Result<()> {
+    let library = library()?;
+    let service = CString::new(service)?;
+    let user = CString::new(user)?;
+    let password = CString::new(password)?;
+    let conversation = Conversation {
+        converse,
+        data: password.as_ptr() as *mut c_void,
+    };
+    let mut handle = std::ptr::null_mut();
+    // SAFETY: every pointer handed over outlives the transaction, which
+    // ends before this function returns.
+    unsafe {
+        let status = (library.start)(service.as_ptr(), user.as_ptr(), &conversation, &mut handle);
+        if status != PAM_SUCCESS {
+            bail!("failed to start PAM: error {}", status);
+        }
+        let mut status = (library.authenticate)(handle, 0);
+        if status == PAM_SUCCESS {
+            status = (library.acct_mgmt)(handle, 0);
+        }
+        let error = match status {
+            PAM_SUCCESS => None,
+            _ => Some(CStr::from_ptr((library.strerror)(handle, status)).to_string_lossy().into_owned()),
+        };
+        (library.end)(handle, status);
+        match error {
+            Some(error) => bail!("{}", error),
+            None => Ok(()),
+        }
+    }
+}
+
+// Answers password prompts, the ones not echoed, with the password and
+// anything else with nothing, as there is no one to ask. The responses
+// are PAM's to free.
+unsafe extern "C" fn converse(
+    count: c_int,
+    messages: *mut *const Message,
+    responses: *mut *mut Response,
+    data: *mut c_void,
+) -> c_int {
+    let Ok(count) = usize::try_from(count) else {
+        return PAM_CONV_ERR;
+    };
+    // SAFETY: PAM passes `count` messages and somewhere to put as many
+    // responses; `data` is the password given to pam_start.
+    unsafe {
+        let answers = libc::calloc(count, size_of::<Response>()) as *mut Response;
+        if answers.is_null() {
+            return PAM_CONV_ERR;
+        }
+        for i in 0..count {
+            let message = &**messages.add(i);
+            if message.style == PAM_PROMPT_ECHO_OFF {
+                (*answers.add(i)).text = libc::strdup(data as *const c_char);
+            }
+        }
+        *responses = answers;
+    }
+    PAM_SUCCESS
+}
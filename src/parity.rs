@@ -0,0 +1,93 @@
+// Parity as the character format has it, mark and space included: the
+// parity bit always 1 or always 0, as 9-bit addressing schemes and some old
+// industrial protocols use it. serialport only knows none, odd and even, so
+// mark and space are odd and even parity with CMSPAR set, which only Linux
+// offers among the platforms built for.
+
+#[cfg(target_os = "linux")]
+use clap::ValueEnum;
+use tokio_serial::{Error, ErrorKind, Parity, SerialPort, SerialStream};
+
+use crate::ParityArg;
+
+pub fn set(port: &mut SerialStream, parity: ParityArg) -> tokio_serial::Result<()> {
+    let (base, stick) = match parity {
+        ParityArg::None => (Parity::None, false),
+        ParityArg::Odd => (Parity::Odd, false),
+        ParityArg::Even => (Parity::Even, false),
+        ParityArg::Mark => (Parity::Odd, true),
+        ParityArg::Space => (Parity::Even, true),
+    };
+    port.set_parity(base)?;
+    #[cfg(target_os = "linux")]
+    return linux::set_stick(port, stick).map_err(|e| {
+        let name = parity.to_possible_value().map_or(String::new(), |value| value.get_name().to_string());
+        Error::new(ErrorKind::InvalidInput, format!("failed to set {} parity: {}", name, e))
+    });
+    #[cfg(not(target_os = "linux"))]
+    match stick {
+        true => Err(Error::new(ErrorKind::InvalidInput, "mark and space parity are only supported on Linux")),
+        false => Ok(()),
+    }
+}
+
+pub fn get(port: &SerialStream) -> ParityArg {
+    let parity = port.parity().unwrap_or(Parity::None);
+    #[cfg(target_os = "linux")]
+    if linux::stick(port) {
+        return match parity {
+            Parity::Odd => ParityArg::Mark,
+            Parity::Even => ParityArg::Space,
+            Parity::None => ParityArg::None,
+        };
+    }
+    parity.into()
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::io;
+    use std::mem::MaybeUninit;
+    use std::os::fd::AsRawFd;
+
+    use tokio_serial::SerialStream;
+
+    fn get(port: &SerialStream) -> io::Result<libc::termios> {
+        let mut termios = MaybeUninit::<libc::termios>::uninit();
+        // SAFETY: tcgetattr fills in `termios` before it is read.
+        unsafe {
+            if libc::tcgetattr(port.as_raw_fd(), termios.as_mut_ptr()) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(termios.assume_init())
+        }
+    }
+
+    // Whether the parity bit is fixed, by the PARODD flag, rather than
+    // computed.
+    pub fn set_stick(port: &SerialStream, stick: bool) -> io::Result<()> {
+        let mut termios = get(port)?;
+        if (termios.c_cflag & libc::CMSPAR != 0) == stick {
+            return Ok(());
+        }
+        termios.c_cflag ^= libc::CMSPAR;
+        // SAFETY: `termios` is a valid termios, as tcgetattr gave it.
+        if unsafe { libc::tcsetattr(port.as_raw_fd(), libc::TCSANOW, &termios) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // Drivers without stick parity clear the flag again, or parity
+        // altogether.
+        if stick && !fixed(&get(port)?) {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "the adapter has no stick parity"));
+        }
+        Ok(())
+    }
+
+    pub fn stick(port: &SerialStream) -> bool {
+        get(port).is_ok_and(|termios| fixed(&termios))
+    }
+
+    fn fixed(termios: &libc::termios) -> bool {
+        termios.c_cflag & (libc::PARENB | libc::CMSPAR) == libc::PARENB | libc::CMSPAR
+    }
+}
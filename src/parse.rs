@@ -0,0 +1,250 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use regex::Regex;
+use serde_json::{Map, Number, Value};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, info, warn};
+
+use crate::http::{self, Url};
+use crate::serial::SerialHandle;
+
+// Longer lines are parsed in pieces of this size.
+const MAX_LINE: usize = 4096;
+const POST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    String,
+    Int,
+    Float,
+    Bool,
+}
+
+// A field of the objects lines are parsed into, as NAME[:TYPE].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub kind: Kind,
+}
+
+impl FromStr for Field {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Field> {
+        let (name, kind) = spec.split_once(':').unwrap_or((spec, "string"));
+        let kind = match kind {
+            "string" => Kind::String,
+            "int" => Kind::Int,
+            "float" => Kind::Float,
+            "bool" => Kind::Bool,
+            _ => bail!("field '{}' must be string, int, float or bool", spec),
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            bail!("field '{}' has no name", spec);
+        }
+        Ok(Field {
+            name: name.to_string(),
+            kind,
+        })
+    }
+}
+
+// Turns serial text lines into JSON objects, for the sinks that would rather
+// send sensor readings than raw output. A line that does not match, or has a
+// value of the wrong type, is not turned into anything.
+#[derive(Debug)]
+pub enum LineParser {
+    // Named groups become fields, strings unless they have a type.
+    Regex { pattern: Regex, types: Vec<Field> },
+    // The columns, in order; further columns are ignored.
+    Csv { delimiter: char, columns: Vec<Field> },
+}
+
+// Patterns compare by their source.
+impl PartialEq for LineParser {
+    fn eq(&self, other: &LineParser) -> bool {
+        match (self, other) {
+            (LineParser::Regex { pattern, types }, LineParser::Regex { pattern: p, types: t }) => {
+                pattern.as_str() == p.as_str() && types == t
+            }
+            (LineParser::Csv { delimiter, columns }, LineParser::Csv { delimiter: d, columns: c }) => {
+                delimiter == d && columns == c
+            }
+            _ => false,
+        }
+    }
+}
+
+impl LineParser {
+    pub fn regex(pattern: &str, types: Vec<Field>) -> Result<LineParser> {
+        let pattern = Regex::new(pattern).map_err(|e| anyhow!("invalid parse_regex '{}': {}", pattern, e))?;
+        if pattern.capture_names().flatten().next().is_none() {
+            bail!("parse_regex has no named groups, e.g. (?<temperature>[0-9.]+)");
+        }
+        if let Some(field) = types.iter().find(|field| !pattern.capture_names().flatten().any(|n| n == field.name)) {
+            bail!("parse_types names '{}', which is not a group in parse_regex", field.name);
+        }
+        Ok(LineParser::Regex { pattern, types })
+    }
+
+    pub fn csv(delimiter: char, columns: Vec<Field>) -> Result<LineParser> {
+        if delimiter == '"' {
+            bail!("parse_delimiter cannot be a quote");
+        }
+        for (i, column) in columns.iter().enumerate() {
+            if columns[..i].iter().any(|other| other.name == column.name) {
+                bail!("parse_csv names '{}' twice", column.name);
+            }
+        }
+        Ok(LineParser::Csv { delimiter, columns })
+    }
+
+    pub fn parse(&self, line: &str) -> Option<Map<String, Value>> {
+        let mut object = Map::new();
+        match self {
+            LineParser::Regex { pattern, types } => {
+                let captures = pattern.captures(line)?;
+                for name in pattern.capture_names().flatten() {
+                    // Groups that took no part in the match are left out.
+                    let Some(text) = captures.name(name) else {
+                        continue;
+                    };
+                    let kind = types.iter().find(|field| field.name == name).map_or(Kind::String, |field| field.kind);
+                    object.insert(name.to_string(), value(text.as_str(), kind)?);
+                }
+            }
+            LineParser::Csv { delimiter, columns } => {
+                let values = split(line, *delimiter);
+                if values.len() < columns.len() {
+                    return None;
+                }
+                for (column, text) in columns.iter().zip(values) {
+                    object.insert(column.name.clone(), value(&text, column.kind)?);
+                }
+            }
+        }
+        Some(object)
+    }
+}
+
+fn value(text: &str, kind: Kind) -> Option<Value> {
+    match kind {
+        Kind::String => Some(Value::String(text.to_string())),
+        Kind::Int => text.trim().parse::<i64>().ok().map(Value::from),
+        Kind::Float => Number::from_f64(text.trim().parse().ok()?).map(Value::Number),
+        Kind::Bool => match text.trim().to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Some(Value::Bool(true)),
+            "false" | "0" | "no" | "off" => Some(Value::Bool(false)),
+            _ => None,
+        },
+    }
+}
+
+// Splits a CSV line. A column may be quoted to hold the delimiter, with ""
+// for a quote inside; anything after the closing quote is ignored. Unquoted
+// columns are trimmed.
+fn split(line: &str, delimiter: char) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut value = String::new();
+    // Whether the column started with a quote, and whether it is still open.
+    let (mut was_quoted, mut quoted) = (false, false);
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                value.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if !was_quoted && value.trim().is_empty() => {
+                value.clear();
+                (was_quoted, quoted) = (true, true);
+            }
+            c if c == delimiter && !quoted => {
+                values.push(column(std::mem::take(&mut value), was_quoted));
+                was_quoted = false;
+            }
+            _ if was_quoted && !quoted => {}
+            c => value.push(c),
+        }
+    }
+    values.push(column(value, was_quoted));
+    values
+}
+
+fn column(value: String, quoted: bool) -> String {
+    match quoted {
+        true => value,
+        false => value.trim().to_string(),
+    }
+}
+
+// Splits serial output into lines at CR, LF or CRLF, decoded as UTF-8 with
+// invalid bytes replaced. Empty lines, such as between CR and LF, are
+// skipped.
+#[derive(Default)]
+pub struct Lines(Vec<u8>);
+
+impl Lines {
+    pub fn push(&mut self, data: &[u8], mut each: impl FnMut(&str)) {
+        for &byte in data {
+            if byte != b'\r' && byte != b'\n' {
+                self.0.push(byte);
+            }
+            if (byte == b'\r' || byte == b'\n' || self.0.len() == MAX_LINE) && !self.0.is_empty() {
+                each(&String::from_utf8_lossy(&self.0));
+                self.0.clear();
+            }
+        }
+    }
+}
+
+// POSTs each line of the port's output that parses, as a JSON object, until
+// the port's task stops. Lines are sent one at a time, and dropped when the
+// server cannot keep up; only the first failure in a row is a warning.
+pub async fn post(url: Url, parser: Arc<LineParser>, serial: SerialHandle) -> Result<()> {
+    let mut output = serial.subscribe();
+    let mut lines = Lines::default();
+    let mut failing = false;
+    loop {
+        let data = match output.recv().await {
+            Ok(data) => data,
+            Err(RecvError::Lagged(n)) => {
+                debug!("post_url fell behind, {} serial reads dropped", n);
+                serial.dropped(n);
+                continue;
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        let mut objects = Vec::new();
+        lines.push(&data, |line| match parser.parse(line) {
+            Some(object) => objects.push(Value::Object(object).to_string()),
+            None => debug!("Not posting unparsed line: {}", line),
+        });
+        for body in objects {
+            let request = http::post(&url, "application/json", body.as_bytes());
+            let result = match tokio::time::timeout(POST_TIMEOUT, request).await {
+                Ok(Ok(status)) if (200..300).contains(&status) => Ok(()),
+                Ok(Ok(status)) => Err(anyhow!("server answered {}", status)),
+                Ok(Err(e)) => Err(e),
+                Err(_) => Err(anyhow!("server did not answer")),
+            };
+            match result {
+                Ok(()) if failing => {
+                    info!("Posting parsed lines is working again");
+                    failing = false;
+                }
+                Ok(()) => {}
+                Err(e) if !failing => {
+                    warn!("Posting parsed line failed: {:#}", e);
+                    failing = true;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+}
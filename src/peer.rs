@@ -0,0 +1,115 @@
+// A second local port bridged to the bridge's own, so that two devices talk
+// to each other through it: for protocol converters, or to put triggers,
+// recording and captures between them. The peer is just another writer as
+// far as the port goes, and clients may still connect to watch or join in.
+// Sniffing, the bridge is a tap between a device and its controller instead,
+// and clients watch the traffic both ways.
+
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_serial::{DataBits, FlowControl, StopBits};
+use tracing::warn;
+
+use crate::ParityArg;
+use crate::record;
+use crate::serial::{self, Buffers, Device, Feed, SerialHandle, Taps};
+
+// The peer port and its own settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PeerConfig {
+    pub path: String,
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: ParityArg,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    // The port's device is controlled from the peer.
+    pub sniff: bool,
+}
+
+// Opens the peer port, which like the bridge's own is reopened if it goes
+// away.
+pub fn open(config: &PeerConfig, buffers: Buffers) -> Result<SerialHandle> {
+    let builder = tokio_serial::new(&config.path, config.baud_rate)
+        .data_bits(config.data_bits)
+        .stop_bits(config.stop_bits)
+        .flow_control(config.flow_control);
+    let port = serial::open(&config.path, builder.clone(), config.baud_rate, config.parity)
+        .with_context(|| format!("failed to open {}", config.path))?;
+    let device = Device {
+        path: config.path.clone(),
+        builder,
+        usb_id: None,
+        rs485: None,
+        pace: false,
+        low_latency: false,
+        init: None,
+        heartbeat: None,
+    };
+    Ok(serial::spawn(port, device, Taps::default(), false, None, buffers, None))
+}
+
+// Copies each port's output to the other until the bridge stops, feeding
+// clients a line per chunk when sniffing.
+pub async fn serve(serial: SerialHandle, peer: SerialHandle, feed: Option<Feed>) -> Result<()> {
+    let mut from_serial = serial.subscribe();
+    let mut from_peer = peer.subscribe();
+    let mut sniffer = feed.map(|feed| Sniffer { feed, last: None });
+    loop {
+        tokio::select! {
+            received = from_serial.recv() => match received {
+                Ok(data) => {
+                    if let Some(sniffer) = &mut sniffer {
+                        sniffer.log("device", &data);
+                    }
+                    peer.write(data).await?
+                }
+                Err(RecvError::Lagged(n)) => {
+                    warn!("Peer port fell behind, {} serial reads dropped", n);
+                    serial.dropped(n);
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            received = from_peer.recv() => match received {
+                Ok(data) => {
+                    if let Some(sniffer) = &mut sniffer {
+                        sniffer.log("controller", &data);
+                    }
+                    serial.write(data).await?
+                }
+                Err(RecvError::Lagged(n)) => {
+                    warn!("Serial port fell behind the peer port, {} reads dropped", n);
+                    peer.dropped(n);
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+        }
+    }
+}
+
+// Traffic as `<timestamp> +<seconds since the last chunk> <sender> <bytes>`
+// lines. Times are when the relay got each read, a little after the port
+// did, which is close enough to tell frames and gaps apart.
+struct Sniffer {
+    feed: Feed,
+    last: Option<Instant>,
+}
+
+impl Sniffer {
+    fn log(&mut self, from: &str, data: &[u8]) {
+        let now = Instant::now();
+        let gap = self.last.map_or(Duration::ZERO, |last| now - last);
+        self.last = Some(now);
+        let line = format!(
+            "{} +{:.6} {} {}\n",
+            humantime::format_rfc3339_micros(SystemTime::now()),
+            gap.as_secs_f64(),
+            from,
+            record::escape(data)
+        );
+        self.feed.send(Bytes::from(line));
+    }
+}
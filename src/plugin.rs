@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow, bail};
+use tracing::warn;
+use wasmi::{Config, Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+use crate::filter::Filter;
+use crate::serial::Direction;
+
+// Enough to translate a chunk many times over; a plugin stuck in a loop is
+// stopped rather than holding up the session.
+const FUEL: u64 = 10_000_000;
+const MAX_MEMORY: usize = 16 << 20;
+
+// A WebAssembly module filtering traffic, for protocol adapters shipped
+// apart from the crate. It may import nothing, so the bytes it is handed
+// are all it can reach. It exports its memory and:
+//
+//   alloc(len: i32) -> i32                where to put `len` bytes of input
+//   filter_output(ptr: i32, len: i32) -> i64  serial output, for a client
+//   filter_input(ptr: i32, len: i32) -> i64   what a client sends
+//
+// Each filter returns where its output is, as ptr << 32 | len. Both buffers
+// stay the module's own; alloc may hand out the same one every time. Only
+// the directions it filters need exporting. Each direction of each session
+// gets its own instance, so a module may keep state between calls.
+pub struct Plugin {
+    engine: Engine,
+    module: Module,
+}
+
+impl Plugin {
+    pub fn load(path: &Path) -> Result<Plugin> {
+        let wasm = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module =
+            Module::new(&engine, wasm).map_err(|e| anyhow!("failed to load plugin {}: {}", path.display(), e))?;
+        let plugin = Plugin { engine, module };
+        // Whatever is wrong with it is better found now than by the first
+        // client.
+        let mut filters = 0;
+        for direction in [Direction::Rx, Direction::Tx] {
+            if plugin.instantiate(direction).with_context(|| format!("plugin {}", path.display()))?.is_some() {
+                filters += 1;
+            }
+        }
+        if filters == 0 {
+            bail!("plugin {} exports neither filter_output nor filter_input", path.display());
+        }
+        Ok(plugin)
+    }
+
+    // A filter for one direction of a session, or None if the module does
+    // not filter that way.
+    pub fn instantiate(&self, direction: Direction) -> Result<Option<PluginFilter>> {
+        let name = match direction {
+            Direction::Rx => "filter_output",
+            Direction::Tx => "filter_input",
+        };
+        if self.module.get_export(name).is_none() {
+            return Ok(None);
+        }
+        let limits = StoreLimitsBuilder::new().memory_size(MAX_MEMORY).instances(1).build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(FUEL)?;
+        let instance = Linker::new(&self.engine).instantiate_and_start(&mut store, &self.module)?;
+        let memory = instance.get_memory(&store, "memory").context("no memory exported")?;
+        let alloc = instance.get_typed_func(&store, "alloc").context("bad alloc export")?;
+        let filter = instance.get_typed_func(&store, name).with_context(|| format!("bad {} export", name))?;
+        Ok(Some(PluginFilter {
+            store,
+            memory,
+            alloc,
+            filter,
+            failed: false,
+        }))
+    }
+}
+
+pub struct PluginFilter {
+    store: Store<StoreLimits>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    filter: TypedFunc<(i32, i32), i64>,
+    // Trapped once. Its state can no longer be trusted, and passing bytes
+    // on untranslated could be worse than not passing them at all.
+    failed: bool,
+}
+
+impl PluginFilter {
+    fn call(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        self.store.set_fuel(FUEL)?;
+        let len = i32::try_from(data.len())?;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        self.memory.write(&mut self.store, ptr as u32 as usize, data)?;
+        let result = self.filter.call(&mut self.store, (ptr, len))? as u64;
+        let mut out = vec![0u8; (result & 0xffff_ffff) as usize];
+        self.memory.read(&self.store, (result >> 32) as usize, &mut out)?;
+        Ok(out)
+    }
+}
+
+impl Filter for PluginFilter {
+    fn apply(&mut self, data: &[u8]) -> Vec<u8> {
+        if self.failed {
+            return Vec::new();
+        }
+        match self.call(data) {
+            Ok(out) => out,
+            Err(e) => {
+                warn!("Plugin failed, dropping what would pass through it: {:#}", e);
+                self.failed = true;
+                Vec::new()
+            }
+        }
+    }
+}
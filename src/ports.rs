@@ -0,0 +1,78 @@
+use anyhow::Result;
+use serde::Serialize;
+use tokio_serial::SerialPortType;
+
+#[derive(Serialize)]
+struct PortInfo {
+    port: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    // The value to pass to --usb-id, for USB adapters.
+    usb_id: Option<String>,
+    vid: Option<String>,
+    pid: Option<String>,
+    serial_number: Option<String>,
+    manufacturer: Option<String>,
+    product: Option<String>,
+}
+
+// Prints the serial ports present on this machine, for the list-ports subcommand.
+pub fn list(json: bool) -> Result<()> {
+    let ports: Vec<PortInfo> = tokio_serial::available_ports()?
+        .into_iter()
+        .map(|port| match port.port_type {
+            SerialPortType::UsbPort(usb) => PortInfo {
+                port: port.port_name,
+                kind: "usb",
+                usb_id: Some(match &usb.serial_number {
+                    Some(serial_number) => format!("{:04x}:{:04x}:{}", usb.vid, usb.pid, serial_number),
+                    None => format!("{:04x}:{:04x}", usb.vid, usb.pid),
+                }),
+                vid: Some(format!("{:04x}", usb.vid)),
+                pid: Some(format!("{:04x}", usb.pid)),
+                serial_number: usb.serial_number,
+                manufacturer: usb.manufacturer,
+                product: usb.product,
+            },
+            other => PortInfo {
+                port: port.port_name,
+                kind: match other {
+                    SerialPortType::PciPort => "pci",
+                    SerialPortType::BluetoothPort => "bluetooth",
+                    _ => "unknown",
+                },
+                usb_id: None,
+                vid: None,
+                pid: None,
+                serial_number: None,
+                manufacturer: None,
+                product: None,
+            },
+        })
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&ports)?);
+        return Ok(());
+    }
+    if ports.is_empty() {
+        println!("No serial ports found");
+        return Ok(());
+    }
+    for port in &ports {
+        let mut line = format!("{:<20} {:<9}", port.port, port.kind);
+        if let Some(usb_id) = &port.usb_id {
+            line.push_str(&format!(" --usb-id {}", usb_id));
+        }
+        let description: Vec<&str> = [&port.manufacturer, &port.product]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        if !description.is_empty() {
+            line.push_str(&format!("  ({})", description.join(" ")));
+        }
+        println!("{}", line.trim_end());
+    }
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use anyhow::{Result, bail};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+// The longest a version 1 header may be, CRLF included.
+const V1_MAX: usize = 107;
+
+// Reads the PROXY protocol header a load balancer such as HAProxy sends
+// ahead of the client's own bytes, in either version, and returns the
+// client's address. None means the balancer did not say, as for its own
+// health checks; the connection is then its own.
+pub async fn read_header<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<SocketAddr>> {
+    // The shortest header of either version, "PROXY UNKNOWN\r\n", is longer
+    // than this, so nothing of the client's is read.
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start).await?;
+    if start == V2_SIGNATURE {
+        return read_v2(stream).await;
+    }
+    if !start.starts_with(b"PROXY ") {
+        bail!("no PROXY protocol header");
+    }
+    // Byte by byte, so as not to read past the line.
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX {
+            bail!("PROXY protocol header too long");
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields[..] {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4" | "TCP6", source, _, port, _] => Ok(Some(SocketAddr::new(source.parse()?, port.parse()?))),
+        _ => bail!("malformed PROXY protocol header '{}'", line),
+    }
+}
+
+async fn read_v2<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<SocketAddr>> {
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head).await?;
+    let [version_command, family, len @ ..] = head;
+    if version_command >> 4 != 2 {
+        bail!("unsupported PROXY protocol version {}", version_command >> 4);
+    }
+    let mut addresses = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut addresses).await?;
+    // LOCAL, from the balancer itself.
+    if version_command & 0x0f == 0 {
+        return Ok(None);
+    }
+    // The address family in the high nibble; TLVs may follow the addresses.
+    let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+    match family >> 4 {
+        1 if addresses.len() >= 12 => {
+            let ip: [u8; 4] = addresses[..4].try_into()?;
+            Ok(Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port(8))))
+        }
+        2 if addresses.len() >= 36 => {
+            let ip: [u8; 16] = addresses[..16].try_into()?;
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port(32))))
+        }
+        // Unix sockets and AF_UNSPEC carry no address we could use.
+        0 | 3 => Ok(None),
+        _ => bail!("malformed PROXY protocol header"),
+    }
+}
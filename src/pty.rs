@@ -0,0 +1,134 @@
+use std::ffi::{CStr, OsStr};
+use std::fs::{self, File};
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tokio::net::unix::pipe;
+use tracing::{info, warn};
+
+use crate::local::{Remote, relay};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// Exposes the bridge at `remote` as a pseudo-terminal. The pty outlives
+// connections, so programs using it only notice an outage as a pause in
+// the data.
+pub async fn serve(remote: &Remote, link: Option<PathBuf>) -> Result<()> {
+    let address = &remote.address;
+    let mut pty = Pty::open()?;
+    let _link = link.as_deref().map(|link| Link::create(link, &pty.path)).transpose()?;
+    match &link {
+        Some(link) => info!("Serving {} on {} ({})", address, link.display(), pty.path.display()),
+        None => info!("Serving {} on {}", address, pty.path.display()),
+    }
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let socket = match remote.connect().await {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!("{:#}; retrying in {:?}", e, backoff);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
+            }
+        };
+        info!("Connected to {}", address);
+        backoff = MIN_BACKOFF;
+        match relay(socket, &mut pty.reader, &mut pty.writer).await {
+            Ok(()) => warn!("{} closed the connection", address),
+            Err(e) => warn!("Connection to {} failed: {}", address, e),
+        }
+    }
+}
+
+// A pseudo-terminal; programs open `path` as if it were a serial port and
+// the master side carries their traffic.
+struct Pty {
+    path: PathBuf,
+    reader: pipe::Receiver,
+    writer: pipe::Sender,
+    // Held open so reading the master does not fail with EIO every time the
+    // last program using the port closes it.
+    _slave: OwnedFd,
+}
+
+impl Pty {
+    fn open() -> Result<Pty> {
+        let (mut master, mut slave) = (-1, -1);
+        // SAFETY: the descriptor pointers are valid, the rest may be null.
+        let result = unsafe {
+            libc::openpty(&mut master, &mut slave, ptr::null_mut(), ptr::null_mut(), ptr::null_mut())
+        };
+        check(result, "openpty failed")?;
+        // SAFETY: openpty just created these descriptors for this process.
+        let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+        make_raw(&slave)?;
+
+        let mut name = [0 as libc::c_char; 128];
+        // SAFETY: the buffer is as long as the length passed.
+        let error = unsafe { libc::ttyname_r(slave.as_raw_fd(), name.as_mut_ptr(), name.len()) };
+        if error != 0 {
+            return Err(io::Error::from_raw_os_error(error)).context("ttyname_r failed");
+        }
+        // SAFETY: ttyname_r succeeded, so the buffer holds a NUL-terminated name.
+        let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+        let path = PathBuf::from(OsStr::from_bytes(name.to_bytes()));
+
+        // The flag is shared with the duplicate made below.
+        // SAFETY: fcntl on a descriptor this function owns.
+        check(unsafe { libc::fcntl(master.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK) }, "fcntl failed")?;
+        let writer = File::from(master.try_clone()?);
+        Ok(Pty {
+            path,
+            reader: pipe::Receiver::from_file_unchecked(File::from(master))?,
+            writer: pipe::Sender::from_file_unchecked(writer)?,
+            _slave: slave,
+        })
+    }
+}
+
+// Bytes pass through unchanged until a program sets its own line discipline.
+fn make_raw(fd: &OwnedFd) -> Result<()> {
+    let mut termios = MaybeUninit::<libc::termios>::uninit();
+    // SAFETY: tcgetattr fills in `termios` before it is read.
+    unsafe {
+        check(libc::tcgetattr(fd.as_raw_fd(), termios.as_mut_ptr()), "tcgetattr failed")?;
+        let mut termios = termios.assume_init();
+        libc::cfmakeraw(&mut termios);
+        check(libc::tcsetattr(fd.as_raw_fd(), libc::TCSANOW, &termios), "tcsetattr failed")
+    }
+}
+
+// A symlink giving the pty a stable name, removed again on exit.
+struct Link(PathBuf);
+
+impl Link {
+    fn create(link: &Path, target: &Path) -> Result<Link> {
+        // Only ever replace a symlink, most likely one left by an earlier run.
+        if fs::symlink_metadata(link).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            fs::remove_file(link).with_context(|| format!("failed to remove stale link {}", link.display()))?;
+        }
+        std::os::unix::fs::symlink(target, link).with_context(|| format!("failed to create {}", link.display()))?;
+        Ok(Link(link.to_path_buf()))
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn check(result: libc::c_int, what: &'static str) -> Result<()> {
+    if result == -1 {
+        return Err(io::Error::last_os_error()).context(what);
+    }
+    Ok(())
+}
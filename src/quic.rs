@@ -0,0 +1,112 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{
+    ClientConfig, Connection, Endpoint, EndpointConfig, RecvStream, SendStream, ServerConfig, TokioRuntime,
+    TransportConfig,
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::io::Join;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+
+use crate::tls;
+
+// Sessions are one bidirectional stream on a connection negotiating this.
+const ALPN: &[u8] = b"remote-serial";
+// The first byte on a session stream: QUIC tells the server about a stream
+// only once data arrives on it, and the client may have nothing to say.
+const VERSION: u8 = 1;
+// Keeps idle consoles from timing out, and NAT mappings open; a peer gone
+// quiet for IDLE_TIMEOUT, keepalives included, has gone away.
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub type QuicStream = Join<RecvStream, SendStream>;
+
+// QUIC endpoints on UDP `port` at each of the addresses, using the
+// bridge's TLS certificate.
+pub fn listen(addrs: &[IpAddr], port: u16, cert: &Path, key: &Path, client_ca: Option<&Path>) -> Result<Vec<Endpoint>> {
+    let mut tls = tls::server_config(cert, key, client_ca)?;
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+    let crypto = QuicServerConfig::try_from(tls).context("TLS configuration unusable for QUIC")?;
+    let mut config = ServerConfig::with_crypto(Arc::new(crypto));
+    config.transport_config(transport());
+    // As for TCP, so that [::] and 0.0.0.0 can both be bound.
+    let v6_only = addrs.iter().any(IpAddr::is_ipv4);
+    addrs
+        .iter()
+        .map(|&ip| {
+            let addr = SocketAddr::new(ip, port);
+            let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+            if addr.is_ipv6() {
+                socket.set_only_v6(v6_only)?;
+            }
+            socket.bind(&addr.into())?;
+            let runtime = Arc::new(TokioRuntime);
+            Endpoint::new(EndpointConfig::default(), Some(config.clone()), socket.into(), runtime)
+        })
+        .map(|endpoint| endpoint.with_context(|| format!("failed to bind QUIC port {}", port)))
+        .collect()
+}
+
+pub fn client_config(ca: Option<&Path>) -> Result<ClientConfig> {
+    let mut tls = tls::client_config(ca)?;
+    tls.alpn_protocols = vec![ALPN.to_vec()];
+    let crypto = QuicClientConfig::try_from(tls).context("TLS configuration unusable for QUIC")?;
+    let mut config = ClientConfig::new(Arc::new(crypto));
+    config.transport_config(transport());
+    Ok(config)
+}
+
+fn transport() -> Arc<TransportConfig> {
+    let mut transport = TransportConfig::default();
+    transport.keep_alive_interval(Some(KEEP_ALIVE));
+    transport.max_idle_timeout(Some(IDLE_TIMEOUT.try_into().expect("idle timeout in range")));
+    Arc::new(transport)
+}
+
+// Opens a session on the bridge at HOST:PORT, verifying its certificate
+// against HOST.
+pub async fn connect(address: &str, config: &ClientConfig) -> Result<QuicStream> {
+    let addr = tokio::net::lookup_host(address)
+        .await
+        .with_context(|| format!("failed to resolve {}", address))?
+        .next()
+        .with_context(|| format!("no address for {}", address))?;
+    let local = match addr {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let endpoint = Endpoint::client(local)?;
+    let connection = endpoint
+        .connect_with(config.clone(), addr, host)?
+        .await
+        .with_context(|| format!("failed to connect to {}", address))?;
+    // The streams keep the connection, and it the endpoint, alive.
+    let (mut send, recv) = connection.open_bi().await?;
+    send.write_all(&[VERSION]).await?;
+    Ok(tokio::io::join(recv, send))
+}
+
+// The session stream a client opens first on a new connection.
+pub async fn accept(connection: &Connection) -> Result<QuicStream> {
+    let (send, mut recv) = connection.accept_bi().await?;
+    let mut version = [0u8];
+    recv.read_exact(&mut version).await?;
+    if version[0] != VERSION {
+        bail!("unsupported session version {}", version[0]);
+    }
+    Ok(tokio::io::join(recv, send))
+}
+
+// Common name of the client's verified certificate, if it presented one.
+pub fn peer_common_name(connection: &Connection) -> Option<String> {
+    let certs = connection.peer_identity()?.downcast::<Vec<CertificateDer<'static>>>().ok()?;
+    tls::common_name(certs.first()?)
+}
@@ -0,0 +1,205 @@
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tracing::{Instrument, debug, info, warn};
+
+use crate::rotate::Rotation;
+use crate::serial::Direction;
+
+// Transcript of one client session. Each chunk becomes a line of the form
+// `<timestamp> RX|TX <bytes>`, with anything unprintable escaped as \xNN.
+// A transcript that rotates goes on in BRIDGE-STARTED-SESSION-PART.log.
+pub struct Recorder {
+    dir: PathBuf,
+    bridge: String,
+    session: u64,
+    rotation: Rotation,
+    keep: Option<usize>,
+    file: BufWriter<File>,
+    path: PathBuf,
+    part: u32,
+    size: u64,
+    started: Instant,
+}
+
+impl Recorder {
+    pub async fn create(
+        dir: &Path,
+        bridge: &str,
+        session: u64,
+        rotation: Rotation,
+        keep: Option<usize>,
+    ) -> Result<Recorder> {
+        let path = dir.join(format!("{}-{}-{}.log", bridge, stamp(), session));
+        let recorder = Recorder {
+            dir: dir.into(),
+            bridge: bridge.into(),
+            session,
+            rotation,
+            keep,
+            file: BufWriter::new(create(&path).await?),
+            path,
+            part: 1,
+            size: 0,
+            started: Instant::now(),
+        };
+        recorder.prune().await;
+        Ok(recorder)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    async fn write(&mut self, direction: Direction, data: &[u8]) -> Result<()> {
+        if self.size > 0 && self.rotation.due(self.size, self.started) {
+            self.rotate().await?;
+        }
+        let mut line = format!(
+            "{} {} ",
+            humantime::format_rfc3339_millis(SystemTime::now()),
+            match direction {
+                Direction::Rx => "RX",
+                Direction::Tx => "TX",
+            }
+        );
+        line.push_str(&escape(data));
+        line.push('\n');
+        self.file.write_all(line.as_bytes()).await?;
+        self.size += line.len() as u64;
+        // Flushed per chunk so the transcript survives a crash.
+        self.file.flush().await?;
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> Result<()> {
+        self.part += 1;
+        let path = self.dir.join(format!("{}-{}-{}-{}.log", self.bridge, stamp(), self.session, self.part));
+        self.file = BufWriter::new(create(&path).await?);
+        info!("Recording continues in {}", path.display());
+        self.path = path;
+        self.size = 0;
+        self.started = Instant::now();
+        self.prune().await;
+        Ok(())
+    }
+
+    // Deletes the bridge's oldest transcripts, by when they were last
+    // written, beyond those to keep. Failing to is only worth a warning:
+    // the transcript itself is fine.
+    async fn prune(&self) {
+        let Some(keep) = self.keep else {
+            return;
+        };
+        let mut transcripts = match transcripts(&self.dir, &self.bridge).await {
+            Ok(transcripts) => transcripts,
+            Err(e) => {
+                warn!("Failed to list transcripts in {}: {}", self.dir.display(), e);
+                return;
+            }
+        };
+        if transcripts.len() <= keep {
+            return;
+        }
+        transcripts.sort();
+        for (_, path) in &transcripts[..transcripts.len() - keep] {
+            match tokio::fs::remove_file(path).await {
+                Ok(()) => debug!("Deleted old transcript {}", path.display()),
+                Err(e) => warn!("Failed to delete old transcript {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    // Writes the transcript on a task of its own, which ends with the last
+    // tap. A failing transcript is dropped rather than taking the session
+    // down with it.
+    pub fn spawn(mut self) -> RecordTap {
+        let (sender, mut chunks) = mpsc::unbounded_channel::<(Direction, Bytes)>();
+        let write = async move {
+            while let Some((direction, data)) = chunks.recv().await {
+                if let Err(e) = self.write(direction, &data).await {
+                    warn!("Stopped recording to {}: {:#}", self.path.display(), e);
+                    return;
+                }
+            }
+        };
+        tokio::spawn(write.in_current_span());
+        RecordTap(sender)
+    }
+}
+
+// The current time, as it appears in transcript names: 20260301T091244Z.
+fn stamp() -> String {
+    humantime::format_rfc3339_seconds(SystemTime::now())
+        .to_string()
+        .chars()
+        .filter(|c| !matches!(c, '-' | ':'))
+        .collect()
+}
+
+async fn create(path: &Path) -> Result<File> {
+    File::create(path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))
+}
+
+// The bridge's transcripts in `dir`, with when each was last written. Other
+// bridges' names may start with this one's, so the rest of the name must be
+// a stamp and session (and part) too.
+async fn transcripts(dir: &Path, bridge: &str) -> std::io::Result<Vec<(SystemTime, PathBuf)>> {
+    let mut transcripts = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let Some(rest) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(bridge)?.strip_prefix('-')?.strip_suffix(".log"))
+        else {
+            continue;
+        };
+        let mut fields = rest.split('-');
+        let stamped = fields.next().is_some_and(|stamp| {
+            stamp.len() == 16 && stamp.ends_with('Z') && stamp.chars().filter(|c| !c.is_ascii_digit()).count() == 2
+        });
+        let numbered: Vec<&str> = fields.collect();
+        if !stamped || !(1..=2).contains(&numbered.len()) || !numbered.iter().all(|n| n.parse::<u64>().is_ok()) {
+            continue;
+        }
+        let modified = entry.metadata().await?.modified()?;
+        transcripts.push((modified, entry.path()));
+    }
+    Ok(transcripts)
+}
+
+// Bytes as text, with anything unprintable escaped as \xNN, or as \r, \n
+// and \t.
+pub fn escape(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len());
+    for &b in data {
+        match b {
+            b'\\' => text.push_str("\\\\"),
+            b'\r' => text.push_str("\\r"),
+            b'\n' => text.push_str("\\n"),
+            b'\t' => text.push_str("\\t"),
+            0x20..=0x7e => text.push(b as char),
+            _ => text.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    text
+}
+
+// Where a session hands chunks to its transcript. Both directions share one
+// so they stay in order.
+#[derive(Clone)]
+pub struct RecordTap(mpsc::UnboundedSender<(Direction, Bytes)>);
+
+impl RecordTap {
+    pub fn record(&self, direction: Direction, data: &[u8]) {
+        let _ = self.0.send((direction, Bytes::copy_from_slice(data)));
+    }
+}
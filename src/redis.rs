@@ -0,0 +1,231 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use bytes::Bytes;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tracing::{debug, info, warn};
+
+use crate::local::Stream;
+use crate::serial::SerialHandle;
+use crate::tls;
+
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+// Longer lines are published in pieces of this size.
+const MAX_LINE: usize = 4096;
+// Longer reply lines are not Redis...
+const MAX_REPLY_LINE: usize = 1024;
+// ...and longer messages are not for a serial port.
+const MAX_BULK: usize = 1024 * 1024;
+
+// Where and how a bridge talks to Redis.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RedisConfig {
+    pub host: String,
+    pub port: u16,
+    // Serial output is published here, a line per message...
+    pub rx_channel: String,
+    // ...and messages on this channel are written to the port.
+    pub tx_channel: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls: bool,
+    // Verify the server against this CA instead of the system's roots.
+    pub ca: Option<PathBuf>,
+}
+
+// Relays between the serial port and Redis, reconnecting whenever the server
+// goes away; lines read meanwhile are not published. Only ends if the serial
+// port task stops.
+pub async fn serve(config: RedisConfig, serial: SerialHandle) -> Result<()> {
+    let connector = match config.tls {
+        true => Some(TlsConnector::from(Arc::new(tls::client_config(config.ca.as_deref())?))),
+        false => None,
+    };
+    loop {
+        // A connection that has subscribed can do nothing else, so publishing
+        // takes a second one.
+        let relayed = async {
+            let mut publisher = connect(&config, connector.as_ref()).await?;
+            let mut subscriber = connect(&config, connector.as_ref()).await?;
+            command(&mut subscriber, &[b"SUBSCRIBE", config.tx_channel.as_bytes()]).await?;
+            info!("Connected to Redis {}:{}", config.host, config.port);
+            tokio::select! {
+                published = publish(&mut publisher, &config.rx_channel, &serial) => published,
+                received = receive(&mut subscriber, &serial) => received,
+            }
+        };
+        match relayed.await {
+            Ok(()) => return Ok(()),
+            Err(e) => warn!("Redis {}:{}: {:#}; retrying in {:?}", config.host, config.port, e, RETRY_DELAY),
+        }
+        tokio::time::sleep(RETRY_DELAY).await;
+    }
+}
+
+async fn connect(config: &RedisConfig, connector: Option<&TlsConnector>) -> Result<BufReader<Box<dyn Stream>>> {
+    let socket = TcpStream::connect((config.host.as_str(), config.port))
+        .await
+        .context("failed to connect")?;
+    let stream: Box<dyn Stream> = match connector {
+        None => Box::new(socket),
+        Some(connector) => {
+            let name = ServerName::try_from(config.host.clone())
+                .with_context(|| format!("invalid host name {}", config.host))?;
+            Box::new(connector.connect(name, socket).await.context("TLS handshake failed")?)
+        }
+    };
+    let mut connection = BufReader::new(stream);
+    if let Some(password) = &config.password {
+        let auth = match &config.username {
+            Some(username) => command(&mut connection, &[b"AUTH", username.as_bytes(), password.as_bytes()]).await,
+            None => command(&mut connection, &[b"AUTH", password.as_bytes()]).await,
+        };
+        auth.context("failed to authenticate")?;
+    }
+    Ok(connection)
+}
+
+// Publishes the port's output until the port's task stops, which is the
+// only way it returns Ok.
+async fn publish<S>(connection: &mut BufReader<S>, channel: &str, serial: &SerialHandle) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut output = serial.subscribe();
+    let mut line = Vec::new();
+    loop {
+        let data = match output.recv().await {
+            Ok(data) => data,
+            Err(RecvError::Lagged(n)) => {
+                debug!("Redis fell behind, {} serial reads dropped", n);
+                serial.dropped(n);
+                continue;
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        // Written together, then answered together.
+        let mut pending = Vec::new();
+        let mut published = 0;
+        for &byte in data.iter() {
+            if byte != b'\r' && byte != b'\n' {
+                line.push(byte);
+            }
+            if (byte == b'\r' || byte == b'\n' || line.len() == MAX_LINE) && !line.is_empty() {
+                encode(&mut pending, &[b"PUBLISH", channel.as_bytes(), &line]);
+                line.clear();
+                published += 1;
+            }
+        }
+        if published == 0 {
+            continue;
+        }
+        connection.get_mut().write_all(&pending).await?;
+        for _ in 0..published {
+            reply(connection).await?;
+        }
+    }
+}
+
+// Writes messages on the subscribed channel to the port, until the port's
+// task stops.
+async fn receive<S>(connection: &mut BufReader<S>, serial: &SerialHandle) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    loop {
+        let Reply::Array(mut message) = reply(connection).await? else {
+            bail!("unexpected reply to SUBSCRIBE");
+        };
+        // ["message", channel, payload]; confirmations and pings are not.
+        if message.len() != 3 || !matches!(&message[0], Reply::Bulk(Some(kind)) if kind == b"message") {
+            continue;
+        }
+        if let Reply::Bulk(Some(payload)) = message.swap_remove(2)
+            && serial.write(Bytes::from(payload)).await.is_err()
+        {
+            return Ok(());
+        }
+    }
+}
+
+enum Reply {
+    Simple,
+    Integer,
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+async fn command<S>(connection: &mut BufReader<S>, args: &[&[u8]]) -> Result<Reply>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut request = Vec::new();
+    encode(&mut request, args);
+    connection.get_mut().write_all(&request).await?;
+    reply(connection).await
+}
+
+fn encode(out: &mut Vec<u8>, args: &[&[u8]]) {
+    out.extend(format!("*{}\r\n", args.len()).into_bytes());
+    for arg in args {
+        out.extend(format!("${}\r\n", arg.len()).into_bytes());
+        out.extend_from_slice(arg);
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+// Reads one RESP2 reply; error replies are errors.
+async fn reply<S>(connection: &mut BufReader<S>) -> Result<Reply>
+where
+    S: AsyncRead + Unpin,
+{
+    let line = reply_line(connection).await?;
+    let Some((kind, rest)) = line.split_at_checked(1) else {
+        bail!("malformed Redis reply '{}'", line);
+    };
+    let length = || rest.parse::<i64>().with_context(|| format!("malformed Redis reply '{}'", line));
+    match kind {
+        "+" => Ok(Reply::Simple),
+        "-" => bail!("Redis replied: {}", rest),
+        ":" => length().map(|_| Reply::Integer),
+        "$" => match usize::try_from(length()?) {
+            Err(_) => Ok(Reply::Bulk(None)),
+            Ok(n) if n > MAX_BULK => bail!("Redis reply too long"),
+            Ok(n) => {
+                let mut payload = vec![0u8; n + 2];
+                connection.read_exact(&mut payload).await?;
+                payload.truncate(n);
+                Ok(Reply::Bulk(Some(payload)))
+            }
+        },
+        "*" => {
+            let mut items = Vec::new();
+            for _ in 0..length()?.max(0) {
+                items.push(Box::pin(reply(connection)).await?);
+            }
+            Ok(Reply::Array(items))
+        }
+        _ => bail!("malformed Redis reply '{}'", line),
+    }
+}
+
+async fn reply_line<S>(connection: &mut BufReader<S>) -> Result<String>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut line = Vec::new();
+    if (&mut *connection).take(MAX_REPLY_LINE as u64).read_until(b'\n', &mut line).await? == 0 {
+        bail!("connection closed");
+    }
+    if !line.ends_with(b"\r\n") || line.len() < 3 {
+        bail!("malformed Redis reply");
+    }
+    line.truncate(line.len() - 2);
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
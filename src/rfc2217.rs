@@ -0,0 +1,301 @@
+use std::collections::VecDeque;
+
+use tokio_serial::{ClearBuffer, DataBits, FlowControl, StopBits};
+use tracing::info;
+
+use crate::ParityArg;
+use crate::serial::{Control, PortStatus};
+use crate::telnet::{self, BINARY, ECHO, Event, IAC, SB, SE, SGA};
+
+const COM_PORT_OPTION: u8 = 44;
+
+const SIGNATURE: u8 = 0;
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+const NOTIFY_LINESTATE: u8 = 6;
+const NOTIFY_MODEMSTATE: u8 = 7;
+const FLOWCONTROL_SUSPEND: u8 = 8;
+const FLOWCONTROL_RESUME: u8 = 9;
+const SET_LINESTATE_MASK: u8 = 10;
+const SET_MODEMSTATE_MASK: u8 = 11;
+const PURGE_DATA: u8 = 12;
+const SERVER_OFFSET: u8 = 100;
+
+const MODEM_CD: u8 = 0x80;
+const MODEM_RI: u8 = 0x40;
+const MODEM_DSR: u8 = 0x20;
+const MODEM_CTS: u8 = 0x10;
+
+// Options we are willing to enable in either direction, without and with
+// the COM-PORT option.
+const TELNET: [u8; 3] = [BINARY, ECHO, SGA];
+const SUPPORTED: [u8; 4] = [BINARY, ECHO, SGA, COM_PORT_OPTION];
+
+// A telnet session, which with `com_port` also lets the client configure
+// the serial port as RFC 2217 describes.
+pub struct Session {
+    telnet: telnet::Session,
+    com_port: bool,
+    port: ComPort,
+}
+
+// What the client has set up through the COM-PORT option.
+struct ComPort {
+    modemstate_mask: u8,
+    last_modemstate: Option<u8>,
+    suspended: bool,
+    // Commands awaiting a PortStatus to acknowledge them with.
+    pending: VecDeque<(u8, u8)>,
+}
+
+impl Session {
+    pub fn new(com_port: bool) -> Self {
+        Session {
+            telnet: telnet::Session::new(if com_port { &SUPPORTED } else { &TELNET }),
+            com_port,
+            port: ComPort {
+                modemstate_mask: 0xff,
+                last_modemstate: None,
+                suspended: false,
+                pending: VecDeque::new(),
+            },
+        }
+    }
+
+    // Negotiation sent to the client as soon as it connects.
+    pub fn greeting(&mut self) -> Vec<u8> {
+        let want: &[u8] = if self.com_port { &[BINARY, SGA, COM_PORT_OPTION] } else { &[BINARY, SGA] };
+        self.telnet.greeting(&[BINARY, ECHO, SGA], want)
+    }
+
+    pub fn suspended(&self) -> bool {
+        self.port.suspended
+    }
+
+    // Escapes serial data for transmission to the client.
+    pub fn encode(data: &[u8]) -> Vec<u8> {
+        telnet::Session::encode(data)
+    }
+
+    // Consumes bytes from the client, in order, as payload for the serial port
+    // and port controls. Responses that need no port access go to `reply`;
+    // every Control event must be answered through `ack`.
+    pub fn decode(&mut self, input: &[u8], reply: &mut Vec<u8>) -> Vec<Event> {
+        let port = &mut self.port;
+        self.telnet.decode(input, reply, |sub, reply| port.subnegotiation(sub, reply))
+    }
+
+    // Answers the oldest outstanding Control event with the resulting port state.
+    pub fn ack(&mut self, status: &PortStatus) -> Vec<u8> {
+        let mut out = Vec::new();
+        let Some((command, value)) = self.port.pending.pop_front() else {
+            return out;
+        };
+        match command {
+            SET_BAUDRATE => respond(&mut out, command, &status.baud_rate.to_be_bytes()),
+            SET_DATASIZE => {
+                let bits = match status.data_bits {
+                    DataBits::Five => 5,
+                    DataBits::Six => 6,
+                    DataBits::Seven => 7,
+                    DataBits::Eight => 8,
+                };
+                respond(&mut out, command, &[bits]);
+            }
+            SET_PARITY => {
+                let parity = match status.parity {
+                    ParityArg::None => 1,
+                    ParityArg::Odd => 2,
+                    ParityArg::Even => 3,
+                    ParityArg::Mark => 4,
+                    ParityArg::Space => 5,
+                };
+                respond(&mut out, command, &[parity]);
+            }
+            SET_STOPSIZE => {
+                let stop_bits = match status.stop_bits {
+                    StopBits::One => 1,
+                    StopBits::Two => 2,
+                };
+                respond(&mut out, command, &[stop_bits]);
+            }
+            SET_CONTROL => respond(&mut out, command, &[control_state(value, status)]),
+            NOTIFY_MODEMSTATE => {
+                let state = modem_state(status);
+                self.port.last_modemstate = Some(state);
+                respond(&mut out, command, &[state & self.port.modemstate_mask]);
+            }
+            PURGE_DATA => respond(&mut out, command, &[value]),
+            _ => {}
+        }
+        out
+    }
+
+    // Reports modem line changes to the client, honouring the modem state mask.
+    pub fn poll_modem(&mut self, status: &PortStatus) -> Option<Vec<u8>> {
+        if !self.telnet.remote(COM_PORT_OPTION) {
+            return None;
+        }
+        let port = &mut self.port;
+        let state = modem_state(status);
+        let previous = port.last_modemstate.replace(state);
+        if previous.is_some_and(|p| (p ^ state) & port.modemstate_mask == 0) {
+            return None;
+        }
+        let deltas = previous.map_or(0, |p| (p ^ state) >> 4);
+        let mut out = Vec::new();
+        respond(
+            &mut out,
+            NOTIFY_MODEMSTATE,
+            &[(state | deltas) & port.modemstate_mask],
+        );
+        Some(out)
+    }
+}
+
+impl ComPort {
+    fn subnegotiation(&mut self, sub: &[u8], reply: &mut Vec<u8>) -> Option<Control> {
+        let [COM_PORT_OPTION, command, value @ ..] = sub else {
+            return None;
+        };
+        let first = value.first().copied().unwrap_or(0);
+        let control = match *command {
+            SIGNATURE => {
+                if value.is_empty() {
+                    let signature = concat!("remote-serial-server ", env!("CARGO_PKG_VERSION"));
+                    respond(reply, SIGNATURE, signature.as_bytes());
+                } else {
+                    info!("RFC 2217 client: {}", String::from_utf8_lossy(value));
+                }
+                return None;
+            }
+            SET_BAUDRATE => match <[u8; 4]>::try_from(value).map(u32::from_be_bytes) {
+                Ok(baud) if baud != 0 => Control::BaudRate(baud),
+                _ => Control::Status,
+            },
+            SET_DATASIZE => match first {
+                5 => Control::DataBits(DataBits::Five),
+                6 => Control::DataBits(DataBits::Six),
+                7 => Control::DataBits(DataBits::Seven),
+                8 => Control::DataBits(DataBits::Eight),
+                _ => Control::Status,
+            },
+            SET_PARITY => match first {
+                1 => Control::Parity(ParityArg::None),
+                2 => Control::Parity(ParityArg::Odd),
+                3 => Control::Parity(ParityArg::Even),
+                4 => Control::Parity(ParityArg::Mark),
+                5 => Control::Parity(ParityArg::Space),
+                _ => Control::Status,
+            },
+            SET_STOPSIZE => match first {
+                1 => Control::StopBits(StopBits::One),
+                2 => Control::StopBits(StopBits::Two),
+                _ => Control::Status,
+            },
+            SET_CONTROL => match first {
+                1 | 14 => Control::FlowControl(FlowControl::None),
+                2 | 15 => Control::FlowControl(FlowControl::Software),
+                3 | 16 => Control::FlowControl(FlowControl::Hardware),
+                5 | 6 => Control::Break(first == 5),
+                8 | 9 => Control::Dtr(first == 8),
+                11 | 12 => Control::Rts(first == 11),
+                _ => Control::Status,
+            },
+            // Line errors and the like are not tracked, so there is never
+            // any line state to report, whatever the mask.
+            NOTIFY_LINESTATE => {
+                respond(reply, NOTIFY_LINESTATE, &[0]);
+                return None;
+            }
+            NOTIFY_MODEMSTATE => Control::Status,
+            FLOWCONTROL_SUSPEND => {
+                self.suspended = true;
+                return None;
+            }
+            FLOWCONTROL_RESUME => {
+                self.suspended = false;
+                return None;
+            }
+            SET_LINESTATE_MASK => {
+                respond(reply, SET_LINESTATE_MASK, &[first]);
+                return None;
+            }
+            SET_MODEMSTATE_MASK => {
+                self.modemstate_mask = first;
+                respond(reply, SET_MODEMSTATE_MASK, &[self.modemstate_mask]);
+                return None;
+            }
+            PURGE_DATA => match first {
+                1 => Control::Purge(ClearBuffer::Input),
+                2 => Control::Purge(ClearBuffer::Output),
+                3 => Control::Purge(ClearBuffer::All),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        self.pending.push_back((*command, first));
+        Some(control)
+    }
+}
+
+// The SET-CONTROL value that acknowledges `request` given the port state.
+fn control_state(request: u8, status: &PortStatus) -> u8 {
+    let flow = match status.flow_control {
+        FlowControl::None => 1,
+        FlowControl::Software => 2,
+        FlowControl::Hardware => 3,
+    };
+    match request {
+        0..=3 => flow,
+        4..=6 => {
+            if status.break_on {
+                5
+            } else {
+                6
+            }
+        }
+        7..=9 => {
+            if status.dtr {
+                8
+            } else {
+                9
+            }
+        }
+        10..=12 => {
+            if status.rts {
+                11
+            } else {
+                12
+            }
+        }
+        13..=16 => flow + 13,
+        other => other,
+    }
+}
+
+fn respond(out: &mut Vec<u8>, command: u8, value: &[u8]) {
+    out.extend_from_slice(&[IAC, SB, COM_PORT_OPTION, command + SERVER_OFFSET]);
+    out.extend_from_slice(&telnet::Session::encode(value));
+    out.extend_from_slice(&[IAC, SE]);
+}
+
+fn modem_state(status: &PortStatus) -> u8 {
+    let mut state = 0;
+    if status.cd {
+        state |= MODEM_CD;
+    }
+    if status.ri {
+        state |= MODEM_RI;
+    }
+    if status.dsr {
+        state |= MODEM_DSR;
+    }
+    if status.cts {
+        state |= MODEM_CTS;
+    }
+    state
+}
@@ -0,0 +1,102 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing_subscriber::fmt::MakeWriter;
+
+// When a file being written is set aside for a new one.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rotation {
+    pub max_size: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+impl Rotation {
+    // Whether a file of `size` bytes, started at `started`, is done with.
+    pub fn due(&self, size: u64, started: Instant) -> bool {
+        self.max_size.is_some_and(|max| size >= max) || self.max_age.is_some_and(|max| started.elapsed() >= max)
+    }
+}
+
+// The process's log in a file, rotated as logrotate would: the current
+// file is renamed to PATH.1, PATH.1 to PATH.2 and so on, and the oldest
+// beyond `keep` is deleted.
+pub struct LogFile {
+    path: PathBuf,
+    rotation: Rotation,
+    keep: usize,
+    current: Mutex<Current>,
+}
+
+struct Current {
+    file: File,
+    size: u64,
+    started: Instant,
+}
+
+impl LogFile {
+    pub fn open(path: &Path, rotation: Rotation, keep: usize) -> io::Result<LogFile> {
+        Ok(LogFile {
+            path: path.into(),
+            rotation,
+            keep,
+            current: Mutex::new(open(path)?),
+        })
+    }
+
+    fn rotate(&self, current: &mut Current) -> io::Result<()> {
+        let numbered = |n: usize| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", n));
+            PathBuf::from(path)
+        };
+        let _ = std::fs::remove_file(numbered(self.keep));
+        for n in (1..self.keep).rev() {
+            let _ = std::fs::rename(numbered(n), numbered(n + 1));
+        }
+        match self.keep {
+            0 => std::fs::remove_file(&self.path)?,
+            _ => std::fs::rename(&self.path, numbered(1))?,
+        }
+        *current = open(&self.path)?;
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> io::Result<Current> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok(Current {
+        file,
+        size,
+        started: Instant::now(),
+    })
+}
+
+impl Write for &LogFile {
+    // Each event arrives in one write, so files split between events.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut current = self.current.lock().unwrap();
+        if current.size > 0 && self.rotation.due(current.size, current.started) {
+            // Nowhere to report it; the old file goes on being written.
+            let _ = self.rotate(&mut current);
+        }
+        current.file.write_all(buf)?;
+        current.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.current.lock().unwrap().file.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogFile {
+    type Writer = &'a LogFile;
+
+    fn make_writer(&'a self) -> &'a LogFile {
+        self
+    }
+}
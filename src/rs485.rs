@@ -0,0 +1,83 @@
+use std::io;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio_serial::{SerialPort, SerialStream};
+use tracing::debug;
+
+// How long to wait for the UART to drain before giving up on it.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+const DRAIN_POLL: Duration = Duration::from_millis(1);
+
+// Pin that enables the line driver of a two-wire RS-485 adapter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pin {
+    Rts,
+    // Number of an exported sysfs GPIO (/sys/class/gpio/gpioN).
+    Gpio(u32),
+}
+
+// Half-duplex direction control: the driver is enabled for the duration of
+// each write and released once the last byte has left the UART.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rs485 {
+    pub pin: Pin,
+    // Drive the pin low, rather than high, while transmitting.
+    pub invert: bool,
+    pub delay_before: Duration,
+    pub delay_after: Duration,
+}
+
+impl Rs485 {
+    pub fn uses_rts(&self) -> bool {
+        matches!(self.pin, Pin::Rts)
+    }
+
+    // Puts the adapter in receive mode.
+    pub fn release(&self, port: &mut SerialStream) -> io::Result<()> {
+        self.drive(port, false)
+    }
+
+    // Only failures of the write itself are returned; a direction pin that
+    // cannot be driven is logged, since the port itself is still usable.
+    pub async fn transmit(&self, port: &mut SerialStream, data: &[u8]) -> io::Result<()> {
+        if let Err(e) = self.drive(port, true) {
+            debug!("Failed to enable RS-485 driver: {}", e);
+        }
+        tokio::time::sleep(self.delay_before).await;
+        let result = async {
+            port.write_all(data).await?;
+            drain(port).await
+        }
+        .await;
+        tokio::time::sleep(self.delay_after).await;
+        // Release the line even if the write failed, so the bus isn't held.
+        if let Err(e) = self.drive(port, false) {
+            debug!("Failed to release RS-485 driver: {}", e);
+        }
+        result
+    }
+
+    fn drive(&self, port: &mut SerialStream, transmit: bool) -> io::Result<()> {
+        let level = transmit != self.invert;
+        match self.pin {
+            Pin::Rts => port.write_request_to_send(level).map_err(io::Error::from),
+            Pin::Gpio(n) => std::fs::write(
+                format!("/sys/class/gpio/gpio{}/value", n),
+                if level { "1" } else { "0" },
+            ),
+        }
+    }
+}
+
+// Waits until the output queue is empty.
+async fn drain(port: &mut SerialStream) -> io::Result<()> {
+    let deadline = tokio::time::Instant::now() + DRAIN_TIMEOUT;
+    while port.bytes_to_write()? > 0 {
+        if tokio::time::Instant::now() >= deadline {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "output did not drain"));
+        }
+        tokio::time::sleep(DRAIN_POLL).await;
+    }
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use rhai::{AST, Blob, CallFnOptions, Dynamic, Engine, FuncArgs, Map, NativeCallContext, Scope};
+use tracing::{debug, info, warn};
+
+use crate::client::SessionInfo;
+
+// Enough for any reasonable hook; one stuck in a loop is stopped rather
+// than holding up the port.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+// A Rhai script hooked into a bridge, for the one-off changes that do not
+// deserve a setting of their own. It defines whichever of these it needs:
+//
+//   fn on_connect(client) { ... }
+//   fn on_disconnect(client) { ... }
+//   fn on_serial_data(data) { ... }
+//   fn on_client_data(client, data) { ... }
+//
+// on_serial_data sees each read from the port before anything else does,
+// and on_client_data each write from a client session after line editing.
+// They return what to pass on in place of `data`, a blob or a string; an
+// empty one drops it, and returning nothing leaves it as it was. `client`
+// is a map of the session's id, peer and read_only.
+//
+// Any hook may call send_serial(data) to write to the port, and reply(data)
+// to answer the client, or all of them from on_serial_data. `this` is a map
+// kept for the life of the bridge. Modbus gateway sessions only see
+// on_serial_data.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    state: Mutex<Dynamic>,
+}
+
+// What a hook sent, to go out after whatever it passed on.
+#[derive(Clone, Default)]
+pub struct Effects {
+    pub to_serial: Vec<Bytes>,
+    pub to_client: Vec<Bytes>,
+}
+
+type Outbox = Arc<Mutex<Effects>>;
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Script> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.on_print(|text| info!("Script: {}", text));
+        engine.on_debug(|text, _, position| debug!("Script at {}: {}", position, text));
+        engine.register_fn("send_serial", |context: NativeCallContext, data: Blob| {
+            send(&context, |effects| effects.to_serial.push(Bytes::from(data)));
+        });
+        engine.register_fn("send_serial", |context: NativeCallContext, text: &str| {
+            send(&context, |effects| effects.to_serial.push(Bytes::copy_from_slice(text.as_bytes())));
+        });
+        engine.register_fn("reply", |context: NativeCallContext, data: Blob| {
+            send(&context, |effects| effects.to_client.push(Bytes::from(data)));
+        });
+        engine.register_fn("reply", |context: NativeCallContext, text: &str| {
+            send(&context, |effects| effects.to_client.push(Bytes::copy_from_slice(text.as_bytes())));
+        });
+        let ast = engine
+            .compile_file(path.into())
+            .map_err(|e| anyhow!("failed to load script {}: {}", path.display(), e))?;
+        Ok(Script {
+            engine,
+            ast,
+            state: Mutex::new(Dynamic::from_map(Map::new())),
+        })
+    }
+
+    pub fn on_connect(&self, client: &SessionInfo) -> Effects {
+        self.call("on_connect", 1, (describe(client),)).map(|(_, effects)| effects).unwrap_or_default()
+    }
+
+    pub fn on_disconnect(&self, client: &SessionInfo) -> Effects {
+        self.call("on_disconnect", 1, (describe(client),)).map(|(_, effects)| effects).unwrap_or_default()
+    }
+
+    pub fn on_serial_data(&self, data: Bytes) -> (Bytes, Effects) {
+        let called = self.call("on_serial_data", 1, (Blob::from(&data[..]),));
+        passed_on("on_serial_data", data, called)
+    }
+
+    pub fn on_client_data(&self, client: &SessionInfo, data: Bytes) -> (Bytes, Effects) {
+        let called = self.call("on_client_data", 2, (describe(client), Blob::from(&data[..])));
+        passed_on("on_client_data", data, called)
+    }
+
+    // Runs a hook if the script defines it, returning None if it does not
+    // or if it failed.
+    fn call(&self, name: &str, arity: usize, args: impl FuncArgs) -> Option<(Dynamic, Effects)> {
+        if !self.ast.iter_functions().any(|f| f.name == name && f.params.len() == arity) {
+            return None;
+        }
+        let outbox = Outbox::default();
+        // Hooks take turns, so `this` needs no care from the script.
+        let mut state = self.state.lock().unwrap();
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut state)
+            .with_tag(outbox.clone());
+        match self.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.ast, name, args) {
+            Ok(value) => Some((value, std::mem::take(&mut outbox.lock().unwrap()))),
+            Err(e) => {
+                warn!("Script {} failed: {}", name, e);
+                None
+            }
+        }
+    }
+}
+
+fn send(context: &NativeCallContext, add: impl FnOnce(&mut Effects)) {
+    if let Some(outbox) = context.tag().and_then(|tag| tag.clone().try_cast::<Outbox>()) {
+        add(&mut outbox.lock().unwrap());
+    }
+}
+
+fn describe(client: &SessionInfo) -> Map {
+    let mut map = Map::new();
+    map.insert("id".into(), Dynamic::from_int(client.id as rhai::INT));
+    map.insert("peer".into(), client.peer.clone().into());
+    map.insert("read_only".into(), client.read_only.into());
+    map
+}
+
+// What a data hook passed on: its return value, or the data as it was if
+// it returned nothing, something else or failed.
+fn passed_on(name: &str, data: Bytes, called: Option<(Dynamic, Effects)>) -> (Bytes, Effects) {
+    let Some((value, effects)) = called else {
+        return (data, Effects::default());
+    };
+    let data = if value.is_unit() {
+        data
+    } else if value.is_blob() {
+        Bytes::from(value.cast::<Blob>())
+    } else if value.is_string() {
+        Bytes::from(value.cast::<String>())
+    } else {
+        warn!("Script {} returned a {}, not a blob or string", name, value.type_name());
+        data
+    };
+    (data, effects)
+}
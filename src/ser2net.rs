@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use serde::de::{Deserializer, MapAccess, Visitor};
+use serde_yaml::Value;
+use tracing::warn;
+
+use crate::config::{ConfigFile, Settings};
+use crate::{FlowControlArg, Mode, ParityArg, Sharing, StopBitsArg};
+
+// A ser2net 4.x YAML file repeats "connection:" at the top level, which
+// is not a valid YAML map, so its entries are read in order instead.
+struct Entries(Vec<(String, Value)>);
+
+impl<'de> Deserialize<'de> for Entries {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor;
+
+        impl<'de> Visitor<'de> for EntriesVisitor {
+            type Value = Entries;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("ser2net entries")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Entries, A::Error> {
+                let mut entries = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(Entries(entries))
+            }
+        }
+
+        deserializer.deserialize_map(EntriesVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Connection {
+    accepter: String,
+    connector: String,
+    enable: Option<Value>,
+    timeout: Option<u64>,
+    #[serde(default)]
+    options: HashMap<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct DefaultEntry {
+    name: String,
+    value: Value,
+    class: Option<String>,
+}
+
+// Translates a ser2net config into bridges. Only serialdev connectors are
+// understood; settings with no equivalent here are warned about and left
+// out rather than failing the whole file.
+pub fn parse(text: &str) -> Result<ConfigFile> {
+    let Entries(entries) = serde_yaml::from_str(text)?;
+    let mut config = ConfigFile::default();
+    for (i, (key, value)) in entries.into_iter().enumerate() {
+        match key.as_str() {
+            "connection" => {
+                let connection: Connection = serde_yaml::from_value(value)
+                    .with_context(|| format!("invalid connection (entry {})", i + 1))?;
+                if let Some(settings) =
+                    connection.into_settings().with_context(|| format!("invalid connection (entry {})", i + 1))?
+                {
+                    config.bridge.push(settings);
+                }
+            }
+            "default" => {
+                let default: DefaultEntry = serde_yaml::from_value(value)
+                    .with_context(|| format!("invalid default (entry {})", i + 1))?;
+                apply_default(&mut config.defaults, default)?;
+            }
+            // Only there to hold anchors, which are already resolved.
+            "define" => {}
+            _ => warn!("Ignoring ser2net {} entry", key),
+        }
+    }
+    Ok(config)
+}
+
+impl Connection {
+    // None for connections that are switched off.
+    fn into_settings(self) -> Result<Option<Settings>> {
+        if self.enable.as_ref().is_some_and(|enable| !truthy(enable)) {
+            return Ok(None);
+        }
+        let mut settings = Settings::default();
+        accepter(&self.accepter, &mut settings)?;
+        connector(&self.connector, &mut settings)?;
+        settings.idle_timeout = self.timeout;
+        for (name, value) in &self.options {
+            match name.as_str() {
+                "max-connections" if value.as_u64().is_some_and(|n| n > 1) => {
+                    settings.sharing = Some(Sharing::FreeForAll);
+                }
+                "max-connections" => {}
+                _ => warn!("Ignoring unsupported ser2net option {} on {}", name, self.accepter),
+            }
+        }
+        Ok(Some(settings))
+    }
+}
+
+// "[filter,...]tcp,[host,]port" or "unix,path".
+fn accepter(accepter: &str, settings: &mut Settings) -> Result<()> {
+    let mut parts = split(accepter).into_iter();
+    loop {
+        let Some(part) = parts.next() else {
+            bail!("accepter '{}' has no tcp or unix layer", accepter);
+        };
+        let (layer, options) = layer(&part);
+        match layer {
+            "telnet" if options.iter().any(|(key, value)| *key == "rfc2217" && *value != Some("false")) => {
+                settings.mode = Some(Mode::Rfc2217)
+            }
+            "telnet" => settings.mode = Some(Mode::Telnet),
+            "ssl" => {
+                for (key, value) in options {
+                    match (key, value) {
+                        ("key", Some(path)) => settings.tls_key = Some(path.into()),
+                        ("cert", Some(path)) => settings.tls_cert = Some(path.into()),
+                        ("CA", Some(path)) => settings.tls_client_ca = Some(path.into()),
+                        _ => warn!("Ignoring unsupported ssl option {}", key),
+                    }
+                }
+            }
+            "tcp" => {
+                for (key, value) in options {
+                    match (key, value) {
+                        ("nodelay", None | Some("true")) => settings.no_delay = true,
+                        _ => warn!("Ignoring unsupported tcp option {}", key),
+                    }
+                }
+                let (host, port) = match (parts.next(), parts.next()) {
+                    (Some(port), None) => (None, port),
+                    (Some(host), Some(port)) => (Some(host), port),
+                    _ => bail!("accepter '{}' has no port", accepter),
+                };
+                let port = port
+                    .parse()
+                    .with_context(|| format!("invalid port in accepter '{}'", accepter))?;
+                settings.tcp_port = Some(port);
+                if let Some(host) = host {
+                    listen_on(&host, settings);
+                }
+                return Ok(());
+            }
+            "unix" => {
+                let path = parts.next().with_context(|| format!("accepter '{}' has no path", accepter))?;
+                settings.unix_socket = Some(path.into());
+                return Ok(());
+            }
+            _ => bail!("unsupported accepter layer '{}'", layer),
+        }
+    }
+}
+
+// Bridges listen where the accepter says; "localhost" is both loopback
+// addresses.
+fn listen_on(host: &str, settings: &mut Settings) {
+    match host {
+        "" => {}
+        "localhost" => settings.bind = vec![Ipv4Addr::LOCALHOST.into(), Ipv6Addr::LOCALHOST.into()],
+        _ => match host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+            Ok(addr) => settings.bind = vec![addr],
+            Err(_) => warn!("Listening on all addresses rather than only {}", host),
+        },
+    }
+}
+
+// "serialdev,/dev/ttyS0,9600n81,rtscts,...".
+fn connector(connector: &str, settings: &mut Settings) -> Result<()> {
+    let parts = split(connector);
+    let (kind, _) = layer(parts.first().map_or("", String::as_str));
+    if kind != "serialdev" {
+        bail!("unsupported connector '{}', only serialdev is", kind);
+    }
+    let device = parts.get(1).with_context(|| format!("connector '{}' has no device", connector))?;
+    settings.serial_port = Some(device.clone());
+    for option in &parts[2..] {
+        match option.as_str() {
+            o if o.starts_with(|c: char| c.is_ascii_digit()) => line_settings(o, settings)?,
+            "rtscts" => settings.flow_control = Some(FlowControlArg::Hardware),
+            "xonxoff" => settings.flow_control = Some(FlowControlArg::Software),
+            // Modem control lines are not watched anyway.
+            "local" | "-local" | "clocal" => {}
+            _ => warn!("Ignoring unsupported serialdev option {}", option),
+        }
+    }
+    Ok(())
+}
+
+// "9600", or "9600n81": speed, then parity, data bits and stop bits.
+fn line_settings(spec: &str, settings: &mut Settings) -> Result<()> {
+    let digits = spec.find(|c: char| !c.is_ascii_digit()).unwrap_or(spec.len());
+    settings.baud_rate = Some(spec[..digits].parse().with_context(|| format!("invalid speed '{}'", spec))?);
+    let rest = &spec.as_bytes()[digits..];
+    if rest.is_empty() {
+        return Ok(());
+    }
+    let [parity, data_bits, stop_bits] = rest else {
+        bail!("invalid serial settings '{}', expected e.g. 9600n81", spec);
+    };
+    settings.parity = Some(parity_arg(*parity as char).with_context(|| format!("unsupported parity in '{}'", spec))?);
+    settings.data_bits = Some(data_bits.wrapping_sub(b'0'));
+    settings.stop_bits = Some(match stop_bits {
+        b'1' => StopBitsArg::One,
+        b'2' => StopBitsArg::Two,
+        _ => bail!("invalid stop bits in '{}'", spec),
+    });
+    Ok(())
+}
+
+fn parity_arg(parity: char) -> Option<ParityArg> {
+    match parity.to_ascii_lowercase() {
+        'n' => Some(ParityArg::None),
+        'e' => Some(ParityArg::Even),
+        'o' => Some(ParityArg::Odd),
+        'm' => Some(ParityArg::Mark),
+        's' => Some(ParityArg::Space),
+        _ => None,
+    }
+}
+
+// ser2net's "default:" entries change a setting for every connection.
+fn apply_default(defaults: &mut Settings, default: DefaultEntry) -> Result<()> {
+    if default.class.as_deref().is_some_and(|class| class != "serialdev") {
+        warn!("Ignoring ser2net default {} for {}", default.name, default.class.unwrap_or_default());
+        return Ok(());
+    }
+    let text = match &default.value {
+        Value::String(text) => text.clone(),
+        value => serde_yaml::to_string(value)?.trim().to_string(),
+    };
+    match default.name.as_str() {
+        "speed" => line_settings(&text, defaults)?,
+        "databits" => defaults.data_bits = Some(text.parse().context("invalid databits default")?),
+        "parity" => {
+            defaults.parity = Some(text.chars().next().and_then(parity_arg).context("unsupported parity default")?)
+        }
+        "stopbits" => {
+            defaults.stop_bits = Some(match text.as_str() {
+                "1" => StopBitsArg::One,
+                "2" => StopBitsArg::Two,
+                _ => bail!("invalid stopbits default '{}'", text),
+            })
+        }
+        "rtscts" if truthy(&default.value) => defaults.flow_control = Some(FlowControlArg::Hardware),
+        "xonxoff" if truthy(&default.value) => defaults.flow_control = Some(FlowControlArg::Software),
+        "rtscts" | "xonxoff" | "local" => {}
+        name => warn!("Ignoring ser2net default {}", name),
+    }
+    Ok(())
+}
+
+// YAML 1.1, which ser2net speaks, also spells booleans on/off and yes/no.
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Bool(b) => *b,
+        Value::String(s) => matches!(s.as_str(), "on" | "yes" | "true"),
+        _ => false,
+    }
+}
+
+// Splits gensio's comma-separated syntax, leaving commas inside a layer's
+// parentheses alone.
+fn split(spec: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut depth = 0usize;
+    for c in spec.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push(part.trim().to_string());
+                part.clear();
+                continue;
+            }
+            _ => {}
+        }
+        part.push(c);
+    }
+    if !part.trim().is_empty() {
+        parts.push(part.trim().to_string());
+    }
+    parts
+}
+
+// "ssl(key=a.pem,cert=b.pem)" into its name and key[=value] options.
+fn layer(part: &str) -> (&str, Vec<(&str, Option<&str>)>) {
+    let Some((name, options)) = part.split_once('(') else {
+        return (part, Vec::new());
+    };
+    let options = options
+        .trim_end_matches(')')
+        .split(',')
+        .map(str::trim)
+        .filter(|option| !option.is_empty())
+        .map(|option| match option.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value.trim())),
+            None => (option, None),
+        })
+        .collect();
+    (name.trim(), options)
+}
@@ -0,0 +1,851 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use tokio_serial::{ClearBuffer, DataBits, FlowControl, SerialPort, SerialPortBuilder, SerialStream, StopBits};
+use tracing::{Instrument, debug, error, info, warn};
+
+use crate::capture::{self, Capture};
+use crate::dump::HexDump;
+use crate::kafka::KafkaTap;
+#[cfg(unix)]
+use crate::lock::Lock;
+use crate::rs485::Rs485;
+use crate::script::Script;
+use crate::usb::UsbId;
+use crate::{Backpressure, LineAction, ParityArg, baud, latency, parity};
+
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// How long a pulse holds the line cleared.
+const PULSE_WIDTH: Duration = Duration::from_millis(100);
+const BREAK_LENGTH: Duration = Duration::from_millis(250);
+// How often a blocked port checks whether subscribers have caught up.
+const CONGESTION_POLL: Duration = Duration::from_millis(10);
+// Paced writes go out in chunks of about this much line time.
+const PACE_INTERVAL: Duration = Duration::from_millis(10);
+
+const DISCONNECTED_NOTICE: &str = "\r\n[serial port disconnected]\r\n";
+const RECONNECTED_NOTICE: &str = "\r\n[serial port reconnected]\r\n";
+const REPLAY_NOTICE: &str = "[recent output]\r\n";
+
+// Operations a client may perform on the port besides writing data.
+#[derive(Clone, Debug)]
+pub enum Control {
+    Status,
+    BaudRate(u32),
+    DataBits(DataBits),
+    Parity(ParityArg),
+    StopBits(StopBits),
+    FlowControl(FlowControl),
+    Break(bool),
+    Dtr(bool),
+    Rts(bool),
+    Purge(ClearBuffer),
+}
+
+#[derive(Clone, Debug)]
+pub struct PortStatus {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: ParityArg,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    pub dtr: bool,
+    pub rts: bool,
+    pub break_on: bool,
+    pub cts: bool,
+    pub dsr: bool,
+    pub ri: bool,
+    pub cd: bool,
+}
+
+// Which way bytes travelled, seen from the serial port.
+#[derive(Clone, Copy, Debug)]
+pub enum Direction {
+    // Read from the device.
+    Rx,
+    // Written to the device.
+    Tx,
+}
+
+// Optional observers of everything that passes through the port.
+#[derive(Default)]
+pub struct Taps {
+    pub capture: Option<Capture>,
+    pub dump: Option<HexDump>,
+    pub kafka: Option<KafkaTap>,
+}
+
+impl Taps {
+    async fn observe(&mut self, direction: Direction, data: &[u8]) {
+        capture::capture(&mut self.capture, direction, data).await;
+        if let Some(dump) = &self.dump {
+            dump.write(direction, data);
+        }
+        if let Some(kafka) = &self.kafka {
+            kafka.observe(direction, data);
+        }
+    }
+}
+
+enum Request {
+    Write(Bytes),
+    Control(Control, oneshot::Sender<PortStatus>),
+    // Drain what has been written so far and close the port.
+    Close(oneshot::Sender<()>),
+    // Close the port and open it again, as if the device had gone away.
+    Reopen,
+}
+
+// Traffic and failures since the port was opened.
+#[derive(Default)]
+pub struct Counters {
+    pub rx_bytes: AtomicU64,
+    pub tx_bytes: AtomicU64,
+    pub errors: AtomicU64,
+    pub reopens: AtomicU64,
+    // Serial reads subscribers fell too far behind to receive.
+    pub dropped_reads: AtomicU64,
+    pub slow_disconnects: AtomicU64,
+    // Unix times of the last read and write, 0 if there has been none.
+    pub last_rx: AtomicU64,
+    pub last_tx: AtomicU64,
+    // Bytes per second, kept up to date by stats::sample.
+    pub rx_rate: AtomicU64,
+    pub tx_rate: AtomicU64,
+    // False while the device is gone and being reopened.
+    pub connected: AtomicBool,
+}
+
+// Serial output on its way to subscribers.
+struct Output {
+    sender: broadcast::Sender<Bytes>,
+    backlog: Option<Mutex<Backlog>>,
+}
+
+// What serial output is kept for client sessions attaching later, up to
+// the given number of bytes with the oldest dropped first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Retain {
+    // Output while no session is attached, handed to the next one.
+    Offline(usize),
+    // The most recent output, replayed to every new session.
+    Recent(usize),
+}
+
+// How serial output is read and queued for subscribers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Buffers {
+    // Bytes read from the port at a time.
+    pub read_size: usize,
+    // Reads queued for each subscriber.
+    pub queue: usize,
+    pub backpressure: Backpressure,
+}
+
+// How full the queues between the port and its users are, as (queued,
+// capacity) pairs.
+pub struct Fill {
+    // Reads the slowest subscriber has yet to take.
+    pub output: (usize, usize),
+    // Requests, writes among them, waiting for the port.
+    pub requests: (usize, usize),
+    // Bytes kept for sessions attaching later, under offline_buffer or
+    // replay_buffer.
+    pub retained: Option<(usize, usize)>,
+}
+
+struct Backlog {
+    retain: Retain,
+    data: VecDeque<u8>,
+    attached: usize,
+}
+
+impl Output {
+    fn send(&self, data: Bytes) {
+        // Held across the send so that a session attaching gets each
+        // chunk either in the backlog or from its receiver, never both.
+        let mut backlog = self.backlog.as_ref().map(|backlog| backlog.lock().unwrap());
+        if let Some(backlog) = backlog.as_mut() {
+            let capacity = match backlog.retain {
+                Retain::Offline(_) if backlog.attached > 0 => 0,
+                Retain::Offline(capacity) | Retain::Recent(capacity) => capacity,
+            };
+            backlog.data.extend(&data[..]);
+            let excess = backlog.data.len().saturating_sub(capacity);
+            backlog.data.drain(..excess);
+        }
+        // Nobody listening is not an error; the data is simply dropped.
+        let _ = self.sender.send(data);
+    }
+}
+
+// A client session's subscription to serial output: what was retained for
+// it, then everything after.
+pub struct Attached {
+    pub backlog: Bytes,
+    pub output: broadcast::Receiver<Bytes>,
+    from: Arc<Output>,
+}
+
+impl Drop for Attached {
+    fn drop(&mut self) {
+        if let Some(backlog) = &self.from.backlog {
+            backlog.lock().unwrap().attached -= 1;
+        }
+    }
+}
+
+// Output for a handle from SerialHandle::fed.
+pub struct Feed(Arc<Output>);
+
+impl Feed {
+    pub fn send(&self, data: Bytes) {
+        self.0.send(data);
+    }
+}
+
+// Cloneable handle to the task that owns the serial port.
+#[derive(Clone)]
+pub struct SerialHandle {
+    requests: mpsc::Sender<Request>,
+    output: Arc<Output>,
+    counters: Arc<Counters>,
+    backpressure: Backpressure,
+    queue: usize,
+}
+
+impl SerialHandle {
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    pub fn backpressure(&self) -> Backpressure {
+        self.backpressure
+    }
+
+    pub fn fill(&self) -> Fill {
+        let capacity = self.requests.max_capacity();
+        let retained = self.output.backlog.as_ref().map(|backlog| {
+            let backlog = backlog.lock().unwrap();
+            let (Retain::Offline(capacity) | Retain::Recent(capacity)) = backlog.retain;
+            (backlog.data.len(), capacity)
+        });
+        Fill {
+            output: (self.output.sender.len(), self.queue),
+            requests: (capacity - self.requests.capacity(), capacity),
+            retained,
+        }
+    }
+
+    // Counts serial reads a lagging subscriber missed.
+    pub fn dropped(&self, reads: u64) {
+        self.counters.dropped_reads.fetch_add(reads, Ordering::Relaxed);
+    }
+
+    // Counts a subscriber disconnected for falling `reads` behind.
+    pub fn disconnected(&self, reads: u64) {
+        self.dropped(reads);
+        self.counters.slow_disconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Bytes> {
+        self.output.sender.subscribe()
+    }
+
+    // Subscribes a client session. Offline output stops being retained
+    // until the last attached session is dropped; recent output is replayed
+    // behind REPLAY_NOTICE.
+    pub fn attach(&self) -> Attached {
+        let mut backlog = self.output.backlog.as_ref().map(|backlog| backlog.lock().unwrap());
+        let kept = match backlog.as_mut() {
+            Some(backlog) => {
+                backlog.attached += 1;
+                match backlog.retain {
+                    Retain::Offline(_) => Vec::from(std::mem::take(&mut backlog.data)),
+                    Retain::Recent(_) if backlog.data.is_empty() => Vec::new(),
+                    Retain::Recent(_) => {
+                        let mut replay = REPLAY_NOTICE.as_bytes().to_vec();
+                        replay.extend(&backlog.data);
+                        replay
+                    }
+                }
+            }
+            None => Vec::new(),
+        };
+        Attached {
+            backlog: Bytes::from(kept),
+            output: self.subscribe(),
+            from: self.output.clone(),
+        }
+    }
+
+    // A handle on the same port whose subscribers and sessions get what is
+    // sent through the returned Feed instead of what the port reads, kept
+    // for late sessions as the port's output is.
+    pub fn fed(&self) -> (SerialHandle, Feed) {
+        let output = Arc::new(Output {
+            sender: broadcast::channel(self.queue).0,
+            backlog: self.output.backlog.as_ref().map(|backlog| {
+                Mutex::new(Backlog {
+                    retain: backlog.lock().unwrap().retain,
+                    data: VecDeque::new(),
+                    attached: 0,
+                })
+            }),
+        });
+        let handle = SerialHandle {
+            output: output.clone(),
+            ..self.clone()
+        };
+        (handle, Feed(output))
+    }
+
+    pub async fn write(&self, data: Bytes) -> Result<()> {
+        self.requests
+            .send(Request::Write(data))
+            .await
+            .map_err(|_| anyhow!("serial port task has stopped"))
+    }
+
+    pub async fn control(&self, control: Control) -> Result<PortStatus> {
+        let (tx, rx) = oneshot::channel();
+        self.requests
+            .send(Request::Control(control, tx))
+            .await
+            .map_err(|_| anyhow!("serial port task has stopped"))?;
+        Ok(rx.await?)
+    }
+
+    // Waits for writes already queued to reach the wire, then closes the
+    // port. Later requests fail as if the task had stopped.
+    pub async fn close(&self) -> Result<()> {
+        let (tx, rx) = oneshot::channel();
+        self.requests
+            .send(Request::Close(tx))
+            .await
+            .map_err(|_| anyhow!("serial port task has stopped"))?;
+        Ok(rx.await?)
+    }
+
+    pub async fn reopen(&self) -> Result<()> {
+        self.requests
+            .send(Request::Reopen)
+            .await
+            .map_err(|_| anyhow!("serial port task has stopped"))
+    }
+
+    // Holds the line in the break condition for BREAK_LENGTH.
+    pub async fn send_break(&self) -> Result<PortStatus> {
+        self.control(Control::Break(true)).await?;
+        tokio::time::sleep(BREAK_LENGTH).await;
+        self.control(Control::Break(false)).await
+    }
+
+    // Sets, clears or pulses a modem control line; `line` is Control::Dtr
+    // or Control::Rts.
+    pub async fn line(&self, line: fn(bool) -> Control, action: LineAction) -> Result<PortStatus> {
+        match action {
+            LineAction::Set => self.control(line(true)).await,
+            LineAction::Clear => self.control(line(false)).await,
+            LineAction::Pulse => {
+                self.control(line(false)).await?;
+                tokio::time::sleep(PULSE_WIDTH).await;
+                self.control(line(true)).await
+            }
+        }
+    }
+}
+
+pub fn spawn(
+    port: Opened,
+    device: Device,
+    taps: Taps,
+    notify: bool,
+    retain: Option<Retain>,
+    buffers: Buffers,
+    script: Option<Arc<Script>>,
+) -> SerialHandle {
+    let (requests, rx) = mpsc::channel(64);
+    let (sender, _) = broadcast::channel(buffers.queue);
+    let output = Arc::new(Output {
+        sender,
+        backlog: retain.map(|retain| {
+            Mutex::new(Backlog {
+                retain,
+                data: VecDeque::new(),
+                attached: 0,
+            })
+        }),
+    });
+    let counters = Arc::new(Counters::default());
+    counters.connected.store(true, Ordering::Relaxed);
+    let task = Task {
+        device,
+        output: output.clone(),
+        counters: counters.clone(),
+        taps,
+        notify,
+        buffers,
+        script,
+        #[cfg(unix)]
+        lock: None,
+    };
+    tokio::spawn(task.run(port, rx).in_current_span());
+    SerialHandle {
+        requests,
+        output,
+        counters,
+        backpressure: buffers.backpressure,
+        queue: buffers.queue,
+    }
+}
+
+// An open port, claimed for as long as it is open.
+pub struct Opened {
+    port: SerialStream,
+    path: String,
+    #[cfg(unix)]
+    lock: Option<Lock>,
+}
+
+// Opens the port at `path` exclusively, with the rate and parity serialport
+// may not know how to set; the builder has the rest.
+pub fn open(path: &str, builder: SerialPortBuilder, baud: u32, parity: ParityArg) -> Result<Opened> {
+    #[cfg(unix)]
+    let lock = Lock::acquire(path)?;
+    let mut port = baud::open(builder.path(path), baud)?;
+    #[cfg(unix)]
+    port.set_exclusive(true)?;
+    parity::set(&mut port, parity)?;
+    Ok(Opened {
+        port,
+        path: path.to_string(),
+        #[cfg(unix)]
+        lock,
+    })
+}
+
+// Where the port lives; a USB adapter is looked up again on every reopen
+// since it may come back under a different path.
+pub struct Device {
+    pub path: String,
+    pub builder: SerialPortBuilder,
+    pub usb_id: Option<UsbId>,
+    pub rs485: Option<Rs485>,
+    // Hold writes to the line rate, for devices without flow control whose
+    // FIFO would otherwise overflow.
+    pub pace: bool,
+    pub low_latency: bool,
+    // Sent on every open.
+    pub init: Option<Bytes>,
+    pub heartbeat: Option<Heartbeat>,
+}
+
+impl Device {
+    // Lets go of the RS-485 bus, if the device is on one.
+    fn release(&self, port: &mut SerialStream) {
+        if let Some(rs485) = &self.rs485
+            && let Err(e) = rs485.release(port)
+        {
+            warn!("Failed to release RS-485 driver: {}", e);
+        }
+    }
+
+    fn path(&self) -> Result<String> {
+        match &self.usb_id {
+            Some(id) => id.find(),
+            None => Ok(self.path.clone()),
+        }
+    }
+}
+
+// Bytes sent to the device at a fixed interval while it is open.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub data: Bytes,
+}
+
+struct LineState {
+    dtr: bool,
+    rts: bool,
+    break_on: bool,
+}
+
+struct Task {
+    // Used to reopen the port after the device goes away.
+    device: Device,
+    output: Arc<Output>,
+    counters: Arc<Counters>,
+    taps: Taps,
+    // Tell clients in-band when the device disappears and returns.
+    notify: bool,
+    buffers: Buffers,
+    script: Option<Arc<Script>>,
+    // Held while the port is open.
+    #[cfg(unix)]
+    lock: Option<Lock>,
+}
+
+impl Task {
+    async fn run(mut self, opened: Opened, mut requests: mpsc::Receiver<Request>) {
+        let mut lines = LineState {
+            dtr: true,
+            rts: true,
+            break_on: false,
+        };
+        let mut port = Some(self.claim(opened));
+        let mut last = status(port.as_mut().unwrap(), &lines);
+        self.device.release(port.as_mut().unwrap());
+        self.init(port.as_mut().unwrap()).await;
+        let mut backoff = MIN_BACKOFF;
+        let mut retry_at = Instant::now();
+        let mut buf = vec![0u8; self.buffers.read_size];
+        // The rest of a paced write, and when its next chunk is due.
+        let mut paced = Bytes::new();
+        let mut next_write = Instant::now();
+        let mut heartbeat = self.device.heartbeat.clone().map(|heartbeat| {
+            let mut interval = tokio::time::interval_at(Instant::now() + heartbeat.interval, heartbeat.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            (interval, heartbeat.data)
+        });
+        loop {
+            let Some(active) = port.as_mut() else {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(retry_at) => {
+                        match self.reopen(&last, &lines) {
+                            Ok(reopened) => {
+                                info!(event = "serial_reconnect", "Serial port is back");
+                                self.counters.reopens.fetch_add(1, Ordering::Relaxed);
+                                self.counters.connected.store(true, Ordering::Relaxed);
+                                self.notice(RECONNECTED_NOTICE);
+                                let active = port.insert(self.claim(reopened));
+                                self.init(active).await;
+                                backoff = MIN_BACKOFF;
+                            }
+                            Err(e) => {
+                                debug!("Reopening serial port failed: {}", e);
+                                backoff = (backoff * 2).min(MAX_BACKOFF);
+                                retry_at = Instant::now() + backoff;
+                            }
+                        }
+                    },
+                    request = requests.recv() => {
+                        match request {
+                            // Nowhere to send it until the device returns.
+                            Some(Request::Write(_)) => {},
+                            Some(Request::Control(control, reply)) => {
+                                remember(&mut last, &mut lines, &control);
+                                let _ = reply.send(offline(&last, &lines));
+                            },
+                            Some(Request::Close(done)) => {
+                                let _ = done.send(());
+                                return;
+                            },
+                            // Already under way.
+                            Some(Request::Reopen) => {},
+                            None => return,
+                        }
+                    }
+                }
+                continue;
+            };
+            let mut closing = None;
+            let mut reopening = false;
+            // A value stays queued until every subscriber has taken it, so
+            // this waits for the slowest one.
+            let congested =
+                self.buffers.backpressure == Backpressure::Block && self.output.sender.len() >= self.buffers.queue;
+            let lost = tokio::select! {
+                read = active.read(&mut buf), if !congested => {
+                    match read {
+                        Ok(n) if n > 0 => {
+                            self.counters.rx_bytes.fetch_add(n as u64, Ordering::Relaxed);
+                            self.counters.last_rx.store(unix_time(), Ordering::Relaxed);
+                            self.taps.observe(Direction::Rx, &buf[..n]).await;
+                            let data = Bytes::copy_from_slice(&buf[..n]);
+                            self.received(active, data).await
+                        },
+                        Ok(_) => Some("end of file".to_string()),
+                        Err(e) => Some(e.to_string()),
+                    }
+                },
+                _ = tokio::time::sleep(CONGESTION_POLL), if congested => None,
+                _ = tokio::time::sleep_until(next_write), if !paced.is_empty() => {
+                    let char_time = char_time(&last);
+                    let chunk = paced.split_to(paced.len().min(pace_chunk(char_time)));
+                    next_write += char_time * chunk.len() as u32;
+                    self.write(active, &chunk).await
+                },
+                data = beat(&mut heartbeat), if paced.is_empty() => self.write(active, &data).await,
+                // Later requests wait for a paced write to finish, so that
+                // nothing overtakes it.
+                request = requests.recv(), if paced.is_empty() => {
+                    match request {
+                        Some(Request::Write(data)) if self.device.pace && !char_time(&last).is_zero() => {
+                            paced = data;
+                            next_write = next_write.max(Instant::now());
+                            None
+                        },
+                        Some(Request::Write(data)) => self.write(active, &data).await,
+                        Some(Request::Control(control, reply)) => {
+                            if matches!(control, Control::Rts(_)) && self.device.rs485.as_ref().is_some_and(Rs485::uses_rts) {
+                                debug!("Ignoring RTS change, RTS drives the RS-485 direction");
+                            } else if !apply(active, &mut lines, &control) {
+                                self.counters.errors.fetch_add(1, Ordering::Relaxed);
+                            }
+                            last = status(active, &lines);
+                            let _ = reply.send(last.clone());
+                            None
+                        },
+                        Some(Request::Close(done)) => {
+                            // tcdrain(); returns once the UART has sent everything.
+                            if let Err(e) = active.flush().await {
+                                warn!("Failed to drain serial output: {}", e);
+                            }
+                            closing = Some(done);
+                            None
+                        },
+                        Some(Request::Reopen) => {
+                            info!("Reopening serial port");
+                            reopening = true;
+                            None
+                        },
+                        None => return,
+                    }
+                }
+            };
+            if let Some(done) = closing {
+                drop(port);
+                info!("Serial port closed");
+                let _ = done.send(());
+                return;
+            }
+            if let Some(e) = &lost {
+                error!(event = "serial_error", error = %e, "Serial port lost: {}; reopening", e);
+                self.counters.errors.fetch_add(1, Ordering::Relaxed);
+            }
+            if lost.is_some() || reopening {
+                self.counters.connected.store(false, Ordering::Relaxed);
+                self.notice(DISCONNECTED_NOTICE);
+                paced.clear();
+                port = None;
+                #[cfg(unix)]
+                {
+                    self.lock = None;
+                }
+                backoff = MIN_BACKOFF;
+                retry_at = Instant::now() + backoff;
+            }
+        }
+    }
+
+    async fn write(&mut self, port: &mut SerialStream, data: &[u8]) -> Option<String> {
+        let written = match &self.device.rs485 {
+            Some(rs485) => rs485.transmit(port, data).await,
+            None => port.write_all(data).await,
+        };
+        match written {
+            Ok(()) => {
+                self.counters.tx_bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+                self.counters.last_tx.store(unix_time(), Ordering::Relaxed);
+                self.taps.observe(Direction::Tx, data).await;
+                None
+            }
+            Err(e) => Some(e.to_string()),
+        }
+    }
+
+    // Passes a read on to subscribers, by way of the script's
+    // on_serial_data if there is one.
+    async fn received(&mut self, port: &mut SerialStream, data: Bytes) -> Option<String> {
+        let Some(script) = self.script.clone() else {
+            self.output.send(data);
+            return None;
+        };
+        let (data, effects) = script.on_serial_data(data);
+        if !data.is_empty() {
+            self.output.send(data);
+        }
+        for reply in effects.to_client {
+            self.output.send(reply);
+        }
+        for data in effects.to_serial {
+            if let Some(e) = self.write(port, &data).await {
+                return Some(e);
+            }
+        }
+        None
+    }
+
+    // Takes on a port just opened, on every reopen as well.
+    fn claim(&mut self, opened: Opened) -> SerialStream {
+        #[cfg(unix)]
+        {
+            self.lock = opened.lock;
+        }
+        if self.device.low_latency {
+            latency::set(&opened.port, &opened.path);
+        }
+        opened.port
+    }
+
+    // Sends init_send ahead of anything from clients. A failed write is
+    // left for the next read to find the port lost by.
+    async fn init(&mut self, port: &mut SerialStream) {
+        let Some(init) = self.device.init.clone() else {
+            return;
+        };
+        if let Some(e) = self.write(port, &init).await {
+            warn!("Failed to send init_send: {}", e);
+        }
+    }
+
+    // Opens the device again with the settings clients last asked for.
+    fn reopen(&self, last: &PortStatus, lines: &LineState) -> Result<Opened> {
+        let builder = self
+            .device
+            .builder
+            .clone()
+            .data_bits(last.data_bits)
+            .stop_bits(last.stop_bits)
+            .flow_control(last.flow_control);
+        let mut opened = open(&self.device.path()?, builder, last.baud_rate, last.parity)?;
+        let port = &mut opened.port;
+        // Not every device has modem lines (ptys don't), so this is best effort.
+        if let Err(e) = port
+            .write_data_terminal_ready(lines.dtr)
+            .and_then(|_| port.write_request_to_send(lines.rts))
+        {
+            debug!("Could not restore DTR/RTS: {}", e);
+        }
+        self.device.release(port);
+        Ok(opened)
+    }
+
+    fn notice(&self, text: &'static str) {
+        if self.notify {
+            self.output.send(Bytes::from_static(text.as_bytes()));
+        }
+    }
+}
+
+// The heartbeat's bytes each time they are due; never without one.
+async fn beat(heartbeat: &mut Option<(Interval, Bytes)>) -> Bytes {
+    match heartbeat {
+        Some((interval, data)) => {
+            interval.tick().await;
+            data.clone()
+        }
+        None => std::future::pending().await,
+    }
+}
+
+// Records a change requested while the device is away, to apply on reopen.
+fn remember(last: &mut PortStatus, lines: &mut LineState, control: &Control) {
+    match *control {
+        Control::BaudRate(baud) => last.baud_rate = baud,
+        Control::DataBits(bits) => last.data_bits = bits,
+        Control::Parity(parity) => last.parity = parity,
+        Control::StopBits(stop_bits) => last.stop_bits = stop_bits,
+        Control::FlowControl(flow) => last.flow_control = flow,
+        Control::Dtr(level) => lines.dtr = level,
+        Control::Rts(level) => lines.rts = level,
+        Control::Status | Control::Break(_) | Control::Purge(_) => {}
+    }
+}
+
+// Status reported while the device is away: the pending settings, with all
+// modem inputs low.
+fn offline(last: &PortStatus, lines: &LineState) -> PortStatus {
+    PortStatus {
+        dtr: lines.dtr,
+        rts: lines.rts,
+        break_on: false,
+        cts: false,
+        dsr: false,
+        ri: false,
+        cd: false,
+        ..last.clone()
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+// How long one character takes on the line: a start bit, the data bits,
+// parity and stop bits. Zero if the baud rate is unknown.
+fn char_time(status: &PortStatus) -> Duration {
+    if status.baud_rate == 0 {
+        return Duration::ZERO;
+    }
+    let data = match status.data_bits {
+        DataBits::Five => 5,
+        DataBits::Six => 6,
+        DataBits::Seven => 7,
+        DataBits::Eight => 8,
+    };
+    let parity = match status.parity {
+        ParityArg::None => 0,
+        ParityArg::Odd | ParityArg::Even | ParityArg::Mark | ParityArg::Space => 1,
+    };
+    let stop = match status.stop_bits {
+        StopBits::One => 1,
+        StopBits::Two => 2,
+    };
+    Duration::from_secs(1 + data + parity + stop) / status.baud_rate
+}
+
+// Characters making up about PACE_INTERVAL of line time, at least one.
+fn pace_chunk(char_time: Duration) -> usize {
+    (PACE_INTERVAL.as_nanos() / char_time.as_nanos()).max(1) as usize
+}
+
+// Returns false if the port rejected the change.
+fn apply(port: &mut SerialStream, lines: &mut LineState, control: &Control) -> bool {
+    let result = match *control {
+        Control::Status => Ok(()),
+        Control::BaudRate(baud) => baud::set(port, baud),
+        Control::DataBits(bits) => port.set_data_bits(bits),
+        Control::Parity(parity) => parity::set(port, parity),
+        Control::StopBits(stop_bits) => port.set_stop_bits(stop_bits),
+        Control::FlowControl(flow) => port.set_flow_control(flow),
+        Control::Break(true) => port.set_break().map(|_| lines.break_on = true),
+        Control::Break(false) => port.clear_break().map(|_| lines.break_on = false),
+        Control::Dtr(level) => port
+            .write_data_terminal_ready(level)
+            .map(|_| lines.dtr = level),
+        Control::Rts(level) => port.write_request_to_send(level).map(|_| lines.rts = level),
+        Control::Purge(buffer) => port.clear(buffer),
+    };
+    if let Err(e) = &result {
+        warn!("Failed to apply {:?}: {}", control, e);
+    }
+    result.is_ok()
+}
+
+fn status(port: &mut SerialStream, lines: &LineState) -> PortStatus {
+    PortStatus {
+        baud_rate: baud::rate(port),
+        data_bits: port.data_bits().unwrap_or(DataBits::Eight),
+        parity: parity::get(port),
+        stop_bits: port.stop_bits().unwrap_or(StopBits::One),
+        flow_control: port.flow_control().unwrap_or(FlowControl::None),
+        dtr: lines.dtr,
+        rts: lines.rts,
+        break_on: lines.break_on,
+        cts: port.read_clear_to_send().unwrap_or(false),
+        dsr: port.read_data_set_ready().unwrap_or(false),
+        ri: port.read_ring_indicator().unwrap_or(false),
+        cd: port.read_carrier_detect().unwrap_or(false),
+    }
+}
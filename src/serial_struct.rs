@@ -0,0 +1,56 @@
+// The driver settings TIOCGSERIAL and TIOCSSERIAL get and set on Linux, for
+// what termios has no flag for.
+
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::fd::AsRawFd;
+
+use libc::{c_char, c_int, c_uchar, c_uint, c_ulong, c_ushort};
+use tokio_serial::SerialStream;
+
+pub const ASYNC_SPD_MASK: c_int = 0x1030;
+pub const ASYNC_SPD_CUST: c_int = 0x0030;
+pub const ASYNC_LOW_LATENCY: c_int = 0x2000;
+
+// struct serial_struct, from <linux/serial.h>.
+#[repr(C)]
+pub struct SerialStruct {
+    kind: c_int,
+    line: c_int,
+    port: c_uint,
+    irq: c_int,
+    pub flags: c_int,
+    xmit_fifo_size: c_int,
+    pub custom_divisor: c_int,
+    pub baud_base: c_int,
+    close_delay: c_ushort,
+    io_type: c_char,
+    reserved_char: c_char,
+    hub6: c_int,
+    closing_wait: c_ushort,
+    closing_wait2: c_ushort,
+    iomem_base: *mut c_uchar,
+    iomem_reg_shift: c_ushort,
+    port_high: c_uint,
+    iomap_base: c_ulong,
+}
+
+pub fn get(port: &SerialStream) -> io::Result<SerialStruct> {
+    let mut serial = MaybeUninit::<SerialStruct>::zeroed();
+    // SAFETY: TIOCGSERIAL fills in a serial_struct, which is all integers
+    // and a pointer, so zeroed is valid even if it does not.
+    unsafe {
+        if libc::ioctl(port.as_raw_fd(), libc::TIOCGSERIAL, serial.as_mut_ptr()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(serial.assume_init())
+    }
+}
+
+pub fn set(port: &SerialStream, serial: &SerialStruct) -> io::Result<()> {
+    // SAFETY: TIOCSSERIAL only reads the serial_struct.
+    if unsafe { libc::ioctl(port.as_raw_fd(), libc::TIOCSSERIAL, serial) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
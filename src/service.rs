@@ -0,0 +1,147 @@
+use std::ffi::OsString;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+use clap::{Parser, Subcommand};
+use tokio::sync::Notify;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode, ServiceInfo,
+    ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+use crate::cli::{self, Args};
+
+const SERVICE_NAME: &str = "remote-serial-server";
+
+#[derive(Subcommand, Debug)]
+pub enum Action {
+    /// Register the service to start at boot. Arguments after "--" are the
+    /// ones it runs with, e.g. -- --config C:\rss\config.toml
+    Install {
+        #[arg(last = true)]
+        args: Vec<OsString>,
+    },
+    /// Stop the service if it is running and remove it.
+    Uninstall,
+    /// Entry point used by the service control manager.
+    #[command(hide = true)]
+    Run {
+        #[arg(last = true)]
+        args: Vec<OsString>,
+    },
+}
+
+// The arguments `service run` was started with, for the service main
+// function, which the dispatcher calls without them.
+static ARGS: Mutex<Option<Args>> = Mutex::new(None);
+
+pub fn handle(action: Action) -> Result<()> {
+    match action {
+        Action::Install { args } => install(args),
+        Action::Uninstall => uninstall(),
+        Action::Run { args } => {
+            let program = std::env::args_os().next().unwrap_or_default();
+            let args = Args::try_parse_from(std::iter::once(program).chain(args))?;
+            *ARGS.lock().unwrap() = Some(args);
+            service_dispatcher::start(SERVICE_NAME, ffi_service_main).context("failed to start the service dispatcher")
+        }
+    }
+}
+
+fn install(args: Vec<OsString>) -> Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+    let mut launch_arguments: Vec<OsString> = vec!["service".into(), "run".into(), "--".into()];
+    launch_arguments.extend(args);
+    let info = ServiceInfo {
+        name: SERVICE_NAME.into(),
+        display_name: "Remote Serial Server".into(),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments,
+        dependencies: Vec::new(),
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .context("failed to create the service")?;
+    service.set_description("Bridges serial ports to TCP")?;
+    println!("Installed service {}", SERVICE_NAME);
+    Ok(())
+}
+
+fn uninstall() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .context("failed to open the service")?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    service.delete()?;
+    println!("Removed service {}", SERVICE_NAME);
+    Ok(())
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        tracing::error!("Service failed: {:#}", e);
+    }
+}
+
+fn run_service() -> Result<()> {
+    let args = ARGS.lock().unwrap().take().ok_or_else(|| anyhow!("service started twice"))?;
+    let stop = Arc::new(Notify::new());
+    let handler = {
+        let stop = stop.clone();
+        move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                stop.notify_one();
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        }
+    };
+    let status = service_control_handler::register(SERVICE_NAME, handler)?;
+    set_state(&status, ServiceState::Running, ServiceExitCode::Win32(0))?;
+
+    let result = tokio::runtime::Runtime::new()?.block_on(cli::serve(args, stop.notified()));
+    let exit_code = match result {
+        Ok(()) => ServiceExitCode::Win32(0),
+        // Shown by the service manager as ERROR_SERVICE_SPECIFIC_ERROR.
+        Err(_) => ServiceExitCode::ServiceSpecific(1),
+    };
+    set_state(&status, ServiceState::Stopped, exit_code)?;
+    result
+}
+
+fn set_state(status: &ServiceStatusHandle, state: ServiceState, exit_code: ServiceExitCode) -> Result<()> {
+    status.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted: match state {
+            ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            _ => ServiceControlAccept::empty(),
+        },
+        exit_code,
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    })?;
+    Ok(())
+}
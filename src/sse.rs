@@ -0,0 +1,93 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use crate::Backpressure;
+use crate::serial::SerialHandle;
+
+// Longer lines are sent in pieces of this size.
+const MAX_LINE: usize = 4096;
+// Comments sent while the port is quiet, so that proxies keep the stream
+// open and a client that went away is noticed.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+// Streams the port's output as server-sent events, one line per "message"
+// event, for `curl -N` or EventSource. No CORS headers are sent, so pages
+// from other origins cannot read it. Lines end at CR, LF or CRLF, are sent
+// once they end, and are decoded as UTF-8 with invalid bytes replaced.
+pub async fn serve<S>(mut stream: S, serial: &SerialHandle) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut output = serial.subscribe();
+    stream
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+              Connection: close\r\n\r\n",
+        )
+        .await?;
+    let mut keepalive = tokio::time::interval(KEEPALIVE);
+    keepalive.tick().await;
+    let mut line = Vec::new();
+    let mut after_cr = false;
+    let mut buf = [0u8; 64];
+    loop {
+        tokio::select! {
+            received = output.recv() => {
+                let data = match received {
+                    Ok(data) => data,
+                    Err(RecvError::Lagged(n)) if serial.backpressure() == Backpressure::Disconnect => {
+                        warn!("Disconnecting event stream, it fell behind by {} serial reads", n);
+                        serial.disconnected(n);
+                        return Ok(());
+                    }
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("Event stream fell behind, {} serial reads dropped", n);
+                        serial.dropped(n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+                let mut events = String::new();
+                for &byte in data.iter() {
+                    let crlf = after_cr && byte == b'\n';
+                    after_cr = byte == b'\r';
+                    if crlf {
+                        continue;
+                    }
+                    if byte == b'\r' || byte == b'\n' {
+                        event(&mut events, &line);
+                        line.clear();
+                        continue;
+                    }
+                    line.push(byte);
+                    if line.len() == MAX_LINE {
+                        event(&mut events, &line);
+                        line.clear();
+                    }
+                }
+                if !events.is_empty() {
+                    stream.write_all(events.as_bytes()).await?;
+                    keepalive.reset();
+                }
+            },
+            _ = keepalive.tick() => stream.write_all(b":\n\n").await?,
+            // Clients send nothing once they have asked; this only notices
+            // them hanging up.
+            read = stream.read(&mut buf) => {
+                if read? == 0 {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+fn event(events: &mut String, line: &[u8]) {
+    events.push_str("data: ");
+    events.push_str(&String::from_utf8_lossy(line));
+    events.push_str("\n\n");
+}
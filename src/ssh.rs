@@ -0,0 +1,163 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use russh::keys::{self, PublicKey};
+use russh::server::{self, Auth, Msg, Session};
+use russh::{Channel, ChannelId, ChannelStream, MethodKind, MethodSet, Pty};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::oneshot;
+use tokio::task::JoinSet;
+use tracing::{debug, info};
+
+// What SSH clients are offered and checked against.
+pub struct SshServer {
+    config: Arc<server::Config>,
+    authorized_keys: Arc<Vec<PublicKey>>,
+}
+
+// An SSH client's session channel, carrying raw serial traffic.
+pub struct Connection {
+    pub stream: ChannelStream<Msg>,
+    pub user: String,
+    channel: ChannelId,
+    handle: server::Handle,
+}
+
+impl Connection {
+    // Reports success and closes the channel, after which the client hangs
+    // up by itself.
+    pub async fn close(self) {
+        let _ = self.handle.exit_status_request(self.channel, 0).await;
+        let _ = self.handle.close(self.channel).await;
+    }
+}
+
+impl SshServer {
+    // Loads the host key and authorized_keys file; only public key
+    // authentication is offered.
+    pub fn load(host_key: &Path, authorized_keys: &Path) -> Result<SshServer> {
+        let host_key = keys::load_secret_key(host_key, None)
+            .with_context(|| format!("failed to load SSH host key {}", host_key.display()))?;
+        let authorized_keys = load_authorized_keys(authorized_keys)?;
+        let config = server::Config {
+            methods: MethodSet::from(&[MethodKind::PublicKey][..]),
+            keys: vec![host_key],
+            // Idle sessions are the bridge's business.
+            inactivity_timeout: None,
+            ..Default::default()
+        };
+        Ok(SshServer {
+            config: Arc::new(config),
+            authorized_keys: Arc::new(authorized_keys),
+        })
+    }
+
+    // Runs the SSH handshake and waits for the client to open a session
+    // channel. The connection carries on in the background afterwards, but
+    // is dropped along with this future if it is cancelled before then.
+    pub async fn accept<S>(&self, stream: S) -> Result<Connection>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (opened, channel) = oneshot::channel();
+        let handler = Handler {
+            authorized_keys: self.authorized_keys.clone(),
+            user: None,
+            opened: Some(opened),
+        };
+        let session = server::run_stream(self.config.clone(), stream, handler).await?;
+        let handle = session.handle();
+        let mut running = JoinSet::new();
+        running.spawn(async move {
+            if let Err(e) = session.await {
+                debug!("SSH connection ended: {}", e);
+            }
+        });
+        let Ok((channel, user)) = channel.await else {
+            bail!("SSH client went away before opening a session");
+        };
+        running.detach_all();
+        Ok(Connection {
+            channel: channel.id(),
+            stream: channel.into_stream(),
+            user,
+            handle,
+        })
+    }
+}
+
+// One line per key, as OpenSSH's authorized_keys. Options before the key
+// type are skipped rather than enforced.
+fn load_authorized_keys(path: &Path) -> Result<Vec<PublicKey>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let keys: Vec<PublicKey> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_whitespace().find_map(|field| keys::parse_public_key_base64(field).ok()))
+        .collect();
+    if keys.is_empty() {
+        bail!("no public keys in {}", path.display());
+    }
+    Ok(keys)
+}
+
+struct Handler {
+    authorized_keys: Arc<Vec<PublicKey>>,
+    user: Option<String>,
+    // Taken by the first session channel; later ones are refused.
+    opened: Option<oneshot::Sender<(Channel<Msg>, String)>>,
+}
+
+impl server::Handler for Handler {
+    type Error = russh::Error;
+
+    async fn auth_publickey(&mut self, user: &str, key: &PublicKey) -> Result<Auth, Self::Error> {
+        if !self.authorized_keys.iter().any(|authorized| authorized.key_data() == key.key_data()) {
+            info!("Rejecting SSH key {} for {}", key.fingerprint(Default::default()), user);
+            return Ok(Auth::reject());
+        }
+        self.user = Some(user.to_string());
+        Ok(Auth::Accept)
+    }
+
+    async fn channel_open_session(
+        &mut self,
+        channel: Channel<Msg>,
+        reply: server::ChannelOpenHandle,
+        _session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        let Some(opened) = self.opened.take() else {
+            // Dropping the reply refuses the channel.
+            return Ok(());
+        };
+        reply.accept().await;
+        let _ = opened.send((channel, self.user.clone().unwrap_or_default()));
+        Ok(())
+    }
+
+    // The serial port is the terminal, so the client's pty settings and
+    // its choice of shell or command change nothing.
+    async fn pty_request(
+        &mut self,
+        channel: ChannelId,
+        _term: &str,
+        _col_width: u32,
+        _row_height: u32,
+        _pix_width: u32,
+        _pix_height: u32,
+        _modes: &[(Pty, u32)],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        session.channel_success(channel)
+    }
+
+    async fn shell_request(&mut self, channel: ChannelId, session: &mut Session) -> Result<(), Self::Error> {
+        session.channel_success(channel)
+    }
+
+    async fn exec_request(&mut self, channel: ChannelId, _data: &[u8], session: &mut Session) -> Result<(), Self::Error> {
+        session.channel_success(channel)
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use tokio::time::Instant;
+use tracing::info;
+
+use crate::serial::SerialHandle;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+// Rates are averaged over this many samples.
+const RATE_WINDOW: usize = 10;
+
+// Keeps the port's byte rates up to date and, every `log_every`, logs
+// throughput since the last time along with when data last went each way.
+// Runs until the bridge stops.
+pub async fn sample(serial: SerialHandle, log_every: Option<Duration>) -> Result<()> {
+    let counters = serial.counters();
+    let totals = || {
+        (
+            counters.rx_bytes.load(Ordering::Relaxed),
+            counters.tx_bytes.load(Ordering::Relaxed),
+        )
+    };
+    let mut samples = VecDeque::from([totals()]);
+    let mut logged = (totals(), Instant::now());
+    let mut ticks = tokio::time::interval(SAMPLE_INTERVAL);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        let (rx, tx) = totals();
+        samples.push_back((rx, tx));
+        if samples.len() > RATE_WINDOW + 1 {
+            samples.pop_front();
+        }
+        let (first_rx, first_tx) = samples[0];
+        let secs = (samples.len() - 1) as u64;
+        counters.rx_rate.store((rx - first_rx) / secs, Ordering::Relaxed);
+        counters.tx_rate.store((tx - first_tx) / secs, Ordering::Relaxed);
+        let ((logged_rx, logged_tx), logged_at) = logged;
+        if let Some(every) = log_every
+            && logged_at.elapsed() >= every
+        {
+            let secs = logged_at.elapsed().as_secs_f64();
+            let (rx_rate, tx_rate) = ((rx - logged_rx) as f64 / secs, (tx - logged_tx) as f64 / secs);
+            info!(
+                event = "serial_stats",
+                rx_bytes = rx,
+                tx_bytes = tx,
+                rx_rate = rx_rate.round() as u64,
+                tx_rate = tx_rate.round() as u64,
+                "Serial rx {:.0} B/s, {} bytes, last {}; tx {:.0} B/s, {} bytes, last {}",
+                rx_rate,
+                rx,
+                ago(counters.last_rx.load(Ordering::Relaxed)),
+                tx_rate,
+                tx,
+                ago(counters.last_tx.load(Ordering::Relaxed)),
+            );
+            logged = ((rx, tx), Instant::now());
+        }
+    }
+}
+
+// Seconds since a Unix time from the counters, None if it is 0 (never).
+pub fn since(at: u64) -> Option<u64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    (at > 0).then(|| now.saturating_sub(at))
+}
+
+fn ago(at: u64) -> String {
+    match since(at) {
+        Some(secs) => format!("{}s ago", secs),
+        None => "never".to_string(),
+    }
+}
@@ -0,0 +1,72 @@
+use std::ffi::CString;
+use std::io::{self, Write};
+
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+// Hands each formatted event to syslog(3), at the priority of its level.
+pub struct Syslog;
+
+impl Syslog {
+    pub fn open() -> Syslog {
+        // SAFETY: the identity is a static C string, as openlog keeps it.
+        unsafe { libc::openlog(c"remote-serial-server".as_ptr(), libc::LOG_PID, libc::LOG_DAEMON) };
+        Syslog
+    }
+}
+
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = Entry;
+
+    fn make_writer(&'a self) -> Entry {
+        Entry {
+            priority: libc::LOG_INFO,
+            text: Vec::new(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Entry {
+        let priority = match *meta.level() {
+            Level::ERROR => libc::LOG_ERR,
+            Level::WARN => libc::LOG_WARNING,
+            Level::INFO => libc::LOG_INFO,
+            Level::DEBUG | Level::TRACE => libc::LOG_DEBUG,
+        };
+        Entry {
+            priority,
+            text: Vec::new(),
+        }
+    }
+}
+
+// One event, sent as a single message once formatted.
+pub struct Entry {
+    priority: libc::c_int,
+    text: Vec<u8>,
+}
+
+impl Write for Entry {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.text.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        let text = self.text.strip_suffix(b"\n").unwrap_or(&self.text);
+        let text: Vec<u8> = text.iter().copied().filter(|&b| b != 0).collect();
+        if text.is_empty() {
+            return;
+        }
+        let Ok(text) = CString::new(text) else {
+            return;
+        };
+        // SAFETY: the format takes exactly the one string given.
+        unsafe { libc::syslog(self.priority, c"%s".as_ptr(), text.as_ptr()) };
+    }
+}
@@ -0,0 +1,66 @@
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+use crate::bridge::Registry;
+use crate::serial::Control;
+
+// Sends a state such as "READY=1" to the service manager, if the process
+// runs under a Type=notify unit.
+pub fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let result = (|| {
+        let addr = match path.as_bytes().strip_prefix(b"@") {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(&path)?,
+        };
+        UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)
+    })();
+    if let Err(e) = result {
+        warn!("Failed to notify systemd: {}", e);
+    }
+}
+
+// Pings the watchdog at half the interval systemd asked for, but only while
+// every running bridge's serial task still answers; a hung bridge stops the
+// pings and gets the service restarted.
+pub fn spawn_watchdog(registry: Arc<Registry>) {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    debug!("Pinging the systemd watchdog every {:?}", interval / 2);
+    tokio::spawn(async move {
+        let period = interval / 2;
+        let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            ticks.tick().await;
+            let mut healthy = true;
+            for bridge in registry.list() {
+                let answered = tokio::time::timeout(interval / 4, bridge.serial.control(Control::Status)).await;
+                if !matches!(answered, Ok(Ok(_))) {
+                    warn!(bridge = %bridge.name, "Bridge is not responding, withholding watchdog ping");
+                    healthy = false;
+                }
+            }
+            if healthy {
+                notify("WATCHDOG=1");
+            }
+        }
+    });
+}
+
+fn watchdog_interval() -> Option<Duration> {
+    if let Some(pid) = std::env::var_os("WATCHDOG_PID")
+        && pid.to_str().and_then(|pid| pid.parse().ok()) != Some(std::process::id())
+    {
+        return None;
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
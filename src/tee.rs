@@ -0,0 +1,111 @@
+// Serial output copied somewhere besides clients, for logging without a
+// client connected to do it. Each tee subscribes on its own, so one that
+// falls behind loses reads without holding up the port or the others.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::process::Child;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+use crate::hook;
+use crate::serial::SerialHandle;
+
+// How long a tee that failed waits before opening its file or starting its
+// command again.
+const RETRY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tee {
+    // Appended to, created if missing.
+    File(PathBuf),
+    // A shell command fed serial output on its standard input, with
+    // REMOTE_SERIAL_BRIDGE and REMOTE_SERIAL_PORT set. Started again if it
+    // exits.
+    Command(String),
+}
+
+impl fmt::Display for Tee {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Tee::File(path) => write!(f, "{}", path.display()),
+            Tee::Command(command) => write!(f, "'{}'", command),
+        }
+    }
+}
+
+// Where a tee writes, and the command behind it if it is one.
+struct Sink {
+    writer: Box<dyn AsyncWrite + Send + Unpin>,
+    _child: Option<Child>,
+}
+
+// Copies serial output into the tee until the bridge stops. What the port
+// sends while the tee is down is not kept for it.
+pub async fn run(serial: SerialHandle, bridge: String, port: String, tee: Tee) -> Result<()> {
+    let mut output = serial.subscribe();
+    loop {
+        let failed = match open(&tee, &bridge, &port).await {
+            Ok(mut sink) => loop {
+                let data = match output.recv().await {
+                    Ok(data) => data,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("Tee to {} fell behind, {} serial reads dropped", tee, n);
+                        serial.dropped(n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+                if let Err(e) = sink.writer.write_all(&data).await {
+                    break anyhow::Error::from(e);
+                }
+                if let Err(e) = sink.writer.flush().await {
+                    break e.into();
+                }
+            },
+            Err(e) => e,
+        };
+        warn!("Tee to {} failed: {:#}; retrying in {}s", tee, failed, RETRY.as_secs());
+        tokio::time::sleep(RETRY).await;
+        output = output.resubscribe();
+    }
+}
+
+async fn open(tee: &Tee, bridge: &str, port: &str) -> Result<Sink> {
+    match tee {
+        Tee::File(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await
+                .with_context(|| format!("failed to open {}", path.display()))?;
+            info!("Teeing serial output to {}", path.display());
+            Ok(Sink {
+                writer: Box::new(file),
+                _child: None,
+            })
+        }
+        Tee::Command(command) => {
+            let mut child = hook::shell(command)
+                .env("REMOTE_SERIAL_BRIDGE", bridge)
+                .env("REMOTE_SERIAL_PORT", port)
+                .stdin(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .with_context(|| format!("failed to start '{}'", command))?;
+            let stdin = child.stdin.take().context("no standard input")?;
+            info!("Teeing serial output to '{}'", command);
+            Ok(Sink {
+                writer: Box::new(stdin),
+                _child: Some(child),
+            })
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use crate::serial::Control;
+
+pub const IAC: u8 = 255;
+pub const DONT: u8 = 254;
+pub const DO: u8 = 253;
+pub const WONT: u8 = 252;
+pub const WILL: u8 = 251;
+pub const SB: u8 = 250;
+pub const SE: u8 = 240;
+const BRK: u8 = 243;
+
+pub const BINARY: u8 = 0;
+pub const ECHO: u8 = 1;
+pub const SGA: u8 = 3;
+
+// No option we speak has longer subnegotiations; one that runs on past this
+// without IAC SE is dropped.
+const MAX_SUB: usize = 1024;
+
+enum State {
+    Data,
+    // A CR outside binary mode, whose NUL or LF belongs to it.
+    Cr,
+    Iac,
+    Negotiate(u8),
+    Sub,
+    SubIac,
+}
+
+pub enum Event {
+    Data(Vec<u8>),
+    Control(Control),
+    // The client asked for a line break (telnet BRK).
+    Break,
+    // A raw client asked for the write lock.
+    Takeover,
+}
+
+// The telnet layer of a session: option negotiation and IAC escaping.
+// Subnegotiations are left to whoever speaks the option.
+pub struct Session {
+    state: State,
+    sub: Vec<u8>,
+    local: u64,
+    remote: u64,
+    // Options we are willing to enable in either direction.
+    supported: &'static [u8],
+}
+
+impl Session {
+    pub fn new(supported: &'static [u8]) -> Self {
+        Session {
+            state: State::Data,
+            sub: Vec::new(),
+            local: 0,
+            remote: 0,
+            supported,
+        }
+    }
+
+    // Offers `will` and asks the client for `want`, as a greeting.
+    pub fn greeting(&mut self, will: &[u8], want: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &option in will {
+            self.local |= 1 << option;
+            out.extend_from_slice(&[IAC, WILL, option]);
+        }
+        for &option in want {
+            self.remote |= 1 << option;
+            out.extend_from_slice(&[IAC, DO, option]);
+        }
+        out
+    }
+
+    // Whether the client has agreed to use `option`.
+    pub fn remote(&self, option: u8) -> bool {
+        self.remote & (1 << option) != 0
+    }
+
+    // Escapes serial data for transmission to the client.
+    pub fn encode(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        for &b in data {
+            out.push(b);
+            if b == IAC {
+                out.push(IAC);
+            }
+        }
+        out
+    }
+
+    // Consumes bytes from the client, in order, as payload for the serial
+    // port. Negotiation answers go to `reply`; each completed subnegotiation
+    // is passed to `sub`, and a Control it returns becomes an event.
+    pub fn decode(
+        &mut self,
+        input: &[u8],
+        reply: &mut Vec<u8>,
+        mut sub: impl FnMut(&[u8], &mut Vec<u8>) -> Option<Control>,
+    ) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut data = Vec::with_capacity(input.len());
+        for &b in input {
+            self.state = match self.state {
+                State::Data | State::Cr if b == IAC => State::Iac,
+                // NVT sends CR as CR NUL, which the device should see as CR.
+                State::Cr if b == 0 => State::Data,
+                State::Data | State::Cr => {
+                    data.push(b);
+                    match b == b'\r' && !self.remote(BINARY) {
+                        true => State::Cr,
+                        false => State::Data,
+                    }
+                }
+                State::Iac => match b {
+                    IAC => {
+                        data.push(IAC);
+                        State::Data
+                    }
+                    WILL | WONT | DO | DONT => State::Negotiate(b),
+                    SB => {
+                        self.sub.clear();
+                        State::Sub
+                    }
+                    BRK => {
+                        if !data.is_empty() {
+                            events.push(Event::Data(std::mem::take(&mut data)));
+                        }
+                        events.push(Event::Break);
+                        State::Data
+                    }
+                    _ => State::Data,
+                },
+                State::Negotiate(verb) => {
+                    self.negotiate(verb, b, reply);
+                    State::Data
+                }
+                State::Sub if b == IAC => State::SubIac,
+                State::Sub => self.sub_push(b),
+                State::SubIac => match b {
+                    IAC => self.sub_push(IAC),
+                    SE => {
+                        if let Some(control) = sub(&std::mem::take(&mut self.sub), reply) {
+                            if !data.is_empty() {
+                                events.push(Event::Data(std::mem::take(&mut data)));
+                            }
+                            events.push(Event::Control(control));
+                        }
+                        State::Data
+                    }
+                    _ => State::Data,
+                },
+            };
+        }
+        if !data.is_empty() {
+            events.push(Event::Data(data));
+        }
+        events
+    }
+
+    fn sub_push(&mut self, b: u8) -> State {
+        if self.sub.len() == MAX_SUB {
+            self.sub.clear();
+            return State::Data;
+        }
+        self.sub.push(b);
+        State::Sub
+    }
+
+    fn negotiate(&mut self, verb: u8, option: u8, reply: &mut Vec<u8>) {
+        let supported = self.supported.contains(&option);
+        let bit = 1u64.checked_shl(option as u32).unwrap_or(0);
+        match verb {
+            WILL if !supported => reply.extend_from_slice(&[IAC, DONT, option]),
+            WILL if self.remote & bit == 0 => {
+                self.remote |= bit;
+                reply.extend_from_slice(&[IAC, DO, option]);
+            }
+            WONT if self.remote & bit != 0 => {
+                self.remote &= !bit;
+                reply.extend_from_slice(&[IAC, DONT, option]);
+            }
+            DO if !supported => reply.extend_from_slice(&[IAC, WONT, option]),
+            DO if self.local & bit == 0 => {
+                self.local |= bit;
+                reply.extend_from_slice(&[IAC, WILL, option]);
+            }
+            DONT if self.local & bit != 0 => {
+                self.local &= !bit;
+                reply.extend_from_slice(&[IAC, WONT, option]);
+            }
+            _ => {}
+        }
+    }
+}
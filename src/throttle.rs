@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use tokio::time::Instant;
+
+// Past this many addresses, those with a full allowance again are
+// forgotten, so that a scan from many addresses cannot grow the map
+// without bound.
+const PRUNE_AT: usize = 1024;
+
+// Connections allowed from each address per minute: a token bucket per
+// address, holding a minute's worth so that a client may reconnect in a
+// burst after a restart.
+pub struct Throttle {
+    per_minute: u32,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Throttle {
+    pub fn new(per_minute: u32) -> Self {
+        Throttle {
+            per_minute,
+            buckets: Mutex::default(),
+        }
+    }
+
+    // Takes a connection from the address's allowance, or returns false
+    // if it has none left.
+    pub fn admit(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let burst = f64::from(self.per_minute);
+        let refill = |bucket: &Bucket| bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * burst / 60.0;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_AT {
+            buckets.retain(|_, bucket| refill(bucket) < burst);
+        }
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens = refill(bucket).min(burst);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+}
@@ -0,0 +1,50 @@
+use std::time::{Instant, SystemTime};
+
+use crate::TimestampFormat;
+
+// Prefixes serial output with the time it arrived, at the start of every
+// line or of every read from the port:
+//
+//   [2026-01-01T12:00:00.000Z] U-Boot 2024.01
+//   [    1.204] Starting kernel ...
+//
+// Relative times count from when the session started.
+pub struct Timestamps {
+    format: TimestampFormat,
+    per_read: bool,
+    started: Instant,
+    at_line_start: bool,
+}
+
+impl Timestamps {
+    pub fn new(format: TimestampFormat, per_read: bool) -> Timestamps {
+        Timestamps {
+            format,
+            per_read,
+            started: Instant::now(),
+            at_line_start: true,
+        }
+    }
+
+    pub fn stamp(&mut self, data: &[u8]) -> Vec<u8> {
+        if data.is_empty() {
+            return Vec::new();
+        }
+        let stamp = match self.format {
+            TimestampFormat::Iso8601 => format!("[{}] ", humantime::format_rfc3339_millis(SystemTime::now())),
+            TimestampFormat::Relative => format!("[{:9.3}] ", self.started.elapsed().as_secs_f64()),
+        };
+        if self.per_read {
+            return [stamp.as_bytes(), data].concat();
+        }
+        let mut out = Vec::with_capacity(data.len() + stamp.len());
+        for &byte in data {
+            if self.at_line_start {
+                out.extend_from_slice(stamp.as_bytes());
+            }
+            out.push(byte);
+            self.at_line_start = byte == b'\n';
+        }
+        out
+    }
+}
@@ -0,0 +1,314 @@
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use socket2::{SockRef, TcpKeepalive};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+use tracing::{info, warn};
+
+use crate::acl::SharedAcl;
+use crate::ban::Bans;
+use crate::client::Peer;
+use crate::noise::{self, NoiseStream};
+use crate::proxy;
+use crate::throttle::Throttle;
+use crate::{tls, ws};
+
+// How long a listener waits after a failed accept, such as when out of file
+// descriptors, before trying again.
+const ACCEPT_RETRY: Duration = Duration::from_millis(500);
+
+// A way for clients to reach a bridge. Accepting should be quick, as one
+// transport serves many clients; anything slow, like a handshake, belongs
+// in `establish`, which runs on each connection's own task. Transports such
+// as TLS wrap another one and do their handshake after its own.
+pub trait Transport: Send + Sync + 'static {
+    // A connection as accepted, before any handshake.
+    type Incoming: Send + 'static;
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    // The next connection, or None once there will be no more.
+    fn accept(&self) -> impl Future<Output = Result<Option<(Self::Incoming, Peer)>>> + Send;
+
+    // Readies a connection for use, and may learn who the peer is.
+    fn establish(&self, incoming: Self::Incoming, peer: &mut Peer)
+    -> impl Future<Output = Result<Self::Stream>> + Send;
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SocketOptions {
+    pub no_delay: bool,
+    pub keepalive: Option<Duration>,
+    pub keepalive_interval: Option<Duration>,
+}
+
+pub fn tune(socket: &TcpStream, options: SocketOptions) -> io::Result<()> {
+    if options.no_delay {
+        socket.set_nodelay(true)?;
+    }
+    if let Some(time) = options.keepalive {
+        let keepalive = TcpKeepalive::new().with_time(time);
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            windows
+        ))]
+        let keepalive = match options.keepalive_interval {
+            Some(interval) => keepalive.with_interval(interval),
+            None => keepalive,
+        };
+        SockRef::from(socket).set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(())
+}
+
+// A TCP listener, admitting only the addresses the ACL permits and that
+// are not banned, and only as often as the throttle, if any, allows.
+// Behind a load balancer sending the PROXY protocol, those are the
+// addresses its headers give, and the balancer's own is not checked.
+pub struct Tcp {
+    listener: TcpListener,
+    acl: SharedAcl,
+    throttle: Option<Arc<Throttle>>,
+    bans: Option<Arc<Bans>>,
+    options: SocketOptions,
+    proxy_protocol: bool,
+}
+
+impl Tcp {
+    pub fn new(
+        listener: TcpListener,
+        acl: SharedAcl,
+        throttle: Option<Arc<Throttle>>,
+        bans: Option<Arc<Bans>>,
+        options: SocketOptions,
+        proxy_protocol: bool,
+    ) -> Self {
+        Tcp {
+            listener,
+            acl,
+            throttle,
+            bans,
+            options,
+            proxy_protocol,
+        }
+    }
+
+    // Whether a client at this address may connect, or why not.
+    fn admit(&self, ip: IpAddr) -> Result<(), &'static str> {
+        if !self.acl.permits(ip) {
+            return Err("address not allowed");
+        }
+        if self.bans.as_ref().is_some_and(|bans| bans.is_banned(ip)) {
+            return Err("banned");
+        }
+        if self.throttle.as_ref().is_some_and(|throttle| !throttle.admit(ip)) {
+            return Err("connecting too often");
+        }
+        Ok(())
+    }
+}
+
+impl Transport for Tcp {
+    type Incoming = TcpStream;
+    type Stream = TcpStream;
+
+    async fn accept(&self) -> Result<Option<(TcpStream, Peer)>> {
+        loop {
+            let (socket, addr) = match self.listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Accept failed: {}", e);
+                    tokio::time::sleep(ACCEPT_RETRY).await;
+                    continue;
+                }
+            };
+            // IPv4 clients of a dual-stack socket arrive as ::ffff:a.b.c.d.
+            let addr = SocketAddr::new(addr.ip().to_canonical(), addr.port());
+            if !self.proxy_protocol
+                && let Err(reason) = self.admit(addr.ip())
+            {
+                info!("Refusing client {}: {}", addr, reason);
+                continue;
+            }
+            let peer = Peer {
+                addr: addr.to_string(),
+                identity: None,
+                read_only: false,
+            };
+            return Ok(Some((socket, peer)));
+        }
+    }
+
+    async fn establish(&self, mut socket: TcpStream, peer: &mut Peer) -> Result<TcpStream> {
+        if let Err(e) = tune(&socket, self.options) {
+            warn!("Failed to set socket options: {}", e);
+        }
+        if self.proxy_protocol {
+            let addr = match proxy::read_header(&mut socket).await? {
+                Some(addr) => addr,
+                None => socket.peer_addr()?,
+            };
+            peer.addr = addr.to_string();
+            if let Err(reason) = self.admit(addr.ip()) {
+                bail!("Refusing client: {}", reason);
+            }
+        }
+        Ok(socket)
+    }
+}
+
+// A Unix domain socket. There is no ACL; the socket's permissions decide
+// who may connect.
+#[cfg(unix)]
+pub struct Unix(pub UnixListener);
+
+#[cfg(unix)]
+impl Transport for Unix {
+    type Incoming = UnixStream;
+    type Stream = UnixStream;
+
+    async fn accept(&self) -> Result<Option<(UnixStream, Peer)>> {
+        let socket = loop {
+            match self.0.accept().await {
+                Ok((socket, _)) => break socket,
+                Err(e) => {
+                    warn!("Accept failed: {}", e);
+                    tokio::time::sleep(ACCEPT_RETRY).await;
+                }
+            }
+        };
+        let addr = match socket.peer_cred() {
+            Ok(cred) => format!("unix:uid={}", cred.uid()),
+            Err(_) => "unix".to_string(),
+        };
+        let peer = Peer {
+            addr,
+            identity: None,
+            read_only: false,
+        };
+        Ok(Some((socket, peer)))
+    }
+
+    async fn establish(&self, socket: UnixStream, _: &mut Peer) -> Result<UnixStream> {
+        Ok(socket)
+    }
+}
+
+// A single connection made elsewhere: stdin/stdout under inetd, or one
+// accepted by systemd.
+pub struct Single<S>(Mutex<Option<(S, Peer)>>);
+
+impl<S> Single<S> {
+    pub fn new(stream: S, peer: Peer) -> Self {
+        Single(Mutex::new(Some((stream, peer))))
+    }
+}
+
+impl<S> Transport for Single<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Incoming = S;
+    type Stream = S;
+
+    async fn accept(&self) -> Result<Option<(S, Peer)>> {
+        Ok(self.0.lock().unwrap().take())
+    }
+
+    async fn establish(&self, stream: S, _: &mut Peer) -> Result<S> {
+        Ok(stream)
+    }
+}
+
+pub fn stdio() -> Single<tokio::io::Join<tokio::io::Stdin, tokio::io::Stdout>> {
+    let peer = Peer {
+        addr: "stdio".to_string(),
+        identity: None,
+        read_only: false,
+    };
+    Single::new(tokio::io::join(tokio::io::stdin(), tokio::io::stdout()), peer)
+}
+
+// TLS over another transport. A client certificate, if asked for, becomes
+// the peer's identity.
+pub struct Tls<T> {
+    inner: T,
+    acceptor: TlsAcceptor,
+}
+
+impl<T> Tls<T> {
+    pub fn new(inner: T, acceptor: TlsAcceptor) -> Self {
+        Tls { inner, acceptor }
+    }
+}
+
+impl<T: Transport> Transport for Tls<T> {
+    type Incoming = T::Incoming;
+    type Stream = TlsStream<T::Stream>;
+
+    fn accept(&self) -> impl Future<Output = Result<Option<(T::Incoming, Peer)>>> + Send {
+        self.inner.accept()
+    }
+
+    async fn establish(&self, incoming: T::Incoming, peer: &mut Peer) -> Result<Self::Stream> {
+        let stream = self.inner.establish(incoming, peer).await?;
+        let stream = self.acceptor.accept(stream).await.context("TLS handshake failed")?;
+        peer.identity = tls::peer_common_name(stream.get_ref().1).map(|cn| format!("CN={}", cn));
+        Ok(stream)
+    }
+}
+
+// Noise over another transport, for clients too small for TLS.
+pub struct Noise<T> {
+    inner: T,
+    key: noise::Key,
+}
+
+impl<T> Noise<T> {
+    pub fn new(inner: T, key: noise::Key) -> Self {
+        Noise { inner, key }
+    }
+}
+
+impl<T: Transport> Transport for Noise<T> {
+    type Incoming = T::Incoming;
+    type Stream = NoiseStream<T::Stream>;
+
+    fn accept(&self) -> impl Future<Output = Result<Option<(T::Incoming, Peer)>>> + Send {
+        self.inner.accept()
+    }
+
+    async fn establish(&self, incoming: T::Incoming, peer: &mut Peer) -> Result<Self::Stream> {
+        let stream = self.inner.establish(incoming, peer).await?;
+        noise::accept(stream, &self.key).await
+    }
+}
+
+// WebSocket binary messages over another transport.
+pub struct WebSocket<T>(pub T);
+
+impl<T: Transport> Transport for WebSocket<T> {
+    type Incoming = T::Incoming;
+    type Stream = DuplexStream;
+
+    fn accept(&self) -> impl Future<Output = Result<Option<(T::Incoming, Peer)>>> + Send {
+        self.0.accept()
+    }
+
+    async fn establish(&self, incoming: T::Incoming, peer: &mut Peer) -> Result<DuplexStream> {
+        let stream = self.0.establish(incoming, peer).await?;
+        ws::accept(stream).await.context("WebSocket handshake failed")
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use bytes::Bytes;
+use regex::Regex;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{Instrument, info, warn};
+
+use crate::hook;
+use crate::http::{self, Url};
+use crate::serial::SerialHandle;
+
+// Lines longer than this are matched in pieces.
+const MAX_LINE: usize = 4096;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// A pattern watched for in serial output, and what to do when a line
+// matches it. Any combination of actions may be given.
+#[derive(Clone, Debug)]
+pub struct Trigger {
+    pub pattern: Regex,
+    // POSTed a JSON object naming the bridge, port, pattern and line.
+    pub webhook: Option<Url>,
+    // Run with REMOTE_SERIAL_BRIDGE, REMOTE_SERIAL_PORT and
+    // REMOTE_SERIAL_LINE set.
+    pub command: Option<String>,
+    // Written to the port, e.g. a user name at a login prompt.
+    pub respond: Option<Bytes>,
+}
+
+// Patterns compare by their source.
+impl PartialEq for Trigger {
+    fn eq(&self, other: &Trigger) -> bool {
+        self.pattern.as_str() == other.pattern.as_str()
+            && self.webhook == other.webhook
+            && self.command == other.command
+            && self.respond == other.respond
+    }
+}
+
+// Matches serial output line by line. The line still being received is
+// matched too, so that prompts not followed by a newline are seen, but each
+// trigger fires at most once per line. Runs until the bridge stops.
+pub async fn run(serial: SerialHandle, bridge: String, port: String, triggers: Arc<[Trigger]>) -> Result<()> {
+    let mut output = serial.subscribe();
+    let mut line = Vec::new();
+    let mut fired = vec![false; triggers.len()];
+    loop {
+        let data = match output.recv().await {
+            Ok(data) => data,
+            Err(RecvError::Lagged(n)) => {
+                serial.dropped(n);
+                line.clear();
+                fired.fill(false);
+                continue;
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        };
+        for &byte in data.iter() {
+            if byte != b'\n' && line.len() < MAX_LINE {
+                line.push(byte);
+                continue;
+            }
+            check(&triggers, &mut fired, &line, &serial, &bridge, &port);
+            line.clear();
+            fired.fill(false);
+            if byte != b'\n' {
+                line.push(byte);
+            }
+        }
+        check(&triggers, &mut fired, &line, &serial, &bridge, &port);
+    }
+}
+
+fn check(triggers: &[Trigger], fired: &mut [bool], line: &[u8], serial: &SerialHandle, bridge: &str, port: &str) {
+    if line.is_empty() {
+        return;
+    }
+    let text = String::from_utf8_lossy(line);
+    let text = text.trim_end_matches('\r');
+    for (trigger, fired) in triggers.iter().zip(fired.iter_mut()) {
+        if *fired || !trigger.pattern.is_match(text) {
+            continue;
+        }
+        *fired = true;
+        info!("Trigger /{}/ matched: {}", trigger.pattern, text);
+        let fire = fire(trigger.clone(), serial.clone(), bridge.to_string(), port.to_string(), text.to_string());
+        tokio::spawn(fire.in_current_span());
+    }
+}
+
+// Actions run in the background so that matching keeps up with the port.
+async fn fire(trigger: Trigger, serial: SerialHandle, bridge: String, port: String, line: String) {
+    if let Some(respond) = trigger.respond
+        && let Err(e) = serial.write(respond).await
+    {
+        warn!("Trigger response failed: {}", e);
+    }
+    if let Some(url) = &trigger.webhook {
+        let body = json!({
+            "bridge": bridge,
+            "serial_port": port,
+            "pattern": trigger.pattern.as_str(),
+            "line": line,
+        })
+        .to_string();
+        match tokio::time::timeout(WEBHOOK_TIMEOUT, http::post(url, "application/json", body.as_bytes())).await {
+            Ok(Ok(status)) if (200..300).contains(&status) => {}
+            Ok(Ok(status)) => warn!("Trigger webhook answered {}", status),
+            Ok(Err(e)) => warn!("Trigger webhook failed: {:#}", e),
+            Err(_) => warn!("Trigger webhook timed out"),
+        }
+    }
+    if let Some(command) = &trigger.command {
+        let env = [
+            ("REMOTE_SERIAL_BRIDGE", bridge.as_str()),
+            ("REMOTE_SERIAL_PORT", port.as_str()),
+            ("REMOTE_SERIAL_LINE", line.as_str()),
+        ];
+        if let Err(e) = hook::run(command, &env).await {
+            warn!("Trigger command failed: {}", e);
+        }
+    }
+}
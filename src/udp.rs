@@ -0,0 +1,117 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+use crate::acl::SharedAcl;
+use crate::serial::SerialHandle;
+
+// Largest payload of a UDP datagram over IPv4.
+const MAX_DATAGRAM: usize = 65507;
+
+// Where serial output is multicast, for any number of listeners.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Multicast {
+    pub group: SocketAddr,
+    // Routers the datagrams may cross; 1 keeps them to the local network.
+    pub ttl: u32,
+    // The local address of the interface to send IPv4 datagrams from,
+    // rather than the one the routing table picks.
+    pub interface: Option<Ipv4Addr>,
+}
+
+// Forwards serial data as datagrams. Every datagram from an allowed address
+// is written to the port, and serial output goes to `peer` if one is
+// configured, otherwise to whoever sent the most recent datagram. Nothing
+// is retransmitted, so a lost datagram is lost data.
+pub async fn serve(socket: UdpSocket, serial: SerialHandle, acl: SharedAcl, peer: Option<SocketAddr>) -> Result<()> {
+    let mut output = serial.subscribe();
+    let mut target = peer;
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    loop {
+        tokio::select! {
+            received = output.recv() => match received {
+                Ok(data) => {
+                    let Some(target) = target else { continue };
+                    if let Err(e) = socket.send_to(&data, target).await {
+                        warn!("Failed to send to {}: {}", target, e);
+                    }
+                }
+                Err(RecvError::Lagged(n)) => {
+                    warn!("UDP peer fell behind, {} serial reads dropped", n);
+                    serial.dropped(n);
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            received = socket.recv_from(&mut buf) => {
+                let (n, addr) = match received {
+                    Ok(received) => received,
+                    // An ICMP port unreachable from an earlier send; the peer
+                    // may come back.
+                    Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                if !acl.permits(addr.ip()) {
+                    continue;
+                }
+                if peer.is_none() && target != Some(addr) {
+                    info!("UDP peer is now {}", addr);
+                    target = Some(addr);
+                }
+                serial.write(Bytes::copy_from_slice(&buf[..n])).await?;
+            },
+        }
+    }
+}
+
+// A socket to send to the group from, bound to an ephemeral port.
+pub fn multicast_socket(multicast: &Multicast) -> Result<UdpSocket> {
+    let group = multicast.group;
+    let open = || -> std::io::Result<UdpSocket> {
+        let socket = Socket::new(Domain::for_address(group), Type::DGRAM, Some(Protocol::UDP))?;
+        let local: SocketAddr = match group {
+            SocketAddr::V4(_) => {
+                socket.set_multicast_ttl_v4(multicast.ttl)?;
+                if let Some(interface) = &multicast.interface {
+                    socket.set_multicast_if_v4(interface)?;
+                }
+                (Ipv4Addr::UNSPECIFIED, 0).into()
+            }
+            SocketAddr::V6(_) => {
+                socket.set_multicast_hops_v6(multicast.ttl)?;
+                (Ipv6Addr::UNSPECIFIED, 0).into()
+            }
+        };
+        socket.bind(&local.into())?;
+        socket.set_nonblocking(true)?;
+        UdpSocket::from_std(socket.into())
+    };
+    open().with_context(|| format!("failed to set up multicast to {}", group))
+}
+
+// Sends serial output to the group, a datagram per read, until the bridge
+// stops. Nothing sent to the socket is read, so listeners cannot write to
+// the port.
+pub async fn multicast(socket: UdpSocket, serial: SerialHandle, group: SocketAddr) -> Result<()> {
+    let mut output = serial.subscribe();
+    loop {
+        match output.recv().await {
+            Ok(data) => {
+                for datagram in data.chunks(MAX_DATAGRAM) {
+                    if let Err(e) = socket.send_to(datagram, group).await {
+                        warn!("Failed to multicast to {}: {}", group, e);
+                    }
+                }
+            }
+            Err(RecvError::Lagged(n)) => {
+                warn!("Multicast fell behind, {} serial reads dropped", n);
+                serial.dropped(n);
+            }
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
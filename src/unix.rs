@@ -0,0 +1,73 @@
+use std::fs;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use tokio::net::UnixListener;
+
+// Binds a Unix domain socket, replacing a stale one left by an earlier run,
+// and applies the requested permissions and owner ("user[:group]", by name
+// or number).
+pub fn bind(path: &Path, mode: Option<u32>, owner: Option<&str>) -> Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(path)
+        && metadata.file_type().is_socket()
+    {
+        fs::remove_file(path).with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+    }
+    let listener = UnixListener::bind(path).with_context(|| format!("failed to bind {}", path.display()))?;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+            .with_context(|| format!("failed to set permissions of {}", path.display()))?;
+    }
+    if let Some(owner) = owner {
+        let (user, group) = match owner.split_once(':') {
+            Some((user, group)) => (Some(user).filter(|u| !u.is_empty()), Some(group)),
+            None => (Some(owner), None),
+        };
+        let uid = user.map(|user| lookup("/etc/passwd", user)).transpose()?;
+        let gid = group.map(|group| lookup("/etc/group", group)).transpose()?;
+        std::os::unix::fs::chown(path, uid, gid).with_context(|| format!("failed to change owner of {}", path.display()))?;
+    }
+    Ok(listener)
+}
+
+// An account from /etc/passwd.
+pub struct User {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+// Looks up a user by name or uid.
+pub fn user(name: &str) -> Result<User> {
+    let fields = entry("/etc/passwd", name)?;
+    let id = |i: usize| fields.get(i).and_then(|f| f.parse().ok()).context("malformed /etc/passwd entry");
+    Ok(User {
+        name: fields[0].clone(),
+        uid: id(2)?,
+        gid: id(3)?,
+    })
+}
+
+// Looks up a group id by name; numeric ids are taken as they are.
+pub fn group(name: &str) -> Result<u32> {
+    lookup("/etc/group", name)
+}
+
+// Resolves a user or group name through /etc/passwd or /etc/group, whose
+// lines both start with name:password:id. Numeric ids are taken as they are.
+fn lookup(database: &str, name: &str) -> Result<u32> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+    entry(database, name)?[2].parse().with_context(|| format!("malformed {} entry", database))
+}
+
+fn entry(database: &str, name: &str) -> Result<Vec<String>> {
+    let contents = fs::read_to_string(database).with_context(|| format!("failed to read {}", database))?;
+    contents
+        .lines()
+        .map(|line| line.split(':').map(str::to_string).collect::<Vec<_>>())
+        .find(|fields| fields.len() >= 4 && (fields[0] == name || fields[2] == name))
+        .ok_or_else(|| anyhow!("'{}' not found in {}", name, database))
+}
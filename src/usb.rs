@@ -0,0 +1,81 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use tokio_serial::SerialPortType;
+
+// A USB serial adapter identified by VID:PID and optionally its serial
+// number, e.g. 0403:6001 or 0403:6001:A10K3WXY.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct UsbId {
+    vid: u16,
+    pid: u16,
+    serial_number: Option<String>,
+}
+
+impl UsbId {
+    // Finds the device path of the matching adapter among the present ports.
+    pub fn find(&self) -> Result<String> {
+        let mut matches: Vec<String> = tokio_serial::available_ports()?
+            .into_iter()
+            .filter(|port| match &port.port_type {
+                SerialPortType::UsbPort(info) => {
+                    info.vid == self.vid
+                        && info.pid == self.pid
+                        && (self.serial_number.is_none() || info.serial_number == self.serial_number)
+                }
+                _ => false,
+            })
+            .map(|port| port.port_name)
+            .collect();
+        match matches.len() {
+            0 => bail!("no serial port matches USB id {}", self),
+            1 => Ok(matches.remove(0)),
+            _ => bail!(
+                "USB id {} matches several ports ({}); add the serial number",
+                self,
+                matches.join(", ")
+            ),
+        }
+    }
+}
+
+impl fmt::Display for UsbId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vid, self.pid)?;
+        if let Some(serial_number) = &self.serial_number {
+            write!(f, ":{}", serial_number)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for UsbId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let hex = |part: Option<&str>| {
+            part.and_then(|p| u16::from_str_radix(p, 16).ok())
+                .ok_or_else(|| format!("expected VID:PID[:SERIAL] in hex, got '{}'", s))
+        };
+        let vid = hex(parts.next())?;
+        let pid = hex(parts.next())?;
+        let serial_number = parts.next().filter(|p| !p.is_empty()).map(str::to_string);
+        Ok(UsbId {
+            vid,
+            pid,
+            serial_number,
+        })
+    }
+}
+
+impl TryFrom<String> for UsbId {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
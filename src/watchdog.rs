@@ -0,0 +1,63 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::hook;
+use crate::LineAction;
+use crate::serial::{Control, SerialHandle};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Recovers a device that has stopped sending anything.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Watchdog {
+    pub after: Duration,
+    pub recovery: Recovery,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Recovery {
+    PulseDtr,
+    Reopen,
+    // A shell command, told which bridge and port through the environment.
+    Hook(String),
+}
+
+// Acts whenever no byte has come from the device for `after`, then waits
+// as long again before acting a second time. Time spent with the device
+// gone doesn't count, as it is being reopened anyway. Runs until the
+// bridge stops.
+pub async fn run(serial: SerialHandle, bridge: String, port: String, watchdog: Watchdog) -> Result<()> {
+    let counters = serial.counters();
+    let mut ticks = tokio::time::interval(CHECK_INTERVAL);
+    let mut last = counters.rx_bytes.load(Ordering::Relaxed);
+    let mut quiet_since = Instant::now();
+    loop {
+        ticks.tick().await;
+        let rx = counters.rx_bytes.load(Ordering::Relaxed);
+        if rx != last || !counters.connected.load(Ordering::Relaxed) {
+            last = rx;
+            quiet_since = Instant::now();
+            continue;
+        }
+        if quiet_since.elapsed() < watchdog.after {
+            continue;
+        }
+        warn!("No data from the serial port for {}s", watchdog.after.as_secs());
+        let recovered = match &watchdog.recovery {
+            Recovery::PulseDtr => serial.line(Control::Dtr, LineAction::Pulse).await.map(|_| ()),
+            Recovery::Reopen => serial.reopen().await,
+            Recovery::Hook(command) => {
+                info!("Running watchdog hook: {}", command);
+                hook::run(command, &[("REMOTE_SERIAL_BRIDGE", &bridge), ("REMOTE_SERIAL_PORT", &port)]).await
+            }
+        };
+        if let Err(e) = recovered {
+            warn!("Serial watchdog recovery failed: {}", e);
+        }
+        quiet_since = Instant::now();
+    }
+}
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+
+use crate::auth::Credentials;
+use crate::{http, ws};
+
+const INDEX: &str = include_str!("../assets/index.html");
+
+// Serves the terminal page, or upgrades `/ws` to a WebSocket and returns the
+// resulting byte stream for the caller to attach to the serial port.
+pub async fn handle<S>(mut stream: S, title: &str, auth: Option<&Credentials>) -> Result<Option<DuplexStream>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let request = http::read_request(&mut stream).await?;
+    if request.method != "GET" {
+        http::respond(&mut stream, 405, "text/plain", b"method not allowed\n").await?;
+        return Ok(None);
+    }
+    match request.path.as_str() {
+        "/" | "/index.html" => {
+            // Which credentials the page asks for.
+            let auth = match auth {
+                Some(Credentials::Token(_)) => "'token'",
+                Some(Credentials::Pam(_)) => "'login'",
+                None => "null",
+            };
+            let page = INDEX.replace("{{title}}", &escape_html(title)).replace("{{auth}}", auth);
+            http::respond(&mut stream, 200, "text/html; charset=utf-8", page.as_bytes()).await?;
+            Ok(None)
+        }
+        "/ws" => {
+            let Some(key) = request.header("sec-websocket-key") else {
+                http::respond(&mut stream, 400, "text/plain", b"expected a WebSocket upgrade\n").await?;
+                return Ok(None);
+            };
+            if !same_origin(&request) {
+                http::respond(&mut stream, 403, "text/plain", b"cross-origin WebSocket refused\n").await?;
+                return Ok(None);
+            }
+            let head = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                derive_accept_key(key.as_bytes())
+            );
+            stream.write_all(head.as_bytes()).await?;
+            let ws = WebSocketStream::from_raw_socket(stream, Role::Server, None).await;
+            Ok(Some(ws::pipe(ws)))
+        }
+        _ => {
+            http::respond(&mut stream, 404, "text/plain", b"not found\n").await?;
+            Ok(None)
+        }
+    }
+}
+
+// Whether a WebSocket upgrade comes from the terminal page itself rather
+// than another site open in the same browser, which could otherwise drive
+// the port. Clients other than browsers send no Origin.
+fn same_origin(request: &http::Request) -> bool {
+    let Some(origin) = request.header("origin") else {
+        return true;
+    };
+    let host = origin.strip_prefix("http://").or_else(|| origin.strip_prefix("https://"));
+    host.zip(request.header("host")).is_some_and(|(origin, host)| origin.eq_ignore_ascii_case(host))
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
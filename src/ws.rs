@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::Message;
+
+const PING_INTERVAL: Duration = Duration::from_secs(20);
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+// Performs the HTTP upgrade and returns a byte stream carrying the payload of
+// the WebSocket's binary (or text) messages. Keepalive pings run in the
+// background until either side closes.
+pub async fn accept<S>(stream: S) -> Result<DuplexStream>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let ws = tokio_tungstenite::accept_async(stream).await?;
+    Ok(pipe(ws))
+}
+
+// Exposes an established WebSocket as a byte stream.
+pub fn pipe<S>(ws: WebSocketStream<S>) -> DuplexStream
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (local, remote) = tokio::io::duplex(PIPE_CAPACITY);
+    tokio::spawn(pump(ws, remote));
+    local
+}
+
+async fn pump<S>(mut ws: WebSocketStream<S>, mut pipe: DuplexStream)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    ping.tick().await;
+    let mut awaiting_pong = false;
+    let mut buf = [0u8; 4096];
+    loop {
+        tokio::select! {
+            message = ws.next() => {
+                let data = match message {
+                    Some(Ok(Message::Binary(data))) => data,
+                    Some(Ok(Message::Text(text))) => text.into(),
+                    Some(Ok(Message::Pong(_))) => {
+                        awaiting_pong = false;
+                        continue;
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => continue,
+                };
+                if pipe.write_all(&data).await.is_err() {
+                    break;
+                }
+            },
+            read = pipe.read(&mut buf) => {
+                match read {
+                    Ok(n) if n > 0 => {
+                        if ws.send(Message::binary(buf[..n].to_vec())).await.is_err() {
+                            return;
+                        }
+                    }
+                    _ => break,
+                }
+            },
+            _ = ping.tick() => {
+                // No answer to the previous ping: the peer is gone.
+                if awaiting_pong {
+                    return;
+                }
+                if ws.send(Message::Ping(Default::default())).await.is_err() {
+                    return;
+                }
+                awaiting_pong = true;
+            }
+        }
+    }
+    let _ = ws.close(None).await;
+}
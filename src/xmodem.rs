@@ -0,0 +1,429 @@
+// XMODEM and YMODEM file transfers with whatever is behind a bridge, as the
+// send-file and recv-file subcommands, for bootloaders that take firmware
+// that way. The bridge must pass every byte through as it is, as one in raw
+// mode with passthrough does.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+use crate::local::{RemoteArgs, Stream};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1a;
+// Sent by a receiver in place of NAK to ask for CRC-16 rather than checksums.
+const CRC: u8 = b'C';
+
+// Long enough to reset the device into its bootloader once started.
+const START_TIMEOUT: Duration = Duration::from_secs(60);
+const TIMEOUT: Duration = Duration::from_secs(10);
+// How often a receiver asks for the transfer to start.
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+// XMODEM receivers fall back to checksums after asking for CRCs this often.
+const CRC_POLLS: u32 = 3;
+const RETRIES: u32 = 10;
+// Before asking again for a damaged block, the line must be quiet this long.
+const QUIET: Duration = Duration::from_secs(1);
+
+#[derive(clap::Args, Debug)]
+pub struct SendArgs {
+    #[command(flatten)]
+    remote: RemoteArgs,
+
+    /// The file to send.
+    file: PathBuf,
+
+    /// Send by YMODEM, which gives the receiver the file's name and size.
+    #[arg(long)]
+    ymodem: bool,
+
+    /// Send XMODEM in 1024-byte blocks (XMODEM-1K), for receivers that take
+    /// them.
+    #[arg(long = "1k", conflicts_with = "ymodem")]
+    one_k: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RecvArgs {
+    #[command(flatten)]
+    remote: RemoteArgs,
+
+    /// The file to write. With --ymodem, the directory to write files to
+    /// under the names the sender gives them (default the current one).
+    #[arg(required_unless_present = "ymodem")]
+    path: Option<PathBuf>,
+
+    /// Receive by YMODEM, a batch of files with their names and sizes.
+    #[arg(long)]
+    ymodem: bool,
+}
+
+// Sends a file to the device, which should be waiting to receive it.
+pub async fn send(args: SendArgs) -> Result<()> {
+    let data = tokio::fs::read(&args.file)
+        .await
+        .with_context(|| format!("failed to read {}", args.file.display()))?;
+    let header = match args.ymodem {
+        true => Some(header(&args.file, data.len())?),
+        false => None,
+    };
+    let mut link = Link::connect(args.remote).await?;
+    let block_size = if args.ymodem || args.one_k { 1024 } else { 128 };
+    let started = Instant::now();
+    let sent = link.send_file(header.as_deref(), &data, block_size).await;
+    link.finish(sent).await?;
+    info!("Sent {} ({} bytes) in {:.1?}", args.file.display(), data.len(), started.elapsed());
+    Ok(())
+}
+
+// Receives a file from the device, or with YMODEM every file it sends.
+pub async fn recv(args: RecvArgs) -> Result<()> {
+    let mut link = Link::connect(args.remote).await?;
+    let started = Instant::now();
+    if !args.ymodem {
+        let path = args.path.context("recv-file needs a path to write to")?;
+        info!("Waiting for the sender");
+        let received = link.recv_file(false, None).await;
+        let data = link.finish(received).await?;
+        write(&path, &data).await?;
+        info!("Received {} ({} bytes) in {:.1?}", path.display(), data.len(), started.elapsed());
+        return Ok(());
+    }
+    let dir = args.path.unwrap_or_else(|| PathBuf::from("."));
+    info!("Waiting for the sender");
+    let mut timeout = START_TIMEOUT;
+    loop {
+        let received = link.recv_header(timeout).await;
+        let Some((name, size)) = link.finish(received).await? else {
+            return Ok(());
+        };
+        let received = link.recv_file(true, size).await;
+        let data = link.finish(received).await?;
+        let path = dir.join(&name);
+        write(&path, &data).await?;
+        info!("Received {} ({} bytes) in {:.1?}", path.display(), data.len(), started.elapsed());
+        timeout = TIMEOUT;
+    }
+}
+
+// YMODEM's block 0 for a file: its name and size, NUL-terminated.
+fn header(path: &Path, size: usize) -> Result<Vec<u8>> {
+    let name = path.file_name().with_context(|| format!("{} is not a file", path.display()))?;
+    let mut header = name.to_string_lossy().into_owned().into_bytes();
+    header.push(0);
+    header.extend_from_slice(size.to_string().as_bytes());
+    header.push(0);
+    if header.len() > 1024 {
+        bail!("the file name is too long for YMODEM");
+    }
+    Ok(header)
+}
+
+// The name and size a YMODEM header gives, or None for the empty one that
+// ends the batch.
+fn parse_header(block: &[u8]) -> Result<Option<(PathBuf, Option<usize>)>> {
+    let mut fields = block.split(|&byte| byte == 0);
+    let name = fields.next().unwrap_or_default();
+    if name.is_empty() {
+        return Ok(None);
+    }
+    let name = String::from_utf8_lossy(name);
+    // Only the name, not where the sender had it.
+    let Some(name) = Path::new(name.as_ref()).file_name() else {
+        bail!("the sender gave an unusable file name '{}'", name);
+    };
+    let size = fields
+        .next()
+        .and_then(|info| std::str::from_utf8(info).ok())
+        .and_then(|info| info.split(' ').next())
+        .and_then(|size| size.parse().ok());
+    Ok(Some((PathBuf::from(name), size)))
+}
+
+async fn write(path: &Path, data: &[u8]) -> Result<()> {
+    tokio::fs::write(path, data)
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+// CRC-16/XMODEM.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+enum Packet {
+    Block(Vec<u8>),
+    End,
+}
+
+// The connection to the bridge, and whether blocks carry CRCs or checksums.
+struct Link {
+    stream: BufReader<Box<dyn Stream>>,
+    crc: bool,
+}
+
+impl Link {
+    async fn connect(args: RemoteArgs) -> Result<Link> {
+        let remote = args.remote()?;
+        let stream = remote.connect().await?;
+        info!("Connected to {}", remote.address);
+        Ok(Link {
+            stream: BufReader::new(stream),
+            crc: true,
+        })
+    }
+
+    // Tells the other end to give up when this end has.
+    async fn finish<T>(&mut self, result: Result<T>) -> Result<T> {
+        if result.is_err() {
+            // Best effort: the connection may be what failed.
+            let _ = self.send(&[CAN; 8]).await;
+        }
+        result
+    }
+
+    async fn send(&mut self, data: &[u8]) -> Result<()> {
+        self.stream.write_all(data).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    // The next byte, or None if none comes by `deadline`.
+    async fn byte(&mut self, deadline: Instant) -> Result<Option<u8>> {
+        match tokio::time::timeout_at(deadline, self.stream.read_u8()).await {
+            Ok(Ok(byte)) => Ok(Some(byte)),
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => bail!("the bridge closed the connection"),
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Ok(None),
+        }
+    }
+
+    // Fails if a CAN is followed by another, as a cancelled transfer is.
+    async fn cancelled(&mut self) -> Result<()> {
+        if self.byte(Instant::now() + TIMEOUT).await? == Some(CAN) {
+            bail!("the other end cancelled the transfer");
+        }
+        Ok(())
+    }
+
+    // Throws away what is still coming, the rest of a damaged block.
+    async fn purge(&mut self) -> Result<()> {
+        while self.byte(Instant::now() + QUIET).await?.is_some() {}
+        Ok(())
+    }
+
+    // Waits for the receiver to answer with ACK, NAK or, to start, 'C';
+    // None if it does not in time. Anything else it sends is ignored.
+    async fn reply(&mut self, timeout: Duration) -> Result<Option<u8>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.byte(deadline).await? {
+                Some(CAN) => self.cancelled().await?,
+                Some(byte @ (ACK | NAK | CRC)) => return Ok(Some(byte)),
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+    }
+
+    // Waits for the receiver to ask for a transfer to start.
+    async fn start(&mut self, timeout: Duration, crc_only: bool) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            match self.reply(deadline - Instant::now()).await? {
+                Some(CRC) => {
+                    self.crc = true;
+                    return Ok(());
+                }
+                Some(NAK) if !crc_only => {
+                    self.crc = false;
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+        bail!("the receiver did not start the transfer");
+    }
+
+    async fn send_file(&mut self, header: Option<&[u8]>, data: &[u8], block_size: usize) -> Result<()> {
+        info!("Waiting for the receiver to start");
+        self.start(START_TIMEOUT, header.is_some()).await?;
+        if let Some(header) = header {
+            self.send_block(0, header, if header.len() <= 128 { 128 } else { 1024 }, 0).await?;
+            self.start(TIMEOUT, true).await?;
+        }
+        for (i, chunk) in data.chunks(block_size).enumerate() {
+            // A short last block goes as 128 bytes, with less padding.
+            let size = if chunk.len() <= 128 { 128 } else { block_size };
+            self.send_block((i + 1) as u8, chunk, size, SUB).await?;
+        }
+        self.send_end().await?;
+        if header.is_some() {
+            // The empty header, for no more files.
+            self.start(TIMEOUT, true).await?;
+            self.send_block(0, &[], 128, 0).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_block(&mut self, number: u8, data: &[u8], size: usize, pad: u8) -> Result<()> {
+        let mut packet = vec![if size == 128 { SOH } else { STX }, number, !number];
+        packet.extend_from_slice(data);
+        packet.resize(3 + size, pad);
+        match self.crc {
+            true => packet.extend_from_slice(&crc16(&packet[3..]).to_be_bytes()),
+            false => packet.push(checksum(&packet[3..])),
+        }
+        for _ in 0..RETRIES {
+            self.send(&packet).await?;
+            match self.reply(TIMEOUT).await? {
+                Some(ACK) => return Ok(()),
+                _ => debug!("Block {} not acknowledged, sending it again", number),
+            }
+        }
+        bail!("the receiver did not acknowledge block {}", number);
+    }
+
+    // YMODEM receivers refuse the first EOT, to be sure of it.
+    async fn send_end(&mut self) -> Result<()> {
+        for _ in 0..RETRIES {
+            self.send(&[EOT]).await?;
+            if self.reply(TIMEOUT).await? == Some(ACK) {
+                return Ok(());
+            }
+        }
+        bail!("the receiver did not acknowledge the end of the file");
+    }
+
+    async fn recv_header(&mut self, timeout: Duration) -> Result<Option<(PathBuf, Option<usize>)>> {
+        let Packet::Block(block) = self.recv_packet(0, Some(timeout), false).await? else {
+            bail!("the sender ended a file before giving its name");
+        };
+        parse_header(&block)
+    }
+
+    // Receives blocks from 1 until the end of the file, which is cut to
+    // the size a YMODEM header gave; XMODEM files keep their padding.
+    async fn recv_file(&mut self, ymodem: bool, size: Option<usize>) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut number = 1u8;
+        let mut start = Some(if ymodem { TIMEOUT } else { START_TIMEOUT });
+        while let Packet::Block(block) = self.recv_packet(number, start.take(), !ymodem).await? {
+            data.extend_from_slice(&block);
+            number = number.wrapping_add(1);
+        }
+        if ymodem {
+            self.send(&[NAK]).await?;
+            if let Packet::Block(_) = self.recv_packet(number, None, false).await? {
+                bail!("the sender went on after the end of the file");
+            }
+        }
+        self.send(&[ACK]).await?;
+        if let Some(size) = size {
+            data.truncate(size);
+        }
+        Ok(data)
+    }
+
+    // Waits for block `number`, acknowledging it, or for the end of the
+    // file, which is left for the caller to answer. With `start`, this is
+    // the first block of a file or its header, asked for with 'C' until
+    // the sender begins or `start` runs out; XMODEM receivers switch to
+    // asking with NAK for checksums if `checksums` allows. Damaged blocks
+    // are asked for again; a block sent again because its ACK was lost is
+    // acknowledged and skipped.
+    async fn recv_packet(&mut self, number: u8, start: Option<Duration>, checksums: bool) -> Result<Packet> {
+        let deadline = start.map(|start| Instant::now() + start);
+        let mut starting = start.is_some();
+        let mut ask = starting.then_some(if self.crc { CRC } else { NAK });
+        let mut polls = 0;
+        let mut errors = 0;
+        loop {
+            if let Some(byte) = ask.take() {
+                self.send(&[byte]).await?;
+            }
+            let wait = if starting { POLL_INTERVAL } else { TIMEOUT };
+            match self.byte(Instant::now() + wait).await? {
+                Some(header @ (SOH | STX)) => match self.recv_block(header).await? {
+                    Some((got, block)) if got == number => {
+                        self.send(&[ACK]).await?;
+                        return Ok(Packet::Block(block));
+                    }
+                    Some((got, _)) if got == number.wrapping_sub(1) => self.send(&[ACK]).await?,
+                    Some((got, _)) => bail!("expected block {}, the sender sent {}", number, got),
+                    None => {
+                        starting = false;
+                        errors += 1;
+                        self.purge().await?;
+                        ask = Some(NAK);
+                    }
+                },
+                // Block 0 is YMODEM's header; the end of the file cannot
+                // come before it.
+                Some(EOT) if number != 0 => return Ok(Packet::End),
+                Some(CAN) => self.cancelled().await?,
+                Some(_) => {}
+                None if starting => {
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        bail!("the sender did not start the transfer");
+                    }
+                    polls += 1;
+                    if checksums && self.crc && polls >= CRC_POLLS {
+                        debug!("No answer to CRC requests, asking for checksums");
+                        self.crc = false;
+                    }
+                    ask = Some(if self.crc { CRC } else { NAK });
+                }
+                None => {
+                    errors += 1;
+                    ask = Some(NAK);
+                }
+            }
+            if errors >= RETRIES {
+                bail!("gave up on block {} after {} attempts", number, errors);
+            }
+        }
+    }
+
+    // The rest of a block after its header: its number and data, or None
+    // if it is damaged.
+    async fn recv_block(&mut self, header: u8) -> Result<Option<(u8, Vec<u8>)>> {
+        let size = if header == SOH { 128 } else { 1024 };
+        let mut packet = vec![0; 2 + size + if self.crc { 2 } else { 1 }];
+        match tokio::time::timeout(TIMEOUT, self.stream.read_exact(&mut packet)).await {
+            Ok(read) => read.context("the bridge closed the connection")?,
+            Err(_) => return Ok(None),
+        };
+        let (number, complement) = (packet[0], packet[1]);
+        let data = &packet[2..2 + size];
+        let valid = match self.crc {
+            true => packet[2 + size..] == crc16(data).to_be_bytes(),
+            false => packet[2 + size] == checksum(data),
+        };
+        if number != !complement || !valid {
+            debug!("Damaged block, asking for it again");
+            return Ok(None);
+        }
+        Ok(Some((number, data.to_vec())))
+    }
+}